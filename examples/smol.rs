@@ -0,0 +1,69 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Four voters finalizing a block on [smol](https://docs.rs/smol).
+//!
+//! The voter never spawns tasks and takes its timers and I/O from the
+//! `Environment`, so nothing ties it to an executor: the voters and the
+//! in-memory network are spawned as smol tasks, and the test
+//! environment's timers work on any executor.
+//!
+//! ```text
+//! cargo run --example smol --features test-helpers
+//! ```
+
+use std::sync::Arc;
+
+use finality_grandpa::round::State as RoundState;
+use finality_grandpa::testing::chain::GENESIS_HASH;
+use finality_grandpa::testing::environment::{make_network, Environment, Id};
+use finality_grandpa::voter::Voter;
+use finality_grandpa::voter_set::VoterSet;
+use futures::prelude::*;
+
+fn main() {
+	let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).expect("total weight doesn't overflow; qed");
+	let (network, routing_task) = make_network();
+
+	smol::block_on(async move {
+		smol::spawn(routing_task).detach();
+
+		let finalized = (0..4).map(|i| {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)));
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env,
+				voters.clone(),
+				network.make_global_comms(),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			smol::spawn(voter.map(|result| result.expect("Error voting"))).detach();
+
+			// wait for the voter to finalize the best block.
+			finalized.skip_while(|&(_, number, _)| future::ready(number < 6)).into_future()
+		});
+
+		for (i, (finalized, _)) in future::join_all(finalized).await.into_iter().enumerate() {
+			let (hash, number, _) = finalized.expect("voters run until the process exits; qed");
+			println!("voter {} finalized {} #{}", i, hash, number);
+		}
+	});
+}
@@ -0,0 +1,575 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Accountable safety: finding the voters responsible for finalizing two
+//! conflicting blocks.
+//!
+//! Say block `B` was finalized in round `r` and a conflicting block `B'` in
+//! round `r' >= r`. If `r == r'` the voters precommitting in both commits
+//! equivocated. Otherwise, honest voters only ever vote against `B` (i.e. for
+//! a block which isn't `B` or one of its descendents) after round `r` if the
+//! votes they have seen allow them to:
+//!
+//! - a precommit against `B` in round `k` requires a prevote-GHOST against `B`
+//!   among the prevotes of round `k`.
+//! - a prevote against `B` in round `k` requires round `k - 1` to be
+//!   completable with an estimate against `B`.
+//!
+//! Starting from the precommits finalizing `B'`, we ask each voter which voted
+//! against `B` for all votes they have seen in the round justifying that vote.
+//! The votes against `B` in a valid response have to be justified in turn, down
+//! to round `r`, where the precommits finalizing `B` are added to the responses
+//! to expose equivocations.
+//!
+//! Voters which equivocated, didn't justify a vote or didn't respond are
+//! culprits. An honest voter is never a culprit, as long as it responds.
+//!
+//! Signatures on the votes in commits and responses are assumed to have been
+//! checked, and the same voter set is assumed to have been used in all rounds
+//! involved.
+
+use crate::collections::{HashMap, HashSet, Vec};
+use crate::round::{Round, RoundParams, State as RoundState};
+use crate::voter_set::VoterSet;
+use crate::{
+	BlockNumberOps, Chain, Commit, Equivocation, Message, Precommit, Prevote, SignedMessage,
+	SignedPrecommit,
+};
+use std::hash::Hash;
+
+/// A query asking a voter for all votes they have seen in a round.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Query<Id> {
+	/// The voter queried.
+	pub voter: Id,
+	/// The round the votes are asked for.
+	pub round: u64,
+}
+
+/// A response to a `Query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response<H, N, S, Id> {
+	/// The voter responding.
+	pub voter: Id,
+	/// The round the votes were seen in.
+	pub round: u64,
+	/// All votes seen in the round.
+	pub votes: Vec<SignedMessage<H, N, S, Id>>,
+}
+
+impl<H, N, S, Id> Response<H, N, S, Id> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	S: Eq + Clone,
+	Id: Hash + Clone + Eq + ::std::fmt::Debug,
+{
+	/// Answer a query with the votes stored in our round.
+	pub fn from_round(voter: Id, round: &Round<Id, H, N, S>) -> Self {
+		Response {
+			voter,
+			round: round.number(),
+			votes: round.historical_votes().seen().clone(),
+		}
+	}
+}
+
+/// Evidence of a voter being responsible for the safety violation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Evidence<H, N, S, Id> {
+	/// The voter equivocated in prevotes.
+	PrevoteEquivocation(Equivocation<Id, Prevote<H, N>, S>),
+	/// The voter equivocated in precommits.
+	PrecommitEquivocation(Equivocation<Id, Precommit<H, N>, S>),
+	/// The votes the voter has seen in a round don't justify a vote it cast.
+	UnjustifiedVote(Response<H, N, S, Id>),
+	/// The voter didn't respond to a query.
+	NoResponse(Query<Id>),
+}
+
+impl<H, N, S, Id> Evidence<H, N, S, Id> {
+	/// The voter responsible.
+	pub fn culprit(&self) -> &Id {
+		match *self {
+			Evidence::PrevoteEquivocation(ref equivocation) => &equivocation.identity,
+			Evidence::PrecommitEquivocation(ref equivocation) => &equivocation.identity,
+			Evidence::UnjustifiedVote(ref response) => &response.voter,
+			Evidence::NoResponse(ref query) => &query.voter,
+		}
+	}
+}
+
+// the votes a voter has to justify with the votes they've seen in a round.
+#[derive(Debug, Default, Clone, Copy)]
+struct Unjustified {
+	// a precommit in the same round.
+	precommit: bool,
+	// a prevote in the following round.
+	next_prevote: bool,
+}
+
+/// An inquiry into a safety violation, identifying the culprits by querying
+/// voters for the votes they have seen.
+pub struct Inquiry<H, N, S, Id: Hash + Eq> {
+	voters: VoterSet<Id>,
+	base: (H, N),
+	// the block finalized in the earlier round.
+	finalized: (H, N),
+	finalized_round: u64,
+	finalized_precommits: Vec<SignedPrecommit<H, N, S, Id>>,
+	required: HashMap<Query<Id>, Unjustified>,
+	responses: HashMap<Query<Id>, Response<H, N, S, Id>>,
+	// voters known to have equivocated, by round and whether in prevotes.
+	equivocators: HashSet<(Id, u64, bool)>,
+	evidence: Vec<Evidence<H, N, S, Id>>,
+}
+
+impl<H, N, S, Id> Inquiry<H, N, S, Id> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	S: Eq + Clone,
+	Id: Hash + Clone + Eq + ::std::fmt::Debug,
+{
+	/// Start an inquiry into two commits with their rounds, in any order,
+	/// finalizing conflicting blocks. `base` must be an ancestor of both
+	/// blocks, e.g. the last block finalized before them.
+	///
+	/// Returns `None` if the commit targets don't conflict.
+	pub fn new<C: Chain<H, N>>(
+		chain: &C,
+		voters: VoterSet<Id>,
+		base: (H, N),
+		first: (u64, Commit<H, N, S, Id>),
+		second: (u64, Commit<H, N, S, Id>),
+	) -> Option<Self> {
+		let (earlier, later) = if first.0 <= second.0 { (first, second) } else { (second, first) };
+		let finalized = (earlier.1.target_hash, earlier.1.target_number);
+		let conflicting = (later.1.target_hash.clone(), later.1.target_number);
+
+		if chain.is_equal_or_descendent_of(finalized.0.clone(), conflicting.0.clone()) ||
+			chain.is_equal_or_descendent_of(conflicting.0, finalized.0.clone())
+		{
+			return None;
+		}
+
+		let mut inquiry = Inquiry {
+			voters,
+			base,
+			finalized,
+			finalized_round: earlier.0,
+			finalized_precommits: earlier.1.precommits,
+			required: HashMap::new(),
+			responses: HashMap::new(),
+			equivocators: HashSet::new(),
+			evidence: Vec::new(),
+		};
+
+		if later.0 == inquiry.finalized_round {
+			// the commits are from the same round, so the voters in both of
+			// them equivocated.
+			let votes = later.1.precommits.into_iter().map(|signed| SignedMessage {
+				message: Message::Precommit(signed.precommit),
+				signature: signed.signature,
+				id: signed.id,
+			});
+
+			inquiry.round_with_commit(chain, later.0, votes);
+		} else {
+			for signed in &later.1.precommits {
+				let target = (signed.precommit.target_hash.clone(), signed.precommit.target_number);
+				if inquiry.is_against_finalized(chain, &target) {
+					inquiry.require(chain, signed.id.clone(), later.0, Unjustified {
+						precommit: true,
+						next_prevote: false,
+					});
+				}
+			}
+		}
+
+		Some(inquiry)
+	}
+
+	/// The queries which haven't been responded to yet.
+	pub fn pending_queries(&self) -> Vec<Query<Id>> {
+		self.required.keys()
+			.filter(|query| !self.responses.contains_key(query))
+			.cloned()
+			.collect()
+	}
+
+	/// Whether all queries have been responded to.
+	pub fn is_complete(&self) -> bool {
+		self.required.keys().all(|query| self.responses.contains_key(query))
+	}
+
+	/// Import a response to a pending query, which may lead to new queries.
+	/// Returns whether the response was to a pending query.
+	pub fn import_response<C: Chain<H, N>>(&mut self, chain: &C, response: Response<H, N, S, Id>) -> bool {
+		let query = Query { voter: response.voter.clone(), round: response.round };
+		let unjustified = match self.required.get(&query) {
+			Some(unjustified) if !self.responses.contains_key(&query) => *unjustified,
+			_ => return false,
+		};
+
+		self.responses.insert(query.clone(), response);
+		self.check(chain, &query, unjustified);
+
+		true
+	}
+
+	/// The evidence gathered so far.
+	pub fn evidence(&self) -> &[Evidence<H, N, S, Id>] {
+		&self.evidence
+	}
+
+	/// Conclude the inquiry, e.g. after a timeout. The voters which haven't
+	/// responded to a query are culprits as well.
+	pub fn conclude(mut self) -> Vec<Evidence<H, N, S, Id>> {
+		for query in self.pending_queries() {
+			self.evidence.push(Evidence::NoResponse(query));
+		}
+
+		self.evidence
+	}
+
+	/// The culprits found so far, each listed once.
+	pub fn culprits(&self) -> Vec<Id> {
+		let mut seen = HashSet::new();
+		self.evidence.iter()
+			.map(Evidence::culprit)
+			.filter(|id| seen.insert(*id))
+			.cloned()
+			.collect()
+	}
+
+	fn is_against_finalized<C: Chain<H, N>>(&self, chain: &C, block: &(H, N)) -> bool {
+		block.1 < self.finalized.1 ||
+			!chain.is_equal_or_descendent_of(self.finalized.0.clone(), block.0.clone())
+	}
+
+	// note the votes a voter has to justify with the votes seen in a round,
+	// checking them right away if we have a response already.
+	fn require<C: Chain<H, N>>(&mut self, chain: &C, voter: Id, round: u64, unjustified: Unjustified) {
+		let query = Query { voter, round };
+		let entry = self.required.entry(query.clone()).or_default();
+
+		let new = Unjustified {
+			precommit: unjustified.precommit && !entry.precommit,
+			next_prevote: unjustified.next_prevote && !entry.next_prevote,
+		};
+
+		entry.precommit |= unjustified.precommit;
+		entry.next_prevote |= unjustified.next_prevote;
+
+		if (new.precommit || new.next_prevote) && self.responses.contains_key(&query) {
+			self.check(chain, &query, new);
+		}
+	}
+
+	fn check<C: Chain<H, N>>(&mut self, chain: &C, query: &Query<Id>, unjustified: Unjustified) {
+		let response = self.responses.get(query)
+			.expect("only responses to known queries are checked; qed")
+			.clone();
+
+		let state = self.round_with(chain, query.round, response.votes.iter().cloned());
+
+		let justified = |against: &Option<(H, N)>| against.as_ref()
+			.is_some_and(|block| self.is_against_finalized(chain, block));
+
+		let precommit_justified = !unjustified.precommit || justified(&state.prevote_ghost);
+		let prevote_justified = !unjustified.next_prevote ||
+			(state.completable && justified(&state.estimate));
+
+		if !(precommit_justified && prevote_justified) {
+			self.evidence.push(Evidence::UnjustifiedVote(response));
+			return;
+		}
+
+		if query.round == self.finalized_round {
+			self.round_with_commit(chain, query.round, response.votes.into_iter());
+			return;
+		}
+
+		// the votes against the finalized block in a valid response have to be
+		// justified as well.
+		for signed in response.votes {
+			let target = (signed.message.target().0.clone(), signed.message.target().1);
+			if !self.is_against_finalized(chain, &target) {
+				continue;
+			}
+
+			match signed.message {
+				Message::Prevote(_) => self.require(chain, signed.id, query.round - 1, Unjustified {
+					precommit: false,
+					next_prevote: true,
+				}),
+				Message::Precommit(_) => self.require(chain, signed.id, query.round, Unjustified {
+					precommit: true,
+					next_prevote: false,
+				}),
+				Message::PrimaryPropose(_) => {},
+			}
+		}
+	}
+
+	// import the given votes along with the precommits finalizing the earlier
+	// block into a round, collecting the equivocations.
+	fn round_with_commit<C: Chain<H, N>>(
+		&mut self,
+		chain: &C,
+		round: u64,
+		votes: impl Iterator<Item=SignedMessage<H, N, S, Id>>,
+	) {
+		let finalized_precommits = self.finalized_precommits.clone().into_iter().map(|signed| SignedMessage {
+			message: Message::Precommit(signed.precommit),
+			signature: signed.signature,
+			id: signed.id,
+		});
+
+		self.round_with(chain, round, finalized_precommits.chain(votes));
+	}
+
+	// import the given votes into a new round, collecting the equivocations.
+	fn round_with<C: Chain<H, N>>(
+		&mut self,
+		chain: &C,
+		round_number: u64,
+		votes: impl Iterator<Item=SignedMessage<H, N, S, Id>>,
+	) -> RoundState<H, N> {
+		let mut round = Round::new(RoundParams {
+			round_number,
+			voters: self.voters.clone(),
+			base: self.base.clone(),
+		});
+
+		for SignedMessage { message, signature, id } in votes {
+			// votes which aren't descendents of the base can't be imported,
+			// they don't affect the checks either way. neither do votes the
+			// round rejects, e.g. for a wrong block number, or whose ancestry
+			// can't be looked up.
+			let (target_hash, target_number) = message.target();
+			if target_number < self.base.1 ||
+				!chain.is_equal_or_descendent_of(self.base.0.clone(), target_hash.clone())
+			{
+				continue;
+			}
+
+			match message {
+				Message::Prevote(prevote) => {
+					let equivocation = round.import_prevote(chain, prevote, id, signature)
+						.ok()
+						.and_then(|imported| imported.equivocation());
+
+					if let Some(equivocation) = equivocation {
+						if self.equivocators.insert((equivocation.identity.clone(), round_number, true)) {
+							self.evidence.push(Evidence::PrevoteEquivocation(equivocation));
+						}
+					}
+				}
+				Message::Precommit(precommit) => {
+					let equivocation = round.import_precommit(chain, precommit, id, signature)
+						.ok()
+						.and_then(|imported| imported.equivocation());
+
+					if let Some(equivocation) = equivocation {
+						if self.equivocators.insert((equivocation.identity.clone(), round_number, false)) {
+							self.evidence.push(Evidence::PrecommitEquivocation(equivocation));
+						}
+					}
+				}
+				Message::PrimaryPropose(_) => {},
+			}
+		}
+
+		round.state()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+
+	const ALICE: u32 = 0;
+	const BOB: u32 = 1;
+	const CHARLIE: u32 = 2;
+	const DAVE: u32 = 3;
+
+	fn voters() -> VoterSet<u32> {
+		[ALICE, BOB, CHARLIE, DAVE].iter().map(|&id| (id, 1)).collect()
+	}
+
+	// A - B - C
+	//  \
+	//   B1 - C1
+	fn chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B1", "C1"]);
+		chain
+	}
+
+	fn commit(target: (&'static str, u32), ids: &[u32]) -> Commit<&'static str, u32, u32, u32> {
+		Commit {
+			target_hash: target.0,
+			target_number: target.1,
+			precommits: ids.iter().map(|&id| SignedPrecommit {
+				precommit: Precommit::new(target.0, target.1),
+				signature: id,
+				id,
+			}).collect(),
+		}
+	}
+
+	fn prevote(target: (&'static str, u32), id: u32) -> SignedMessage<&'static str, u32, u32, u32> {
+		SignedMessage { message: Message::Prevote(Prevote::new(target.0, target.1)), signature: id, id }
+	}
+
+	fn precommit(target: (&'static str, u32), id: u32) -> SignedMessage<&'static str, u32, u32, u32> {
+		SignedMessage { message: Message::Precommit(Precommit::new(target.0, target.1)), signature: id, id }
+	}
+
+	fn response(
+		voter: u32,
+		round: u64,
+		votes: Vec<SignedMessage<&'static str, u32, u32, u32>>,
+	) -> Response<&'static str, u32, u32, u32> {
+		Response { voter, round, votes }
+	}
+
+	#[test]
+	fn no_inquiry_without_conflict() {
+		let chain = chain();
+		assert!(Inquiry::new(
+			&chain,
+			voters(),
+			(GENESIS_HASH, 1),
+			(1, commit(("B", 3), &[ALICE, BOB, CHARLIE])),
+			(2, commit(("C", 4), &[ALICE, BOB, CHARLIE])),
+		).is_none());
+	}
+
+	#[test]
+	fn same_round_commits_expose_equivocators() {
+		let chain = chain();
+		let inquiry = Inquiry::new(
+			&chain,
+			voters(),
+			(GENESIS_HASH, 1),
+			(1, commit(("B", 3), &[ALICE, BOB, CHARLIE])),
+			(1, commit(("B1", 3), &[BOB, CHARLIE, DAVE])),
+		).unwrap();
+
+		assert!(inquiry.is_complete());
+
+		let mut culprits = inquiry.culprits();
+		culprits.sort();
+		assert_eq!(culprits, vec![BOB, CHARLIE]);
+	}
+
+	#[test]
+	fn queries_down_to_finalized_round() {
+		let chain = chain();
+
+		// B was finalized in round 1, B1 in round 2. BOB and CHARLIE switched
+		// sides without justification, ALICE was honest but saw their votes.
+		let mut inquiry = Inquiry::new(
+			&chain,
+			voters(),
+			(GENESIS_HASH, 1),
+			(2, commit(("B1", 3), &[ALICE, BOB, CHARLIE])),
+			(1, commit(("B", 3), &[BOB, CHARLIE, DAVE])),
+		).unwrap();
+
+		let mut pending = inquiry.pending_queries();
+		pending.sort_by_key(|query| query.voter);
+		assert_eq!(pending, vec![
+			Query { voter: ALICE, round: 2 },
+			Query { voter: BOB, round: 2 },
+			Query { voter: CHARLIE, round: 2 },
+		]);
+
+		// BOB doesn't justify the precommit, there is no prevote-GHOST.
+		assert!(inquiry.import_response(&chain, response(BOB, 2, vec![prevote(("B1", 3), BOB)])));
+
+		// ALICE saw a prevote-GHOST on the other fork.
+		let round_2_prevotes = vec![
+			prevote(("C1", 4), ALICE),
+			prevote(("B1", 3), BOB),
+			prevote(("C1", 4), CHARLIE),
+		];
+		assert!(inquiry.import_response(&chain, response(ALICE, 2, round_2_prevotes.clone())));
+		assert!(!inquiry.import_response(&chain, response(ALICE, 2, round_2_prevotes)));
+
+		// the prevotes ALICE saw now have to be justified with round 1.
+		let mut pending = inquiry.pending_queries();
+		pending.sort_by_key(|query| (query.voter, query.round));
+		assert_eq!(pending, vec![
+			Query { voter: ALICE, round: 1 },
+			Query { voter: BOB, round: 1 },
+			Query { voter: CHARLIE, round: 1 },
+			Query { voter: CHARLIE, round: 2 },
+		]);
+
+		// round 1 was completable with an estimate of A for ALICE, as BOB and
+		// CHARLIE precommitted against B. they also precommitted for B though.
+		let round_1_votes = vec![
+			prevote(("A", 2), ALICE),
+			prevote(("B1", 3), BOB),
+			prevote(("B1", 3), CHARLIE),
+			precommit(("A", 2), ALICE),
+			precommit(("B1", 3), BOB),
+			precommit(("A", 2), CHARLIE),
+			precommit(("B", 3), DAVE),
+		];
+		assert!(inquiry.import_response(&chain, response(ALICE, 1, round_1_votes)));
+
+		let evidence = inquiry.conclude();
+		let equivocators: Vec<_> = evidence.iter().filter_map(|evidence| match evidence {
+			Evidence::PrecommitEquivocation(equivocation) => Some(equivocation.identity),
+			_ => None,
+		}).collect();
+
+		assert_eq!(evidence[0], Evidence::UnjustifiedVote(response(BOB, 2, vec![prevote(("B1", 3), BOB)])));
+		assert_eq!(equivocators.len(), 2);
+		assert!(equivocators.contains(&BOB) && equivocators.contains(&CHARLIE));
+		assert!(evidence.contains(&Evidence::NoResponse(Query { voter: CHARLIE, round: 2 })));
+		assert!(evidence.iter().all(|evidence| *evidence.culprit() != ALICE));
+	}
+
+	#[test]
+	fn votes_with_wrong_block_numbers_are_skipped() {
+		let chain = chain();
+		let mut inquiry = Inquiry::new(
+			&chain,
+			voters(),
+			(GENESIS_HASH, 1),
+			(2, commit(("B1", 3), &[ALICE, BOB, CHARLIE])),
+			(1, commit(("B", 3), &[BOB, CHARLIE, DAVE])),
+		).unwrap();
+
+		// C1 is block 4, the votes don't justify anything.
+		assert!(inquiry.import_response(&chain, response(BOB, 2, vec![
+			prevote(("C1", 9), ALICE),
+			prevote(("C1", 9), BOB),
+			prevote(("C1", 9), CHARLIE),
+		])));
+
+		let evidence = inquiry.conclude();
+		assert!(evidence.contains(&Evidence::UnjustifiedVote(response(BOB, 2, vec![
+			prevote(("C1", 9), ALICE),
+			prevote(("C1", 9), BOB),
+			prevote(("C1", 9), CHARLIE),
+		]))));
+	}
+}
@@ -27,6 +27,9 @@
 //!
 //! Bitfields on regular vote-nodes will tend to be live, but the equivocating
 //! bitfield will be mostly empty.
+//!
+//! The same layout is used to track which voters have voted at all in a round,
+//! which can be advertised to peers.
 
 use std::fmt;
 
@@ -123,6 +126,30 @@ impl Bitfield {
 		}
 	}
 
+	/// Whether the voter with the given canonical index is set for prevotes.
+	pub fn prevoted(&self, voter_idx: usize) -> bool {
+		self.bit(voter_idx * 2)
+	}
+
+	/// Whether the voter with the given canonical index is set for precommits.
+	pub fn precommitted(&self, voter_idx: usize) -> bool {
+		self.bit(voter_idx * 2 + 1)
+	}
+
+	/// The words of the bitfield, with a prevote and a precommit bit per voter
+	/// starting from the most significant bit. Empty if the bitfield is blank.
+	pub fn words(&self) -> &[u64] {
+		match *self {
+			Bitfield::Blank => &[],
+			Bitfield::Live(ref live) => &live.bits,
+		}
+	}
+
+	fn bit(&self, bit_idx: usize) -> bool {
+		self.words().get(bit_idx / 64)
+			.is_some_and(|word| word & (1 << (63 - bit_idx % 64)) != 0)
+	}
+
 	/// Set a bit in the bitfield.
 	fn set_bit(&mut self, bit: usize, n_voters: usize) -> Result<(), Error> {
 		let mut live = match ::std::mem::replace(self, Bitfield::Blank) {
@@ -390,4 +417,21 @@ mod tests {
 
 		assert_eq!(a.overlap(&b).unwrap(), c);
 	}
+
+	#[test]
+	fn read_bits() {
+		let mut bitfield = Bitfield::Blank;
+		assert!(!bitfield.prevoted(0));
+		assert!(bitfield.words().is_empty());
+
+		bitfield.set_bit(to_prevote(1), 40).unwrap();
+		bitfield.set_bit(to_precommit(33), 40).unwrap();
+
+		assert!(bitfield.prevoted(1));
+		assert!(!bitfield.precommitted(1));
+		assert!(bitfield.precommitted(33));
+		assert!(!bitfield.prevoted(33));
+		assert!(!bitfield.prevoted(100));
+		assert_eq!(bitfield.words(), &[1 << 61, 1 << 60]);
+	}
 }
@@ -15,64 +15,103 @@
 //! Bridging round state between rounds.
 
 use crate::round::State as RoundState;
-use futures::task;
-use parking_lot::{RwLock, RwLockReadGuard};
-use std::sync::Arc;
+use futures::task::{AtomicWaker, Waker};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use std::sync::{Arc, Weak};
 
 // round state bridged across rounds.
 struct Bridged<H, N> {
 	inner: RwLock<RoundState<H, N>>,
-	task: task::AtomicTask,
+	// the wakers of all latter views, dropped along with their views.
+	wakers: Mutex<Vec<Weak<AtomicWaker>>>,
 }
 
 impl<H, N> Bridged<H, N> {
 	fn new(inner: RwLock<RoundState<H, N>>) -> Self {
 		Bridged {
 			inner,
-			task: task::AtomicTask::new(),
+			wakers: Mutex::new(Vec::new()),
 		}
 	}
+
+	// wake all latter views, forgetting those which were dropped.
+	fn wake(&self) {
+		self.wakers.lock().retain(|waker| match waker.upgrade() {
+			Some(waker) => { waker.wake(); true },
+			None => false,
+		});
+	}
 }
 
 /// A prior view of a round-state.
 pub(crate) struct PriorView<H, N>(Arc<Bridged<H, N>>);
 
 impl<H, N> PriorView<H, N> {
-	/// Push an update to the latter view.
+	/// Push an update to all latter views.
 	pub(crate) fn update(&self, new: RoundState<H, N>) {
 		*self.0.inner.write() = new;
-		self.0.task.notify();
+		self.0.wake();
+	}
+
+	/// Create another latter view, which receives the same updates as the
+	/// others.
+	pub(crate) fn latter_view(&self) -> LatterView<H, N> {
+		LatterView::new(self.0.clone())
 	}
 }
 
 /// A latter view of a round-state.
-pub(crate) struct LatterView<H, N>(Arc<Bridged<H, N>>);
+pub(crate) struct LatterView<H, N> {
+	bridged: Arc<Bridged<H, N>>,
+	waker: Arc<AtomicWaker>,
+}
 
 impl<H, N> LatterView<H, N> {
-	/// Fetch a handle to the last round-state.
-	pub(crate) fn get(&self) -> RwLockReadGuard<RoundState<H, N>> {
-		self.0.task.register();
-		self.0.inner.read()
+	fn new(bridged: Arc<Bridged<H, N>>) -> Self {
+		let waker = Arc::new(AtomicWaker::new());
+		bridged.wakers.lock().push(Arc::downgrade(&waker));
+		LatterView { bridged, waker }
+	}
+
+	/// Fetch a handle to the last round-state, waking the given waker on
+	/// the next update.
+	pub(crate) fn get(&self, waker: &Waker) -> RwLockReadGuard<'_, RoundState<H, N>> {
+		self.waker.register(waker);
+		self.bridged.inner.read()
+	}
+
+	/// Fetch a handle to the last round-state without waiting for updates.
+	pub(crate) fn read(&self) -> RwLockReadGuard<'_, RoundState<H, N>> {
+		self.bridged.inner.read()
+	}
+}
+
+impl<H, N> Clone for LatterView<H, N> {
+	/// Create another latter view of the same round-state, with its own waker.
+	fn clone(&self) -> Self {
+		LatterView::new(self.bridged.clone())
 	}
 }
 
 /// Constructs two views of a bridged round-state.
 ///
-/// The prior view is held by a round which produces the state and pushes updates to a latter view.
-/// When updating, the latter view's task is updated.
+/// The prior view is held by a round which produces the state and pushes updates to the latter
+/// views. When updating, the wakers of all latter views are woken.
 ///
 /// The latter view is held by the subsequent round, which blocks certain activity
-/// while waiting for events on an older round.
+/// while waiting for events on an older round. More latter views, e.g. for observers of the
+/// round, are created with `PriorView::latter_view`.
 pub(crate) fn bridge_state<H, N>(initial: RoundState<H, N>) -> (PriorView<H, N>, LatterView<H, N>) {
 	let inner = Arc::new(Bridged::new(RwLock::new(initial)));
 	(
-		PriorView(inner.clone()), LatterView(inner)
+		PriorView(inner.clone()), LatterView::new(inner)
 	)
 }
 
 #[cfg(test)]
 mod tests {
-	use futures::prelude::*;
+	use futures::executor::block_on;
+	use futures::task::Poll;
 	use std::sync::Barrier;
 	use super::*;
 
@@ -86,11 +125,11 @@ mod tests {
 		};
 
 		let (prior, latter) = bridge_state(initial);
-		let waits_for_finality = ::futures::future::poll_fn(move || -> Poll<(), ()> {
-			if latter.get().finalized.is_some() {
-				Ok(Async::Ready(()))
+		let waits_for_finality = ::futures::future::poll_fn(move |cx| -> Poll<()> {
+			if latter.get(cx.waker()).finalized.is_some() {
+				Poll::Ready(())
 			} else {
-				Ok(Async::NotReady)
+				Poll::Pending
 			}
 		});
 
@@ -107,6 +146,45 @@ mod tests {
 		});
 
 		barrier.wait();
-		waits_for_finality.wait().unwrap();
+		block_on(waits_for_finality);
+	}
+
+	#[test]
+	fn all_latter_views_are_updated() {
+		use futures::task::ArcWake;
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		struct CountWakes(AtomicUsize);
+
+		impl ArcWake for CountWakes {
+			fn wake_by_ref(arc_self: &Arc<Self>) {
+				arc_self.0.fetch_add(1, Ordering::SeqCst);
+			}
+		}
+
+		let state = |completable| RoundState {
+			prevote_ghost: Some(("5", 5)),
+			finalized: None,
+			estimate: Some(("3", 3)),
+			completable,
+		};
+
+		let (prior, first) = bridge_state(state(false));
+		let second = prior.latter_view();
+		let third = first.clone();
+		let dropped = prior.latter_view();
+		drop(dropped);
+
+		let counts: Vec<_> = (0..3).map(|_| Arc::new(CountWakes(AtomicUsize::new(0)))).collect();
+		for (view, count) in [&first, &second, &third].iter().zip(&counts) {
+			assert!(!view.get(&futures::task::waker(count.clone())).completable);
+		}
+
+		prior.update(state(true));
+		for (view, count) in [&first, &second, &third].iter().zip(&counts) {
+			assert_eq!(count.0.load(Ordering::SeqCst), 1);
+			assert!(view.read().completable);
+		}
+		assert_eq!(prior.0.wakers.lock().len(), 3);
 	}
 }
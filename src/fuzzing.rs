@@ -0,0 +1,309 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fuzzing entry points, enabled by the `fuzzing` feature.
+//!
+//! Each function turns arbitrary bytes into a block tree and inputs for the
+//! code under test, and panics if that code panics or breaks one of its
+//! invariants. The `cargo-fuzz` targets in `fuzz/` call into these:
+//!
+//! ```sh
+//! cargo +nightly fuzz run vote_graph
+//! ```
+//!
+//! Votes may carry a block number which doesn't match their block, as they
+//! would from an adversary. The invariants are only checked for inputs
+//! where they match, but no input may cause a panic.
+
+use crate::collections::{HashSet, Vec};
+use crate::vote_graph::VoteGraph;
+use crate::voter_set::VoterSet;
+use crate::{Chain, Commit, Error, Precommit, SignedPrecommit};
+
+const MAX_BLOCKS: usize = 32;
+const MAX_VOTERS: usize = 8;
+
+// reads the fuzzer's input, yielding zeroes once it's exhausted.
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+	fn byte(&mut self) -> u8 {
+		match self.0.split_first() {
+			Some((&byte, rest)) => {
+				self.0 = rest;
+				byte
+			}
+			None => 0,
+		}
+	}
+
+	// a number below `bound`, which must not be zero.
+	fn below(&mut self, bound: usize) -> usize {
+		self.byte() as usize % bound
+	}
+
+	fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+
+// a block tree where block `i` has hash `i`. block 0 is the genesis, which
+// has number 1.
+struct FuzzChain {
+	parents: Vec<u32>,
+	numbers: Vec<u32>,
+}
+
+impl FuzzChain {
+	fn from_input(input: &mut Input) -> Self {
+		let len = 1 + input.below(MAX_BLOCKS);
+		let mut parents = vec![0];
+		let mut numbers = vec![1];
+
+		for i in 1..len {
+			let parent = input.below(i);
+			parents.push(parent as u32);
+			numbers.push(numbers[parent] + 1);
+		}
+
+		FuzzChain { parents, numbers }
+	}
+
+	fn is_known(&self, hash: u32) -> bool {
+		(hash as usize) < self.numbers.len()
+	}
+
+	// a known block.
+	fn known_block(&self, input: &mut Input) -> (u32, u32) {
+		let hash = input.below(self.numbers.len());
+		(hash as u32, self.numbers[hash])
+	}
+
+	// a block which may be unknown, and a number which may not match it.
+	// returns whether the block is known and the number is its own.
+	fn block(&self, input: &mut Input) -> ((u32, u32), bool) {
+		let hash = input.below(self.numbers.len() + 2) as u32;
+		let arbitrary_number = input.byte();
+
+		match self.numbers.get(hash as usize) {
+			Some(&number) if arbitrary_number >= 32 => ((hash, number), true),
+			_ => ((hash, arbitrary_number as u32), false),
+		}
+	}
+
+	// the votes on the given block and its descendents.
+	fn weight_at(&self, hash: u32, votes: &[(u32, u64)]) -> u64 {
+		votes.iter()
+			.filter(|&&(block, _)| self.is_equal_or_descendent_of(hash, block))
+			.map(|&(_, weight)| weight)
+			.sum()
+	}
+}
+
+impl Chain<u32, u32> for FuzzChain {
+	fn ancestry(&self, base: u32, mut block: u32) -> Result<Vec<u32>, Error> {
+		if !self.is_known(base) || !self.is_known(block) {
+			return Err(Error::NotDescendent);
+		}
+
+		let mut ancestry = Vec::new();
+		loop {
+			if block == 0 { return Err(Error::NotDescendent) }
+
+			block = self.parents[block as usize];
+			if block == base { return Ok(ancestry) }
+
+			ancestry.push(block);
+		}
+	}
+
+	fn best_chain_containing(&self, base: u32) -> Option<(u32, u32)> {
+		if !self.is_known(base) { return None }
+
+		(0..self.numbers.len() as u32)
+			.filter(|&block| self.is_equal_or_descendent_of(base, block))
+			.max_by_key(|&block| self.numbers[block as usize])
+			.map(|block| (block, self.numbers[block as usize]))
+	}
+}
+
+/// Insert arbitrary votes into a `VoteGraph` and query it. After every
+/// insertion, the cumulative vote of each vote-node must be the sum of the
+/// votes on it and its descendents, and the heads must be exactly the
+/// vote-nodes without descendents. Query results must fulfill the condition
+/// they were searched for.
+pub fn vote_graph(data: &[u8]) {
+	let mut input = Input(data);
+	let chain = FuzzChain::from_input(&mut input);
+	let (base_hash, base_number) = chain.known_block(&mut input);
+
+	let mut graph = VoteGraph::new(base_hash, base_number);
+	let mut inserted = Vec::new();
+	let mut consistent = true;
+
+	while !input.is_empty() {
+		let ((hash, number), matches) = chain.block(&mut input);
+		let threshold = input.byte() as u64;
+		let condition = |weight: &u64| *weight >= threshold;
+
+		match input.byte() % 4 {
+			0 => {
+				// only checked for panics, as it needn't start from the base.
+				let _ = graph.find_ghost(Some((hash, number)), condition);
+
+				let ghost = graph.find_ghost(None, condition);
+				if let (true, Some((ghost_hash, _))) = (consistent, ghost) {
+					assert!(chain.weight_at(ghost_hash, &inserted) >= threshold, "GHOST fulfills the condition");
+				}
+			}
+			1 => {
+				let ancestor = graph.find_ancestor(hash, number, condition);
+				if let (true, true, Some((ancestor_hash, _))) = (consistent, matches, ancestor) {
+					assert!(chain.is_equal_or_descendent_of(ancestor_hash, hash), "ancestor is in the chain of the block");
+					assert!(chain.weight_at(ancestor_hash, &inserted) >= threshold, "ancestor fulfills the condition");
+				}
+			}
+			2 => {
+				let weight = graph.cumulative_vote_at(hash, number, &chain);
+				if consistent && matches && number >= base_number {
+					assert_eq!(weight, chain.weight_at(hash, &inserted), "cumulative vote at {}", hash);
+				}
+			}
+			_ => {
+				if graph.insert(hash, number, threshold, &chain).is_ok() {
+					consistent &= matches;
+					inserted.push((hash, threshold));
+				}
+			}
+		}
+
+		if consistent {
+			check_vote_graph(&graph, &chain, &inserted);
+		}
+	}
+}
+
+fn check_vote_graph(graph: &VoteGraph<u32, u32, u64>, chain: &FuzzChain, inserted: &[(u32, u64)]) {
+	graph.check_invariants();
+
+	let mut heads = HashSet::new();
+	for node in graph.iter() {
+		assert_eq!(node.number, chain.numbers[*node.hash as usize], "number of {}", node.hash);
+		assert_eq!(*node.cumulative_vote, chain.weight_at(*node.hash, inserted), "cumulative vote of {}", node.hash);
+
+		if node.descendents.is_empty() {
+			heads.insert(*node.hash);
+		}
+	}
+
+	assert_eq!(graph.heads().cloned().collect::<HashSet<_>>(), heads, "heads are the vote-nodes without descendents");
+
+	for (hash, _) in inserted {
+		assert!(graph.iter().any(|node| node.hash == hash), "{} is a vote-node", hash);
+	}
+}
+
+/// Validate an arbitrary commit from an arbitrary voter set. If the commit
+/// is valid, its GHOST must descend from its target and have precommits on
+/// it or its descendents from voters reaching the threshold, counting
+/// equivocators as voting for everything.
+pub fn validate_commit(data: &[u8]) {
+	let mut input = Input(data);
+	let chain = FuzzChain::from_input(&mut input);
+
+	let num_voters = 1 + input.below(MAX_VOTERS);
+	let voters = (0..num_voters).map(|id| (id as u8, 1 + input.byte() as u64)).collect::<Vec<_>>();
+	let voters = match VoterSet::new(voters) {
+		Ok(voters) => voters,
+		Err(_) => return,
+	};
+
+	let ((target_hash, target_number), mut consistent) = chain.block(&mut input);
+	let mut precommits = Vec::new();
+
+	while !input.is_empty() {
+		let ((hash, number), matches) = chain.block(&mut input);
+		consistent &= matches;
+
+		precommits.push(SignedPrecommit {
+			precommit: Precommit::new(hash, number),
+			// include some voters which aren't in the set.
+			id: input.below(num_voters + 2) as u8,
+			signature: input.byte(),
+		});
+	}
+
+	let commit = Commit { target_hash, target_number, precommits };
+	let result = match crate::validate_commit(&commit, &voters, &chain) {
+		Ok(result) => result,
+		Err(_) => return,
+	};
+
+	assert_eq!(result.num_precommits(), commit.precommits.len());
+	assert_eq!(result.is_valid(), result.ghost().is_some(), "a commit is valid if and only if it has a GHOST");
+
+	let (ghost_hash, ghost_number) = match (consistent, result.ghost()) {
+		(true, Some(&ghost)) => ghost,
+		_ => return,
+	};
+
+	assert_eq!(ghost_number, chain.numbers[ghost_hash as usize], "number of the GHOST");
+	assert!(chain.is_equal_or_descendent_of(target_hash, ghost_hash), "GHOST descends from the target");
+
+	// the same vote with another signature is an equivocation as well.
+	let support = voters.iter()
+		.filter(|&(id, _)| {
+			let mut votes = commit.precommits.iter()
+				.filter(|signed| signed.id == *id)
+				.map(|signed| (signed.precommit.target_hash, signed.signature))
+				.collect::<Vec<_>>();
+			votes.sort();
+			votes.dedup();
+
+			votes.len() > 1 || votes.iter().any(|&(hash, _)| chain.is_equal_or_descendent_of(ghost_hash, hash))
+		})
+		.map(|(_, weight)| weight)
+		.sum::<u64>();
+
+	assert!(support >= voters.threshold(), "GHOST has {} out of {} needed", support, voters.threshold());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+
+	fn random_inputs(seed: u64, count: usize) -> impl Iterator<Item = Vec<u8>> {
+		let mut rng = StdRng::seed_from_u64(seed);
+		(0..count).map(move |_| {
+			let len = rng.gen_range(0, 256);
+			(0..len).map(|_| rng.gen()).collect()
+		})
+	}
+
+	#[test]
+	fn vote_graph_survives_random_inputs() {
+		for data in random_inputs(0, 5000) {
+			vote_graph(&data);
+		}
+	}
+
+	#[test]
+	fn validate_commit_survives_random_inputs() {
+		for data in random_inputs(1, 5000) {
+			validate_commit(&data);
+		}
+	}
+}
@@ -0,0 +1,365 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validation of commits, e.g. when they're used as justifications of
+//! finality during block import, without running a voter.
+
+use crate::collections::HashSet;
+use crate::round::{self, ImportResult};
+use crate::voter_set::VoterSet;
+use crate::{BlockNumberOps, Chain, Commit, PrefetchedAncestry, SignedPrecommit};
+
+/// Why a commit is invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidCommit {
+	/// A precommit isn't for the commit target or one of its descendents.
+	BadPrecommitAncestry,
+	/// A voter equivocated more than once in the commit.
+	Equivocation,
+	/// The precommits don't have enough weight to finalize the commit target
+	/// and some of them are signed by voters which aren't in the voter set.
+	NonVoterSigner,
+	/// The precommits don't have enough weight to finalize the commit target.
+	InsufficientWeight,
+}
+
+/// Struct returned from `validate_commit` function with information
+/// about the validation result.
+pub struct CommitValidationResult<H, N> {
+	ghost: Option<(H, N)>,
+	invalid: Option<InvalidCommit>,
+	num_precommits: usize,
+	num_duplicated_precommits: usize,
+	num_equivocations: usize,
+	num_invalid_voters: usize,
+}
+
+impl<H, N> CommitValidationResult<H, N> {
+	/// Returns the commit GHOST i.e. the block with highest number for which
+	/// the cumulative votes of descendents and itself reach finalization
+	/// threshold.
+	pub fn ghost(&self) -> Option<&(H, N)> {
+		self.ghost.as_ref()
+	}
+
+	/// Whether the commit is valid, i.e. finalizes its target.
+	pub fn is_valid(&self) -> bool {
+		self.invalid.is_none()
+	}
+
+	/// Returns why the commit is invalid, if it is.
+	pub fn invalid_reason(&self) -> Option<InvalidCommit> {
+		self.invalid
+	}
+
+	/// Returns the number of precommits in the commit.
+	pub fn num_precommits(&self) -> usize {
+		self.num_precommits
+	}
+
+	/// Returns the number of duplicate precommits in the commit.
+	pub fn num_duplicated_precommits(&self) -> usize {
+		self.num_duplicated_precommits
+	}
+
+	/// Returns the number of equivocated precommits in the commit.
+	pub fn num_equivocations(&self) -> usize {
+		self.num_equivocations
+	}
+
+	/// Returns the number of invalid voters in the commit.
+	pub fn num_invalid_voters(&self) -> usize {
+		self.num_invalid_voters
+	}
+}
+
+impl<H, N> Default for CommitValidationResult<H, N> {
+	fn default() -> Self {
+		CommitValidationResult {
+			ghost: None,
+			invalid: None,
+			num_precommits: 0,
+			num_duplicated_precommits: 0,
+			num_equivocations: 0,
+			num_invalid_voters: 0,
+		}
+	}
+}
+
+/// Validates a GRANDPA commit message and returns the ghost calculated using
+/// the precommits in the commit message and using the commit target as a
+/// base.
+///
+/// Signatures on precommits are assumed to have been checked. If the commit
+/// is invalid, the result says why. Fails if the chain can't tell whether
+/// the precommits descend from the target, e.g. with
+/// `Error::AncestryUnavailable`.
+///
+/// Duplicate votes or votes from voters not in the voter-set will be ignored, but it is recommended
+/// for the caller of this function to remove those at signature-verification time.
+pub fn validate_commit<H, N, S, I, C: Chain<H, N>>(
+	commit: &Commit<H, N, S, I>,
+	voters: &VoterSet<I>,
+	chain: &C,
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+	where
+	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
+	S: Clone + Eq,
+{
+	let mut validation_result = CommitValidationResult {
+		num_precommits: commit.precommits.len(),
+		..CommitValidationResult::default()
+	};
+
+	// look up the ancestry of all precommit targets from the commit target in
+	// one batch, it serves both the ancestry check and the vote-graph below.
+	let chain = &PrefetchedAncestry::new(
+		chain,
+		commit.target_hash.clone(),
+		commit.precommits.iter().map(|signed| signed.precommit.target_hash.clone()),
+	);
+
+	// check that all precommits are for blocks higher than the target
+	// commit block, and that they're its descendents. if the ancestry of a
+	// precommit can't be retrieved, the commit can't be validated at all.
+	for signed in &commit.precommits {
+		let higher_than_target = signed.precommit.target_number >= commit.target_number &&
+			chain.check_descendent_of(
+				commit.target_hash.clone(),
+				signed.precommit.target_hash.clone(),
+			)?;
+
+		if !higher_than_target {
+			validation_result.invalid = Some(InvalidCommit::BadPrecommitAncestry);
+			return Ok(validation_result);
+		}
+	}
+
+	let mut equivocated = HashSet::new();
+
+	// Add all precommits to the round with correct counting logic
+	// using the commit target as a base.
+	let mut round = round::Round::new(round::RoundParams {
+		round_number: 0, // doesn't matter here.
+		voters: voters.clone(),
+		base: (commit.target_hash.clone(), commit.target_number),
+	});
+
+	for SignedPrecommit { precommit, id, signature } in &commit.precommits {
+		match round.import_precommit(chain, precommit.clone(), id.clone(), signature.clone())? {
+			ImportResult::Equivocation(_) => {
+				validation_result.num_equivocations += 1;
+				// allow only one equivocation per voter, as extras are redundant.
+				if !equivocated.insert(id) {
+					validation_result.invalid = Some(InvalidCommit::Equivocation);
+					return Ok(validation_result)
+				}
+			},
+			ImportResult::BadNumber => {
+				validation_result.invalid = Some(InvalidCommit::BadPrecommitAncestry);
+				return Ok(validation_result)
+			},
+			ImportResult::Duplicate => validation_result.num_duplicated_precommits += 1,
+			ImportResult::NotVoter => validation_result.num_invalid_voters += 1,
+			ImportResult::Imported | ImportResult::Excess | ImportResult::BelowBase => {},
+		}
+	}
+
+	// if a ghost is found then it must be equal or higher than the commit
+	// target, otherwise the commit is invalid
+	validation_result.ghost = round.precommit_ghost();
+	if validation_result.ghost.is_none() {
+		validation_result.invalid = Some(if validation_result.num_invalid_voters > 0 {
+			InvalidCommit::NonVoterSigner
+		} else {
+			InvalidCommit::InsufficientWeight
+		});
+	}
+
+	Ok(validation_result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Precommit;
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+	use crate::Error;
+	use std::cell::Cell;
+
+	// counts the single and batched ancestry lookups made on a chain.
+	struct CountingChain {
+		chain: DummyChain,
+		lookups: Cell<usize>,
+		batches: Cell<usize>,
+	}
+
+	impl Chain<&'static str, u32> for CountingChain {
+		fn ancestry(&self, base: &'static str, block: &'static str) -> Result<Vec<&'static str>, Error> {
+			self.lookups.set(self.lookups.get() + 1);
+			self.chain.ancestry(base, block)
+		}
+
+		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
+			self.chain.best_chain_containing(base)
+		}
+
+		fn batch_ancestry(&self, base: &'static str, blocks: &[&'static str])
+			-> Vec<Result<Vec<&'static str>, Error>>
+		{
+			self.batches.set(self.batches.get() + 1);
+			blocks.iter().map(|block| self.chain.ancestry(base, block)).collect()
+		}
+	}
+
+	fn precommit(target: (&'static str, u32), id: u32, signature: u32)
+		-> SignedPrecommit<&'static str, u32, u32, u32>
+	{
+		SignedPrecommit { precommit: Precommit::new(target.0, target.1), signature, id }
+	}
+
+	fn commit(precommits: Vec<SignedPrecommit<&'static str, u32, u32, u32>>)
+		-> Commit<&'static str, u32, u32, u32>
+	{
+		Commit { target_hash: "B", target_number: 3, precommits }
+	}
+
+	#[test]
+	fn reports_why_commits_are_invalid() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B1"]);
+
+		let voters: VoterSet<u32> = (1..5).map(|id| (id, 1)).collect();
+		let reason = |commit| validate_commit(&commit, &voters, &chain).unwrap().invalid_reason();
+
+		let valid = validate_commit(&commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("C", 4), 2, 2),
+			precommit(("C", 4), 3, 3),
+		]), &voters, &chain).unwrap();
+		assert!(valid.is_valid());
+		assert_eq!(valid.ghost(), Some(&("B", 3)));
+
+		assert_eq!(reason(commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("B", 3), 2, 2),
+			precommit(("B1", 3), 3, 3),
+		])), Some(InvalidCommit::BadPrecommitAncestry));
+
+		// a precommit whose number doesn't match its block.
+		assert_eq!(reason(commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("B", 3), 2, 2),
+			precommit(("C", 9), 3, 3),
+		])), Some(InvalidCommit::BadPrecommitAncestry));
+
+		// equivocations count towards every block.
+		let equivocated = validate_commit(&commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("C", 4), 1, 2),
+			precommit(("B", 3), 2, 3),
+			precommit(("C", 4), 3, 4),
+		]), &voters, &chain).unwrap();
+		assert!(equivocated.is_valid());
+		assert_eq!(equivocated.num_equivocations(), 1);
+
+		assert_eq!(reason(commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("B", 3), 2, 2),
+			precommit(("B", 3), 5, 5),
+		])), Some(InvalidCommit::NonVoterSigner));
+
+		assert_eq!(reason(commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("B", 3), 2, 2),
+		])), Some(InvalidCommit::InsufficientWeight));
+	}
+
+	#[test]
+	fn unavailable_ancestry_fails_validation() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.set_ancestry_unavailable("C");
+
+		let voters: VoterSet<u32> = (1..5).map(|id| (id, 1)).collect();
+		assert_eq!(validate_commit(&commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("C", 4), 2, 2),
+			precommit(("C", 4), 3, 3),
+		]), &voters, &chain).err(), Some(Error::AncestryUnavailable));
+	}
+
+	#[test]
+	fn minimized_commits_stay_valid() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		let voters: VoterSet<u32> = vec![(1, 1), (2, 3), (3, 1), (4, 2)].into_iter().collect();
+		assert_eq!(voters.threshold(), 5);
+
+		let mut full = commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("C", 4), 2, 2),
+			precommit(("B", 3), 2, 3),
+			precommit(("B", 3), 5, 5),
+			precommit(("C", 4), 3, 3),
+			precommit(("B", 3), 4, 4),
+		]);
+		assert!(full.minimize(&voters));
+		assert_eq!(full.precommits, vec![
+			precommit(("C", 4), 2, 2),
+			precommit(("B", 3), 4, 4),
+		]);
+		assert!(validate_commit(&full, &voters, &chain).unwrap().is_valid());
+
+		let mut short = commit(vec![
+			precommit(("B", 3), 1, 1),
+			precommit(("B", 3), 4, 4),
+			precommit(("B", 3), 5, 5),
+		]);
+		let before = short.clone();
+		assert!(!short.minimize(&voters));
+		assert_eq!(short, before);
+	}
+
+	#[test]
+	fn commit_ancestry_is_looked_up_in_one_batch() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D1", "E1"]);
+		let chain = CountingChain { chain, lookups: Cell::new(0), batches: Cell::new(0) };
+
+		let voters: VoterSet<u32> = (0..1000).map(|id| (id, 1)).collect();
+		let targets = [("B", 3), ("C", 4), ("D", 5), ("E", 6), ("D1", 5), ("E1", 6)];
+		let valid = validate_commit(&commit(
+			(0..1000).map(|id| precommit(targets[id as usize % targets.len()], id, id)).collect(),
+		), &voters, &chain).unwrap();
+
+		assert!(valid.is_valid());
+		assert_eq!(valid.ghost(), Some(&("C", 4)));
+		assert_eq!((chain.batches.get(), chain.lookups.get()), (1, 0));
+
+		// a precommit which doesn't descend from the target is caught by the batch.
+		chain.batches.set(0);
+		let invalid = validate_commit(&commit(vec![
+			precommit(("C", 4), 1, 1),
+			precommit(("A", 2), 2, 2),
+		]), &voters, &chain).unwrap();
+		assert_eq!(invalid.invalid_reason(), Some(InvalidCommit::BadPrecommitAncestry));
+		assert_eq!((chain.batches.get(), chain.lookups.get()), (1, 0));
+	}
+}
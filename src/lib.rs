@@ -24,6 +24,10 @@
 //!
 //! Equivocation detection and vote-set management is done in the `round` module.
 //! The work for actually casting votes is done in the `voter` module.
+//!
+//! Without the default `std` feature the crate builds on `no_std` + `alloc`,
+//! so that commits and equivocation proofs can be checked in a runtime. Only
+//! the `voter` module, which drives the protocol as a future, requires `std`.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -34,10 +38,18 @@ extern crate core as std;
 #[macro_use]
 extern crate alloc;
 
+pub mod accountability;
+
 pub mod bitfield;
 
+pub mod justification;
+pub use justification::{validate_commit, CommitValidationResult, InvalidCommit};
+
+pub mod misbehavior;
+
 pub mod round;
-use round::ImportResult;
+
+pub mod view;
 
 pub mod vote_graph;
 
@@ -50,8 +62,11 @@ pub mod voter;
 #[cfg(feature = "std")]
 mod bridge_state;
 
-#[cfg(test)]
-mod testing;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod testing;
+
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
 
 use std::fmt;
 
@@ -121,15 +136,45 @@ impl<H, N> PrimaryPropose<H, N> {
 	}
 }
 
+/// Errors raised by the finality gadget.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Error {
+	/// A block isn't a descendent of the given base.
 	NotDescendent,
+	/// The ancestry of a block couldn't be retrieved, e.g. because of an I/O
+	/// error or because it was pruned. Unlike `NotDescendent`, this says
+	/// nothing about whether the block descends from the base.
+	AncestryUnavailable,
+	/// The total weight of a voter set overflows.
+	WeightOverflow,
+	/// A voter set can't reach its own threshold, e.g. because too much of
+	/// its weight is assumed to be faulty.
+	InvalidVoterSet,
+	/// A round with the given number was created more than once.
+	DuplicateRound(u64),
+	/// A vote's block number doesn't match the number of its block.
+	InvalidBlockNumber,
+	/// A vote targets a block further ahead of the round's base than
+	/// allowed.
+	VoteTooDistant,
+	/// A block was finalized which is not a descendent of the previously
+	/// finalized block, i.e. two conflicting blocks were finalized. The
+	/// environment is given both commits, see
+	/// `voter::Environment::finality_violation`.
+	SafetyViolation,
 }
 
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
 			Error::NotDescendent => write!(f, "Block not descendent of base"),
+			Error::AncestryUnavailable => write!(f, "Block ancestry unavailable"),
+			Error::WeightOverflow => write!(f, "Total weight of voters overflows"),
+			Error::InvalidVoterSet => write!(f, "Voter set threshold exceeds total weight"),
+			Error::DuplicateRound(round) => write!(f, "Round {} created more than once", round),
+			Error::InvalidBlockNumber => write!(f, "Block number doesn't match the block"),
+			Error::VoteTooDistant => write!(f, "Vote too far ahead of the round base"),
+			Error::SafetyViolation => write!(f, "Finalized block not descendent of previously finalized block"),
 		}
 	}
 }
@@ -139,6 +184,13 @@ impl std::error::Error for Error {
 	fn description(&self) -> &str {
 		match *self {
 			Error::NotDescendent => "Block not descendent of base",
+			Error::AncestryUnavailable => "Block ancestry unavailable",
+			Error::WeightOverflow => "Total weight of voters overflows",
+			Error::InvalidVoterSet => "Voter set threshold exceeds total weight",
+			Error::DuplicateRound(_) => "Round created more than once",
+			Error::InvalidBlockNumber => "Block number doesn't match the block",
+			Error::VoteTooDistant => "Vote too far ahead of the round base",
+			Error::SafetyViolation => "Finalized block not descendent of previously finalized block",
 		}
 	}
 }
@@ -169,26 +221,128 @@ pub trait Chain<H: Eq, N: Copy + BlockNumberOps> {
 	/// Get the ancestry of a block up to but not including the base hash.
 	/// Should be in reverse order from `block`'s parent.
 	///
-	/// If the block is not a descendent of `base`, returns an error.
+	/// If the block is not a descendent of `base`, returns
+	/// `Error::NotDescendent`. If the ancestry can't be retrieved, e.g. because
+	/// of an I/O error, returns `Error::AncestryUnavailable`.
 	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error>;
 
 	/// Return the hash of the best block whose chain contains the given block hash,
 	/// even if that block is `base` itself.
 	///
 	/// If `base` is unknown, return `None`.
+	///
+	/// The best chain may switch between forks at any time, e.g. on a re-org.
+	/// The voter only consults it when casting its prevote: a switch after
+	/// that doesn't change the votes it has cast, and its precommit follows
+	/// the prevote-GHOST whether or not that is still on the best chain.
 	fn best_chain_containing(&self, base: H) -> Option<(H, N)>;
 
 	/// Returns true if `block` is a descendent of or equal to the given `base`.
+	///
+	/// The default implementation is in terms of `ancestry`, which walks the
+	/// whole chain between the two blocks. Implementors with a cheaper way of
+	/// checking descent, e.g. an index of the canonical chain, should override
+	/// it along with `check_descendent_of`; the voter and commit validation
+	/// only ever check descent through these two methods.
+	///
+	/// A block whose ancestry can't be retrieved isn't known to descend from
+	/// the base, so this returns false for it.
 	fn is_equal_or_descendent_of(&self, base: H, block: H) -> bool {
-		if base == block { return true; }
+		self.check_descendent_of(base, block).unwrap_or(false)
+	}
+
+	/// Returns whether `block` is a descendent of or equal to the given
+	/// `base`, failing if that can't be determined, e.g. with
+	/// `Error::AncestryUnavailable`. Only `Error::NotDescendent` from
+	/// `ancestry` means that it isn't.
+	///
+	/// Used where a block on another fork is treated very differently from
+	/// one whose ancestry is just unavailable, e.g. when checking for
+	/// conflicting finality.
+	fn check_descendent_of(&self, base: H, block: H) -> Result<bool, Error> {
+		if base == block { return Ok(true); }
 
-		// TODO: currently this function always succeeds since the only error
-		// variant is `Error::NotDescendent`, this may change in the future as
-		// other errors (e.g. IO) are not being exposed.
 		match self.ancestry(base, block) {
-			Ok(_) => true,
-			Err(Error::NotDescendent) => false,
+			Ok(_) => Ok(true),
+			Err(Error::NotDescendent) => Ok(false),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Get the ancestry of each of the given blocks up to but not including
+	/// the base hash, in the same order as `blocks`. Each result is what
+	/// `ancestry` would return for the block.
+	///
+	/// The default implementation walks the chain once per block. Chains
+	/// backed by a database should answer the whole batch with a single walk,
+	/// since the blocks usually share most of their ancestry.
+	fn batch_ancestry(&self, base: H, blocks: &[H]) -> Vec<Result<Vec<H>, Error>> where H: Clone {
+		blocks.iter().map(|block| self.ancestry(base.clone(), block.clone())).collect()
+	}
+
+	/// Returns, in the same order as `blocks`, whether each of them is a
+	/// descendent of or equal to the given `base`, using a single
+	/// `batch_ancestry` query.
+	fn is_descendent_of_many(&self, base: H, blocks: &[H]) -> Vec<bool> where H: Clone {
+		let lookups: Vec<H> = blocks.iter().filter(|block| **block != base).cloned().collect();
+		let mut ancestries = self.batch_ancestry(base.clone(), &lookups).into_iter();
+
+		blocks.iter()
+			.map(|block| *block == base || ancestries.next().is_some_and(|ancestry| ancestry.is_ok()))
+			.collect()
+	}
+}
+
+// a chain which answers the ancestry of a set of blocks from `base` with a
+// single batch lookup made up front, and defers everything else to the
+// underlying chain.
+pub(crate) struct PrefetchedAncestry<'a, H, C> {
+	chain: &'a C,
+	base: H,
+	ancestries: collections::HashMap<H, Result<Vec<H>, Error>>,
+}
+
+impl<'a, H, C> PrefetchedAncestry<'a, H, C> where H: std::hash::Hash + Clone + Eq {
+	// look up the ancestry of the distinct blocks among the given ones, other
+	// than the base itself.
+	pub(crate) fn new<N, I>(chain: &'a C, base: H, blocks: I) -> Self where
+		N: Copy + BlockNumberOps,
+		C: Chain<H, N>,
+		I: IntoIterator<Item=H>,
+	{
+		let mut seen = collections::HashSet::new();
+		let blocks: Vec<H> = blocks.into_iter()
+			.filter(|block| *block != base && seen.insert(block.clone()))
+			.collect();
+
+		let ancestries = if blocks.is_empty() {
+			Default::default()
+		} else {
+			let ancestries = chain.batch_ancestry(base.clone(), &blocks);
+			blocks.into_iter().zip(ancestries).collect()
+		};
+
+		PrefetchedAncestry { chain, base, ancestries }
+	}
+}
+
+impl<'a, H, N, C> Chain<H, N> for PrefetchedAncestry<'a, H, C> where
+	H: std::hash::Hash + Clone + Eq,
+	N: Copy + BlockNumberOps,
+	C: Chain<H, N>,
+{
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error> {
+		if base == self.base {
+			if let Some(ancestry) = self.ancestries.get(&block) {
+				return ancestry.clone();
+			}
 		}
+
+		self.chain.ancestry(base, block)
+	}
+
+	fn best_chain_containing(&self, base: H) -> Option<(H, N)> {
+		self.chain.best_chain_containing(base)
 	}
 }
 
@@ -206,6 +360,45 @@ pub struct Equivocation<Id, V, S> {
 	pub second: (V, S),
 }
 
+/// Why an equivocation proof is invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidEquivocation {
+	/// The equivocator isn't in the voter set.
+	UnknownVoter,
+	/// Both votes are the same, which isn't an equivocation.
+	SameVote,
+	/// The signature on one of the votes is invalid.
+	BadSignature,
+}
+
+impl<Id: std::hash::Hash + Eq, V: PartialEq, S> Equivocation<Id, V, S> {
+	/// Check that this is a valid proof of an equivocation by a voter in the
+	/// given set, without having to reconstruct the round.
+	///
+	/// Signatures are checked with `check_signature`, which is given the
+	/// signer, the vote, its signature and the round number, since these are
+	/// usually signed together.
+	pub fn verify<F>(&self, voters: &VoterSet<Id>, mut check_signature: F) -> Result<(), InvalidEquivocation>
+		where F: FnMut(&Id, &V, &S, u64) -> bool
+	{
+		if !voters.contains_key(&self.identity) {
+			return Err(InvalidEquivocation::UnknownVoter);
+		}
+
+		if self.first.0 == self.second.0 {
+			return Err(InvalidEquivocation::SameVote);
+		}
+
+		for (vote, signature) in [&self.first, &self.second].iter() {
+			if !check_signature(&self.identity, vote, signature, self.round_number) {
+				return Err(InvalidEquivocation::BadSignature);
+			}
+		}
+
+		Ok(())
+	}
+}
+
 /// A protocol message or vote.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
@@ -263,6 +456,47 @@ pub struct Commit<H, N, S, Id> {
 	pub precommits: Vec<SignedPrecommit<H, N, S, Id>>,
 }
 
+/// A commit, or compact commit, together with the round it was made in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub struct CommitMessage<C> {
+	/// The round the commit was made in.
+	pub round_number: u64,
+	/// The commit itself.
+	pub commit: C,
+}
+
+impl<C> CommitMessage<C> {
+	/// Create a commit message for the given round.
+	pub fn new(round_number: u64, commit: C) -> Self {
+		CommitMessage { round_number, commit }
+	}
+}
+
+impl<C> From<(u64, C)> for CommitMessage<C> {
+	fn from((round_number, commit): (u64, C)) -> Self {
+		CommitMessage { round_number, commit }
+	}
+}
+
+impl<C> From<CommitMessage<C>> for (u64, C) {
+	fn from(message: CommitMessage<C>) -> (u64, C) {
+		(message.round_number, message.commit)
+	}
+}
+
+impl<H, N, S, Id> From<CommitMessage<CompactCommit<H, N, S, Id>>> for CommitMessage<Commit<H, N, S, Id>> {
+	fn from(message: CommitMessage<CompactCommit<H, N, S, Id>>) -> Self {
+		CommitMessage { round_number: message.round_number, commit: message.commit.into() }
+	}
+}
+
+impl<H: Clone, N: Clone, S, Id> From<CommitMessage<Commit<H, N, S, Id>>> for CommitMessage<CompactCommit<H, N, S, Id>> {
+	fn from(message: CommitMessage<Commit<H, N, S, Id>>) -> Self {
+		CommitMessage { round_number: message.round_number, commit: message.commit.into() }
+	}
+}
+
 /// A signed prevote message.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
@@ -301,6 +535,119 @@ pub struct CompactCommit<H, N, S, Id> {
 	pub auth_data: MultiAuthData<S, Id>,
 }
 
+/// A commit message which references its signers by their index in the
+/// canonical ordering of a `VoterSet` instead of by id.
+///
+/// Precommits are ordered by the index of their signer. See
+/// `IndexedCommit::from_commit` and `IndexedCommit::into_commit` for converting
+/// from and to a regular commit given the voter set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub struct IndexedCommit<H, N, S> {
+	/// The target block's hash.
+	pub target_hash: H,
+	/// The target block's number.
+	pub target_number: N,
+	/// Two bits per voter starting from the most significant bit. The first is
+	/// set if the voter signed a precommit and the second if it signed another
+	/// one, i.e. equivocated.
+	pub signers: Vec<u64>,
+	/// Precommits for target block or any block after it that justify this commit.
+	pub precommits: Vec<Precommit<H, N>>,
+	/// The signatures on the precommits, in the same order.
+	pub signatures: Vec<S>,
+}
+
+fn signer_bit(words: &[u64], bit: usize) -> bool {
+	words.get(bit / 64).is_some_and(|word| word & (1 << (63 - bit % 64)) != 0)
+}
+
+impl<H, N, S> IndexedCommit<H, N, S> {
+	/// Convert a commit, ordering its precommits by signer.
+	///
+	/// Returns `None` if a precommit is signed by a voter which isn't in the
+	/// set, or if a voter signed more than two precommits.
+	pub fn from_commit<Id: Eq + std::hash::Hash>(
+		commit: Commit<H, N, S, Id>,
+		voters: &VoterSet<Id>,
+	) -> Option<Self> {
+		let mut signed = Vec::with_capacity(commit.precommits.len());
+		for SignedPrecommit { precommit, signature, id } in commit.precommits {
+			signed.push((voters.info(&id)?.canon_idx(), precommit, signature));
+		}
+		signed.sort_by_key(|&(idx, _, _)| idx);
+
+		let mut signers = vec![0u64; (voters.len() * 2).div_ceil(64)];
+		let mut precommits = Vec::with_capacity(signed.len());
+		let mut signatures = Vec::with_capacity(signed.len());
+		let mut last = None;
+		for (idx, precommit, signature) in signed {
+			let bit = if last == Some(idx) { idx * 2 + 1 } else { idx * 2 };
+			if signer_bit(&signers, bit) {
+				return None;
+			}
+
+			signers[bit / 64] |= 1 << (63 - bit % 64);
+			precommits.push(precommit);
+			signatures.push(signature);
+			last = Some(idx);
+		}
+
+		Some(IndexedCommit {
+			target_hash: commit.target_hash,
+			target_number: commit.target_number,
+			signers,
+			precommits,
+			signatures,
+		})
+	}
+
+	/// Convert back to a commit, looking up the signers in the voter set.
+	///
+	/// Returns `None` if the commit doesn't match the voter set, e.g. because
+	/// it is for a different number of voters.
+	pub fn into_commit<Id: Clone + Eq + std::hash::Hash>(
+		self,
+		voters: &VoterSet<Id>,
+	) -> Option<Commit<H, N, S, Id>> {
+		let bits = voters.len() * 2;
+		if self.signers.len() != bits.div_ceil(64) || self.precommits.len() != self.signatures.len() {
+			return None;
+		}
+
+		// padding bits must be unset, otherwise the encoding isn't unique.
+		if (bits..self.signers.len() * 64).any(|bit| signer_bit(&self.signers, bit)) {
+			return None;
+		}
+
+		let mut ids = Vec::with_capacity(self.precommits.len());
+		for idx in 0..voters.len() {
+			let (signed, equivocated) = (signer_bit(&self.signers, idx * 2), signer_bit(&self.signers, idx * 2 + 1));
+			if equivocated && !signed {
+				return None;
+			}
+
+			let id = &voters.voter_by_index(idx).0;
+			if signed { ids.push(id.clone()); }
+			if equivocated { ids.push(id.clone()); }
+		}
+
+		if ids.len() != self.precommits.len() {
+			return None;
+		}
+
+		Some(Commit {
+			target_hash: self.target_hash,
+			target_number: self.target_number,
+			precommits: self.precommits.into_iter()
+				.zip(self.signatures)
+				.zip(ids)
+				.map(|((precommit, signature), id)| SignedPrecommit { precommit, signature, id })
+				.collect(),
+		})
+	}
+}
+
 /// A catch-up message, which is an aggregate of prevotes and precommits necessary
 /// to complete a round.
 ///
@@ -349,133 +696,90 @@ impl<H: Clone, N: Clone, S, Id> From<Commit<H, N, S, Id>> for CompactCommit<H, N
 	}
 }
 
-/// Struct returned from `validate_commit` function with information
-/// about the validation result.
-pub struct CommitValidationResult<H, N> {
-	ghost: Option<(H, N)>,
-	num_precommits: usize,
-	num_duplicated_precommits: usize,
-	num_equivocations: usize,
-	num_invalid_voters: usize,
-}
+impl<H, N, S, Id: Eq + std::hash::Hash> Commit<H, N, S, Id> {
+	/// Drop every precommit which isn't needed to justify the commit, keeping
+	/// the fewest precommits whose signers reach the voter set's threshold.
+	///
+	/// Precommits all encode to the same size, so the heaviest voters are
+	/// picked first. Only one precommit is kept per voter and precommits from
+	/// voters not in the set are dropped. The precommits are assumed to target
+	/// descendents of the commit target, as checked by `validate_commit`.
+	///
+	/// Returns `false` and leaves the commit untouched if the precommits don't
+	/// have enough weight.
+	pub fn minimize(&mut self, voters: &VoterSet<Id>) -> bool {
+		let mut candidates = Vec::new();
+		{
+			let mut seen = collections::HashSet::new();
+			for (idx, signed) in self.precommits.iter().enumerate() {
+				if let Some(info) = voters.info(&signed.id) {
+					if seen.insert(&signed.id) {
+						candidates.push((idx, info.weight()));
+					}
+				}
+			}
+		}
 
-impl<H, N> CommitValidationResult<H, N> {
-	/// Returns the commit GHOST i.e. the block with highest number for which
-	/// the cumulative votes of descendents and itself reach finalization
-	/// threshold.
-	pub fn ghost(&self) -> Option<&(H, N)> {
-		self.ghost.as_ref()
-	}
+		// stable, so earlier precommits win between voters of equal weight.
+		candidates.sort_by_key(|&(_, weight)| std::cmp::Reverse(weight));
 
-	/// Returns the number of precommits in the commit.
-	pub fn num_precommits(&self) -> usize {
-		self.num_precommits
-	}
+		let mut keep = vec![false; self.precommits.len()];
+		let mut weight = 0u64;
+		for (idx, voter_weight) in candidates {
+			if weight >= voters.threshold() {
+				break;
+			}
 
-	/// Returns the number of duplicate precommits in the commit.
-	pub fn num_duplicated_precommits(&self) -> usize {
-		self.num_duplicated_precommits
-	}
+			keep[idx] = true;
+			weight = weight.saturating_add(voter_weight);
+		}
 
-	/// Returns the number of equivocated precommits in the commit.
-	pub fn num_equivocations(&self) -> usize {
-		self.num_equivocations
-	}
+		if weight < voters.threshold() {
+			return false;
+		}
 
-	/// Returns the number of invalid voters in the commit.
-	pub fn num_invalid_voters(&self) -> usize {
-		self.num_invalid_voters
+		let mut keep = keep.into_iter();
+		self.precommits.retain(|_| keep.next().unwrap_or(false));
+		true
 	}
 }
 
-impl<H, N> Default for CommitValidationResult<H, N> {
-	fn default() -> Self {
-		CommitValidationResult {
-			ghost: None,
-			num_precommits: 0,
-			num_duplicated_precommits: 0,
-			num_equivocations: 0,
-			num_invalid_voters: 0,
-		}
-	}
+/// Get the threshold weight given the total voting weight.
+pub fn threshold(total_weight: u64) -> u64 {
+	let faulty = total_weight.saturating_sub(1) / 3;
+	total_weight - faulty
 }
 
-/// Validates a GRANDPA commit message and returns the ghost calculated using
-/// the precommits in the commit message and using the commit target as a
-/// base.
-///
-/// Signatures on precommits are assumed to have been checked.
-///
-/// Duplicate votes or votes from voters not in the voter-set will be ignored, but it is recommended
-/// for the caller of this function to remove those at signature-verification time.
-pub fn validate_commit<H, N, S, I, C: Chain<H, N>>(
-	commit: &Commit<H, N, S, I>,
-	voters: &VoterSet<I>,
-	chain: &C,
-) -> Result<CommitValidationResult<H, N>, crate::Error>
-	where
-	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
-	N: Copy + BlockNumberOps + std::fmt::Debug,
-	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
-	S: Clone + Eq,
-{
-	let mut validation_result = CommitValidationResult::default();
-	validation_result.num_precommits = commit.precommits.len();
-
-	// check that all precommits are for blocks higher than the target
-	// commit block, and that they're its descendents
-	let all_precommits_higher_than_target = commit.precommits.iter().all(|signed| {
-		signed.precommit.target_number >= commit.target_number &&
-			chain.is_equal_or_descendent_of(
-				commit.target_hash.clone(),
-				signed.precommit.target_hash.clone(),
-			)
-	});
-
-	if !all_precommits_higher_than_target {
-		return Ok(validation_result);
-	}
-
-	let mut equivocated = crate::collections::HashSet::new();
-
-	// Add all precommits to the round with correct counting logic
-	// using the commit target as a base.
-	let mut round = round::Round::new(round::RoundParams {
-		round_number: 0, // doesn't matter here.
-		voters: voters.clone(),
-		base: (commit.target_hash.clone(), commit.target_number),
-	});
-
-	for SignedPrecommit { precommit, id, signature } in &commit.precommits {
-		match round.import_precommit(chain, precommit.clone(), id.clone(), signature.clone())? {
-			ImportResult { equivocation: Some(_), .. } => {
-				validation_result.num_equivocations += 1;
-				// allow only one equivocation per voter, as extras are redundant.
-				if !equivocated.insert(id) {
-					return Ok(validation_result)
-				}
-			},
-			ImportResult { duplicated, valid_voter, .. } => {
-				if duplicated {
-					validation_result.num_duplicated_precommits += 1;
-				}
-				if !valid_voter {
-					validation_result.num_invalid_voters += 1;
+/// The fault assumption from which the supermajority threshold of a voter set
+/// is derived. See `VoterSet::with_threshold_policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdPolicy {
+	/// Less than a third of the total weight is faulty. This is the standard
+	/// assumption, see `threshold`.
+	#[default]
+	OneThirdFaulty,
+	/// At most the given weight is faulty. The threshold is the smallest weight
+	/// such that any two sets of voters with it have an honest voter in common,
+	/// i.e. more than half of the total and faulty weight.
+	MaxFaultyWeight(u64),
+}
+
+impl ThresholdPolicy {
+	/// Get the threshold weight given the total voting weight. This may exceed
+	/// the total weight if too much of it is assumed to be faulty.
+	pub fn threshold(&self, total_weight: u64) -> u64 {
+		match *self {
+			ThresholdPolicy::OneThirdFaulty => threshold(total_weight),
+			ThresholdPolicy::MaxFaultyWeight(faulty) => {
+				let threshold = (u128::from(total_weight) + u128::from(faulty)) / 2 + 1;
+				if threshold > u128::from(u64::MAX) {
+					u64::MAX
+				} else {
+					threshold as u64
 				}
 			}
 		}
 	}
-
-	// if a ghost is found then it must be equal or higher than the commit
-	// target, otherwise the commit is invalid
-	validation_result.ghost = round.precommit_ghost();
-	Ok(validation_result)
-}
-
-/// Get the threshold weight given the total voting weight.
-pub fn threshold(total_weight: u64) -> u64 {
-	let faulty = total_weight.saturating_sub(1) / 3;
-	total_weight - faulty
 }
 
 /// Runs the callback with the appropriate `CommitProcessingOutcome` based on
@@ -486,7 +790,7 @@ pub fn process_commit_validation_result<H, N>(
 	validation_result: CommitValidationResult<H, N>,
 	mut callback: voter::Callback<voter::CommitProcessingOutcome>,
 ) {
-	if validation_result.ghost.is_some() {
+	if validation_result.ghost().is_some() {
 		callback.run(
 			voter::CommitProcessingOutcome::Good(voter::GoodCommit::new())
 		)
@@ -564,7 +868,13 @@ impl<H, N, S, Id> HistoricalVotes<H, N, S, Id> {
 
 #[cfg(test)]
 mod tests {
-	use super::threshold;
+	use super::{
+		threshold, Commit, Equivocation, IndexedCommit, InvalidEquivocation, Precommit, Prevote,
+		SignedPrecommit, ThresholdPolicy,
+	};
+	use crate::voter_set::VoterSet;
+	use crate::Chain;
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
 
 	#[test]
 	fn threshold_is_right() {
@@ -580,6 +890,120 @@ mod tests {
 		assert_eq!(threshold(103), 69);
 	}
 
+	#[test]
+	fn threshold_policies() {
+		for total in 1..200 {
+			assert_eq!(ThresholdPolicy::OneThirdFaulty.threshold(total), threshold(total));
+		}
+
+		// agrees with the standard threshold at `3f + 1`.
+		assert_eq!(ThresholdPolicy::MaxFaultyWeight(33).threshold(100), threshold(100));
+		assert_eq!(ThresholdPolicy::MaxFaultyWeight(0).threshold(3), 2);
+		assert_eq!(ThresholdPolicy::MaxFaultyWeight(3).threshold(4), 4);
+		assert_eq!(ThresholdPolicy::MaxFaultyWeight(10).threshold(4), 8);
+		assert_eq!(
+			ThresholdPolicy::MaxFaultyWeight(u64::MAX).threshold(u64::MAX),
+			u64::MAX,
+		);
+	}
+
+	#[test]
+	fn indexed_commit_conversion() {
+		let voters: VoterSet<u32> = (0..70).map(|id| (id * 10, 1)).collect();
+		let precommit = |target: (&'static str, u32), id| SignedPrecommit {
+			precommit: Precommit::new(target.0, target.1),
+			signature: id + 1,
+			id,
+		};
+		let commit = |precommits| Commit { target_hash: "B", target_number: 2, precommits };
+
+		// equivocating voter 640 has index 64, so its bits are in the third word.
+		let full = commit(vec![
+			precommit(("B", 2), 0),
+			precommit(("C", 3), 30),
+			precommit(("B", 2), 640),
+			precommit(("C", 3), 640),
+			precommit(("B", 2), 690),
+		]);
+		let indexed = IndexedCommit::from_commit(full.clone(), &voters).unwrap();
+		assert_eq!(indexed.signers, vec![1 << 63 | 1 << 57, 0, 1 << 63 | 1 << 62 | 1 << 53]);
+		assert_eq!(indexed.clone().into_commit(&voters), Some(full.clone()));
+
+		// precommits are ordered by signer.
+		let mut shuffled = full.clone();
+		shuffled.precommits.swap(0, 4);
+		assert_eq!(IndexedCommit::from_commit(shuffled, &voters), Some(indexed.clone()));
+
+		// non-voters and a third precommit from a voter can't be represented.
+		assert!(IndexedCommit::from_commit(commit(vec![precommit(("B", 2), 1)]), &voters).is_none());
+		assert!(IndexedCommit::from_commit(commit(vec![
+			precommit(("B", 2), 0),
+			precommit(("C", 3), 0),
+			precommit(("D", 4), 0),
+		]), &voters).is_none());
+
+		// the signers must match the voter set and precommits.
+		let smaller: VoterSet<u32> = (0..10).map(|id| (id * 10, 1)).collect();
+		assert!(indexed.clone().into_commit(&smaller).is_none());
+
+		let mut missing = indexed.clone();
+		missing.signers[0] &= !(1 << 63);
+		assert!(missing.into_commit(&voters).is_none());
+
+		let mut padded = indexed;
+		padded.signers[2] |= 1;
+		assert!(padded.into_commit(&voters).is_none());
+	}
+
+	#[test]
+	fn equivocation_verification() {
+		let voters: VoterSet<u32> = [(1, 1), (2, 1), (3, 1)].iter().cloned().collect();
+
+		// signatures are the signer's id plus the round number.
+		let check_signature = |id: &u32, _: &Prevote<&str, u32>, signature: &u64, round: u64| {
+			*signature == *id as u64 + round
+		};
+
+		let equivocation = |identity, first, second| Equivocation {
+			round_number: 5,
+			identity,
+			first: (Prevote::new(first, 1), identity as u64 + 5),
+			second: (Prevote::new(second, 1), identity as u64 + 5),
+		};
+
+		assert_eq!(equivocation(1, "A", "B").verify(&voters, check_signature), Ok(()));
+		assert_eq!(
+			equivocation(4, "A", "B").verify(&voters, check_signature),
+			Err(InvalidEquivocation::UnknownVoter),
+		);
+		assert_eq!(
+			equivocation(1, "A", "A").verify(&voters, check_signature),
+			Err(InvalidEquivocation::SameVote),
+		);
+
+		let mut forged = equivocation(1, "A", "B");
+		forged.second.1 = 7;
+		assert_eq!(forged.verify(&voters, check_signature), Err(InvalidEquivocation::BadSignature));
+	}
+
+	#[test]
+	fn batched_ancestry_matches_single_lookups() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B1", "C1"]);
+
+		let blocks = ["C", "A", "C1", "B", "Z", "B1"];
+		assert_eq!(
+			chain.batch_ancestry("A", &blocks),
+			blocks.iter().map(|block| chain.ancestry("A", block)).collect::<Vec<_>>(),
+		);
+		assert_eq!(
+			chain.is_descendent_of_many("B", &blocks),
+			blocks.iter().map(|block| chain.is_equal_or_descendent_of("B", block)).collect::<Vec<_>>(),
+		);
+		assert_eq!(chain.is_descendent_of_many("B", &blocks), vec![true, false, false, true, false, false]);
+	}
+
 	#[cfg(feature = "derive-codec")]
 	#[test]
 	fn codec_was_derived() {
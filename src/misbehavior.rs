@@ -0,0 +1,149 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Suggested penalties for misbehaving peers.
+//!
+//! Peers aren't scored by this crate, that's up to the network layer. The
+//! costs here are kept alongside the protocol so that all network layers
+//! penalize the same misbehavior in the same way.
+
+/// How serious a misbehavior is, ordered from least to most serious.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+	/// Honest peers can cause this, e.g. when lagging behind.
+	Minor,
+	/// A protocol violation which wastes our resources. Not necessarily an
+	/// attack, the peer may just be running a faulty implementation.
+	Major,
+}
+
+/// A suggested penalty for some misbehavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cost {
+	/// The change to the peer's reputation. Always negative.
+	pub reputation_delta: i32,
+	/// How serious the misbehavior is.
+	pub severity: Severity,
+}
+
+const INVALID_COMMIT: i32 = -5000;
+const PER_PRECOMMIT_CHECKED: i32 = -25;
+const MALFORMED_COMPACT_COMMIT: i32 = -1000;
+const INVALID_CATCH_UP: i32 = -5000;
+const OUT_OF_WINDOW_ROUND: i32 = -500;
+const VOTE_SPAM: i32 = -100;
+const DISTANT_VOTE: i32 = -1000;
+const UNKNOWN_VOTER: i32 = -1000;
+const VOTE_BELOW_BASE: i32 = -250;
+const INVALID_BLOCK_NUMBER: i32 = -1000;
+
+/// Misbehavior of a peer which should be penalized by the network layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+	/// A commit which failed validation, with the number of precommits we had
+	/// to check.
+	InvalidCommit(usize),
+	/// A compact commit which doesn't have authentication data for each of
+	/// its precommits.
+	MalformedCompactCommit,
+	/// A catch-up which failed validation.
+	InvalidCatchUp,
+	/// A message for a round too far from the current one to be processed.
+	OutOfWindowRound,
+	/// A vote which was already received, or more votes from a single voter
+	/// than the protocol allows for.
+	VoteSpam,
+	/// A vote for a block too far ahead of its round's base, see
+	/// `Round::set_max_vote_distance`.
+	DistantVote,
+	/// A vote from an authority which isn't a voter of the round.
+	UnknownVoter,
+	/// A vote for a block lower than its round's base. Peers with another
+	/// view of the finalized chain may send these.
+	VoteBelowBase,
+	/// A vote or commit whose block number doesn't match the number of its
+	/// block.
+	InvalidBlockNumber,
+}
+
+impl Misbehavior {
+	/// The suggested penalty for this misbehavior.
+	pub fn cost(&self) -> Cost {
+		let (reputation_delta, severity) = match *self {
+			Misbehavior::InvalidCommit(num_precommits) => {
+				let num_precommits = if num_precommits > i32::MAX as usize {
+					i32::MAX
+				} else {
+					num_precommits as i32
+				};
+
+				let checked = PER_PRECOMMIT_CHECKED.saturating_mul(num_precommits);
+				(INVALID_COMMIT.saturating_add(checked), Severity::Major)
+			}
+			Misbehavior::MalformedCompactCommit => (MALFORMED_COMPACT_COMMIT, Severity::Major),
+			Misbehavior::InvalidCatchUp => (INVALID_CATCH_UP, Severity::Major),
+			Misbehavior::OutOfWindowRound => (OUT_OF_WINDOW_ROUND, Severity::Minor),
+			Misbehavior::VoteSpam => (VOTE_SPAM, Severity::Minor),
+			Misbehavior::DistantVote => (DISTANT_VOTE, Severity::Major),
+			Misbehavior::UnknownVoter => (UNKNOWN_VOTER, Severity::Major),
+			Misbehavior::VoteBelowBase => (VOTE_BELOW_BASE, Severity::Minor),
+			Misbehavior::InvalidBlockNumber => (INVALID_BLOCK_NUMBER, Severity::Major),
+		};
+
+		Cost { reputation_delta, severity }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn invalid_commit_cost_scales_with_precommits() {
+		assert_eq!(Misbehavior::InvalidCommit(0).cost(), Cost {
+			reputation_delta: INVALID_COMMIT,
+			severity: Severity::Major,
+		});
+		assert_eq!(Misbehavior::InvalidCommit(4).cost().reputation_delta, INVALID_COMMIT - 100);
+		assert_eq!(Misbehavior::InvalidCommit(usize::MAX).cost().reputation_delta, i32::MIN);
+	}
+
+	#[test]
+	fn minor_misbehavior_is_cheaper() {
+		let all = [
+			Misbehavior::InvalidCommit(0),
+			Misbehavior::MalformedCompactCommit,
+			Misbehavior::InvalidCatchUp,
+			Misbehavior::OutOfWindowRound,
+			Misbehavior::VoteSpam,
+			Misbehavior::DistantVote,
+			Misbehavior::UnknownVoter,
+			Misbehavior::VoteBelowBase,
+			Misbehavior::InvalidBlockNumber,
+		];
+
+		let cheapest_major = all.iter().map(Misbehavior::cost)
+			.filter(|cost| cost.severity == Severity::Major)
+			.map(|cost| cost.reputation_delta)
+			.max()
+			.unwrap();
+
+		for cost in all.iter().map(Misbehavior::cost) {
+			assert!(cost.reputation_delta < 0);
+			if cost.severity == Severity::Minor {
+				assert!(cost.reputation_delta > cheapest_major);
+			}
+		}
+	}
+}
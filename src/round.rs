@@ -20,12 +20,15 @@ use std::ops::AddAssign;
 #[cfg(feature = "derive-codec")]
 use parity_scale_codec::{Encode, Decode};
 
-use crate::collections::{hash_map::{HashMap, Entry}, Vec};
+use crate::collections::{hash_map::{HashMap, Entry}, HashSet, Vec};
 use crate::bitfield::{Context as BitfieldContext, Bitfield};
 use crate::vote_graph::VoteGraph;
 use crate::voter_set::VoterSet;
 
-use super::{Equivocation, Prevote, Precommit, Chain, BlockNumberOps, HistoricalVotes, Message, SignedMessage};
+use super::{
+	Equivocation, Prevote, Precommit, Chain, BlockNumberOps, HistoricalVotes, Message, SignedMessage,
+	PrefetchedAncestry,
+};
 
 #[derive(Hash, Eq, PartialEq)]
 struct Address;
@@ -36,7 +39,7 @@ struct TotalWeight {
 	precommit: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct VoteWeight {
 	bitfield: Bitfield,
 }
@@ -107,6 +110,7 @@ struct VoteTracker<Id: Hash + Eq, Vote, Signature> {
 pub(crate) struct AddVoteResult<'a, Vote, Signature> {
 	multiplicity: Option<&'a VoteMultiplicity<Vote, Signature>>,
 	duplicated: bool,
+	excess: bool,
 }
 
 impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker<Id, Vote, Signature> {
@@ -138,11 +142,12 @@ impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker
 				AddVoteResult {
 					multiplicity: Some(multiplicity),
 					duplicated: false,
+					excess: false,
 				}
 			}
 			Entry::Occupied(mut occupied) => {
 				if occupied.get().contains(&vote, &signature) {
-					return AddVoteResult { multiplicity: None, duplicated: true };
+					return AddVoteResult { multiplicity: None, duplicated: true, excess: false };
 				}
 
 				// import, but ignore further equivocations.
@@ -150,7 +155,7 @@ impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker
 					VoteMultiplicity::Single(ref v, ref s) =>
 						Some(VoteMultiplicity::Equivocated((v.clone(), s.clone()), (vote, signature))),
 					VoteMultiplicity::Equivocated(_, _) => {
-						return AddVoteResult { multiplicity: None, duplicated: false }
+						return AddVoteResult { multiplicity: None, duplicated: false, excess: true }
 					}
 				};
 
@@ -160,7 +165,8 @@ impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker
 
 				AddVoteResult {
 					multiplicity: Some(&*occupied.into_mut()),
-					duplicated: false
+					duplicated: false,
+					excess: false,
 				}
 			}
 		}
@@ -212,6 +218,32 @@ impl<H: Clone, N: Clone> State<H, N> {
 	}
 }
 
+/// Whether a voter's votes have been imported in a round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoterParticipation<Id> {
+	/// The voter.
+	pub id: Id,
+	/// Whether a prevote has been imported.
+	pub prevoted: bool,
+	/// Whether a precommit has been imported.
+	pub precommitted: bool,
+}
+
+/// Participation of all voters in a round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Participation<Id> {
+	/// Each voter's participation, in canonical order.
+	pub voters: Vec<VoterParticipation<Id>>,
+	/// The number of voters which have prevoted.
+	pub prevotes: usize,
+	/// The number of voters which have precommitted.
+	pub precommits: usize,
+	/// The weight of the voters which haven't prevoted.
+	pub missing_prevote_weight: u64,
+	/// The weight of the voters which haven't precommitted.
+	pub missing_precommit_weight: u64,
+}
+
 /// Parameters for starting a round.
 pub struct RoundParams<Id: Hash + Eq, H, N> {
 	/// The round number for votes.
@@ -232,29 +264,63 @@ pub struct Round<Id: Hash + Eq, H: Hash + Eq, N, Signature> {
 	voters: VoterSet<Id>,
 	total_weight: u64,
 	bitfield_context: BitfieldContext,
+	participation: Bitfield, // voters whose prevotes and precommits have been imported
 	prevote_ghost: Option<(H, N)>, // current memoized prevote-GHOST block
 	precommit_ghost: Option<(H, N)>, // current memoized precommit-GHOST block
 	finalized: Option<(H, N)>, // best finalized block in this round.
 	estimate: Option<(H, N)>, // current memoized round-estimate
 	completable: bool, // whether the round is completable
+	excess_votes: u64, // votes dropped since their voter already equivocated
+	max_vote_distance: Option<N>, // how far ahead of the base votes may target
+}
+
+/// Equivocations found when importing a batch of votes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equivocations<Id, H, N, Signature> {
+	/// Equivocations in prevotes.
+	pub prevotes: Vec<Equivocation<Id, Prevote<H, N>, Signature>>,
+	/// Equivocations in precommits.
+	pub precommits: Vec<Equivocation<Id, Precommit<H, N>, Signature>>,
 }
 
 /// Result of importing a Prevote or Precommit.
-pub(crate) struct ImportResult<Id, P, Signature> {
-	/// Indicates if the voter is part of the voter set.
-	pub(crate) valid_voter: bool,
-	/// Indicates if the vote is duplicated.
-	pub(crate) duplicated: bool,
-	/// An equivocation proof, if the vote is an equivocation.
-	pub(crate) equivocation: Option<Equivocation<Id, P, Signature>>,
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ImportResult<Id, P, Signature> {
+	/// The vote was added to the round.
+	Imported,
+	/// The vote was added to the round and proves that its voter equivocated.
+	Equivocation(Equivocation<Id, P, Signature>),
+	/// The vote was already imported.
+	Duplicate,
+	/// The vote was dropped because the voter already cast two different
+	/// votes of the same kind, which prove its equivocation.
+	Excess,
+	/// The vote isn't from a voter of the round.
+	NotVoter,
+	/// The vote targets a block lower than the round's base.
+	BelowBase,
+	/// The vote's block number doesn't match the number of its block.
+	BadNumber,
 }
 
-impl<Id, P, Signature> Default for ImportResult<Id, P, Signature> {
-	fn default() -> Self {
-		ImportResult {
-			valid_voter: false,
-			duplicated: false,
-			equivocation: None,
+impl<Id, P, Signature> ImportResult<Id, P, Signature> {
+	/// Whether the vote was added to the round.
+	pub(crate) fn is_new(&self) -> bool {
+		match *self {
+			ImportResult::Imported | ImportResult::Equivocation(_) => true,
+			ImportResult::Duplicate |
+			ImportResult::Excess |
+			ImportResult::NotVoter |
+			ImportResult::BelowBase |
+			ImportResult::BadNumber => false,
+		}
+	}
+
+	/// The equivocation proof, if the vote is an equivocation.
+	pub(crate) fn equivocation(self) -> Option<Equivocation<Id, P, Signature>> {
+		match self {
+			ImportResult::Equivocation(equivocation) => Some(equivocation),
+			_ => None,
 		}
 	}
 }
@@ -281,11 +347,14 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			precommit: VoteTracker::new(),
 			historical_votes: HistoricalVotes::new(),
 			bitfield_context: BitfieldContext::new(n_validators),
+			participation: Bitfield::Blank,
 			prevote_ghost: None,
 			precommit_ghost: None,
 			finalized: None,
 			estimate: None,
 			completable: false,
+			excess_votes: 0,
+			max_vote_distance: None,
 		}
 	}
 
@@ -294,10 +363,32 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		self.round_number
 	}
 
-	/// Import a prevote. Returns an equivocation proof, if the vote is an equivocation,
-	/// and a bool indicating if the vote is duplicated (see `ImportResult`).
+	/// Return the approximate memory used by the round's vote graph and
+	/// votes, in bytes.
+	pub fn approximate_size(&self) -> usize {
+		let tracked_votes = self.prevote.votes.len() *
+			std::mem::size_of::<(Id, VoteMultiplicity<Prevote<H, N>, Signature>)>() +
+			self.precommit.votes.len() *
+			std::mem::size_of::<(Id, VoteMultiplicity<Precommit<H, N>, Signature>)>();
+
+		self.graph.approximate_size() +
+			tracked_votes +
+			self.historical_votes.seen().len() * std::mem::size_of::<SignedMessage<H, N, Signature, Id>>()
+	}
+
+	/// Prune the vote-nodes on forks which don't contain the given block from
+	/// the vote graph, e.g. once it has been finalized in this round. Returns
+	/// the prevote and precommit weight of the pruned votes.
+	pub fn prune_before(&mut self, hash: H, number: N) -> (u64, u64) {
+		let pruned = self.graph.prune_before(hash, number);
+		let voters = &self.voters;
+		pruned.bitfield.total_weight(|idx| voters.weight_by_index(idx).unwrap_or(0))
+	}
+
+	/// Import a prevote. Returns whether it was added to the round, and the
+	/// equivocation proof if it is an equivocation (see `ImportResult`).
 	///
-	/// Ignores duplicate prevotes (not equivocations).
+	/// Ignores votes which aren't added, without updating the round's state.
 	pub(crate) fn import_prevote<C: Chain<H, N>>(
 		&mut self,
 		chain: &C,
@@ -305,21 +396,196 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		signer: Id,
 		signature: Signature,
 	) -> Result<ImportResult<Id, Prevote<H, N>, Signature>, crate::Error> {
-		let mut import_result = ImportResult::default();
+		let target = vote.target_hash.clone();
+		let import_result = self.add_prevote(chain, vote, signer, signature)?;
+		if import_result.is_new() {
+			match import_result {
+				ImportResult::Imported => self.prevote_imported(&target),
+				_ => {
+					// equivocators count as voting for everything.
+					self.update_prevote_ghost();
+					self.update();
+				}
+			}
+		}
+		Ok(import_result)
+	}
+
+	/// Import a precommit. Returns whether it was added to the round, and the
+	/// equivocation proof if it is an equivocation (see `ImportResult`).
+	///
+	/// Ignores votes which aren't added, without updating the round's state.
+	pub(crate) fn import_precommit<C: Chain<H, N>>(
+		&mut self,
+		chain: &C,
+		vote: Precommit<H, N>,
+		signer: Id,
+		signature: Signature,
+	) -> Result<ImportResult<Id, Precommit<H, N>, Signature>, crate::Error> {
+		let target = vote.target_hash.clone();
+		let precommit_weight = self.precommit.current_weight;
+		let import_result = self.add_precommit(chain, vote, signer, signature)?;
+		if import_result.is_new() {
+			match import_result {
+				// until there are threshold precommits, updating is cheap.
+				ImportResult::Imported if precommit_weight >= self.threshold() =>
+					self.update_after_precommit(Some(&target)),
+				_ => self.update(),
+			}
+		}
+		Ok(import_result)
+	}
+
+	/// Reject votes for blocks more than the given number of blocks ahead of
+	/// the round's base with `Error::VoteTooDistant`, before their ancestry
+	/// is looked up. Honest voters only vote for blocks they have imported,
+	/// so this should be well above the number of blocks the chain may
+	/// advance during a round. Not limited if `None`, the default.
+	pub fn set_max_vote_distance(&mut self, max_distance: Option<N>) {
+		self.max_vote_distance = max_distance;
+	}
+
+	/// Whether the given signed vote was imported already, e.g. to skip
+	/// redelivered votes before checking their signature. Primary proposals
+	/// aren't tracked by the round.
+	pub fn has_vote(&self, vote: &SignedMessage<H, N, Signature, Id>) -> bool {
+		let SignedMessage { message, signature, id } = vote;
+		match message {
+			Message::Prevote(prevote) => self.prevote.votes.get(id)
+				.is_some_and(|votes| votes.contains(prevote, signature)),
+			Message::Precommit(precommit) => self.precommit.votes.get(id)
+				.is_some_and(|votes| votes.contains(precommit, signature)),
+			Message::PrimaryPropose(_) => false,
+		}
+	}
+
+	/// Check that a vote for a block with the given number isn't further
+	/// ahead of the round's base than allowed.
+	pub(crate) fn check_vote_distance(&self, target_number: N) -> Result<(), crate::Error> {
+		let base_number = self.graph.base().1;
+		match self.max_vote_distance {
+			Some(max) if target_number > base_number && target_number - base_number > max =>
+				Err(crate::Error::VoteTooDistant),
+			_ => Ok(()),
+		}
+	}
+
+	// check that a vote's block number matches its block, as the vote-graph
+	// relies on it. blocks which aren't descendents of the base are left for
+	// the vote-graph to reject.
+	fn has_valid_number<C: Chain<H, N>>(&self, chain: &C, hash: &H, number: N) -> bool {
+		let (base_hash, base_number) = self.graph.base();
+		if *hash == base_hash {
+			return number == base_number;
+		}
+
+		match chain.ancestry(base_hash, hash.clone()) {
+			Ok(ancestry) => number > base_number && (number - base_number).as_() == ancestry.len() + 1,
+			Err(_) => true,
+		}
+	}
+
+	/// Import a batch of votes, e.g. from a catch-up, updating the round's
+	/// state only once all of them have been imported. Primary proposals are
+	/// ignored, as are duplicate votes and votes from unknown voters.
+	///
+	/// The ancestry of all vote targets is looked up with a single
+	/// `Chain::batch_ancestry` query.
+	///
+	/// Fails on the first vote which can't be imported, the votes before it
+	/// stay imported.
+	pub fn import_votes<C, I>(
+		&mut self,
+		chain: &C,
+		votes: I,
+	) -> Result<Equivocations<Id, H, N, Signature>, crate::Error> where
+		C: Chain<H, N>,
+		I: IntoIterator<Item=SignedMessage<H, N, Signature, Id>>,
+	{
+		let votes: Vec<_> = votes.into_iter().collect();
+		let chain = &PrefetchedAncestry::new(
+			chain,
+			self.graph.base().0,
+			votes.iter().filter_map(|vote| match vote.message {
+				Message::Prevote(ref prevote) => Some(prevote.target_hash.clone()),
+				Message::Precommit(ref precommit) => Some(precommit.target_hash.clone()),
+				Message::PrimaryPropose(_) => None,
+			}),
+		);
+
+		let mut equivocations = Equivocations { prevotes: Vec::new(), precommits: Vec::new() };
+		let mut result = Ok(());
+
+		for SignedMessage { message, signature, id } in votes {
+			// votes below the base can't be descendents of it, nor can votes be
+			// kept whose number doesn't match their block.
+			let imported = match message {
+				Message::Prevote(prevote) => self.add_prevote(chain, prevote, id, signature)
+					.and_then(|imported| match imported {
+						ImportResult::BelowBase => Err(crate::Error::NotDescendent),
+						ImportResult::BadNumber => Err(crate::Error::InvalidBlockNumber),
+						imported => {
+							equivocations.prevotes.extend(imported.equivocation());
+							Ok(())
+						},
+					}),
+				Message::Precommit(precommit) => self.add_precommit(chain, precommit, id, signature)
+					.and_then(|imported| match imported {
+						ImportResult::BelowBase => Err(crate::Error::NotDescendent),
+						ImportResult::BadNumber => Err(crate::Error::InvalidBlockNumber),
+						imported => {
+							equivocations.precommits.extend(imported.equivocation());
+							Ok(())
+						},
+					}),
+				Message::PrimaryPropose(_) => Ok(()),
+			};
+
+			if let Err(e) = imported {
+				result = Err(e);
+				break;
+			}
+		}
+
+		self.update_prevote_ghost();
+		self.update();
+
+		result.map(|()| equivocations)
+	}
+
+	// track a prevote and add it to the vote graph, without updating the
+	// round's state.
+	fn add_prevote<C: Chain<H, N>>(
+		&mut self,
+		chain: &C,
+		vote: Prevote<H, N>,
+		signer: Id,
+		signature: Signature,
+	) -> Result<ImportResult<Id, Prevote<H, N>, Signature>, crate::Error> {
+		self.check_vote_distance(vote.target_number)?;
 
 		let info = match self.voters.info(&signer) {
 			Some(info) => info,
-			None => return Ok(import_result),
+			None => return Ok(ImportResult::NotVoter),
 		};
-		import_result.valid_voter = true;
 		let weight = info.weight();
 
-		let equivocation = {
+		if vote.target_number < self.graph.base().1 {
+			return Ok(ImportResult::BelowBase);
+		}
+
+		if !self.has_valid_number(chain, &vote.target_hash, vote.target_number) {
+			return Ok(ImportResult::BadNumber);
+		}
+
+		let import_result = {
 			let multiplicity = match self.prevote.add_vote(signer.clone(), vote.clone(), signature.clone(), weight) {
 				AddVoteResult { multiplicity: Some(m), .. } => m,
-				AddVoteResult { duplicated, .. } => {
-					import_result.duplicated = duplicated;
-					return Ok(import_result)
+				AddVoteResult { duplicated: true, .. } => return Ok(ImportResult::Duplicate),
+				AddVoteResult { excess, .. } => {
+					debug_assert!(excess, "votes are either added, duplicated or in excess; qed");
+					self.excess_votes += 1;
+					return Ok(ImportResult::Excess)
 				},
 			};
 			let round_number = self.round_number;
@@ -331,6 +597,9 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 							.expect("info is instantiated from same voter set as context; qed"),
 					};
 
+					self.participation = self.participation.merge(&vote_weight.bitfield)
+						.expect("both bitfields set to same length; qed");
+
 					self.graph.insert(
 						single_vote.target_hash.clone(),
 						single_vote.target_number,
@@ -343,7 +612,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 					let signed_message = SignedMessage { id: signer, signature, message };
 					self.historical_votes.push_vote(signed_message);
 
-					None
+					ImportResult::Imported
 				}
 				VoteMultiplicity::Equivocated(ref first, ref second) => {
 					// mark the equivocator as such. no need to "undo" the first vote.
@@ -355,7 +624,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 					let signed_message = SignedMessage { id: signer.clone(), signature, message };
 					self.historical_votes.push_vote(signed_message);
 
-					Some(Equivocation {
+					ImportResult::Equivocation(Equivocation {
 						round_number,
 						identity: signer,
 						first: first.clone(),
@@ -365,48 +634,42 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			}
 		};
 
-		// update prevote-GHOST
-		let threshold = self.threshold();
-		if self.prevote.current_weight >= threshold {
-			let equivocators = self.bitfield_context.equivocators();
-
-			self.prevote_ghost = self.graph.find_ghost(
-				self.prevote_ghost.take(),
-				|v| v.total_weight(&equivocators, &self.voters).prevote >= threshold,
-			);
-		}
-
-		self.update();
-		import_result.equivocation = equivocation;
 		Ok(import_result)
 	}
 
-	/// Import a precommit. Returns an equivocation proof, if the vote is an
-	/// equivocation, and a bool indicating if the vote is duplicated (see `ImportResult`).
-	///
-	/// Ignores duplicate precommits (not equivocations).
-	pub(crate) fn import_precommit<C: Chain<H, N>>(
+	// track a precommit and add it to the vote graph, without updating the
+	// round's state.
+	fn add_precommit<C: Chain<H, N>>(
 		&mut self,
 		chain: &C,
 		vote: Precommit<H, N>,
 		signer: Id,
 		signature: Signature,
 	) -> Result<ImportResult<Id, Precommit<H, N>, Signature>, crate::Error> {
-		let mut import_result = ImportResult::default();
+		self.check_vote_distance(vote.target_number)?;
 
 		let info = match self.voters.info(&signer) {
 			Some(info) => info,
-			None => return Ok(import_result),
+			None => return Ok(ImportResult::NotVoter),
 		};
-		import_result.valid_voter = true;
 		let weight = info.weight();
 
-		let equivocation = {
+		if vote.target_number < self.graph.base().1 {
+			return Ok(ImportResult::BelowBase);
+		}
+
+		if !self.has_valid_number(chain, &vote.target_hash, vote.target_number) {
+			return Ok(ImportResult::BadNumber);
+		}
+
+		let import_result = {
 			let multiplicity = match self.precommit.add_vote(signer.clone(), vote.clone(), signature.clone(), weight) {
 				AddVoteResult { multiplicity: Some(m), .. } => m,
-				AddVoteResult { duplicated, .. } => {
-					import_result.duplicated = duplicated;
-					return Ok(import_result)
+				AddVoteResult { duplicated: true, .. } => return Ok(ImportResult::Duplicate),
+				AddVoteResult { excess, .. } => {
+					debug_assert!(excess, "votes are either added, duplicated or in excess; qed");
+					self.excess_votes += 1;
+					return Ok(ImportResult::Excess)
 				},
 			};
 			let round_number = self.round_number;
@@ -418,6 +681,9 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 							.expect("info is instantiated from same voter set as context; qed"),
 					};
 
+					self.participation = self.participation.merge(&vote_weight.bitfield)
+						.expect("both bitfields set to same length; qed");
+
 					self.graph.insert(
 						single_vote.target_hash.clone(),
 						single_vote.target_number,
@@ -429,7 +695,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 					let signed_message = SignedMessage { id: signer, signature, message };
 					self.historical_votes.push_vote(signed_message);
 
-					None
+					ImportResult::Imported
 				},
 				VoteMultiplicity::Equivocated(ref first, ref second) => {
 					// mark the equivocator as such. no need to "undo" the first vote.
@@ -441,7 +707,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 					let signed_message = SignedMessage { id: signer.clone(), signature, message };
 					self.historical_votes.push_vote(signed_message);
 
-					Some(Equivocation {
+					ImportResult::Equivocation(Equivocation {
 						round_number,
 						identity: signer,
 						first: first.clone(),
@@ -451,11 +717,38 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			}
 		};
 
-		self.update();
-		import_result.equivocation = equivocation;
 		Ok(import_result)
 	}
 
+	// update the round's state after importing a prevote for the given block.
+	// only prevotes for the prevote-GHOST or its descendents can move it, and
+	// the rest of the state only depends on where it is.
+	fn prevote_imported(&mut self, target: &H) {
+		if let Some((ref g_hash, g_num)) = self.prevote_ghost {
+			if self.graph.is_equal_or_descendent_of((g_hash, g_num), target) == Some(false) {
+				return;
+			}
+		}
+
+		let prevote_ghost = self.prevote_ghost.clone();
+		self.update_prevote_ghost();
+		if self.prevote_ghost != prevote_ghost {
+			self.update();
+		}
+	}
+
+	fn update_prevote_ghost(&mut self) {
+		let threshold = self.threshold();
+		if self.prevote.current_weight >= threshold {
+			let equivocators = self.bitfield_context.equivocators();
+
+			self.prevote_ghost = self.graph.find_ghost(
+				self.prevote_ghost.take(),
+				|v| v.total_weight(&equivocators, &self.voters).prevote >= threshold,
+			);
+		}
+	}
+
 	// Get current
 	pub fn state(&self) -> State<H, N> {
 		State {
@@ -521,12 +814,28 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		}
 
 		let (f_hash, _f_num) = self.finalized.clone()?;
+
+		// find out which single votes branch off of the target with one query.
+		let single_targets: Vec<H> = self.precommit.votes.values()
+			.filter_map(|multiplicity| match *multiplicity {
+				VoteMultiplicity::Single(ref v, _) => Some(v.target_hash.clone()),
+				VoteMultiplicity::Equivocated(..) => None,
+			})
+			.collect::<HashSet<_>>()
+			.into_iter()
+			.collect();
+		let descendents: HashSet<H> = chain.is_descendent_of_many(f_hash, &single_targets)
+			.into_iter()
+			.zip(single_targets)
+			.filter_map(|(descends, target)| if descends { Some(target) } else { None })
+			.collect();
+
 		let find_valid_precommits = self.precommit.votes.iter()
 			.filter(move |&(_id, multiplicity)| {
 				if let VoteMultiplicity::Single(ref v, _) = *multiplicity {
 					// if there is a single vote from this voter, we only include it
 					// if it branches off of the target.
-					chain.is_equal_or_descendent_of(f_hash.clone(), v.target_hash.clone())
+					descendents.contains(&v.target_hash)
 				} else {
 					// equivocations count for everything, so we always include them.
 					true
@@ -547,6 +856,15 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 
 	// update the round-estimate and whether the round is completable.
 	fn update(&mut self) {
+		self.update_after_precommit(None)
+	}
+
+	// update the finalized block, the estimate and completability. given the
+	// block of a precommit which was just imported, with threshold precommits
+	// imported before it and without equivocating, the finalized block and
+	// estimate are only searched for again if that precommit could have moved
+	// them.
+	fn update_after_precommit(&mut self, precommit: Option<&H>) {
 		let threshold = self.threshold();
 		if self.prevote.current_weight < threshold { return }
 
@@ -564,7 +882,14 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		// 2/3+ prevote and precommit weight.
 		let threshold = self.threshold();
 		let current_precommits = self.precommit.current_weight;
-		if current_precommits >= threshold {
+		let graph = &self.graph;
+		let precommit_descends = |block: &(H, N)| precommit.is_none_or(|hash| {
+			graph.is_equal_or_descendent_of((&block.0, block.1), hash) != Some(false)
+		});
+
+		// only precommits for the finalized block or its descendents can
+		// finalize blocks beyond it.
+		if current_precommits >= threshold && self.finalized.as_ref().is_none_or(&precommit_descends) {
 			self.finalized = self.graph.find_ancestor(
 				g_hash.clone(),
 				g_num,
@@ -623,17 +948,34 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		//
 		// the round-estimate is the highest block in the chain with head
 		// `prevote_ghost` that could have supermajority-commits.
+		//
+		// a precommit leaves whether its block or the block's ancestors could
+		// have supermajority-commits unchanged, and makes it less likely for
+		// all other blocks. so the estimate stays the same after a precommit
+		// for it or its descendents, and can only move back after others.
 		if self.precommit.current_weight >= threshold {
-			self.estimate = self.graph.find_ancestor(
-				g_hash.clone(),
-				g_num,
-				possible_to_precommit,
-			);
+			self.estimate = match (precommit, self.estimate.take()) {
+				(None, _) => self.graph.find_ancestor(g_hash.clone(), g_num, possible_to_precommit),
+				(Some(_), None) => None,
+				(Some(_), Some(estimate)) => if precommit_descends(&estimate) {
+					Some(estimate)
+				} else {
+					self.graph.find_ancestor(estimate.0, estimate.1, possible_to_precommit)
+				},
+			};
 		} else {
 			self.estimate = Some((g_hash, g_num));
 			return;
 		}
 
+		// once completable, a round stays completable: further precommits only
+		// make it less likely for blocks to get commit-supermajority, and the
+		// prevote-GHOST only moves on to descendents of the estimate. so the
+		// graph is only searched until the round becomes completable.
+		if self.completable {
+			return;
+		}
+
 		self.completable = self.estimate.clone().map_or(false, |(b_hash, b_num)| {
 			b_hash != g_hash || {
 				// round-estimate is the same as the prevote-ghost.
@@ -685,7 +1027,83 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 
 	/// Return the primary voter of the round.
 	pub fn primary_voter(&self) -> &(Id, u64) {
-		self.voters.voter_by_index(self.round_number as usize % self.voters.len())
+		self.voters.primary_voter(self.round_number)
+	}
+
+	/// Return the total weight and number of voters that have prevoted.
+	/// Equivocators are only counted once.
+	pub fn prevote_participation(&self) -> (u64, usize) {
+		(self.prevote.current_weight, self.prevote.votes.len())
+	}
+
+	/// Return the total weight and number of voters that have precommitted.
+	/// Equivocators are only counted once.
+	pub fn precommit_participation(&self) -> (u64, usize) {
+		(self.precommit.current_weight, self.precommit.votes.len())
+	}
+
+	/// Return the total weight of the voters that have prevoted.
+	/// Equivocators are only counted once.
+	pub fn prevote_weight(&self) -> u64 {
+		self.prevote.current_weight
+	}
+
+	/// Return the total weight of the voters that have precommitted.
+	/// Equivocators are only counted once.
+	pub fn precommit_weight(&self) -> u64 {
+		self.precommit.current_weight
+	}
+
+	/// Return the number of votes which were dropped because their voter had
+	/// already cast two different votes of the same kind in this round. Those
+	/// two are all that's needed to prove the equivocation, further votes
+	/// would only bloat the round.
+	pub fn excess_votes(&self) -> u64 {
+		self.excess_votes
+	}
+
+	/// Return the prevote and precommit weight still missing to reach the
+	/// threshold, which is zero once it has been reached.
+	///
+	/// Reaching the threshold is necessary but not sufficient for the round
+	/// to be completable.
+	pub fn threshold_shortfall(&self) -> (u64, u64) {
+		let threshold = self.threshold();
+		(
+			threshold.saturating_sub(self.prevote_weight()),
+			threshold.saturating_sub(self.precommit_weight()),
+		)
+	}
+
+	/// Return the prevote and precommit weight on the given block, i.e. of the
+	/// votes for it or its descendents. Equivocators count towards every block.
+	pub fn weight_at<C: Chain<H, N>>(&self, chain: &C, hash: H, number: N) -> (u64, u64) {
+		let equivocators = self.bitfield_context.equivocators();
+		let weight = self.graph.cumulative_vote_at(hash, number, chain)
+			.total_weight(&equivocators, &self.voters);
+
+		(weight.prevote, weight.precommit)
+	}
+
+	/// Return which voters have prevoted and precommitted, e.g. to find
+	/// voters which are offline.
+	pub fn participation(&self) -> Participation<Id> {
+		let voters = self.voters.iter().enumerate().map(|(idx, (id, _))| VoterParticipation {
+			id: id.clone(),
+			prevoted: self.participation.prevoted(idx),
+			precommitted: self.participation.precommitted(idx),
+		}).collect();
+
+		let (prevote_weight, prevotes) = self.prevote_participation();
+		let (precommit_weight, precommits) = self.precommit_participation();
+
+		Participation {
+			voters,
+			prevotes,
+			precommits,
+			missing_prevote_weight: self.total_weight - prevote_weight,
+			missing_precommit_weight: self.total_weight - precommit_weight,
+		}
 	}
 
 	/// Return all imported prevotes.
@@ -706,6 +1124,19 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		&self.historical_votes
 	}
 
+	/// Return a bitfield of the voters whose prevotes and precommits have been
+	/// imported, indexed canonically like the voter set. Peers can use this to
+	/// only send us votes we don't have yet.
+	pub fn participation_bitfield(&self) -> &Bitfield {
+		&self.participation
+	}
+
+	/// Return a bitfield of the voters which equivocated in prevotes and
+	/// precommits, indexed canonically like the voter set.
+	pub fn equivocators_bitfield(&self) -> Bitfield {
+		self.bitfield_context.equivocators().clone()
+	}
+
 	/// Set the number of prevotes and precommits received at the moment of prevoting.
 	/// It should be called inmediatly after prevoting.
 	pub fn set_prevoted_index(&mut self) {
@@ -735,6 +1166,8 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 mod tests {
 	use super::*;
 	use crate::testing::chain::{GENESIS_HASH, DummyChain};
+	use crate::testing::reference;
+	use proptest::prelude::*;
 
 	fn voters() -> VoterSet<&'static str> {
 		[
@@ -817,6 +1250,8 @@ mod tests {
 		).unwrap();
 
 		assert_eq!(round.finalized, None);
+		assert_eq!(round.precommit_participation(), (11, 2));
+		assert_eq!(round.prevote_participation(), (0, 0));
 
 		// import some prevotes.
 		{
@@ -868,12 +1303,12 @@ mod tests {
 		});
 
 		// first prevote by eve
-		assert!(round.import_prevote(
+		assert_eq!(round.import_prevote(
 			&chain,
 			Prevote::new("FC", 10),
 			"Eve", // 3 on F, E
 			Signature("Eve-1"),
-		).unwrap().equivocation.is_none());
+		).unwrap(), ImportResult::Imported);
 
 
 		assert!(round.prevote_ghost.is_none());
@@ -884,30 +1319,345 @@ mod tests {
 			Prevote::new("ED", 10),
 			"Eve", // still 3 on E
 			Signature("Eve-2"),
-		).unwrap().equivocation.is_some());
+		).unwrap().equivocation().is_some());
 
-		// third prevote: returns nothing.
-		assert!(round.import_prevote(
+		// third prevote: dropped as excess.
+		assert_eq!(round.import_prevote(
 			&chain,
 			Prevote::new("F", 7),
 			"Eve", // still 3 on F and E
 			Signature("Eve-2"),
-		).unwrap().equivocation.is_none());
+		).unwrap(), ImportResult::Excess);
 
 		// three eves together would be enough.
 
 		assert!(round.prevote_ghost.is_none());
 
-		assert!(round.import_prevote(
+		assert_eq!(round.import_prevote(
 			&chain,
 			Prevote::new("FA", 8),
 			"Bob", // add 7 to FA and you get FA.
 			Signature("Bob-1"),
-		).unwrap().equivocation.is_none());
+		).unwrap(), ImportResult::Imported);
 
 		assert_eq!(round.prevote_ghost, Some(("FA", 8)));
 	}
 
+	#[test]
+	fn distant_votes_are_rejected() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+		round.set_max_vote_distance(Some(2));
+
+		// the block doesn't even need to be known.
+		assert_eq!(
+			round.import_prevote(&chain, Prevote::new("Z", 1_000_000), "Alice", Signature("Alice")).err(),
+			Some(crate::Error::VoteTooDistant),
+		);
+		assert_eq!(
+			round.import_precommit(&chain, Precommit::new("F", 7), "Alice", Signature("Alice")).err(),
+			Some(crate::Error::VoteTooDistant),
+		);
+		assert_eq!(round.prevote_participation(), (0, 0));
+		assert_eq!(round.precommit_participation(), (0, 0));
+
+		assert!(round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).is_ok());
+		assert_eq!(round.prevote_participation().1, 1);
+	}
+
+	#[test]
+	fn redelivered_votes_are_duplicates() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		let signed = |message, signature| SignedMessage { message, signature: Signature(signature), id: "Alice" };
+		let prevote = signed(Message::Prevote(Prevote::new("E", 6)), "Alice");
+		assert!(!round.has_vote(&prevote));
+
+		let first = round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		assert!(first.is_new());
+		assert!(round.has_vote(&prevote));
+		let state = round.state();
+
+		let again = round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		assert_eq!(again, ImportResult::Duplicate);
+		assert_eq!(round.state(), state);
+		assert_eq!(round.prevotes().len(), 1);
+
+		// the same vote with another signature is not a duplicate, nor is the
+		// same vote as a precommit.
+		assert!(!round.has_vote(&signed(Message::Prevote(Prevote::new("E", 6)), "Alice-2")));
+		assert!(!round.has_vote(&signed(Message::Precommit(Precommit::new("E", 6)), "Alice")));
+	}
+
+	#[test]
+	fn invalid_votes_are_not_imported() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		assert_eq!(
+			round.import_prevote(&chain, Prevote::new("E", 6), "Mallory", Signature("Mallory")).unwrap(),
+			ImportResult::NotVoter,
+		);
+		assert_eq!(
+			round.import_precommit(&chain, Precommit::new("B", 3), "Alice", Signature("Alice")).unwrap(),
+			ImportResult::BelowBase,
+		);
+		assert_eq!(
+			round.import_prevote(&chain, Prevote::new("E", 9), "Alice", Signature("Alice")).unwrap(),
+			ImportResult::BadNumber,
+		);
+		assert_eq!(
+			round.import_precommit(&chain, Precommit::new("C", 5), "Alice", Signature("Alice")).unwrap(),
+			ImportResult::BadNumber,
+		);
+		assert_eq!(round.prevote_participation(), (0, 0));
+		assert_eq!(round.precommit_participation(), (0, 0));
+		assert!(round.historical_votes().seen().is_empty());
+
+		// the voter may still vote once its previous votes were rejected.
+		assert_eq!(
+			round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap(),
+			ImportResult::Imported,
+		);
+	}
+
+	#[test]
+	fn excess_votes_are_dropped() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		let first = round.import_precommit(&chain, Precommit::new("FC", 10), "Eve", Signature("Eve-1")).unwrap();
+		let second = round.import_precommit(&chain, Precommit::new("ED", 10), "Eve", Signature("Eve-2")).unwrap();
+		assert_eq!(first, ImportResult::Imported);
+		assert!(second.equivocation().is_some());
+
+		let size = round.approximate_size();
+		let blocks = [("D", 5), ("E", 6), ("F", 7), ("EA", 7), ("FA", 8), ("FB", 9)];
+		for &(hash, number) in blocks.iter().cycle().take(100) {
+			let spam = round.import_precommit(&chain, Precommit::new(hash, number), "Eve", Signature("Eve-3")).unwrap();
+			assert_eq!(spam, ImportResult::Excess);
+		}
+
+		// a redelivered vote is a duplicate rather than an excess vote.
+		let again = round.import_precommit(&chain, Precommit::new("FC", 10), "Eve", Signature("Eve-1")).unwrap();
+		assert_eq!(again, ImportResult::Duplicate);
+
+		assert_eq!(round.excess_votes(), 100);
+		assert_eq!(round.approximate_size(), size);
+		assert_eq!(round.precommits().len(), 2);
+	}
+
+	#[test]
+	fn equivocator_weight_counts_on_every_branch() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		round.import_prevote(&chain, Prevote::new("ED", 10), "Bob", Signature("Bob")).unwrap();
+		round.import_prevote(&chain, Prevote::new("FC", 10), "Eve", Signature("Eve-1")).unwrap();
+
+		// bob and eve only agree on E.
+		assert_eq!(round.prevote_ghost, Some(("E", 6)));
+
+		// eve equivocates on the F branch, which counts her weight towards the
+		// E branch as well.
+		assert!(round.import_prevote(
+			&chain,
+			Prevote::new("FA", 8),
+			"Eve",
+			Signature("Eve-2"),
+		).unwrap().equivocation().is_some());
+
+		assert_eq!(round.prevote_ghost, Some(("ED", 10)));
+	}
+
+	#[test]
+	fn tracks_participation_in_bitfields() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		let alice = round.voters().info(&"Alice").unwrap().canon_idx();
+		let bob = round.voters().info(&"Bob").unwrap().canon_idx();
+
+		round.import_prevote(&chain, Prevote::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		round.import_prevote(&chain, Prevote::new("E", 6), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("E", 6), "Bob", Signature("Bob-2")).unwrap();
+
+		let participation = round.participation_bitfield();
+		assert!(participation.prevoted(alice) && !participation.precommitted(alice));
+		assert!(participation.prevoted(bob) && participation.precommitted(bob));
+
+		let weight = |idx| round.voters().weight_by_index(idx).unwrap();
+		assert_eq!(participation.total_weight(weight), (11, 7));
+		assert_eq!(round.equivocators_bitfield().total_weight(weight), (0, 7));
+	}
+
+	#[test]
+	fn reports_participation() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		round.import_prevote(&chain, Prevote::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		round.import_prevote(&chain, Prevote::new("E", 6), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Bob", Signature("Bob")).unwrap();
+
+		assert_eq!(round.prevote_weight(), 11);
+		assert_eq!(round.precommit_weight(), 7);
+		assert_eq!(round.threshold_shortfall(), (0, 3));
+		assert_eq!(round.weight_at(&chain, "D", 5), (11, 7));
+		assert_eq!(round.weight_at(&chain, "E", 6), (7, 0));
+
+		let participation = round.participation();
+		let voter = |id| VoterParticipation {
+			id,
+			prevoted: id != "Eve",
+			precommitted: id == "Bob",
+		};
+
+		assert_eq!(participation, Participation {
+			voters: vec![voter("Alice"), voter("Bob"), voter("Eve")],
+			prevotes: 2,
+			precommits: 1,
+			missing_prevote_weight: 3,
+			missing_precommit_weight: 7,
+		});
+	}
+
+	#[test]
+	fn batch_import_matches_individual_imports() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let new_round = || Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		let prevote = |target: (&'static str, u32), id| SignedMessage {
+			message: Message::Prevote(Prevote::new(target.0, target.1)),
+			signature: Signature(id),
+			id,
+		};
+
+		let precommit = |target: (&'static str, u32), id, signature| SignedMessage {
+			message: Message::Precommit(Precommit::new(target.0, target.1)),
+			signature: Signature(signature),
+			id,
+		};
+
+		let votes = vec![
+			prevote(("FC", 10), "Alice"),
+			prevote(("ED", 10), "Bob"),
+			prevote(("F", 7), "Eve"),
+			precommit(("E", 6), "Alice", "Alice"),
+			precommit(("ED", 10), "Bob", "Bob"),
+			precommit(("E", 6), "Eve", "Eve-1"),
+			precommit(("FA", 8), "Eve", "Eve-2"),
+		];
+
+		let mut individually = new_round();
+		for SignedMessage { message, signature, id } in votes.clone() {
+			match message {
+				Message::Prevote(prevote) => { individually.import_prevote(&chain, prevote, id, signature).unwrap(); }
+				Message::Precommit(precommit) => { individually.import_precommit(&chain, precommit, id, signature).unwrap(); }
+				Message::PrimaryPropose(_) => {}
+			}
+		}
+
+		let mut batched = new_round();
+		let equivocations = batched.import_votes(&chain, votes).unwrap();
+
+		assert_eq!(batched.state(), individually.state());
+		assert_eq!(batched.state().finalized, Some(("E", 6)));
+		assert!(equivocations.prevotes.is_empty());
+		assert_eq!(equivocations.precommits, vec![Equivocation {
+			round_number: 1,
+			identity: "Eve",
+			first: (Precommit::new("E", 6), Signature("Eve-1")),
+			second: (Precommit::new("FA", 8), Signature("Eve-2")),
+		}]);
+
+		// votes below the base can't be imported.
+		assert!(new_round().import_votes(&chain, vec![prevote(("A", 2), "Alice")]).is_err());
+	}
+
+	#[test]
+	fn completable_round_stays_completable() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("E", &["EA", "EB"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		round.import_prevote(&chain, Prevote::new("E", 6), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		assert!(!round.completable());
+
+		round.import_precommit(&chain, Precommit::new("E", 6), "Bob", Signature("Bob")).unwrap();
+		assert!(round.completable());
+		assert_eq!(round.estimate(), Some(&("E", 6)));
+
+		round.import_prevote(&chain, Prevote::new("EB", 8), "Eve", Signature("Eve")).unwrap();
+		round.import_precommit(&chain, Precommit::new("EA", 7), "Eve", Signature("Eve")).unwrap();
+		assert!(round.completable());
+		assert_eq!(round.estimate(), Some(&("E", 6)));
+	}
+
 	#[test]
 	fn vote_weight_discounts_equivocators() {
 		let v: VoterSet<_> = [
@@ -990,7 +1740,7 @@ mod tests {
 
 		round.import_prevote(
 			&chain,
-			Prevote::new("EC", 10),
+			Prevote::new("ED", 10),
 			"Alice",
 			Signature("Alice"),
 		).unwrap();
@@ -1022,7 +1772,7 @@ mod tests {
 				},
 				SignedMessage {
 					message: Message::Prevote(
-						Prevote { target_hash: "EC", target_number: 10 }
+						Prevote { target_hash: "ED", target_number: 10 }
 					),
 					signature: Signature("Alice"),
 					id: "Alice"
@@ -1032,4 +1782,172 @@ mod tests {
 			Some(4),
 		));
 	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_vectors_hold() {
+		use crate::testing::vectors;
+
+		let vectors = vectors::from_json(include_str!("../test-vectors/round.json")).unwrap();
+		assert!(!vectors.is_empty());
+
+		for vector in &vectors {
+			vector.check().unwrap();
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_vectors_are_up_to_date() {
+		use crate::testing::vectors;
+
+		let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test-vectors/round.json");
+		let generated = vectors::to_json(&vectors::generate());
+		if std::env::var_os("UPDATE_TEST_VECTORS").is_some() {
+			std::fs::write(path, &generated).unwrap();
+		}
+
+		assert!(
+			std::fs::read_to_string(path).unwrap() == generated,
+			"test vectors are outdated, regenerate them with UPDATE_TEST_VECTORS=1",
+		);
+	}
+
+	// voters for differential tests, which tolerate equivocators of weight 9.
+	const MANY_VOTERS: [(&str, u64); 7] = [
+		("Alice", 4),
+		("Bob", 7),
+		("Charlie", 2),
+		("Dave", 5),
+		("Eve", 3),
+		("Ferdie", 1),
+		("Gavin", 6),
+	];
+
+	// the base of the round and all blocks above it.
+	const ROUND_BLOCKS: [(&str, u32); 11] = [
+		("C", 4), ("D", 5), ("E", 6), ("F", 7),
+		("EA", 7), ("EB", 8), ("EC", 9), ("ED", 10),
+		("FA", 8), ("FB", 9), ("FC", 10),
+	];
+
+	type TestMessage = SignedMessage<&'static str, u32, (usize, usize), &'static str>;
+	type TestRound = Round<&'static str, &'static str, u32, (usize, usize)>;
+
+	// prevotes and precommits of each of `MANY_VOTERS`, as indices into
+	// `ROUND_BLOCKS`. votes lean towards one block, so that rounds get
+	// somewhere.
+	fn random_votes() -> impl Strategy<Value = Vec<(Vec<usize>, Vec<usize>)>> {
+		(0..ROUND_BLOCKS.len()).prop_flat_map(|favourite| {
+			let block = prop_oneof![3 => Just(favourite), 1 => 0..ROUND_BLOCKS.len()];
+			let votes = prop::collection::vec(block, 0..=2);
+			prop::collection::vec((votes.clone(), votes), MANY_VOTERS.len())
+		})
+	}
+
+	// sign the votes, keeping only as many equivocators as are tolerated.
+	// a second vote of a kind is an equivocation, even on the same block, as
+	// it has another signature.
+	fn signed_messages(votes: &[(Vec<usize>, Vec<usize>)]) -> Vec<TestMessage> {
+		let voters: VoterSet<_> = MANY_VOTERS.iter().cloned().collect();
+		let mut tolerated = voters.total_weight() - voters.threshold();
+		let mut messages = Vec::new();
+
+		for (voter, (prevotes, precommits)) in votes.iter().enumerate() {
+			let (id, weight) = MANY_VOTERS[voter];
+			let equivocates = prevotes.len() > 1 || precommits.len() > 1;
+			let max_votes = if equivocates && weight <= tolerated {
+				tolerated -= weight;
+				2
+			} else {
+				1
+			};
+
+			for (n, &block) in prevotes.iter().take(max_votes).enumerate() {
+				let (hash, number) = ROUND_BLOCKS[block];
+				let message = Message::Prevote(Prevote::new(hash, number));
+				messages.push(SignedMessage { message, signature: (voter, n), id });
+			}
+
+			for (n, &block) in precommits.iter().take(max_votes).enumerate() {
+				let (hash, number) = ROUND_BLOCKS[block];
+				let message = Message::Precommit(Precommit::new(hash, number));
+				messages.push(SignedMessage { message, signature: (voter, 2 + n), id });
+			}
+		}
+
+		messages
+	}
+
+	// random messages in random order, including some duplicates.
+	fn random_messages() -> impl Strategy<Value = Vec<TestMessage>> {
+		(random_votes(), prop::collection::vec(any::<prop::sample::Index>(), 0..4))
+			.prop_flat_map(|(votes, duplicates)| {
+				let mut messages = signed_messages(&votes);
+				if !messages.is_empty() {
+					for duplicate in duplicates {
+						messages.push(messages[duplicate.index(messages.len())].clone());
+					}
+				}
+
+				Just(messages).prop_shuffle()
+			})
+	}
+
+	fn differential_round() -> (DummyChain, TestRound) {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let round = Round::new(RoundParams {
+			round_number: 1,
+			voters: MANY_VOTERS.iter().cloned().collect(),
+			base: ("C", 4),
+		});
+
+		(chain, round)
+	}
+
+	proptest! {
+		#[test]
+		fn state_matches_reference_after_every_vote(messages in random_messages()) {
+			let (chain, mut round) = differential_round();
+			let voters = round.voters().clone();
+
+			for (imported, message) in messages.iter().enumerate() {
+				let SignedMessage { message, signature, id } = message.clone();
+				match message {
+					Message::Prevote(prevote) => {
+						round.import_prevote(&chain, prevote, id, signature).unwrap();
+					}
+					Message::Precommit(precommit) => {
+						round.import_precommit(&chain, precommit, id, signature).unwrap();
+					}
+					Message::PrimaryPropose(_) => {}
+				}
+
+				prop_assert_eq!(
+					round.state(),
+					reference::round_state(&chain, &ROUND_BLOCKS, &voters, &messages[..=imported]),
+					"after importing {:?}", &messages[..=imported],
+				);
+			}
+
+			round.graph.check_invariants();
+		}
+
+		#[test]
+		fn state_after_batch_import_matches_reference(messages in random_messages()) {
+			let (chain, mut round) = differential_round();
+			let voters = round.voters().clone();
+
+			round.import_votes(&chain, messages.clone()).unwrap();
+
+			prop_assert_eq!(
+				round.state(),
+				reference::round_state(&chain, &ROUND_BLOCKS, &voters, &messages),
+			);
+		}
+	}
 }
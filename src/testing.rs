@@ -12,12 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Helpers for testing
-
+//! Helpers for testing.
+//!
+//! Available to downstream crates with the `test-helpers` feature. The
+//! `chain` module provides an in-memory `DummyChain`, and the `environment`
+//! module (which requires `std`) a simulated gossip network and a voter
+//! `Environment` running on top of it:
+//!
+//! ```ignore
+//! let (network, routing_task) = make_network();
+//! let env = Arc::new(Environment::new(network.clone(), Id(0)));
+//! env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]));
+//! let finalized = env.finalized_stream();
+//! ```
+//!
+//! The `simulation` module runs many voters over randomized, seeded
+//! schedules and checks safety and liveness:
+//!
+//! ```ignore
+//! for seed in 0..1000 {
+//!     Simulation::default().check(seed).unwrap();
+//! }
+//! ```
+
+/// An in-memory block tree.
 pub mod chain {
 	use crate::{Chain, Error};
-	use crate::collections::{HashMap, Vec};
+	use crate::collections::{HashMap, HashSet, Vec};
 
+	/// The hash of the genesis block, which has number 1.
 	pub const GENESIS_HASH: &str = "genesis";
 	const NULL_HASH: &str = "NULL";
 
@@ -26,13 +49,21 @@ pub mod chain {
 		parent: &'static str,
 	}
 
+	/// A block tree keyed by static string hashes, which tracks its leaves and
+	/// the last finalized block.
+	///
+	/// The best chain is the longest one, unless overridden by
+	/// `set_best_block` to simulate a re-org.
 	pub struct DummyChain {
 		inner: HashMap<&'static str, BlockRecord>,
 		leaves: Vec<&'static str>,
+		best: Option<&'static str>,
 		finalized: (&'static str, u32),
+		unavailable: HashSet<&'static str>,
 	}
 
 	impl DummyChain {
+		/// Create a chain containing only the genesis block.
 		pub fn new() -> Self {
 			let mut inner = HashMap::new();
 			inner.insert(GENESIS_HASH, BlockRecord { number: 1, parent: NULL_HASH });
@@ -40,13 +71,17 @@ pub mod chain {
 			DummyChain {
 				inner,
 				leaves: vec![GENESIS_HASH],
+				best: None,
 				finalized: (GENESIS_HASH, 1),
+				unavailable: HashSet::new(),
 			}
 		}
 
+		/// Import the given blocks as a chain descending from `parent`.
 		pub fn push_blocks(&mut self, mut parent: &'static str, blocks: &[&'static str]) {
 			if blocks.is_empty() { return }
 
+			let parent_of_first = parent;
 			let base_number = self.inner.get(parent).unwrap().number + 1;
 
 			if let Some(pos) = self.leaves.iter().position(|x| x == &parent) {
@@ -63,6 +98,10 @@ pub mod chain {
 			}
 
 			let new_leaf = blocks.last().unwrap();
+			if self.best == Some(parent_of_first) {
+				self.best = Some(new_leaf);
+			}
+
 			let new_leaf_number = self.inner.get(new_leaf).unwrap().number;
 
 			let insertion_index = self.leaves.binary_search_by(
@@ -72,21 +111,57 @@ pub mod chain {
 			self.leaves.insert(insertion_index, new_leaf);
 		}
 
+		/// Switch the best chain to the one ending in the given block, even if
+		/// it is shorter than other forks, as a fork-choice rule would on a
+		/// re-org. Blocks pushed on top of it extend the best chain; `None`
+		/// goes back to preferring the longest chain.
+		pub fn set_best_block(&mut self, best: Option<&'static str>) {
+			if let Some(best) = best {
+				assert!(self.inner.contains_key(best), "Unknown block {:?}", best);
+			}
+
+			self.best = best;
+		}
+
+		/// Whether the block has been imported.
+		pub fn contains(&self, hash: &'static str) -> bool {
+			self.inner.contains_key(hash)
+		}
+
+		/// The number of a known block.
 		pub fn number(&self, hash: &'static str) -> u32 {
 			self.inner.get(hash).unwrap().number
 		}
 
+		/// The last finalized block.
 		pub fn last_finalized(&self) -> (&'static str, u32) {
 			self.finalized.clone()
 		}
 
+		/// Set the last finalized block.
 		pub fn set_last_finalized(&mut self, last_finalized: (&'static str, u32)) {
 			self.finalized = last_finalized;
 		}
+
+		/// Fail ancestry lookups for the given block with
+		/// `Error::AncestryUnavailable`, as if its state had been pruned.
+		pub fn set_ancestry_unavailable(&mut self, hash: &'static str) {
+			self.unavailable.insert(hash);
+		}
+	}
+
+	impl Default for DummyChain {
+		fn default() -> Self {
+			Self::new()
+		}
 	}
 
 	impl Chain<&'static str, u32> for DummyChain {
 		fn ancestry(&self, base: &'static str, mut block: &'static str) -> Result<Vec<&'static str>, Error> {
+			if self.unavailable.contains(block) {
+				return Err(Error::AncestryUnavailable);
+			}
+
 			let mut ancestry = Vec::new();
 
 			loop {
@@ -107,6 +182,12 @@ pub mod chain {
 		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
 			let base_number = self.inner.get(base)?.number;
 
+			if let Some(best) = self.best {
+				if self.is_equal_or_descendent_of(base, best) {
+					return Some((best, self.inner.get(best).unwrap().number));
+				}
+			}
+
 			for leaf in &self.leaves {
 				// leaves are in descending order.
 				let leaf_number = self.inner.get(leaf).unwrap().number;
@@ -116,7 +197,7 @@ pub mod chain {
 					return Some((leaf, leaf_number))
 				}
 
-				if let Ok(_) = self.ancestry(base, leaf) {
+				if self.is_equal_or_descendent_of(base, leaf) {
 					return Some((leaf, leaf_number));
 				}
 			}
@@ -126,54 +207,627 @@ pub mod chain {
 	}
 }
 
+/// Naive models of the vote-graph and of round state, computed directly over
+/// the block tree, for checking `VoteGraph` and `Round` against. Votes are
+/// given on descendents of the base.
+pub mod reference {
+	use core::hash::Hash;
+
+	use crate::collections::Vec;
+	use crate::round::State;
+	use crate::voter_set::VoterSet;
+	use crate::{BlockNumberOps, Chain, Message, SignedMessage};
+
+	/// The votes on the given block and its descendents.
+	pub fn cumulative_vote<H, N, C>(chain: &C, block: &H, votes: &[(H, u64)]) -> u64 where
+		H: Clone + Eq,
+		N: Copy + BlockNumberOps,
+		C: Chain<H, N>,
+	{
+		votes.iter()
+			.filter(|(voted, _)| chain.is_equal_or_descendent_of(block.clone(), voted.clone()))
+			.map(|&(_, weight)| weight)
+			.sum()
+	}
+
+	/// The GHOST of the votes: starting from the base, move to a child whose
+	/// cumulative vote fulfills the condition until there is none. `None` if
+	/// the base doesn't fulfill it.
+	///
+	/// `blocks` must contain all blocks of the tree, and the condition must be
+	/// fulfilled by at most one child of any block, e.g. a strict majority.
+	pub fn ghost<H, N, C>(
+		chain: &C,
+		blocks: &[(H, N)],
+		base: (H, N),
+		votes: &[(H, u64)],
+		condition: impl Fn(u64) -> bool,
+	) -> Option<(H, N)> where
+		H: Clone + Eq,
+		N: Copy + BlockNumberOps,
+		C: Chain<H, N>,
+	{
+		if !condition(cumulative_vote(chain, &base.0, votes)) { return None }
+
+		let mut ghost = base;
+		while let Some(child) = blocks.iter().find(|(hash, number)| {
+			*number == ghost.1 + N::one() &&
+				chain.is_equal_or_descendent_of(ghost.0.clone(), hash.clone()) &&
+				condition(cumulative_vote(chain, hash, votes))
+		}) {
+			ghost = child.clone();
+		}
+
+		Some(ghost)
+	}
+
+	/// The descendents of a vote-node: the lowest vote-nodes above it in each
+	/// of its forks.
+	pub fn descendent_vote_nodes<H, N, C>(chain: &C, vote_nodes: &[H], node: &H) -> Vec<H> where
+		H: Clone + Eq,
+		N: Copy + BlockNumberOps,
+		C: Chain<H, N>,
+	{
+		let above = |lower: &H, higher: &H| {
+			lower != higher && chain.is_equal_or_descendent_of(lower.clone(), higher.clone())
+		};
+
+		vote_nodes.iter()
+			.filter(|&candidate| above(node, candidate) &&
+				!vote_nodes.iter().any(|between| above(node, between) && above(between, candidate))
+			)
+			.cloned()
+			.collect()
+	}
+
+	// the votes of a single kind from each voter in the set.
+	struct VoterVotes<'a, Id: Hash + Eq, H, S> {
+		voters: &'a VoterSet<Id>,
+		// for each voter in canonical order, its distinct votes and signatures.
+		votes: Vec<Vec<(&'a H, &'a S)>>,
+	}
+
+	impl<'a, Id: Hash + Eq, H: Clone + Eq, S: Eq> VoterVotes<'a, Id, H, S> {
+		fn new<N>(
+			voters: &'a VoterSet<Id>,
+			messages: &'a [SignedMessage<H, N, S, Id>],
+			target: impl Fn(&'a Message<H, N>) -> Option<&'a H>,
+		) -> Self {
+			let votes = voters.iter().map(|(id, _)| {
+				let mut distinct = Vec::new();
+				for message in messages.iter().filter(|message| message.id == *id) {
+					if let Some(hash) = target(&message.message) {
+						if !distinct.contains(&(hash, &message.signature)) {
+							distinct.push((hash, &message.signature));
+						}
+					}
+				}
+				distinct
+			}).collect();
+
+			VoterVotes { voters, votes }
+		}
+
+		fn weight_where(&self, counts: impl Fn(&[(&H, &S)]) -> bool) -> u64 {
+			self.voters.iter()
+				.zip(&self.votes)
+				.filter(|(_, votes)| counts(votes))
+				.map(|((_, weight), _)| weight)
+				.sum()
+		}
+
+		// the weight of all voters which voted.
+		fn participation(&self) -> u64 {
+			self.weight_where(|votes| !votes.is_empty())
+		}
+
+		fn equivocators(&self) -> u64 {
+			self.weight_where(|votes| votes.len() > 1)
+		}
+
+		// the weight of voters which voted for the block or its descendents,
+		// or equivocated.
+		fn weight_on<N: BlockNumberOps, C: Chain<H, N>>(&self, chain: &C, block: &H) -> u64 {
+			self.weight_where(|votes| match votes {
+				[(voted, _)] => chain.is_equal_or_descendent_of(block.clone(), (*voted).clone()),
+				_ => votes.len() > 1,
+			})
+		}
+	}
+
+	/// The state of a round after importing the given messages, computed by
+	/// set arithmetic over the voters as in the GRANDPA paper, without a
+	/// vote-graph.
+	///
+	/// A voter with more than one distinct vote or signature of a kind is an
+	/// equivocator, and counts as voting for every block. Messages from
+	/// unknown voters and primary proposals are ignored. As in `Round`, the
+	/// estimate is the prevote-GHOST itself until the precommits reach the
+	/// threshold, and the round can't be completable before that.
+	///
+	/// `blocks` must contain the base and all blocks above it, and the
+	/// equivocators must not have more weight than the voter set tolerates.
+	pub fn round_state<Id, H, N, S, C>(
+		chain: &C,
+		blocks: &[(H, N)],
+		voters: &VoterSet<Id>,
+		messages: &[SignedMessage<H, N, S, Id>],
+	) -> State<H, N> where
+		Id: Hash + Eq,
+		H: Clone + Eq,
+		N: Copy + BlockNumberOps,
+		S: Eq,
+		C: Chain<H, N>,
+	{
+		let threshold = voters.threshold();
+		let tolerated_equivocations = voters.total_weight() - threshold;
+
+		let prevotes = VoterVotes::new(voters, messages, |message| match message {
+			Message::Prevote(prevote) => Some(&prevote.target_hash),
+			_ => None,
+		});
+		let precommits = VoterVotes::new(voters, messages, |message| match message {
+			Message::Precommit(precommit) => Some(&precommit.target_hash),
+			_ => None,
+		});
+
+		// the highest of the given blocks fulfilling the condition.
+		let highest = |candidates: &mut dyn Iterator<Item = &(H, N)>, condition: &dyn Fn(&H) -> bool| {
+			candidates
+				.filter(|(hash, _)| condition(hash))
+				.fold(None, |highest: Option<&(H, N)>, block| match highest {
+					Some(highest) if highest.1 >= block.1 => Some(highest),
+					_ => Some(block),
+				})
+				.cloned()
+		};
+
+		let mut state = State {
+			prevote_ghost: None,
+			finalized: None,
+			estimate: None,
+			completable: false,
+		};
+
+		if prevotes.participation() < threshold { return state }
+
+		// with few enough equivocators, the blocks with supermajority prevotes
+		// form a chain, and the GHOST is its head.
+		let (ghost_hash, ghost_number) = match highest(
+			&mut blocks.iter(),
+			&|block| prevotes.weight_on(chain, block) >= threshold,
+		) {
+			Some(ghost) => ghost,
+			None => return state,
+		};
+		state.prevote_ghost = Some((ghost_hash.clone(), ghost_number));
+
+		let participation = precommits.participation();
+		if participation < threshold {
+			state.estimate = state.prevote_ghost.clone();
+			return state;
+		}
+
+		let ghost_chain = || blocks.iter()
+			.filter(|(hash, _)| chain.is_equal_or_descendent_of(hash.clone(), ghost_hash.clone()));
+
+		state.finalized = highest(
+			&mut ghost_chain(),
+			&|block| precommits.weight_on(chain, block) >= threshold,
+		);
+
+		// a block could still get supermajority precommits from the voters
+		// which haven't precommitted yet, and from new equivocations of those
+		// which have, up to the tolerated weight of equivocators.
+		let additional_equivocations = tolerated_equivocations.saturating_sub(precommits.equivocators());
+		let possible = |block: &H| {
+			let on_block = precommits.weight_on(chain, block);
+			let elsewhere = participation - on_block;
+
+			on_block + (voters.total_weight() - participation) +
+				core::cmp::min(elsewhere, additional_equivocations) >= threshold
+		};
+
+		state.estimate = highest(&mut ghost_chain(), &possible);
+		state.completable = match state.estimate {
+			Some((ref hash, _)) if *hash != ghost_hash => true,
+			Some(_) => !blocks.iter().any(|(hash, number)| {
+				*number == ghost_number + N::one() &&
+					chain.is_equal_or_descendent_of(ghost_hash.clone(), hash.clone()) &&
+					possible(hash)
+			}),
+			None => false,
+		};
+
+		state
+	}
+}
+
+/// A simulated network and voter environment.
 #[cfg(feature = "std")]
 pub mod environment {
 	use super::chain::*;
+	use crate::misbehavior::Misbehavior;
 	use crate::round::State as RoundState;
-	use crate::voter::{RoundData, CommunicationIn, CommunicationOut, Callback};
+	use crate::voter::{CompletionReport, RoundData, RoundSnapshot, RoundVotesUpdate, CommunicationIn, CommunicationOut, Callback, ErrorSeverity, FinalityViolation, HasVoted, Metrics, VoterSnapshot, VoteDecision, VotingRule};
 	use crate::{Chain, Commit, Error, Equivocation, Message, Prevote, Precommit, PrimaryPropose, SignedMessage, HistoricalVotes};
+	use crate::voter_set::VoterSet;
 	use futures::prelude::*;
-	use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+	use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+	use futures::task::{AtomicWaker, Context, Poll, Waker};
+	use futures_timer::Delay;
 	use parking_lot::Mutex;
-	use std::collections::HashMap;
+	use rand::{Rng, SeedableRng, rngs::StdRng};
+	use std::collections::{BTreeMap, HashMap};
+	use std::pin::Pin;
 	use std::sync::Arc;
-	use std::time::{Instant, Duration};
-	use tokio::timer::Delay;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::time::Duration;
 
+	/// A voter ID.
 	#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
 	pub struct Id(pub u32);
 
+	/// A signature, which is just the ID of the signer.
 	#[derive(Debug, Clone, PartialEq, Eq)]
 	pub struct Signature(pub u32);
 
+	/// Metrics recorded by a test environment.
+	#[derive(Debug, Default, Clone, PartialEq)]
+	pub struct RecordedMetrics {
+		pub imported: usize,
+		pub dropped: usize,
+		pub equivocations: usize,
+		pub commits_validated: usize,
+		pub commits_rejected: usize,
+		pub current_round: u64,
+		pub background_rounds: usize,
+		pub completed_rounds: usize,
+	}
+
+	/// An equivocation reported to a test environment, with its round.
+	pub type RecordedEquivocation<V> = (u64, Equivocation<Id, V, Signature>);
+
+	/// Equivocations reported to a test environment, with their rounds.
+	#[derive(Debug, Default, Clone, PartialEq)]
+	pub struct RecordedEquivocations {
+		pub prevotes: Vec<RecordedEquivocation<Prevote<&'static str, u32>>>,
+		pub precommits: Vec<RecordedEquivocation<Precommit<&'static str, u32>>>,
+	}
+
+	#[derive(Default)]
+	struct TestMetrics(Mutex<RecordedMetrics>);
+
+	impl Metrics for TestMetrics {
+		fn message_imported(&self) {
+			self.0.lock().imported += 1;
+		}
+
+		fn message_dropped(&self) {
+			self.0.lock().dropped += 1;
+		}
+
+		fn equivocation(&self) {
+			self.0.lock().equivocations += 1;
+		}
+
+		fn commit_validated(&self) {
+			self.0.lock().commits_validated += 1;
+		}
+
+		fn commit_rejected(&self) {
+			self.0.lock().commits_rejected += 1;
+		}
+
+		fn current_round(&self, round: u64) {
+			self.0.lock().current_round = round;
+		}
+
+		fn background_rounds(&self, rounds: usize) {
+			self.0.lock().background_rounds = rounds;
+		}
+
+		fn round_duration(&self, _duration: Duration) {
+			self.0.lock().completed_rounds += 1;
+		}
+	}
+
+	/// A voter environment backed by a `DummyChain` and connected to a test
+	/// `Network`.
 	pub struct Environment {
-		chain: Mutex<DummyChain>,
+		chain: Arc<Mutex<DummyChain>>,
 		local_id: Id,
 		network: Network,
 		listeners: Mutex<Vec<UnboundedSender<(&'static str, u32, Commit<&'static str, u32, Signature, Id>)>>>,
+		round_voters: Mutex<BTreeMap<u64, VoterSet<Id>>>,
+		persisted_votes: Mutex<HashMap<u64, PersistedVotes>>,
+		has_voted: Mutex<HashMap<u64, HasVoted<&'static str, u32>>>,
+		unexpected_own_votes: Mutex<Vec<(u64, Message<&'static str, u32>)>>,
+		last_finalized_round: Mutex<u64>,
+		voting_rule: Option<Box<dyn VotingRule<&'static str, u32>>>,
+		metrics: Option<TestMetrics>,
+		equivocations: Option<Mutex<RecordedEquivocations>>,
+		finality_violations: Mutex<Vec<FinalityViolation<&'static str, u32, Signature, Id>>>,
+		block_imported: Mutex<Option<UnboundedReceiver<(&'static str, u32)>>>,
+		round_activity: Mutex<Option<UnboundedReceiver<u64>>>,
+		transient_errors: Mutex<Vec<Error>>,
+		stall_timeout: Mutex<Option<Duration>>,
+		stalled_rounds: Mutex<Vec<StalledRound>>,
+		estimate_updates: Mutex<Vec<(u64, (&'static str, u32))>>,
+		gossip_duration_hints: Mutex<Vec<(u64, Option<Duration>)>>,
+		rebroadcast_interval: Mutex<Option<Duration>>,
+		completion_reports: Mutex<Vec<CompletionReport<&'static str, u32>>>,
+		finalization_delay: Mutex<Option<Duration>>,
+		chain_lookup_delay: Mutex<Option<Duration>>,
+		ancestry_lookup_delays: Mutex<HashMap<&'static str, Duration>>,
+		ancestry_lookups: Mutex<Vec<&'static str>>,
+		durably_finalized: Arc<Mutex<(&'static str, u32)>>,
+		sign_locally: Mutex<bool>,
+		broadcast_veto: Mutex<Option<BroadcastVeto>>,
+		max_vote_distance: Mutex<Option<u32>>,
+		rejected_votes: Mutex<Vec<RejectedVote>>,
 	}
 
+	// an incoming vote rejected in a round, with the misbehavior of its sender.
+	type RejectedVote = (u64, SignedMessage<&'static str, u32, Signature, Id>, Misbehavior);
+
+	// decides whether to veto broadcasting one of our messages in a round.
+	type BroadcastVeto = fn(u64, &Message<&'static str, u32>) -> bool;
+
+	// a round noted as stalled, with its state and missing weight.
+	type StalledRound = (u64, RoundState<&'static str, u32>, u64);
+
+	// the votes persisted for a round, to restore them on restart.
+	type PersistedVotes = RoundSnapshot<&'static str, u32, Signature, Id>;
+
 	impl Environment {
+		/// Create an environment for the voter with the given ID.
 		pub fn new(network: Network, local_id: Id) -> Self {
 			Environment {
-				chain: Mutex::new(DummyChain::new()),
+				chain: Arc::new(Mutex::new(DummyChain::new())),
 				local_id,
 				network,
 				listeners: Mutex::new(Vec::new()),
+				round_voters: Mutex::new(BTreeMap::new()),
+				persisted_votes: Mutex::new(HashMap::new()),
+				has_voted: Mutex::new(HashMap::new()),
+				unexpected_own_votes: Mutex::new(Vec::new()),
+				last_finalized_round: Mutex::new(0),
+				voting_rule: None,
+				metrics: None,
+				equivocations: None,
+				finality_violations: Mutex::new(Vec::new()),
+				block_imported: Mutex::new(None),
+				round_activity: Mutex::new(None),
+				transient_errors: Mutex::new(Vec::new()),
+				stall_timeout: Mutex::new(None),
+				stalled_rounds: Mutex::new(Vec::new()),
+				estimate_updates: Mutex::new(Vec::new()),
+				gossip_duration_hints: Mutex::new(Vec::new()),
+				rebroadcast_interval: Mutex::new(None),
+				completion_reports: Mutex::new(Vec::new()),
+				finalization_delay: Mutex::new(None),
+				chain_lookup_delay: Mutex::new(None),
+				ancestry_lookup_delays: Mutex::new(HashMap::new()),
+				ancestry_lookups: Mutex::new(Vec::new()),
+				durably_finalized: Arc::new(Mutex::new((GENESIS_HASH, 1))),
+				sign_locally: Mutex::new(false),
+				broadcast_veto: Mutex::new(None),
+				max_vote_distance: Mutex::new(None),
+				rejected_votes: Mutex::new(Vec::new()),
 			}
 		}
 
+		/// Treat the given error as transient rather than fatal.
+		pub fn set_transient(&self, error: Error) {
+			self.transient_errors.lock().push(error);
+		}
+
+		/// Notify the voter of imported blocks through the returned sender.
+		/// Must be called before the voter is created.
+		pub fn notify_block_imports(&self) -> UnboundedSender<(&'static str, u32)> {
+			let (tx, rx) = mpsc::unbounded();
+			*self.block_imported.lock() = Some(rx);
+			tx
+		}
+
+		/// Notify the voter of activity in rounds through the returned sender.
+		/// Must be called before the voter is created.
+		pub fn notify_round_activity(&self) -> UnboundedSender<u64> {
+			let (tx, rx) = mpsc::unbounded();
+			*self.round_activity.lock() = Some(rx);
+			tx
+		}
+
+		/// Consider rounds stalled if they aren't completable after the given
+		/// timeout.
+		pub fn set_stall_timeout(&self, timeout: Duration) {
+			*self.stall_timeout.lock() = Some(timeout);
+		}
+
+		/// The rounds noted as stalled, with their state and missing weight.
+		pub fn stalled_rounds(&self) -> Vec<(u64, RoundState<&'static str, u32>, u64)> {
+			self.stalled_rounds.lock().clone()
+		}
+
+		/// The round estimates we were told about, in order.
+		pub fn estimate_updates(&self) -> Vec<(u64, (&'static str, u32))> {
+			self.estimate_updates.lock().clone()
+		}
+
+		/// Send our votes again after the given interval while rounds aren't
+		/// completable.
+		pub fn set_rebroadcast_interval(&self, interval: Duration) {
+			*self.rebroadcast_interval.lock() = Some(interval);
+		}
+
+		/// Delay the futures returned by `finalize_block`, as if writing
+		/// finalized blocks to disk took that long.
+		pub fn set_finalization_delay(&self, delay: Duration) {
+			*self.finalization_delay.lock() = Some(delay);
+		}
+
+		/// Delay the futures returned by `best_chain_lookup` and
+		/// `ancestry_lookup`, as if the chain were read from a slow database.
+		/// Lookups see the chain as it is once they resolve.
+		pub fn set_chain_lookup_delay(&self, delay: Duration) {
+			*self.chain_lookup_delay.lock() = Some(delay);
+		}
+
+		/// Delay `ancestry_lookup`s of the given block, overriding the delay
+		/// set with `set_chain_lookup_delay`.
+		pub fn set_ancestry_lookup_delay(&self, block: &'static str, delay: Duration) {
+			self.ancestry_lookup_delays.lock().insert(block, delay);
+		}
+
+		/// The blocks `ancestry_lookup` was called for, in order.
+		pub fn ancestry_lookups(&self) -> Vec<&'static str> {
+			self.ancestry_lookups.lock().clone()
+		}
+
+		/// Sign our votes with `sign_message` rather than in the outgoing
+		/// sink, and drop them when the network returns them to us. Applies
+		/// to rounds started afterwards.
+		pub fn set_sign_locally(&self) {
+			*self.sign_locally.lock() = true;
+		}
+
+		/// Veto broadcasting our messages for which the given function
+		/// returns true.
+		pub fn set_broadcast_veto(&self, veto: BroadcastVeto) {
+			*self.broadcast_veto.lock() = Some(veto);
+		}
+
+		/// Reject votes for blocks more than the given distance ahead of their
+		/// round's base. Applies to rounds started afterwards.
+		pub fn set_max_vote_distance(&self, max_distance: u32) {
+			*self.max_vote_distance.lock() = Some(max_distance);
+		}
+
+		/// The incoming votes rejected by the voter, with their round and the
+		/// misbehavior of their sender.
+		pub fn rejected_votes(&self) -> Vec<RejectedVote> {
+			self.rejected_votes.lock().clone()
+		}
+
+		/// The last finalized block whose finalization future has resolved.
+		pub fn durably_finalized(&self) -> (&'static str, u32) {
+			*self.durably_finalized.lock()
+		}
+
+		/// The gossip duration hints given for each round, in order.
+		pub fn gossip_duration_hints(&self) -> Vec<(u64, Option<Duration>)> {
+			self.gossip_duration_hints.lock().clone()
+		}
+
+		/// The reports of all completed rounds, in order.
+		pub fn completion_reports(&self) -> Vec<CompletionReport<&'static str, u32>> {
+			self.completion_reports.lock().clone()
+		}
+
+		/// Restrict prevotes with the given voting rule.
+		pub fn with_voting_rule<R: VotingRule<&'static str, u32> + 'static>(mut self, rule: R) -> Self {
+			self.voting_rule = Some(Box::new(rule));
+			self
+		}
+
+		/// Record metrics, which can be read with `recorded_metrics`.
+		pub fn with_metrics(mut self) -> Self {
+			self.metrics = Some(TestMetrics::default());
+			self
+		}
+
+		/// Record equivocations, which can be read with `recorded_equivocations`,
+		/// instead of panicking on them.
+		pub fn with_equivocation_reports(mut self) -> Self {
+			self.equivocations = Some(Mutex::default());
+			self
+		}
+
+		/// The equivocations recorded so far. Empty unless
+		/// `with_equivocation_reports` was used.
+		pub fn recorded_equivocations(&self) -> RecordedEquivocations {
+			self.equivocations.as_ref().map(|equivocations| equivocations.lock().clone()).unwrap_or_default()
+		}
+
+		/// The metrics recorded so far. Empty unless `with_metrics` was used.
+		pub fn recorded_metrics(&self) -> RecordedMetrics {
+			self.metrics.as_ref().map(|metrics| metrics.0.lock().clone()).unwrap_or_default()
+		}
+
+		/// Access the underlying chain, e.g. to push blocks.
 		pub fn with_chain<F, U>(&self, f: F) -> U where F: FnOnce(&mut DummyChain) -> U {
 			let mut chain = self.chain.lock();
 			f(&mut *chain)
 		}
 
+		/// Use the given voter weights starting at the given round.
+		pub fn set_round_voters(&self, round: u64, voters: VoterSet<Id>) {
+			self.round_voters.lock().insert(round, voters);
+		}
+
+		/// The votes persisted for a round, if any.
+		pub fn persisted_votes(&self, round: u64) -> Option<RoundSnapshot<&'static str, u32, Signature, Id>> {
+			self.persisted_votes.lock().get(&round).cloned()
+		}
+
+		/// Store the votes of a round as if they were persisted earlier.
+		pub fn set_persisted_votes(&self, snapshot: RoundSnapshot<&'static str, u32, Signature, Id>) {
+			self.persisted_votes.lock().insert(snapshot.round_number, snapshot);
+		}
+
+		/// A snapshot to resume from after a crash, built from persisted state
+		/// as a client would: the latest round with persisted votes, the round
+		/// before it if persisted, and the last finalized block. `None` if no
+		/// votes were persisted.
+		pub fn crash_snapshot(&self) -> Option<VoterSnapshot<&'static str, u32, Signature, Id>> {
+			let persisted = self.persisted_votes.lock();
+			let best_round = persisted.keys().max().and_then(|round| persisted.get(round)).cloned()?;
+			let background_rounds = persisted.get(&(best_round.round_number - 1)).cloned().into_iter().collect();
+			let last_finalized = self.chain.lock().last_finalized();
+
+			Some(VoterSnapshot {
+				best_round,
+				background_rounds,
+				last_round_state: RoundState::genesis(last_finalized),
+				last_finalized,
+			})
+		}
+
+		/// Report that we have already voted in the given round.
+		pub fn set_has_voted(&self, round: u64, has_voted: HasVoted<&'static str, u32>) {
+			self.has_voted.lock().insert(round, has_voted);
+		}
+
+		/// Our own votes which were seen without having been cast.
+		pub fn unexpected_own_votes(&self) -> Vec<(u64, Message<&'static str, u32>)> {
+			self.unexpected_own_votes.lock().clone()
+		}
+
+		/// The round in which the last block was finalized.
+		pub fn last_finalized_round(&self) -> u64 {
+			*self.last_finalized_round.lock()
+		}
+
+		/// The finality violations reported so far.
+		pub fn finality_violations(&self) -> Vec<FinalityViolation<&'static str, u32, Signature, Id>> {
+			self.finality_violations.lock().clone()
+		}
+
 		/// Stream of finalized blocks.
 		pub fn finalized_stream(&self) -> UnboundedReceiver<(&'static str, u32, Commit<&'static str, u32, Signature, Id>)> {
 			let (tx, rx) = mpsc::unbounded();
 			self.listeners.lock().push(tx);
 			rx
 		}
+
+		// a timer for the given delay, or a ready future without one.
+		fn delay(&self, delay: Option<Duration>) -> Box<dyn Future<Output=Result<(),Error>> + Send + Unpin + 'static> {
+			match delay {
+				Some(delay) => self.network.timer(delay),
+				None => Box::new(future::ready(Ok(()))),
+			}
+		}
 	}
 
 	impl Chain<&'static str, u32> for Environment {
@@ -187,39 +841,50 @@ pub mod environment {
 	}
 
 	impl crate::voter::Environment<&'static str, u32> for Environment {
-		type Timer = Box<dyn Future<Item=(),Error=Error> + Send + 'static>;
+		type Timer = Box<dyn Future<Output=Result<(),Error>> + Send + Unpin + 'static>;
+		type Finalize = Box<dyn Future<Output=Result<(),Error>> + Send + Unpin + 'static>;
+		type BestChain = Box<dyn Future<Output=Result<Option<(&'static str, u32)>,Error>> + Send + Unpin + 'static>;
+		type Ancestry = Box<dyn Future<Output=Result<Option<Vec<&'static str>>,Error>> + Send + Unpin + 'static>;
 		type Id = Id;
 		type Signature = Signature;
-		type In = Box<dyn Stream<Item=SignedMessage<&'static str, u32, Signature, Id>,Error=Error> + Send + 'static>;
-		type Out = Box<dyn Sink<SinkItem=Message<&'static str, u32>,SinkError=Error> + Send + 'static>;
+		type In = Box<dyn Stream<Item=Result<SignedMessage<&'static str, u32, Signature, Id>,Error>> + Send + Unpin + 'static>;
+		type Out = Box<dyn Sink<Message<&'static str, u32>,Error=Error> + Send + Unpin + 'static>;
 		type Error = Error;
 
-		fn round_data(&self, round: u64) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
+		fn round_data(
+			&self,
+			round: u64,
+			gossip_duration_hint: Option<Duration>,
+		) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
 			const GOSSIP_DURATION: Duration = Duration::from_millis(500);
 
-			let now = Instant::now();
+			self.gossip_duration_hints.lock().push((round, gossip_duration_hint));
+			let gossip_duration = gossip_duration_hint
+				.map_or(GOSSIP_DURATION, |hint| std::cmp::min(hint, GOSSIP_DURATION));
+
+			let rebroadcast_interval = *self.rebroadcast_interval.lock();
 			let (incoming, outgoing) = self.network.make_round_comms(round, self.local_id);
+			let (local_id, sign_locally) = (self.local_id, *self.sign_locally.lock());
+			let incoming = incoming.try_filter(move |signed| future::ready(!sign_locally || signed.id != local_id));
+			let network = self.network.clone();
 			RoundData {
 				voter_id: Some(self.local_id),
-				prevote_timer: Box::new(Delay::new(now + GOSSIP_DURATION)
-										.map_err(|_| panic!("Timer failed"))),
-				precommit_timer: Box::new(Delay::new(now + GOSSIP_DURATION + GOSSIP_DURATION)
-										  .map_err(|_| panic!("Timer failed"))),
+				prevote_timer: self.network.timer(gossip_duration),
+				precommit_timer: self.network.timer(gossip_duration + gossip_duration),
+				rebroadcast_timer: rebroadcast_interval.map(|interval| Box::new(move || -> Self::Timer {
+					network.timer(interval)
+				}) as Box<_>),
 				incoming: Box::new(incoming),
 				outgoing: Box::new(outgoing),
 			}
 		}
 
 		fn round_commit_timer(&self) -> Self::Timer {
-			use rand::Rng;
-
 			const COMMIT_DELAY_MILLIS: u64 = 100;
 
-			let delay = Duration::from_millis(
-				rand::thread_rng().gen_range(0, COMMIT_DELAY_MILLIS));
+			let delay = Duration::from_millis(self.network.gen_range(COMMIT_DELAY_MILLIS));
 
-			let now = Instant::now();
-			Box::new(Delay::new(now + delay).map_err(|_| panic!("Timer failed")))
+			self.network.timer(delay)
 		}
 
 		fn completed(
@@ -232,7 +897,11 @@ pub mod environment {
 			Ok(())
 		}
 
-		fn finalize_block(&self, hash: &'static str, number: u32, _round: u64, commit: Commit<&'static str, u32, Signature, Id>) -> Result<(), Error> {
+		fn completed_with_report(&self, report: CompletionReport<&'static str, u32>) {
+			self.completion_reports.lock().push(report);
+		}
+
+		fn finalize_block(&self, hash: &'static str, number: u32, round: u64, commit: Commit<&'static str, u32, Signature, Id>) -> Self::Finalize {
 			let mut chain = self.chain.lock();
 
 			let last_finalized = chain.last_finalized();
@@ -241,43 +910,317 @@ pub mod environment {
 			}
 
 			assert!(
-				chain.ancestry(last_finalized.0, hash).is_ok(),
+				chain.is_equal_or_descendent_of(last_finalized.0, hash),
 				"Safety violation: reverting finalized block.",
 			);
 
 			chain.set_last_finalized((hash, number));
+			*self.last_finalized_round.lock() = round;
 			self.listeners.lock().retain(|s| s.unbounded_send((hash, number as _, commit.clone())).is_ok());
 
-			Ok(())
+			let durably_finalized = self.durably_finalized.clone();
+			Box::new(self.delay(*self.finalization_delay.lock())
+				.map_ok(move |()| *durably_finalized.lock() = (hash, number)))
+		}
+
+		fn best_chain_lookup(&self, base: &'static str) -> Self::BestChain {
+			let chain = self.chain.clone();
+			Box::new(self.delay(*self.chain_lookup_delay.lock())
+				.map_ok(move |()| chain.lock().best_chain_containing(base)))
+		}
+
+		fn ancestry_lookup(&self, base: &'static str, block: &'static str) -> Self::Ancestry {
+			self.ancestry_lookups.lock().push(block);
+			let delay = self.ancestry_lookup_delays.lock().get(block).cloned()
+				.or(*self.chain_lookup_delay.lock());
+
+			let chain = self.chain.clone();
+			Box::new(self.delay(delay)
+				.map_ok(move |()| chain.lock().ancestry(base, block).ok()))
 		}
 
-		fn proposed(&self, _round: u64, _propose: PrimaryPropose<&'static str, u32>) -> Result<(), Self::Error> {
+		fn proposed(&self, round: u64, propose: PrimaryPropose<&'static str, u32>) -> Result<(), Self::Error> {
+			self.has_voted.lock().insert(round, HasVoted::Proposed(propose));
 			Ok(())
 		}
 
-		fn prevoted(&self, _round: u64, _prevote: Prevote<&'static str, u32>) -> Result<(), Self::Error> {
+		fn prevoted(&self, round: u64, prevote: Prevote<&'static str, u32>) -> Result<(), Self::Error> {
+			let mut has_voted = self.has_voted.lock();
+			let propose = has_voted.get(&round).and_then(|v| v.propose().cloned());
+			has_voted.insert(round, HasVoted::Prevoted(propose, prevote));
 			Ok(())
 		}
 
-		fn precommitted(&self, _round: u64, _precommit: Precommit<&'static str, u32>) -> Result<(), Self::Error> {
+		fn precommitted(&self, round: u64, precommit: Precommit<&'static str, u32>) -> Result<(), Self::Error> {
+			let mut has_voted = self.has_voted.lock();
+			let (propose, prevote) = match has_voted.get(&round) {
+				Some(voted) => (voted.propose().cloned(), voted.prevote().cloned()),
+				None => (None, None),
+			};
+			has_voted.insert(round, HasVoted::Precommitted(propose, prevote, precommit));
 			Ok(())
 		}
 
 		fn prevote_equivocation(&self, round: u64, equivocation: Equivocation<Id, Prevote<&'static str, u32>, Signature>) {
-			panic!("Encountered equivocation in round {}: {:?}", round, equivocation);
+			match self.equivocations {
+				Some(ref equivocations) => equivocations.lock().prevotes.push((round, equivocation)),
+				None => panic!("Encountered equivocation in round {}: {:?}", round, equivocation),
+			}
 		}
 
 		fn precommit_equivocation(&self, round: u64, equivocation: Equivocation<Id, Precommit<&'static str, u32>, Signature>) {
-			panic!("Encountered equivocation in round {}: {:?}", round, equivocation);
+			match self.equivocations {
+				Some(ref equivocations) => equivocations.lock().precommits.push((round, equivocation)),
+				None => panic!("Encountered equivocation in round {}: {:?}", round, equivocation),
+			}
+		}
+
+		fn round_voters(&self, round: u64) -> Option<VoterSet<Id>> {
+			self.round_voters.lock().range(..=round).next_back().map(|(_, voters)| voters.clone())
+		}
+
+		fn unexpected_own_vote(&self, round: u64, message: Message<&'static str, u32>) {
+			self.unexpected_own_votes.lock().push((round, message));
+		}
+
+		fn sign_message(&self, _round: u64, id: &Id, _message: &Message<&'static str, u32>) -> Option<Signature> {
+			if *self.sign_locally.lock() { Some(Signature(id.0)) } else { None }
+		}
+
+		fn max_vote_distance(&self) -> Option<u32> {
+			*self.max_vote_distance.lock()
+		}
+
+		fn vote_rejected(
+			&self,
+			round: u64,
+			vote: &SignedMessage<&'static str, u32, Signature, Id>,
+			misbehavior: Misbehavior,
+		) {
+			self.rejected_votes.lock().push((round, vote.clone(), misbehavior));
+		}
+
+		fn pre_broadcast(&self, round: u64, message: &Message<&'static str, u32>) -> VoteDecision {
+			match *self.broadcast_veto.lock() {
+				Some(veto) if veto(round, message) => VoteDecision::Veto,
+				_ => VoteDecision::Broadcast,
+			}
+		}
+
+		fn has_voted(&self, round: u64) -> HasVoted<&'static str, u32> {
+			self.has_voted.lock().get(&round).cloned().unwrap_or(HasVoted::No)
+		}
+
+		fn persist_round_votes(
+			&self,
+			update: RoundVotesUpdate<&'static str, u32, Signature, Id>,
+		) -> Result<(), Error> {
+			let mut persisted = self.persisted_votes.lock();
+			let snapshot = persisted.entry(update.round_number)
+				.or_insert_with(|| RoundSnapshot::new(update.round_number, update.base));
+
+			// every vote is handed over once.
+			assert_eq!(update.first_vote, snapshot.votes.seen().len(), "votes are persisted in order");
+			snapshot.apply(&update);
+			Ok(())
+		}
+
+		fn voting_rule(&self) -> Option<&dyn VotingRule<&'static str, u32>> {
+			self.voting_rule.as_deref()
+		}
+
+		fn metrics(&self) -> Option<&dyn Metrics> {
+			self.metrics.as_ref().map(|metrics| metrics as &dyn Metrics)
+		}
+
+		fn finality_violation(&self, violation: FinalityViolation<&'static str, u32, Signature, Id>) {
+			self.finality_violations.lock().push(violation);
+		}
+
+		fn round_stall_timer(&self, _round: u64) -> Option<Self::Timer> {
+			let timeout = (*self.stall_timeout.lock())?;
+			Some(self.network.timer(timeout))
+		}
+
+		fn round_stalled(&self, round: u64, state: RoundState<&'static str, u32>, missing_weight: u64) {
+			self.stalled_rounds.lock().push((round, state, missing_weight));
+		}
+
+		fn round_estimate_updated(&self, round: u64, estimate: (&'static str, u32)) {
+			self.estimate_updates.lock().push((round, estimate));
+		}
+
+		fn block_imported(&self) -> Option<UnboundedReceiver<(&'static str, u32)>> {
+			self.block_imported.lock().take()
+		}
+
+		fn is_block_imported(&self, block: &'static str) -> bool {
+			self.chain.lock().contains(block)
+		}
+
+		fn round_activity(&self) -> Option<UnboundedReceiver<u64>> {
+			self.round_activity.lock().take()
+		}
+
+		fn error_severity(&self, error: &Error) -> ErrorSeverity {
+			if self.transient_errors.lock().contains(error) {
+				ErrorSeverity::Transient
+			} else {
+				ErrorSeverity::Fatal
+			}
+		}
+	}
+
+	/// Virtual time, which only moves forward when advanced explicitly, so that
+	/// runs on a single-threaded executor are deterministic.
+	#[derive(Clone, Default)]
+	pub struct VirtualClock(Arc<Mutex<ClockInner>>);
+
+	#[derive(Default)]
+	struct ClockInner {
+		now: Duration,
+		timers: Vec<(Duration, Waker)>,
+	}
+
+	impl VirtualClock {
+		/// The time elapsed since the clock was created.
+		pub fn now(&self) -> Duration {
+			self.0.lock().now
+		}
+
+		/// A future which resolves once the clock has advanced by `duration`.
+		pub fn delay(&self, duration: Duration) -> VirtualDelay {
+			self.delay_until(self.now() + duration)
+		}
+
+		/// A future which resolves once the clock reaches `deadline`.
+		pub fn delay_until(&self, deadline: Duration) -> VirtualDelay {
+			VirtualDelay { clock: self.clone(), deadline }
+		}
+
+		/// Advance to the earliest pending deadline, waking all timers due by
+		/// then. Returns `false` if there are no pending timers.
+		pub fn advance(&self) -> bool {
+			let mut inner = self.0.lock();
+			let next = match inner.timers.iter().map(|&(deadline, _)| deadline).min() {
+				Some(next) => next,
+				None => return false,
+			};
+
+			inner.now = std::cmp::max(inner.now, next);
+			let now = inner.now;
+			inner.timers.retain(|(deadline, waker)| if *deadline <= now {
+				waker.wake_by_ref();
+				false
+			} else {
+				true
+			});
+
+			true
+		}
+
+		fn wake_at(&self, deadline: Duration, waker: &Waker) {
+			self.0.lock().timers.push((deadline, waker.clone()));
+		}
+	}
+
+	/// A timer in virtual time. Create this with `VirtualClock::delay`.
+	pub struct VirtualDelay {
+		clock: VirtualClock,
+		deadline: Duration,
+	}
+
+	impl Future for VirtualDelay {
+		type Output = ();
+
+		fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+			if self.clock.now() >= self.deadline {
+				return Poll::Ready(());
+			}
+
+			self.clock.wake_at(self.deadline, cx.waker());
+			Poll::Pending
+		}
+	}
+
+	/// The delay of messages sent over a link, uniformly distributed over
+	/// `delay ± jitter`.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct LinkLatency {
+		pub delay: Duration,
+		pub jitter: Duration,
+	}
+
+	// latencies and randomness for a network running in virtual time.
+	struct Timing {
+		clock: VirtualClock,
+		latency: LinkLatency,
+		links: Mutex<HashMap<(Id, Id), LinkLatency>>,
+		drop_probability: Mutex<f64>,
+		rng: Mutex<StdRng>,
+	}
+
+	impl Timing {
+		// whether to drop a message sent between two different nodes.
+		fn dropped(&self, from: Option<Id>, to: Option<Id>) -> bool {
+			let drop_probability = *self.drop_probability.lock();
+			from.is_some() && from != to && drop_probability > 0.0
+				&& self.rng.lock().gen_bool(drop_probability)
+		}
+
+		// sample the delay of a message over the link between two nodes.
+		// global messages have no known origin and use the default latency.
+		fn sample(&self, from: Option<Id>, to: Option<Id>) -> Duration {
+			let latency = match (from, to) {
+				(Some(from), Some(to)) => self.links.lock().get(&(from, to)).cloned(),
+				_ => None,
+			}.unwrap_or(self.latency);
+
+			let jitter = latency.jitter.as_millis() as u64;
+			let offset = self.rng.lock().gen_range(0, 2 * jitter + 1);
+			(latency.delay + Duration::from_millis(offset))
+				.checked_sub(latency.jitter)
+				.unwrap_or_else(|| Duration::from_millis(0))
+		}
+	}
+
+	// the groups of a partitioned network. messages between nodes in
+	// different groups, or from nodes in no group, are held back. messages
+	// of unknown origin are never held back.
+	#[derive(Default, Clone)]
+	struct Partition(Option<HashMap<Id, usize>>);
+
+	impl Partition {
+		fn separates(&self, from: Option<Id>, to: Option<Id>) -> bool {
+			match (&self.0, from, to) {
+				(Some(groups), Some(from), Some(to)) if from != to =>
+					groups.get(&from).is_none_or(|group| groups.get(&to) != Some(group)),
+				_ => false,
+			}
 		}
 	}
 
-	// p2p network data for a round.
+	// a node connected to a broadcast network. observers receive messages
+	// without delay, regardless of partitions.
+	struct Peer<M> {
+		id: Option<Id>,
+		observer: bool,
+		sender: UnboundedSender<M>,
+	}
+
+	// p2p network data for a round. messages are tagged with the node which
+	// sent them, if known.
 	struct BroadcastNetwork<M> {
-		receiver: UnboundedReceiver<M>,
-		raw_sender: UnboundedSender<M>,
-		senders: Vec<UnboundedSender<M>>,
-		history: Vec<M>,
+		receiver: UnboundedReceiver<(Option<Id>, M)>,
+		raw_sender: UnboundedSender<(Option<Id>, M)>,
+		peers: Vec<Peer<M>>,
+		history: Vec<(Option<Id>, M)>,
+		// messages in flight: delivery time, index of the peer and the message.
+		in_flight: Vec<(Duration, usize, M)>,
+		// messages held back by a partition: origin, index of the peer and the message.
+		held: Vec<(Option<Id>, usize, M)>,
+		// whether to send the history to all peers again on the next routing.
+		regossip: bool,
 	}
 
 	impl<M: Clone> BroadcastNetwork<M> {
@@ -286,50 +1229,117 @@ pub mod environment {
 			BroadcastNetwork {
 				receiver: rx,
 				raw_sender: tx,
-				senders: Vec::new(),
+				peers: Vec::new(),
 				history: Vec::new(),
+				in_flight: Vec::new(),
+				held: Vec::new(),
+				regossip: false,
 			}
 		}
 
-		pub fn send_message(&self, message: M) {
-			let _ = self.raw_sender.unbounded_send(message);
+		fn send_message(&self, message: M) {
+			let _ = self.raw_sender.unbounded_send((None, message));
+		}
+
+		// receive all messages, starting with the history.
+		fn connect(&mut self, id: Option<Id>, observer: bool, partition: &Partition) -> UnboundedReceiver<M> {
+			let (tx, rx) = mpsc::unbounded();
+			let index = self.peers.len();
+
+			for (from, prior_message) in self.history.iter().cloned() {
+				if !observer && partition.separates(from, id) {
+					self.held.push((from, index, prior_message));
+				} else {
+					let _ = tx.unbounded_send(prior_message);
+				}
+			}
+
+			self.peers.push(Peer { id, observer, sender: tx });
+			rx
 		}
 
 		// add a node to the network for a round.
-		fn add_node<N, F: Fn(N) -> M>(&mut self, f: F) -> (
-			impl Stream<Item=M,Error=Error>,
-			impl Sink<SinkItem=N,SinkError=Error>
+		fn add_node<N, F: Fn(N) -> M>(&mut self, id: Option<Id>, partition: &Partition, f: F) -> (
+			impl Stream<Item=Result<M,Error>> + Unpin,
+			impl Sink<N,Error=Error> + Unpin
 		) {
-			let (tx, rx) = mpsc::unbounded();
 			let messages_out = self.raw_sender.clone()
-				.sink_map_err(|e| panic!("Error sending messages: {:?}", e))
-				.with(move |message| Ok(f(message)));
+				.sink_map_err(|e| -> Error { panic!("Error sending messages: {:?}", e) })
+				.with(move |message| future::ready(Ok((id, f(message)))));
 
-			// get history to the node.
-			for prior_message in self.history.iter().cloned() {
-				let _ = tx.unbounded_send(prior_message);
-			}
+			(self.connect(id, false, partition).map(Ok), messages_out)
+		}
 
-			self.senders.push(tx);
-			let rx = rx.map_err(|e| panic!("Error receiving messages: {:?}", e));
+		// send a message to a peer, unless held back by a partition, delayed
+		// by the latency of the link.
+		fn send(
+			&mut self,
+			from: Option<Id>,
+			index: usize,
+			message: M,
+			timing: Option<&Timing>,
+			partition: &Partition,
+		) {
+			let peer = &self.peers[index];
+			if peer.observer {
+				let _ = peer.sender.unbounded_send(message);
+			} else if partition.separates(from, peer.id) {
+				self.held.push((from, index, message));
+			} else if let Some(timing) = timing {
+				if timing.dropped(from, peer.id) {
+					return;
+				}
 
-			(rx, messages_out)
+				let at = timing.clock.now() + timing.sample(from, peer.id);
+				self.in_flight.push((at, index, message));
+			} else {
+				let _ = peer.sender.unbounded_send(message);
+			}
 		}
 
 		// do routing work
-		fn route(&mut self) -> Poll<(), ()> {
+		fn route(&mut self, cx: &mut Context, timing: Option<&Timing>, partition: &Partition) -> Poll<()> {
+			// release messages which are no longer held back.
+			for (from, index, message) in std::mem::take(&mut self.held) {
+				self.send(from, index, message, timing, partition);
+			}
+
+			if std::mem::replace(&mut self.regossip, false) {
+				for (from, message) in self.history.clone() {
+					for index in 0..self.peers.len() {
+						self.send(from, index, message.clone(), timing, partition);
+					}
+				}
+			}
+
 			loop {
-				match self.receiver.poll().map_err(|e| panic!("Error routing messages: {:?}", e))? {
-					Async::NotReady => return Ok(Async::NotReady),
-					Async::Ready(None) => return Ok(Async::Ready(())),
-					Async::Ready(Some(item)) => {
-						self.history.push(item.clone());
-						for sender in &self.senders {
-							let _ = sender.unbounded_send(item.clone());
+				match self.receiver.poll_next_unpin(cx) {
+					Poll::Pending => break,
+					Poll::Ready(None) => return Poll::Ready(()),
+					Poll::Ready(Some((from, item))) => {
+						self.history.push((from, item.clone()));
+						for index in 0..self.peers.len() {
+							self.send(from, index, item.clone(), timing, partition);
 						}
 					}
 				}
 			}
+
+			if let Some(timing) = timing {
+				let now = timing.clock.now();
+				self.in_flight.sort_by_key(|&(at, _, _)| at);
+
+				let arrived = self.in_flight.iter().take_while(|&&(at, _, _)| at <= now).count();
+				for (_, index, message) in self.in_flight.drain(..arrived) {
+					let _ = self.peers[index].sender.unbounded_send(message);
+				}
+
+				if let Some(&(at, _, _)) = self.in_flight.first() {
+					timing.clock.wake_at(at, cx.waker());
+				}
+			}
+
+			Poll::Pending
 		}
 	}
 
@@ -337,11 +1347,38 @@ pub mod environment {
 	/// Give the network future to node environments and spawn the routing task
 	/// to run.
 	pub fn make_network() -> (Network, NetworkRouting) {
+		build_network(None)
+	}
+
+	/// Make a test network running in virtual time, which delays messages by
+	/// the given latency. Timers of environments on the network use its
+	/// `clock`, and all randomness is drawn from the given seed.
+	pub fn make_network_with_latency(latency: LinkLatency, seed: u64) -> (Network, NetworkRouting) {
+		build_network(Some(Arc::new(Timing {
+			clock: VirtualClock::default(),
+			latency,
+			links: Mutex::new(HashMap::new()),
+			drop_probability: Mutex::new(0.0),
+			rng: Mutex::new(StdRng::seed_from_u64(seed)),
+		})))
+	}
+
+	fn build_network(timing: Option<Arc<Timing>>) -> (Network, NetworkRouting) {
 		let global_messages = Arc::new(Mutex::new(GlobalMessageNetwork::new()));
-		let rounds = Arc::new(Mutex::new(HashMap::new()));
+		let rounds = Arc::new(Mutex::new(BTreeMap::new()));
+		let routing_task = Arc::new(AtomicWaker::new());
+		let paused = Arc::new(AtomicBool::new(false));
+		let partition = Arc::new(Mutex::new(Partition::default()));
 		(
-			Network { global_messages: global_messages.clone(), rounds: rounds.clone() },
-			NetworkRouting { global_messages, rounds }
+			Network {
+				global_messages: global_messages.clone(),
+				rounds: rounds.clone(),
+				routing_task: routing_task.clone(),
+				paused: paused.clone(),
+				partition: partition.clone(),
+				timing: timing.clone(),
+			},
+			NetworkRouting { global_messages, rounds, routing_task, paused, partition, timing }
 		)
 	}
 
@@ -351,32 +1388,144 @@ pub mod environment {
 	/// A test network. Instantiate this with `make_network`,
 	#[derive(Clone)]
 	pub struct Network {
-		rounds: Arc<Mutex<HashMap<u64, RoundNetwork>>>,
+		rounds: Arc<Mutex<BTreeMap<u64, RoundNetwork>>>,
 		global_messages: Arc<Mutex<GlobalMessageNetwork>>,
+		routing_task: Arc<AtomicWaker>,
+		paused: Arc<AtomicBool>,
+		partition: Arc<Mutex<Partition>>,
+		timing: Option<Arc<Timing>>,
 	}
 
 	impl Network {
+		/// The virtual clock of the network, if made with
+		/// `make_network_with_latency`.
+		pub fn clock(&self) -> Option<VirtualClock> {
+			self.timing.as_ref().map(|timing| timing.clock.clone())
+		}
+
+		/// Use a different latency for messages sent from one voter to
+		/// another. Only applies to networks in virtual time.
+		pub fn set_link_latency(&self, from: Id, to: Id, latency: LinkLatency) {
+			if let Some(ref timing) = self.timing {
+				timing.links.lock().insert((from, to), latency);
+			}
+		}
+
+		/// Drop messages sent between voters with the given probability. Only
+		/// applies to networks in virtual time.
+		pub fn set_drop_probability(&self, probability: f64) {
+			if let Some(ref timing) = self.timing {
+				*timing.drop_probability.lock() = probability;
+			}
+		}
+
+		// a timer on the clock of the network.
+		fn timer(&self, duration: Duration) -> Box<dyn Future<Output=Result<(),Error>> + Send + Unpin + 'static> {
+			match self.timing {
+				Some(ref timing) => Box::new(timing.clock.delay(duration).map(Ok)),
+				None => Box::new(Delay::new(duration).map(Ok)),
+			}
+		}
+
+		// a random number below `high`, seeded for networks in virtual time.
+		fn gen_range(&self, high: u64) -> u64 {
+			match self.timing {
+				Some(ref timing) => timing.rng.lock().gen_range(0, high),
+				None => rand::thread_rng().gen_range(0, high),
+			}
+		}
+
+		/// Stop delivering messages until `resume` is called. Messages sent in
+		/// the meantime are delivered on resume.
+		pub fn pause(&self) {
+			self.paused.store(true, Ordering::SeqCst);
+		}
+
+		/// Resume delivering messages.
+		pub fn resume(&self) {
+			self.paused.store(false, Ordering::SeqCst);
+			self.routing_task.wake();
+		}
+
+		/// Partition the network into the given groups of voters. Messages
+		/// between voters in different groups, or from voters in no group, are
+		/// held back until the partition is healed. Nodes connected with
+		/// `make_global_comms` and messages sent with `send_message` are not
+		/// affected.
+		pub fn partition(&self, groups: &[&[Id]]) {
+			let groups = groups.iter().enumerate()
+				.flat_map(|(group, ids)| ids.iter().map(move |id| (*id, group)))
+				.collect();
+
+			*self.partition.lock() = Partition(Some(groups));
+		}
+
+		/// Send the messages of the latest `rounds` rounds to all nodes again,
+		/// as a gossip layer would for nodes which missed them. They are
+		/// subject to latency and drops like new messages.
+		pub fn regossip(&self, rounds: usize) {
+			for (_, round) in self.rounds.lock().iter_mut().rev().take(rounds) {
+				round.regossip = true;
+			}
+
+			self.routing_task.wake();
+		}
+
+		/// Heal a partition, delivering all messages held back by it.
+		pub fn heal(&self) {
+			*self.partition.lock() = Partition(None);
+			self.routing_task.wake();
+		}
+
+		/// Connect a node to the given round, returning its incoming and
+		/// outgoing messages.
 		pub fn make_round_comms(&self, round_number: u64, node_id: Id) -> (
-			impl Stream<Item=SignedMessage<&'static str, u32, Signature, Id>,Error=Error>,
-			impl Sink<SinkItem=Message<&'static str, u32>,SinkError=Error>
+			impl Stream<Item=Result<SignedMessage<&'static str, u32, Signature, Id>,Error>> + Unpin,
+			impl Sink<Message<&'static str, u32>,Error=Error> + Unpin
 		) {
 			let mut rounds = self.rounds.lock();
-			rounds.entry(round_number)
+			let comms = rounds.entry(round_number)
 				.or_insert_with(RoundNetwork::new)
-				.add_node(move |message| SignedMessage {
+				.add_node(Some(node_id), &self.partition.lock(), move |message| SignedMessage {
 					message,
 					signature: Signature(node_id.0),
 					id: node_id,
-				})
+				});
+
+			// the routing task must start listening on new rounds.
+			self.routing_task.wake();
+			comms
 		}
 
+		/// Connect a node to the global (commit) messages, returning its
+		/// incoming and outgoing messages.
 		pub fn make_global_comms(&self) -> (
-			impl Stream<Item=CommunicationIn<&'static str, u32, Signature, Id>,Error=Error>,
-			impl Sink<SinkItem=CommunicationOut<&'static str, u32, Signature, Id>,SinkError=Error>
+			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>,Error>> + Unpin,
+			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>,Error=Error> + Unpin
+		) {
+			self.global_comms(None)
+		}
+
+		/// Like `make_global_comms`, for the given voter, so that its global
+		/// messages are subject to partitions.
+		// returns the same `impl Trait` pair as `make_global_comms`, which a
+		// type alias can't name.
+		#[allow(clippy::type_complexity)]
+		pub fn make_global_comms_for(&self, node_id: Id) -> (
+			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>,Error>> + Unpin,
+			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>,Error=Error> + Unpin
+		) {
+			self.global_comms(Some(node_id))
+		}
+
+		#[allow(clippy::type_complexity)]
+		fn global_comms(&self, node_id: Option<Id>) -> (
+			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>,Error>> + Unpin,
+			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>,Error=Error> + Unpin
 		) {
 			let mut global_messages = self.global_messages.lock();
-			global_messages.add_node(|message| match message {
-				CommunicationOut::Commit(r, commit) => CommunicationIn::Commit(r, commit.into(), Callback::Blank),
+			global_messages.add_node(node_id, &self.partition.lock(), |message| match message {
+				CommunicationOut::Commit(message) => CommunicationIn::Commit(message.into(), Callback::Blank),
 			})
 		}
 
@@ -384,29 +1533,893 @@ pub mod environment {
 		pub fn send_message(&self, message: CommunicationIn<&'static str, u32, Signature, Id>) {
 			self.global_messages.lock().send_message(message);
 		}
+
+		/// The messages the given voter has sent in a round, in order, e.g. to
+		/// replay them with `inject` after a restart.
+		pub fn sent_messages(&self, round_number: u64, node_id: Id) -> Vec<Message<&'static str, u32>> {
+			self.rounds.lock().get(&round_number).map_or_else(Vec::new, |round| round.history.iter()
+				.filter(|(from, _)| *from == Some(node_id))
+				.map(|(_, signed)| signed.message.clone())
+				.collect()
+			)
+		}
+
+		/// Send a message in a round as if from the given voter.
+		pub fn inject(&self, round_number: u64, node_id: Id, message: Message<&'static str, u32>) {
+			let signed = SignedMessage { message, signature: Signature(node_id.0), id: node_id };
+			let _ = self.rounds.lock()
+				.entry(round_number)
+				.or_insert_with(RoundNetwork::new)
+				.raw_sender
+				.unbounded_send((Some(node_id), signed));
+
+			self.routing_task.wake();
+		}
+
+		/// Observe all messages sent in the given round, including those sent
+		/// before this was called.
+		pub fn tap_round(&self, round_number: u64) -> UnboundedReceiver<SignedMessage<&'static str, u32, Signature, Id>> {
+			let rx = self.rounds.lock()
+				.entry(round_number)
+				.or_insert_with(RoundNetwork::new)
+				.connect(None, true, &self.partition.lock());
+
+			self.routing_task.wake();
+			rx
+		}
+
+		/// Observe all global messages, including those sent before this was
+		/// called.
+		pub fn tap_global(&self) -> UnboundedReceiver<CommunicationIn<&'static str, u32, Signature, Id>> {
+			self.global_messages.lock().connect(None, true, &self.partition.lock())
+		}
 	}
 
-	/// the network routing task.
+	/// The network routing task, which must be spawned for messages to be
+	/// delivered.
 	pub struct NetworkRouting {
-		rounds: Arc<Mutex<HashMap<u64, RoundNetwork>>>,
+		rounds: Arc<Mutex<BTreeMap<u64, RoundNetwork>>>,
 		global_messages: Arc<Mutex<GlobalMessageNetwork>>,
+		routing_task: Arc<AtomicWaker>,
+		paused: Arc<AtomicBool>,
+		partition: Arc<Mutex<Partition>>,
+		timing: Option<Arc<Timing>>,
 	}
 
 	impl Future for NetworkRouting {
-		type Item = ();
-		type Error = ();
+		type Output = ();
+
+		fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+			self.routing_task.register(cx.waker());
+			if self.paused.load(Ordering::SeqCst) {
+				return Poll::Pending;
+			}
 
-		fn poll(&mut self) -> Poll<(), ()> {
+			let timing = self.timing.as_deref();
+			let partition = self.partition.lock().clone();
 			let mut rounds = self.rounds.lock();
-			rounds.retain(|_, round| match round.route() {
-				Ok(Async::Ready(())) | Err(()) => false,
-				Ok(Async::NotReady) => true,
-			});
+			let done: Vec<_> = rounds.iter_mut()
+				.filter_map(|(round, network)| match network.route(cx, timing, &partition) {
+					Poll::Ready(()) => Some(*round),
+					Poll::Pending => None,
+				})
+				.collect();
+
+			for round in done {
+				rounds.remove(&round);
+			}
 
 			let mut global_messages = self.global_messages.lock();
-			let _ = global_messages.route();
+			let _ = global_messages.route(cx, timing, &partition);
+
+			Poll::Pending
+		}
+	}
+}
+
+/// Voters which deviate from the protocol, for checking that honest voters
+/// tolerate them.
+#[cfg(feature = "std")]
+pub mod byzantine {
+	use super::environment::{Id, Network, Signature};
+	use crate::voter::CommunicationOut;
+	use crate::{Commit, CommitMessage, Error, Message, Precommit, Prevote, SignedPrecommit};
+	use futures::prelude::*;
+	use futures::stream;
+	use std::pin::Pin;
+
+	type Block = (&'static str, u32);
+
+	/// How a byzantine voter misbehaves.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub enum Behavior {
+		/// Prevote and precommit for both of the given blocks.
+		Equivocate(Block, Block),
+		/// Prevote and precommit for the given block, e.g. on a fork the
+		/// honest voters don't build on.
+		VoteFor(Block),
+		/// Prevote for the given block, but never precommit.
+		WithholdPrecommits(Block),
+		/// Cast no votes, but broadcast commits for the given block which are
+		/// signed by non-voters and so are invalid.
+		JunkCommits(Block),
+	}
+
+	impl Behavior {
+		fn votes(&self) -> Vec<Message<&'static str, u32>> {
+			let prevote = |(hash, number): Block| Message::Prevote(Prevote::new(hash, number));
+			let precommit = |(hash, number): Block| Message::Precommit(Precommit::new(hash, number));
+
+			match *self {
+				Behavior::Equivocate(first, second) =>
+					vec![prevote(first), prevote(second), precommit(first), precommit(second)],
+				Behavior::VoteFor(block) => vec![prevote(block), precommit(block)],
+				Behavior::WithholdPrecommits(block) => vec![prevote(block)],
+				Behavior::JunkCommits(_) => Vec::new(),
+			}
+		}
+
+		fn commit(&self, id: Id) -> Option<Commit<&'static str, u32, Signature, Id>> {
+			let (target_hash, target_number) = match *self {
+				Behavior::JunkCommits(block) => block,
+				_ => return None,
+			};
+
+			let precommits = std::iter::once(id).chain((100..103).map(Id))
+				.map(|id| SignedPrecommit {
+					precommit: Precommit::new(target_hash, target_number),
+					signature: Signature(id.0),
+					id,
+				})
+				.collect();
+
+			Some(Commit { target_hash, target_number, precommits })
+		}
+	}
+
+	// messages of a byzantine voter being sent to the network.
+	type PendingSend = Pin<Box<dyn Future<Output=Result<(), Error>>>>;
+
+	/// Run a byzantine voter with the given ID on the network, misbehaving in
+	/// rounds `1..=rounds`. Messages are kept in the history of the network,
+	/// so the returned future resolves once they are sent.
+	pub fn byzantine_voter(network: &Network, id: Id, behavior: Behavior, rounds: u64) -> impl Future<Output=()> {
+		let mut sends: Vec<PendingSend> = Vec::new();
 
-			Ok(Async::NotReady)
+		for round in 1..=rounds {
+			let (_, outgoing) = network.make_round_comms(round, id);
+			sends.push(Box::pin(stream::iter(behavior.votes().into_iter().map(Ok)).forward(outgoing)));
 		}
+
+		if let Some(commit) = behavior.commit(id) {
+			let (_, outgoing) = network.make_global_comms_for(id);
+			let commits = (1..=rounds)
+				.map(move |round| Ok(CommunicationOut::Commit(CommitMessage::new(round, commit.clone()))));
+
+			sends.push(Box::pin(stream::iter(commits).forward(outgoing)));
+		}
+
+		future::join_all(sends).map(|_| ())
+	}
+}
+
+/// A seeded simulation of honest voters, for checking safety and liveness
+/// over many randomized schedules.
+///
+/// Voters run in virtual time on a network with random latencies and message
+/// drops, while a randomly forking chain is produced and imported by each
+/// voter at different times. Everything random is drawn from the seed, so a
+/// failing schedule can be replayed by running the same seed again.
+#[cfg(feature = "std")]
+pub mod simulation {
+	use super::chain::{DummyChain, GENESIS_HASH};
+	use super::environment::{make_network_with_latency, Environment, Id, LinkLatency, VirtualClock};
+	use crate::round::State as RoundState;
+	use crate::voter::Voter;
+	use crate::voter_set::VoterSet;
+	use crate::{Chain, Error};
+	use futures::executor::{LocalPool, LocalSpawner};
+	use futures::prelude::*;
+	use futures::stream;
+	use futures::task::LocalSpawnExt;
+	use parking_lot::Mutex;
+	use rand::{Rng, SeedableRng, rngs::StdRng};
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	const BLOCK_NAMES: [&str; 26] = [
+		"A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M",
+		"N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z",
+	];
+
+	type Block = (&'static str, u32);
+
+	const GOSSIP_INTERVAL: Duration = Duration::from_secs(2);
+
+	/// Run the future created by `f` on a single-threaded executor, along
+	/// with the tasks it spawns, advancing the virtual clock whenever all tasks
+	/// are waiting on it. Panics if all tasks are stalled and no timers are
+	/// pending.
+	pub fn block_on_virtual<F>(clock: &VirtualClock, f: impl FnOnce(&LocalSpawner) -> F) -> F::Output
+		where F: Future + 'static, F::Output: 'static
+	{
+		let mut pool = LocalPool::new();
+		let spawner = pool.spawner();
+		let mut handle = spawner.spawn_local_with_handle(f(&spawner)).unwrap();
+
+		loop {
+			pool.run_until_stalled();
+			if let Some(output) = (&mut handle).now_or_never() {
+				return output;
+			}
+
+			assert!(clock.advance(), "stalled without pending timers");
+		}
+	}
+
+	/// Parameters of a simulation.
+	#[derive(Debug, Clone)]
+	pub struct Simulation {
+		/// The number of voters, all with equal weight.
+		pub voters: u32,
+		/// The latency of all links, which is also the delay with which voters
+		/// import new blocks.
+		pub latency: LinkLatency,
+		/// The probability that a message between two voters is dropped.
+		pub drop_probability: f64,
+		/// The number of blocks to produce, each on one of the three most
+		/// recent blocks. At most 26.
+		pub blocks: usize,
+		/// The average time between blocks.
+		pub block_time: Duration,
+		/// How long voters may take to finalize the best block, in virtual
+		/// time.
+		pub duration: Duration,
+	}
+
+	impl Default for Simulation {
+		fn default() -> Self {
+			Simulation {
+				voters: 4,
+				latency: LinkLatency { delay: Duration::from_millis(500), jitter: Duration::from_millis(200) },
+				drop_probability: 0.1,
+				blocks: 10,
+				block_time: Duration::from_secs(1),
+				duration: Duration::from_secs(120),
+			}
+		}
+	}
+
+	/// The outcome of a simulation.
+	#[derive(Debug, Clone, PartialEq)]
+	pub struct Outcome {
+		/// The blocks finalized by each voter, in order.
+		pub finalized: Vec<Vec<Block>>,
+		/// The best block containing the highest finalized block, once all
+		/// blocks were produced.
+		pub best: Block,
+		/// The virtual time taken.
+		pub elapsed: Duration,
+	}
+
+	// when each voter imports each block, as (time, parent, block), in order.
+	fn block_schedule(sim: &Simulation, rng: &mut StdRng) -> Vec<Vec<(Duration, &'static str, &'static str)>> {
+		assert!(sim.blocks <= BLOCK_NAMES.len(), "at most {} blocks are supported", BLOCK_NAMES.len());
+
+		let block_time = sim.block_time.as_millis() as u64;
+		let import_delay = (sim.latency.delay + sim.latency.jitter).as_millis() as u64;
+
+		let mut produced_at = 0;
+		let mut produced = Vec::new();
+		let mut schedule = vec![Vec::new(); sim.voters as usize];
+		for &name in &BLOCK_NAMES[..sim.blocks] {
+			produced_at += rng.gen_range(0, 2 * block_time + 1);
+			let parent = match produced.len() {
+				0 => GENESIS_HASH,
+				n => produced[n - 1 - rng.gen_range(0, std::cmp::min(n, 3))],
+			};
+			produced.push(name);
+
+			for imports in &mut schedule {
+				// blocks are imported in order, so parents are always known.
+				let previous = imports.last().map_or(Duration::from_millis(0), |&(at, _, _)| at);
+				let at = Duration::from_millis(produced_at + rng.gen_range(0, import_delay + 1));
+				imports.push((std::cmp::max(previous, at), parent, name));
+			}
+		}
+
+		schedule
+	}
+
+	impl Simulation {
+		/// Run the simulation with the given seed.
+		pub fn run(&self, seed: u64) -> Outcome {
+			let mut rng = StdRng::seed_from_u64(seed);
+			let schedule = block_schedule(self, &mut rng);
+
+			let voters = VoterSet::new((0..self.voters).map(|i| (Id(i), 1)))
+				.expect("total weight of unit voters doesn't overflow; qed");
+			let (network, routing_task) = make_network_with_latency(self.latency, rng.gen());
+			network.set_drop_probability(self.drop_probability);
+
+			let clock = network.clock().expect("network is in virtual time; qed");
+			let deadline = clock.now() + self.duration;
+			let all_produced = schedule.iter().flatten().map(|&(at, _, _)| at).max().unwrap_or_default();
+
+			let finalized = Arc::new(Mutex::new(vec![Vec::new(); self.voters as usize]));
+			let envs: Vec<_> = (0..self.voters).map(|i| {
+				let env = Arc::new(Environment::new(network.clone(), Id(i)));
+				// voters may see votes on blocks they haven't imported yet.
+				env.set_transient(Error::NotDescendent);
+				env
+			}).collect();
+
+			let task_clock = clock.clone();
+			let task_envs = envs.clone();
+			let task_finalized = finalized.clone();
+			block_on_virtual(&clock, move |spawner| {
+				spawner.spawn_local(routing_task).unwrap();
+
+				// repair drops by gossiping the latest rounds again, so that
+				// voters which missed votes can complete their round.
+				let gossip_clock = task_clock.clone();
+				let gossip_network = network.clone();
+				spawner.spawn_local(stream::repeat(()).for_each(move |_| {
+					let network = gossip_network.clone();
+					gossip_clock.delay(GOSSIP_INTERVAL).map(move |_| network.regossip(3))
+				})).unwrap();
+
+				for ((i, env), imports) in task_envs.iter().enumerate().zip(schedule) {
+					let import_clock = task_clock.clone();
+					let import_env = env.clone();
+					spawner.spawn_local(stream::iter(imports).for_each(move |(at, parent, name)| {
+						let env = import_env.clone();
+						import_clock.delay_until(at)
+							.map(move |_| env.with_chain(|chain| chain.push_blocks(parent, &[name])))
+					})).unwrap();
+
+					let finalized = task_finalized.clone();
+					spawner.spawn_local(env.finalized_stream().for_each(move |(hash, number, _)| {
+						finalized.lock()[i].push((hash, number));
+						future::ready(())
+					})).unwrap();
+
+					let voter = Voter::new(
+						env.clone(),
+						voters.clone(),
+						network.make_global_comms_for(Id(i as u32)),
+						0,
+						RoundState::genesis((GENESIS_HASH, 1)),
+						(GENESIS_HASH, 1),
+					);
+					spawner.spawn_local(voter.map(|res| res.expect("Error voting"))).unwrap();
+				}
+
+				// check on the voters every second until they have all finalized
+				// the best block, or time is up.
+				let check_clock = task_clock.clone();
+				let finished = move || {
+					let now = check_clock.now();
+					now >= deadline || now > all_produced && {
+						let finalized = task_envs[0].with_chain(|chain| chain.last_finalized());
+						best_block(&task_envs[0], finalized) == finalized
+							&& task_envs.iter().all(|env| env.with_chain(|chain| chain.last_finalized()) == finalized)
+					}
+				};
+
+				stream::repeat(())
+					.then(move |_| task_clock.delay(Duration::from_secs(1)))
+					.take_while(move |_| future::ready(!finished()))
+					.for_each(|_| future::ready(()))
+			});
+
+			let finalized = finalized.lock().clone();
+			let highest = finalized.iter().flatten().max_by_key(|&&(_, number)| number)
+				.cloned()
+				.unwrap_or((GENESIS_HASH, 1));
+
+			Outcome { finalized, best: best_block(&envs[0], highest), elapsed: clock.now() }
+		}
+
+		// the chain once all blocks of the simulation with the given seed are
+		// produced.
+		fn chain(&self, seed: u64) -> DummyChain {
+			let mut chain = DummyChain::new();
+			let mut rng = StdRng::seed_from_u64(seed);
+			for (_, parent, name) in block_schedule(self, &mut rng).swap_remove(0) {
+				chain.push_blocks(parent, &[name]);
+			}
+
+			chain
+		}
+
+		/// Run the simulation with the given seed, and check that no
+		/// conflicting blocks were finalized and that all voters finalized the
+		/// best block in time. Describes the first violation otherwise.
+		pub fn check(&self, seed: u64) -> Result<Outcome, String> {
+			let outcome = self.run(seed);
+			let chain = self.chain(seed);
+
+			let on_same_chain = |(a, a_number): Block, (b, b_number): Block| {
+				let (lower, higher) = if a_number <= b_number { (a, b) } else { (b, a) };
+				chain.is_equal_or_descendent_of(lower, higher)
+			};
+
+			let all_finalized: Vec<_> = outcome.finalized.iter().flatten().cloned().collect();
+			for (i, &a) in all_finalized.iter().enumerate() {
+				for &b in &all_finalized[i + 1..] {
+					if !on_same_chain(a, b) {
+						return Err(format!("seed {}: conflicting blocks {:?} and {:?} were finalized", seed, a, b));
+					}
+				}
+			}
+
+			for (i, finalized) in outcome.finalized.iter().enumerate() {
+				if finalized.last() != Some(&outcome.best) {
+					return Err(format!(
+						"seed {}: voter {} finalized {:?} by {:?} rather than the best block {:?}",
+						seed, i, finalized.last(), outcome.elapsed, outcome.best,
+					));
+				}
+			}
+
+			Ok(outcome)
+		}
+	}
+
+	fn best_block(env: &Environment, base: Block) -> Block {
+		env.best_chain_containing(base.0).unwrap_or(base)
+	}
+}
+
+/// Golden test vectors pinning the semantics of rounds and commits, for
+/// checking other implementations of the protocol against this one.
+///
+/// Each vector describes a voter set, a chain, and signed votes which are
+/// imported into a round in order, along with the expected prevote-GHOST,
+/// precommit-GHOST, estimate, finalized block, completability and
+/// equivocators. Vectors may also carry a commit, whose expected validity
+/// and GHOST are given. Signatures are opaque numbers which are assumed to
+/// be checked already, but the same vote with another signature is an
+/// equivocation.
+///
+/// The vectors are kept as JSON in `test-vectors/round.json`, and are
+/// regenerated by running the tests with `UPDATE_TEST_VECTORS=1`.
+#[cfg(feature = "std")]
+pub mod vectors {
+	use crate::round::{Round, RoundParams};
+	use crate::voter_set::VoterSet;
+	use crate::{Chain, Commit, Error, Precommit, Prevote, SignedPrecommit};
+	use serde::{Deserialize, Serialize};
+	use std::collections::HashMap;
+
+	/// A block by hash and number.
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct Block {
+		/// The hash of the block.
+		pub hash: String,
+		/// The number of the block.
+		pub number: u32,
+	}
+
+	impl From<(String, u32)> for Block {
+		fn from((hash, number): (String, u32)) -> Self {
+			Block { hash, number }
+		}
+	}
+
+	/// A block of the chain, whose parent is `None` for the first block.
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct ChainBlock {
+		/// The hash of the block.
+		pub hash: String,
+		/// The number of the block.
+		pub number: u32,
+		/// The hash of the parent block.
+		pub parent: Option<String>,
+	}
+
+	/// A voter and its weight.
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct Voter {
+		/// The voter's id.
+		pub id: u32,
+		/// The voter's weight.
+		pub weight: u64,
+	}
+
+	/// The kind of a vote.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+	#[serde(rename_all = "lowercase")]
+	pub enum VoteKind {
+		/// A prevote.
+		Prevote,
+		/// A precommit.
+		Precommit,
+	}
+
+	/// A signed vote.
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct Vote {
+		/// Whether the vote is a prevote or a precommit.
+		pub kind: VoteKind,
+		/// The block voted for.
+		pub target: Block,
+		/// The id of the signer, which may not be a voter.
+		pub voter: u32,
+		/// The signature.
+		pub signature: u32,
+	}
+
+	/// A commit, whose precommits are all of `VoteKind::Precommit`.
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct VectorCommit {
+		/// The block the commit claims to finalize.
+		pub target: Block,
+		/// The precommits justifying it.
+		pub precommits: Vec<Vote>,
+	}
+
+	/// The expected outcome of a test vector.
+	#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct Expected {
+		/// The prevote-GHOST of the round.
+		pub prevote_ghost: Option<Block>,
+		/// The precommit-GHOST of the round.
+		pub precommit_ghost: Option<Block>,
+		/// The estimate of the round.
+		pub estimate: Option<Block>,
+		/// The block finalized in the round.
+		pub finalized: Option<Block>,
+		/// Whether the round is completable.
+		pub completable: bool,
+		/// The voters whose prevotes were reported as equivocations, in order.
+		pub prevote_equivocators: Vec<u32>,
+		/// The voters whose precommits were reported as equivocations, in order.
+		pub precommit_equivocators: Vec<u32>,
+		/// Whether the commit is valid, if the vector has one.
+		pub commit_valid: Option<bool>,
+		/// The GHOST of the commit's precommits, if the vector has a commit.
+		pub commit_ghost: Option<Block>,
+	}
+
+	/// A test vector: the inputs of a round and the expected outcome.
+	#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+	pub struct TestVector {
+		/// A unique name.
+		pub name: String,
+		/// What the vector checks.
+		pub description: String,
+		/// The voter set.
+		pub voters: Vec<Voter>,
+		/// The chain, with parents before their children.
+		pub chain: Vec<ChainBlock>,
+		/// The base of the round.
+		pub base: Block,
+		/// The votes, imported in order.
+		pub votes: Vec<Vote>,
+		/// A commit to validate.
+		pub commit: Option<VectorCommit>,
+		/// The expected outcome.
+		pub expected: Expected,
+	}
+
+	impl TestVector {
+		/// Compute the outcome of the vector with this implementation.
+		pub fn compute(&self) -> Result<Expected, Error> {
+			let voters = VoterSet::new(self.voters.iter().map(|voter| (voter.id, voter.weight)))?;
+			let chain = VectorChain::new(&self.chain);
+
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				voters: voters.clone(),
+				base: (self.base.hash.clone(), self.base.number),
+			});
+
+			let mut expected = Expected::default();
+			for vote in &self.votes {
+				let (hash, number) = (vote.target.hash.clone(), vote.target.number);
+				match vote.kind {
+					VoteKind::Prevote => {
+						let imported = round.import_prevote(&chain, Prevote::new(hash, number), vote.voter, vote.signature)?;
+						if imported.equivocation().is_some() {
+							expected.prevote_equivocators.push(vote.voter);
+						}
+					}
+					VoteKind::Precommit => {
+						let imported = round.import_precommit(&chain, Precommit::new(hash, number), vote.voter, vote.signature)?;
+						if imported.equivocation().is_some() {
+							expected.precommit_equivocators.push(vote.voter);
+						}
+					}
+				}
+			}
+
+			let state = round.state();
+			expected.prevote_ghost = state.prevote_ghost.map(Block::from);
+			expected.precommit_ghost = round.precommit_ghost().map(Block::from);
+			expected.estimate = state.estimate.map(Block::from);
+			expected.finalized = state.finalized.map(Block::from);
+			expected.completable = state.completable;
+
+			if let Some(ref commit) = self.commit {
+				let commit = Commit {
+					target_hash: commit.target.hash.clone(),
+					target_number: commit.target.number,
+					precommits: commit.precommits.iter().map(|vote| SignedPrecommit {
+						precommit: Precommit::new(vote.target.hash.clone(), vote.target.number),
+						signature: vote.signature,
+						id: vote.voter,
+					}).collect(),
+				};
+
+				let result = crate::validate_commit(&commit, &voters, &chain)?;
+				expected.commit_valid = Some(result.is_valid());
+				expected.commit_ghost = result.ghost().cloned().map(Block::from);
+			}
+
+			Ok(expected)
+		}
+
+		/// Check the expected outcome against this implementation.
+		pub fn check(&self) -> Result<(), String> {
+			let computed = self.compute().map_err(|e| format!("{}: {}", self.name, e))?;
+			if computed != self.expected {
+				return Err(format!("{}: expected {:?}, computed {:?}", self.name, self.expected, computed));
+			}
+
+			Ok(())
+		}
+	}
+
+	/// Generate all test vectors.
+	pub fn generate() -> Vec<TestVector> {
+		cases().into_iter().map(|case| {
+			let mut vector = case.into_vector();
+			vector.expected = vector.compute()
+				.unwrap_or_else(|e| panic!("Test vector {} can't be computed: {}", vector.name, e));
+			vector
+		}).collect()
+	}
+
+	/// Serialize test vectors as pretty-printed JSON.
+	pub fn to_json(vectors: &[TestVector]) -> String {
+		serde_json::to_string_pretty(vectors).expect("test vectors are serializable; qed") + "\n"
+	}
+
+	/// Parse test vectors from JSON.
+	pub fn from_json(json: &str) -> Result<Vec<TestVector>, serde_json::Error> {
+		serde_json::from_str(json)
+	}
+
+	// a chain built from the blocks of a test vector.
+	struct VectorChain(HashMap<String, ChainBlock>);
+
+	impl VectorChain {
+		fn new(blocks: &[ChainBlock]) -> Self {
+			VectorChain(blocks.iter().map(|block| (block.hash.clone(), block.clone())).collect())
+		}
+	}
+
+	impl Chain<String, u32> for VectorChain {
+		fn ancestry(&self, base: String, block: String) -> Result<Vec<String>, Error> {
+			let mut ancestry = Vec::new();
+			let mut parent = self.0.get(&block).and_then(|block| block.parent.clone());
+
+			while let Some(hash) = parent {
+				if hash == base { return Ok(ancestry) }
+
+				parent = self.0.get(&hash).and_then(|block| block.parent.clone());
+				ancestry.push(hash);
+			}
+
+			Err(Error::NotDescendent)
+		}
+
+		fn best_chain_containing(&self, base: String) -> Option<(String, u32)> {
+			if !self.0.contains_key(&base) { return None }
+
+			self.0.values()
+				.filter(|block| self.is_equal_or_descendent_of(base.clone(), block.hash.clone()))
+				.max_by(|a, b| a.number.cmp(&b.number).then_with(|| b.hash.cmp(&a.hash)))
+				.map(|block| (block.hash.clone(), block.number))
+		}
+	}
+
+	// a vote of a case: its kind, the hash of its target, the voter and the
+	// signature.
+	type CaseVote = (VoteKind, &'static str, u32, u32);
+
+	// the commit of a case: the hash of its target and its precommits, given
+	// by the hash of their target, the voter and the signature.
+	type CaseCommit = (&'static str, Vec<(&'static str, u32, u32)>);
+
+	// a test vector before its outcome is computed. blocks are given as
+	// (hash, parent) pairs descending from the genesis, and votes by the hash
+	// of their target.
+	struct Case {
+		name: &'static str,
+		description: &'static str,
+		voters: &'static [(u32, u64)],
+		chain: &'static [(&'static str, &'static str)],
+		votes: Vec<CaseVote>,
+		commit: Option<CaseCommit>,
+	}
+
+	impl Case {
+		fn into_vector(self) -> TestVector {
+			let mut chain = vec![ChainBlock { hash: "genesis".into(), number: 1, parent: None }];
+			for &(hash, parent) in self.chain {
+				let number = chain.iter().find(|block| block.hash == parent)
+					.unwrap_or_else(|| panic!("Parent {} of {} is defined before it", parent, hash))
+					.number + 1;
+
+				chain.push(ChainBlock { hash: hash.into(), number, parent: Some(parent.into()) });
+			}
+
+			let block = |hash: &str| {
+				let number = chain.iter().find(|block| block.hash == hash)
+					.unwrap_or_else(|| panic!("Block {} is in the chain", hash))
+					.number;
+
+				Block { hash: hash.into(), number }
+			};
+
+			let vote = |kind, target, voter, signature| Vote { kind, target: block(target), voter, signature };
+
+			TestVector {
+				name: self.name.into(),
+				description: self.description.into(),
+				voters: self.voters.iter().map(|&(id, weight)| Voter { id, weight }).collect(),
+				base: block("genesis"),
+				votes: self.votes.into_iter()
+					.map(|(kind, target, voter, signature)| vote(kind, target, voter, signature))
+					.collect(),
+				commit: self.commit.map(|(target, precommits)| VectorCommit {
+					target: block(target),
+					precommits: precommits.into_iter()
+						.map(|(target, voter, signature)| vote(VoteKind::Precommit, target, voter, signature))
+						.collect(),
+				}),
+				expected: Expected::default(),
+				chain,
+			}
+		}
+	}
+
+	const FOUR_VOTERS: &[(u32, u64)] = &[(0, 1), (1, 1), (2, 1), (3, 1)];
+	const WEIGHTED_VOTERS: &[(u32, u64)] = &[(0, 5), (1, 3), (2, 1), (3, 1)];
+
+	const LINEAR: &[(&str, &str)] = &[("A", "genesis"), ("B", "A"), ("C", "B"), ("D", "C"), ("E", "D")];
+	// forks at "B" into "C", "D", "E" and "F", "G".
+	const FORKED: &[(&str, &str)] = &[
+		("A", "genesis"), ("B", "A"), ("C", "B"), ("D", "C"), ("E", "D"), ("F", "B"), ("G", "F"),
+	];
+
+	// votes signed with the voter's id.
+	fn votes(kind: VoteKind, votes: &[(&'static str, u32)]) -> Vec<CaseVote> {
+		votes.iter().map(|&(target, voter)| (kind, target, voter, voter)).collect()
+	}
+
+	fn precommits(votes: &[(&'static str, u32)]) -> Vec<(&'static str, u32, u32)> {
+		votes.iter().map(|&(target, voter)| (target, voter, voter)).collect()
+	}
+
+	fn cases() -> Vec<Case> {
+		use VoteKind::{Precommit, Prevote};
+
+		vec![
+			Case {
+				name: "prevote-supermajority",
+				description: "A supermajority of prevotes sets the prevote-GHOST, but nothing is finalized without precommits.",
+				voters: FOUR_VOTERS,
+				chain: LINEAR,
+				votes: votes(Prevote, &[("E", 0), ("E", 1), ("E", 2)]),
+				commit: None,
+			},
+			Case {
+				name: "finalized",
+				description: "A supermajority of prevotes and precommits on the same block finalizes it.",
+				voters: FOUR_VOTERS,
+				chain: LINEAR,
+				votes: [
+					votes(Prevote, &[("E", 0), ("E", 1), ("E", 2), ("E", 3)]),
+					votes(Precommit, &[("E", 0), ("E", 1), ("E", 2)]),
+				].concat(),
+				commit: Some(("E", precommits(&[("E", 0), ("E", 1), ("E", 2)]))),
+			},
+			Case {
+				name: "prevotes-split-on-fork",
+				description: "Prevotes on two forks only support their common ancestor.",
+				voters: FOUR_VOTERS,
+				chain: FORKED,
+				votes: votes(Prevote, &[("E", 0), ("E", 1), ("G", 2), ("G", 3)]),
+				commit: None,
+			},
+			Case {
+				name: "votes-on-descendents",
+				description: "Votes count towards all ancestors of their target, so the highest block with enough of them is chosen.",
+				voters: FOUR_VOTERS,
+				chain: LINEAR,
+				votes: [
+					votes(Prevote, &[("E", 0), ("E", 1), ("E", 2), ("D", 3)]),
+					votes(Precommit, &[("D", 0), ("E", 1), ("C", 2)]),
+				].concat(),
+				commit: Some(("C", precommits(&[("D", 0), ("E", 1), ("C", 2)]))),
+			},
+			Case {
+				name: "estimate-below-prevote-ghost",
+				description: "Once precommits reach a supermajority, the estimate is the highest block in the chain of the prevote-GHOST which could still be finalized, counting possible equivocations.",
+				voters: FOUR_VOTERS,
+				chain: FORKED,
+				votes: [
+					votes(Prevote, &[("E", 0), ("E", 1), ("E", 2), ("G", 3)]),
+					votes(Precommit, &[("E", 0), ("G", 1), ("G", 2), ("G", 3)]),
+				].concat(),
+				commit: None,
+			},
+			Case {
+				name: "weighted-voters",
+				description: "Votes are weighted by the voter's weight.",
+				voters: WEIGHTED_VOTERS,
+				chain: LINEAR,
+				votes: [
+					votes(Prevote, &[("E", 0), ("E", 1)]),
+					votes(Precommit, &[("E", 0), ("C", 1)]),
+				].concat(),
+				commit: Some(("C", precommits(&[("E", 0), ("C", 1)]))),
+			},
+			Case {
+				name: "equivocations-count-for-everything",
+				description: "An equivocating voter is counted as voting for every block, but only its first vote is tracked.",
+				voters: FOUR_VOTERS,
+				chain: FORKED,
+				votes: [
+					votes(Prevote, &[("E", 0), ("E", 1), ("G", 2), ("E", 2)]),
+					votes(Precommit, &[("E", 0), ("G", 1), ("E", 1), ("E", 3)]),
+				].concat(),
+				commit: Some(("B", precommits(&[("E", 0), ("G", 1), ("E", 1), ("E", 3)]))),
+			},
+			Case {
+				name: "same-vote-new-signature",
+				description: "The same vote with another signature is an equivocation, while a duplicate vote is ignored.",
+				voters: FOUR_VOTERS,
+				chain: LINEAR,
+				votes: vec![
+					(Prevote, "E", 0, 0),
+					(Prevote, "E", 0, 0),
+					(Prevote, "E", 1, 1),
+					(Prevote, "E", 1, 100),
+				],
+				commit: None,
+			},
+			Case {
+				name: "insufficient-precommits",
+				description: "Precommits short of a supermajority finalize nothing and don't make a valid commit.",
+				voters: FOUR_VOTERS,
+				chain: LINEAR,
+				votes: [
+					votes(Prevote, &[("E", 0), ("E", 1), ("E", 2), ("E", 3)]),
+					votes(Precommit, &[("E", 0), ("E", 1)]),
+				].concat(),
+				commit: Some(("E", precommits(&[("E", 0), ("E", 1)]))),
+			},
+			Case {
+				name: "non-voters-are-ignored",
+				description: "Votes signed by ids outside the voter set carry no weight.",
+				voters: FOUR_VOTERS,
+				chain: LINEAR,
+				votes: votes(Prevote, &[("E", 0), ("E", 1), ("E", 7), ("E", 8)]),
+				commit: Some(("E", precommits(&[("E", 0), ("E", 1), ("E", 7)]))),
+			},
+			Case {
+				name: "commit-with-bad-ancestry",
+				description: "A commit is invalid if any precommit isn't on its target or a descendent of it.",
+				voters: FOUR_VOTERS,
+				chain: FORKED,
+				votes: Vec::new(),
+				commit: Some(("C", precommits(&[("C", 0), ("E", 1), ("G", 2), ("D", 3)]))),
+			},
+		]
 	}
 }
@@ -0,0 +1,312 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The local view of the protocol, for deciding which gossiped messages
+//! matter.
+//!
+//! Network layers shouldn't process or relay messages which can't affect the
+//! local state. Which ones can is part of the protocol, so it is decided here
+//! rather than by each network layer. Messages are only checked against the
+//! view, not validated.
+
+use crate::{BlockNumberOps, CatchUp, Chain, CommitMessage, CompactCommit, SignedMessage};
+
+/// How a gossiped message relates to the local view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relevance {
+	/// The message may affect the local state, and should be processed and
+	/// relayed.
+	Relevant,
+	/// The message is for a state we have moved past, and can be dropped.
+	Stale,
+	/// The message is for a state we haven't reached yet, e.g. a later voter
+	/// set. It may become relevant once we do.
+	Future,
+}
+
+/// A gossiped message, along with the voter set it was sent for.
+#[derive(Debug)]
+pub enum GossipMessage<'a, H, N, S, Id> {
+	/// A vote in the given round.
+	Vote {
+		/// The voter set id.
+		set_id: u64,
+		/// The round of the vote.
+		round: u64,
+		/// The vote itself.
+		vote: &'a SignedMessage<H, N, S, Id>,
+	},
+	/// A commit.
+	Commit {
+		/// The voter set id.
+		set_id: u64,
+		/// The commit itself.
+		commit: &'a CommitMessage<CompactCommit<H, N, S, Id>>,
+	},
+	/// A catch-up.
+	CatchUp {
+		/// The voter set id.
+		set_id: u64,
+		/// The catch-up itself.
+		catch_up: &'a CatchUp<H, N, S, Id>,
+	},
+}
+
+/// The local view of the protocol: the voter set and round we're in, and the
+/// last block we finalized.
+///
+/// The view only moves forward, updates which would move it back are
+/// ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct View<H, N> {
+	set_id: u64,
+	round: u64,
+	last_finalized: (H, N),
+}
+
+impl<H: Clone + Eq, N: Copy + BlockNumberOps> View<H, N> {
+	/// Create a view at the given voter set, round and last finalized block.
+	pub fn new(set_id: u64, round: u64, last_finalized: (H, N)) -> Self {
+		View { set_id, round, last_finalized }
+	}
+
+	/// The current voter set id.
+	pub fn set_id(&self) -> u64 {
+		self.set_id
+	}
+
+	/// The current round.
+	pub fn round(&self) -> u64 {
+		self.round
+	}
+
+	/// The last finalized block.
+	pub fn last_finalized(&self) -> &(H, N) {
+		&self.last_finalized
+	}
+
+	/// Note that we moved to a later round of the current voter set.
+	pub fn update_round(&mut self, round: u64) {
+		if round > self.round {
+			self.round = round;
+		}
+	}
+
+	/// Note that a new voter set took over, whose rounds start again at the
+	/// given one.
+	pub fn update_set(&mut self, set_id: u64, round: u64) {
+		if set_id > self.set_id {
+			self.set_id = set_id;
+			self.round = round;
+		}
+	}
+
+	/// Note that a later block was finalized.
+	pub fn update_finalized(&mut self, last_finalized: (H, N)) {
+		if last_finalized.1 > self.last_finalized.1 {
+			self.last_finalized = last_finalized;
+		}
+	}
+
+	/// How the given message relates to the view.
+	///
+	/// Votes are relevant for the previous, current and next round of the
+	/// current voter set, as the previous round may still be completed and
+	/// the next one started by peers slightly ahead of us. Commits of the
+	/// current voter set are relevant if they finalize a block we haven't,
+	/// whatever their round, which includes blocks conflicting with the last
+	/// finalized one so that the voter can report the violation. Only those
+	/// commits are checked against the chain. Catch-ups of the current voter
+	/// set are relevant if they are for a later round.
+	pub fn relevance<C, S, Id>(&self, chain: &C, message: &GossipMessage<H, N, S, Id>) -> Relevance where
+		C: Chain<H, N>,
+	{
+		let set_id = match *message {
+			GossipMessage::Vote { set_id, .. } |
+			GossipMessage::Commit { set_id, .. } |
+			GossipMessage::CatchUp { set_id, .. } => set_id,
+		};
+
+		if set_id < self.set_id {
+			return Relevance::Stale;
+		}
+		if set_id > self.set_id {
+			return Relevance::Future;
+		}
+
+		match *message {
+			GossipMessage::Vote { round, .. } => {
+				if round.saturating_add(1) < self.round {
+					Relevance::Stale
+				} else if round > self.round.saturating_add(1) {
+					Relevance::Future
+				} else {
+					Relevance::Relevant
+				}
+			}
+			GossipMessage::Commit { commit, .. } => {
+				let (ref finalized_hash, finalized_number) = self.last_finalized;
+				let target = &commit.commit;
+				if target.target_number > finalized_number {
+					Relevance::Relevant
+				} else if chain.is_equal_or_descendent_of(target.target_hash.clone(), finalized_hash.clone()) {
+					Relevance::Stale
+				} else {
+					Relevance::Relevant
+				}
+			}
+			GossipMessage::CatchUp { catch_up, .. } => {
+				if catch_up.round_number > self.round {
+					Relevance::Relevant
+				} else {
+					Relevance::Stale
+				}
+			}
+		}
+	}
+
+	/// Whether the given message may affect the local state, see
+	/// `View::relevance`.
+	pub fn is_relevant<C, S, Id>(&self, chain: &C, message: &GossipMessage<H, N, S, Id>) -> bool where
+		C: Chain<H, N>,
+	{
+		self.relevance(chain, message) == Relevance::Relevant
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Message, Prevote};
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+
+	type Vote = SignedMessage<&'static str, u32, u32, u32>;
+	type Commit = CommitMessage<CompactCommit<&'static str, u32, u32, u32>>;
+	type CatchUpMessage = CatchUp<&'static str, u32, u32, u32>;
+
+	fn vote() -> Vote {
+		SignedMessage { message: Message::Prevote(Prevote::new("A", 1)), signature: 1, id: 1 }
+	}
+
+	// genesis <- A <- B <- C <- D, and a fork B <- E.
+	fn chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+		chain.push_blocks("B", &["E"]);
+		chain
+	}
+
+	fn commit(round: u64, target: (&'static str, u32)) -> Commit {
+		CommitMessage::new(round, CompactCommit {
+			target_hash: target.0,
+			target_number: target.1,
+			precommits: Vec::new(),
+			auth_data: Vec::new(),
+		})
+	}
+
+	fn catch_up(round_number: u64) -> CatchUpMessage {
+		CatchUp {
+			round_number,
+			prevotes: Vec::new(),
+			precommits: Vec::new(),
+			base_hash: "A",
+			base_number: 1,
+		}
+	}
+
+	#[test]
+	fn votes_are_relevant_around_the_current_round() {
+		let chain = chain();
+		let view = View::new(1, 5, ("C", 3));
+		let vote = vote();
+		let relevance = |set_id, round| view.relevance(&chain, &GossipMessage::Vote { set_id, round, vote: &vote });
+
+		assert_eq!(relevance(1, 3), Relevance::Stale);
+		assert_eq!(relevance(1, 4), Relevance::Relevant);
+		assert_eq!(relevance(1, 5), Relevance::Relevant);
+		assert_eq!(relevance(1, 6), Relevance::Relevant);
+		assert_eq!(relevance(1, 7), Relevance::Future);
+
+		assert_eq!(relevance(0, 5), Relevance::Stale);
+		assert_eq!(relevance(2, 5), Relevance::Future);
+
+		let view = View::new(0, 0, (GENESIS_HASH, 0));
+		assert!(view.is_relevant(&chain, &GossipMessage::Vote { set_id: 0, round: 1, vote: &vote }));
+	}
+
+	#[test]
+	fn commits_are_relevant_if_they_finalize_later_blocks() {
+		let chain = chain();
+		let view = View::new(1, 5, ("C", 3));
+		let relevance = |set_id, commit| view.relevance(&chain, &GossipMessage::Commit { set_id, commit: &commit });
+
+		// old rounds may still finalize blocks we haven't.
+		assert_eq!(relevance(1, commit(1, ("D", 4))), Relevance::Relevant);
+		assert_eq!(relevance(1, commit(9, ("D", 4))), Relevance::Relevant);
+		assert_eq!(relevance(1, commit(9, ("C", 3))), Relevance::Stale);
+		assert_eq!(relevance(1, commit(9, ("B", 2))), Relevance::Stale);
+		assert_eq!(relevance(2, commit(1, ("D", 4))), Relevance::Future);
+	}
+
+	#[test]
+	fn commits_conflicting_with_the_last_finalized_block_are_relevant() {
+		let chain = chain();
+		let view = View::new(1, 5, ("C", 3));
+
+		// E is on another fork at the same height as C, the voter has to see
+		// it to report the conflicting finality.
+		assert!(view.is_relevant(&chain, &GossipMessage::Commit { set_id: 1, commit: &commit(9, ("E", 3)) }));
+
+		// E is also below D, which is on the finalized fork.
+		let view = View::new(1, 5, ("D", 4));
+		assert!(view.is_relevant(&chain, &GossipMessage::Commit { set_id: 1, commit: &commit(9, ("E", 3)) }));
+		assert!(!view.is_relevant(&chain, &GossipMessage::Commit { set_id: 1, commit: &commit(9, ("A", 1)) }));
+	}
+
+	#[test]
+	fn catch_ups_are_relevant_for_later_rounds() {
+		let chain = chain();
+		let view = View::new(1, 5, ("C", 3));
+
+		assert!(view.is_relevant(&chain, &GossipMessage::CatchUp { set_id: 1, catch_up: &catch_up(6) }));
+		assert!(view.is_relevant(&chain, &GossipMessage::CatchUp { set_id: 1, catch_up: &catch_up(50) }));
+		assert_eq!(
+			view.relevance(&chain, &GossipMessage::CatchUp { set_id: 1, catch_up: &catch_up(5) }),
+			Relevance::Stale,
+		);
+		assert_eq!(
+			view.relevance(&chain, &GossipMessage::CatchUp { set_id: 0, catch_up: &catch_up(50) }),
+			Relevance::Stale,
+		);
+	}
+
+	#[test]
+	fn view_only_moves_forward() {
+		let mut view = View::new(1, 5, ("C", 3));
+
+		view.update_round(4);
+		view.update_finalized(("B", 2));
+		view.update_set(0, 1);
+		assert_eq!(view, View::new(1, 5, ("C", 3)));
+
+		view.update_round(6);
+		view.update_finalized(("D", 4));
+		assert_eq!(view, View::new(1, 6, ("D", 4)));
+
+		// a new voter set starts its rounds over.
+		view.update_set(2, 1);
+		assert_eq!((view.set_id(), view.round(), view.last_finalized()), (2, 1, &("D", 4)));
+	}
+}
@@ -18,21 +18,157 @@
 
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::mem;
 use std::ops::AddAssign;
 
+#[cfg(not(feature = "std"))]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Encode, Decode};
+
 use crate::collections::{HashMap, HashSet, Vec};
 use super::{Chain, Error, BlockNumberOps};
 
 #[derive(Debug)]
 struct Entry<H, N, V> {
+	hash: H,
 	number: N,
 	// ancestor hashes in reverse order, e.g. ancestors[0] is the parent
 	// and the last entry is the hash of the parent vote-node.
-	ancestors: Vec<H>,
-	descendents: Vec<H>, // descendent vote-nodes
+	ancestors: Ancestry<H>,
+	descendents: Vec<usize>, // indices of descendent vote-nodes
 	cumulative_vote: V,
 }
 
+// a run of blocks in reverse order, whose storage may be shared with other
+// ancestor edges.
+#[derive(Debug, Clone)]
+struct Span<H> {
+	blocks: Arc<[H]>,
+	start: usize,
+	end: usize,
+}
+
+impl<H> Span<H> {
+	fn blocks(&self) -> &[H] {
+		&self.blocks[self.start..self.end]
+	}
+
+	// identifies the storage of the span, which is the same for all spans
+	// sharing it.
+	fn storage(&self) -> *const H {
+		self.blocks.as_ptr()
+	}
+}
+
+// the blocks of an ancestor edge in reverse order.
+//
+// an edge is made up of spans of shared storage, so that splitting it, or
+// forking off it at a block which isn't a vote-node, doesn't copy any blocks.
+// the spans are indexed by their offset, so that looking up a block only takes
+// a binary search.
+#[derive(Debug, Clone)]
+struct Ancestry<H> {
+	// the spans along with the offset of their first block in the edge.
+	spans: Vec<(usize, Span<H>)>,
+	len: usize,
+}
+
+impl<H> Ancestry<H> {
+	fn new() -> Self {
+		Ancestry { spans: Vec::new(), len: 0 }
+	}
+
+	// an edge with the given blocks in new storage.
+	fn from_vec(blocks: Vec<H>) -> Self {
+		let mut ancestry = Ancestry::new();
+		ancestry.push_span(Span { start: 0, end: blocks.len(), blocks: blocks.into() });
+		ancestry
+	}
+
+	fn len(&self) -> usize {
+		self.len
+	}
+
+	fn spans(&self) -> impl Iterator<Item = &Span<H>> {
+		self.spans.iter().map(|(_, span)| span)
+	}
+
+	fn get(&self, offset: usize) -> Option<&H> {
+		if offset >= self.len() { return None }
+
+		let (start, span) = &self.spans[self.span_index(offset)];
+		span.blocks().get(offset - start)
+	}
+
+	fn last(&self) -> Option<&H> {
+		self.spans.last().and_then(|(_, span)| span.blocks().last())
+	}
+
+	fn to_vec(&self) -> Vec<H> where H: Clone {
+		let mut blocks = Vec::with_capacity(self.len());
+		for span in self.spans() {
+			blocks.extend_from_slice(span.blocks());
+		}
+
+		blocks
+	}
+
+	// add a span to the end of the edge.
+	fn push_span(&mut self, span: Span<H>) {
+		let len = span.end - span.start;
+		if len == 0 { return }
+
+		self.spans.push((self.len, span));
+		self.len += len;
+	}
+
+	// append the blocks of another edge, sharing their storage.
+	fn append(&mut self, other: Ancestry<H>) {
+		for (_, span) in other.spans {
+			self.push_span(span);
+		}
+	}
+
+	// split the edge at the given offset, keeping the blocks before it and
+	// returning the rest, which share the storage of this edge.
+	fn split_off(&mut self, at: usize) -> Ancestry<H> where H: Clone {
+		let mut rest = Ancestry::new();
+		if at >= self.len() { return rest }
+
+		let mut idx = self.span_index(at);
+		let (start, span) = &mut self.spans[idx];
+		if at > *start {
+			let cut = span.start + at - *start;
+			rest.push_span(Span { blocks: span.blocks.clone(), start: cut, end: span.end });
+			span.end = cut;
+			idx += 1;
+		}
+
+		for (_, span) in self.spans.drain(idx..) {
+			rest.push_span(span);
+		}
+
+		self.len = at;
+		rest
+	}
+
+	fn truncate(&mut self, len: usize) where H: Clone {
+		self.split_off(len);
+	}
+
+	// the index of the span containing the given offset, which must be in the edge.
+	fn span_index(&self, offset: usize) -> usize {
+		match self.spans.binary_search_by_key(&offset, |&(start, _)| start) {
+			Ok(idx) => idx,
+			Err(idx) => idx - 1,
+		}
+	}
+}
+
 impl<H: Hash + PartialEq + Clone, N: BlockNumberOps, V> Entry<H, N, V> {
 	// whether the given hash, number pair is a direct ancestor of this node.
 	// `None` signifies that the graph must be traversed further back.
@@ -49,41 +185,141 @@ impl<H: Hash + PartialEq + Clone, N: BlockNumberOps, V> Entry<H, N, V> {
 		self.ancestors.get(offset.as_())
 	}
 
-	// get ancestor vote-node.
-	fn ancestor_node(&self) -> Option<H> {
-		self.ancestors.last().cloned()
+	// get the hash of the ancestor vote-node.
+	fn ancestor_node(&self) -> Option<&H> {
+		self.ancestors.last()
 	}
 }
 
-// a subchain of blocks by hash.
-struct Subchain<H, N> {
-	hashes: Vec<H>, // forward order.
+// a subchain of blocks from a vote-node up to the best block, which is in the
+// ancestry of the `through` vote-node unless it is the vote-node itself.
+struct Subchain<'a, H, N, V> {
+	node: &'a Entry<H, N, V>,
+	through: Option<&'a Entry<H, N, V>>,
 	best_number: N,
 }
 
-impl<H: Clone, N: Copy + BlockNumberOps> Subchain<H, N> {
-	fn blocks_reverse<'a>(&'a self) -> impl Iterator<Item = (H, N)> + 'a {
-		let best = self.best_number;
-		let mut count = N::zero();
-		self.hashes.iter().rev().cloned().map(move |x| {
-			let res = (x, best - count);
-			count = count + N::one();
-			res
-		})
+impl<'a, H: Hash + PartialEq + Clone, N: BlockNumberOps, V> Subchain<'a, H, N, V> {
+	// get the block in the subchain with the given number.
+	fn block(&self, number: N) -> Option<&'a H> {
+		if number == self.node.number { return Some(&self.node.hash) }
+		if number < self.node.number || number > self.best_number { return None }
+
+		self.through.and_then(|through| through.ancestor_block(number))
 	}
 
 	fn best(&self) -> Option<(H, N)> {
-		self.hashes.last().map(|x| (x.clone(), self.best_number))
+		self.block(self.best_number).map(|hash| (hash.clone(), self.best_number))
 	}
 }
 
+// find the highest number between `low` and `high` for which `condition`
+// holds, given that it holds for `low` and fails for all numbers above one for
+// which it fails. `condition` is only evaluated a logarithmic number of times.
+fn highest_where<N: Copy + BlockNumberOps>(mut low: N, high: N, condition: impl Fn(N) -> bool) -> N {
+	// block numbers can't be halved, so first take steps of doubling length
+	// and then go back through them in reverse.
+	let mut steps = Vec::new();
+	let mut step = N::one();
+	while step <= high - low && condition(low + step) {
+		low = low + step;
+		steps.push(step);
+		if step > high - low { break }
+		step = step + step;
+	}
+
+	while let Some(step) = steps.pop() {
+		if step <= high - low && condition(low + step) {
+			low = low + step;
+		}
+	}
+
+	low
+}
+
+/// A view of a vote-node in the graph.
+#[derive(Debug, PartialEq)]
+pub struct VoteNode<'a, H, N, V> {
+	/// The hash of the block.
+	pub hash: &'a H,
+	/// The number of the block.
+	pub number: N,
+	/// The votes on the block and all of its descendents.
+	pub cumulative_vote: &'a V,
+	/// The hashes of the descendent vote-nodes.
+	pub descendents: Vec<&'a H>,
+}
+
+/// The result of `VoteGraph::find_ancestor_with_weight`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AncestorSearchResult<H, N, V> {
+	/// The hash of the found block.
+	pub hash: H,
+	/// The number of the found block.
+	pub number: N,
+	/// The votes on the found block and all of its descendents.
+	pub cumulative_vote: V,
+	/// The vote-nodes whose cumulative votes make up `cumulative_vote`: the
+	/// block itself if it is a vote-node, otherwise the vote-nodes above it.
+	pub vote_nodes: Vec<H>,
+}
+
+/// A vote-node in a `Snapshot` of the graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub struct SnapshotNode<H, N, V> {
+	/// The hash of the block.
+	pub hash: H,
+	/// The number of the block.
+	pub number: N,
+	/// The ancestor blocks in reverse order, down to the ancestor vote-node.
+	pub ancestors: Vec<H>,
+	/// The indices of the descendent vote-nodes in the snapshot.
+	pub descendents: Vec<u32>,
+	/// The votes on the block and all of its descendents.
+	pub cumulative_vote: V,
+}
+
+/// A snapshot of the vote-graph, from which it can be restored without
+/// re-importing all votes, e.g. to persist a round's votes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub struct Snapshot<H, N, V> {
+	/// The index of the base vote-node.
+	pub base: u32,
+	/// All vote-nodes.
+	pub nodes: Vec<SnapshotNode<H, N, V>>,
+	/// The voters and blocks of votes inserted with `insert_by_voter`.
+	pub voter_votes: Vec<(u32, H)>,
+}
+
 /// Maintains a DAG of blocks in the chain which have votes attached to them,
 /// and vote data which is accumulated along edges.
+///
+/// Vote-nodes are kept in an arena and refer to each other by index, so that
+/// each hash is only stored once in the index besides the ancestor edges.
+/// Ancestor edges share the storage of the blocks they have in common, so
+/// that branching off an edge doesn't copy it, and the search for the point
+/// where descendents merge skips over the blocks they all agree on.
 pub struct VoteGraph<H: Hash + Eq, N, V> {
-	entries: HashMap<H, Entry<H, N, V>>,
-	heads: HashSet<H>,
-	base: H,
-	base_number: N,
+	entries: Vec<Entry<H, N, V>>,
+	indices: HashMap<H, usize>,
+	heads: HashSet<usize>,
+	base: usize,
+	// (voter index, block) pairs inserted with `insert_by_voter`.
+	voter_votes: HashSet<(usize, H)>,
+	// the number of hashes in the storage of the ancestor edges, counting
+	// shared storage once, so that the size of the graph can be estimated
+	// without walking it.
+	ancestor_hashes: usize,
+	// votes inserted directly at each vote-node, used to check the cumulative
+	// votes in tests and when fuzzing.
+	#[cfg(any(test, feature = "fuzzing"))]
+	inserted: HashMap<H, V>,
+	// whether the graph was restored from a snapshot, in which case the votes
+	// inserted before aren't known.
+	#[cfg(any(test, feature = "fuzzing"))]
+	restored: bool,
 }
 
 impl<H, N, V> VoteGraph<H, N, V> where
@@ -93,28 +329,272 @@ impl<H, N, V> VoteGraph<H, N, V> where
 {
 	/// Create a new `VoteGraph` with base node as given.
 	pub fn new(base_hash: H, base_number: N) -> Self {
-		let mut entries = HashMap::new();
-		entries.insert(base_hash.clone(), Entry {
-			number: base_number,
-			ancestors: Vec::new(),
-			descendents: Vec::new(),
-			cumulative_vote: V::default(),
-		});
+		let mut indices = HashMap::new();
+		indices.insert(base_hash.clone(), 0);
 
 		let mut heads = HashSet::new();
-		heads.insert(base_hash.clone());
+		heads.insert(0);
 
 		VoteGraph {
-			entries,
+			entries: vec![Entry {
+				hash: base_hash,
+				number: base_number,
+				ancestors: Ancestry::new(),
+				descendents: Vec::new(),
+				cumulative_vote: V::default(),
+			}],
+			indices,
 			heads,
-			base: base_hash,
-			base_number,
+			base: 0,
+			voter_votes: HashSet::new(),
+			ancestor_hashes: 0,
+			#[cfg(any(test, feature = "fuzzing"))]
+			inserted: HashMap::new(),
+			#[cfg(any(test, feature = "fuzzing"))]
+			restored: false,
 		}
 	}
 
 	/// Get the base block.
 	pub fn base(&self) -> (H, N) {
-		(self.base.clone(), self.base_number)
+		let base = &self.entries[self.base];
+		(base.hash.clone(), base.number)
+	}
+
+	/// Iterate over all vote-nodes in the graph, in no particular order.
+	pub fn iter(&self) -> impl Iterator<Item = VoteNode<'_, H, N, V>> {
+		self.entries.iter().map(move |entry| VoteNode {
+			hash: &entry.hash,
+			number: entry.number,
+			cumulative_vote: &entry.cumulative_vote,
+			descendents: entry.descendents.iter().map(|&d| &self.entries[d].hash).collect(),
+		})
+	}
+
+	/// Iterate over the vote-nodes without descendents.
+	pub fn heads(&self) -> impl Iterator<Item = &H> {
+		self.heads.iter().map(move |&head| &self.entries[head].hash)
+	}
+
+	/// Take a snapshot of the graph.
+	pub fn to_snapshot(&self) -> Snapshot<H, N, V> {
+		let nodes = self.entries.iter().map(|entry| SnapshotNode {
+			hash: entry.hash.clone(),
+			number: entry.number,
+			ancestors: entry.ancestors.to_vec(),
+			descendents: entry.descendents.iter().map(|&d| d as u32).collect(),
+			cumulative_vote: entry.cumulative_vote.clone(),
+		}).collect();
+
+		let voter_votes = self.voter_votes.iter()
+			.map(|(voter, hash)| (*voter as u32, hash.clone()))
+			.collect();
+
+		Snapshot { base: self.base as u32, nodes, voter_votes }
+	}
+
+	/// Restore a graph from a snapshot. Returns `None` if the snapshot doesn't
+	/// describe a consistent graph, e.g. because it was corrupted.
+	pub fn from_snapshot(snapshot: Snapshot<H, N, V>) -> Option<Self> {
+		let base = snapshot.base as usize;
+		if !snapshot.nodes.get(base)?.ancestors.is_empty() { return None }
+
+		let mut indices = HashMap::new();
+		for (idx, node) in snapshot.nodes.iter().enumerate() {
+			if indices.insert(node.hash.clone(), idx).is_some() { return None }
+		}
+
+		// every edge must be consistent in both directions. since numbers
+		// increase along edges, this also rules out cycles.
+		for (idx, node) in snapshot.nodes.iter().enumerate() {
+			for &descendent in &node.descendents {
+				let descendent = snapshot.nodes.get(descendent as usize)?;
+				let consistent = descendent.ancestors.last() == Some(&node.hash) &&
+					descendent.number > node.number &&
+					(descendent.number - node.number).as_() == descendent.ancestors.len();
+
+				if !consistent { return None }
+			}
+
+			if idx != base {
+				let ancestor = &snapshot.nodes[*indices.get(node.ancestors.last()?)?];
+				if !ancestor.descendents.contains(&(idx as u32)) { return None }
+			}
+		}
+
+		let heads = snapshot.nodes.iter()
+			.enumerate()
+			.filter(|(_, node)| node.descendents.is_empty())
+			.map(|(idx, _)| idx)
+			.collect();
+
+		let ancestor_hashes = snapshot.nodes.iter().map(|node| node.ancestors.len()).sum();
+		let entries = snapshot.nodes.into_iter().map(|node| Entry {
+			hash: node.hash,
+			number: node.number,
+			ancestors: Ancestry::from_vec(node.ancestors),
+			descendents: node.descendents.into_iter().map(|d| d as usize).collect(),
+			cumulative_vote: node.cumulative_vote,
+		}).collect();
+
+		let voter_votes = snapshot.voter_votes.into_iter()
+			.map(|(voter, hash)| (voter as usize, hash))
+			.collect();
+
+		Some(VoteGraph {
+			entries,
+			indices,
+			heads,
+			base,
+			voter_votes,
+			ancestor_hashes,
+			#[cfg(any(test, feature = "fuzzing"))]
+			inserted: HashMap::new(),
+			#[cfg(any(test, feature = "fuzzing"))]
+			restored: true,
+		})
+	}
+
+	/// Get the approximate memory used by the graph, in bytes. Any memory
+	/// owned by the vote data itself is not accounted for.
+	///
+	/// This doesn't walk the graph, so it is cheap to call after every change.
+	pub fn approximate_size(&self) -> usize {
+		// every vote-node but the base is the descendent of exactly one other.
+		let descendent_links = self.entries.len() - 1;
+
+		self.entries.len() * mem::size_of::<Entry<H, N, V>>() +
+			self.ancestor_hashes * mem::size_of::<H>() +
+			descendent_links * mem::size_of::<usize>() +
+			self.indices.len() * mem::size_of::<(H, usize)>() +
+			self.heads.len() * mem::size_of::<usize>() +
+			self.voter_votes.len() * mem::size_of::<(usize, H)>()
+	}
+
+	/// Whether the vote-node with the given hash is equal to or a descendent of
+	/// the given block, which needn't be a vote-node. Only the vote-nodes
+	/// between the two are walked.
+	///
+	/// Returns `None` if there is no vote-node with the given hash.
+	pub fn is_equal_or_descendent_of(&self, ancestor: (&H, N), hash: &H) -> Option<bool> {
+		let (ancestor_hash, ancestor_number) = ancestor;
+		let mut entry = self.node(hash)?;
+
+		loop {
+			if entry.number == ancestor_number {
+				return Some(&entry.hash == ancestor_hash);
+			}
+
+			if entry.number < ancestor_number {
+				return Some(false);
+			}
+
+			match entry.in_direct_ancestry(ancestor_hash, ancestor_number) {
+				Some(in_ancestry) => return Some(in_ancestry),
+				None => match self.ancestor_index(entry) {
+					Some(idx) => entry = &self.entries[idx],
+					None => return Some(false),
+				},
+			}
+		}
+	}
+
+	// get the vote-node with the given hash.
+	fn node(&self, hash: &H) -> Option<&Entry<H, N, V>> {
+		self.indices.get(hash).map(|&idx| &self.entries[idx])
+	}
+
+	// get the index of the ancestor vote-node of the given one.
+	fn ancestor_index(&self, entry: &Entry<H, N, V>) -> Option<usize> {
+		entry.ancestor_node().map(|hash| self.indices[hash])
+	}
+
+	// add a vote-node to the arena, returning its index.
+	fn push_entry(&mut self, entry: Entry<H, N, V>) -> usize {
+		let idx = self.entries.len();
+		assert!(
+			self.indices.insert(entry.hash.clone(), idx).is_none(),
+			"vote-nodes are only added for blocks which aren't vote-nodes already; qed",
+		);
+
+		self.entries.push(entry);
+		idx
+	}
+
+	// drop all vote-nodes for which `keep` is false, compacting the arena.
+	// descendent links to dropped vote-nodes are removed, the base must be kept.
+	fn retain_entries(&mut self, keep: &[bool]) {
+		let mut remap = vec![None; self.entries.len()];
+		let mut entries = Vec::with_capacity(keep.iter().filter(|&&k| k).count());
+
+		for (idx, entry) in self.entries.drain(..).enumerate() {
+			if keep[idx] {
+				remap[idx] = Some(entries.len());
+				entries.push(entry);
+			}
+		}
+
+		for entry in &mut entries {
+			entry.descendents = entry.descendents.iter().filter_map(|&d| remap[d]).collect();
+		}
+
+		self.indices = entries.iter().enumerate().map(|(idx, entry)| (entry.hash.clone(), idx)).collect();
+		self.heads = self.heads.iter().filter_map(|&head| remap[head]).collect();
+		self.base = remap[self.base].expect("base is always kept; qed");
+		self.entries = entries;
+		self.compact_ancestors();
+
+		#[cfg(any(test, feature = "fuzzing"))]
+		{
+			let indices = &self.indices;
+			self.inserted.retain(|hash, _| indices.contains_key(hash));
+		}
+	}
+
+	// move the blocks of ancestor edges out of storage which they only use
+	// part of anymore, e.g. after edges were truncated or vote-nodes were
+	// dropped, so that the rest of the storage is freed. recounts the stored
+	// hashes.
+	fn compact_ancestors(&mut self) {
+		// the range of each storage which is still used by any edge.
+		let mut used: HashMap<*const H, (usize, usize)> = HashMap::new();
+		for span in self.entries.iter().flat_map(|entry| entry.ancestors.spans()) {
+			let range = used.entry(span.storage()).or_insert((span.start, span.end));
+			range.0 = range.0.min(span.start);
+			range.1 = range.1.max(span.end);
+		}
+
+		// the old storage is kept until all edges have been moved, so that
+		// new storage can't be allocated where it was.
+		let mut moved: HashMap<*const H, Arc<[H]>> = HashMap::new();
+		let mut old = Vec::new();
+		for entry in &mut self.entries {
+			for (_, span) in &mut entry.ancestors.spans {
+				let (start, end) = used[&span.storage()];
+				if start == 0 && end == span.blocks.len() { continue }
+
+				let blocks = moved.entry(span.storage()).or_insert_with(|| {
+					old.push(span.blocks.clone());
+					span.blocks[start..end].to_vec().into()
+				});
+
+				span.blocks = blocks.clone();
+				span.start -= start;
+				span.end -= start;
+			}
+		}
+
+		self.ancestor_hashes = used.values().map(|(start, end)| end - start).sum();
+	}
+
+	// drop an ancestor edge, uncounting storage which isn't shared with other
+	// edges.
+	fn release(&mut self, ancestry: Ancestry<H>) {
+		for (_, span) in ancestry.spans {
+			if Arc::strong_count(&span.blocks) == 1 {
+				self.ancestor_hashes -= span.blocks.len();
+			}
+		}
 	}
 
 	/// Adjust the base of the graph. The new base must be an ancestor of the
@@ -128,15 +608,17 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			Some(h) => h,
 		};
 
+		let base_number = self.entries[self.base].number;
+
 		// not a valid ancestry proof. TODO: error?
-		if ancestry_proof.len() > self.base_number.as_() { return }
+		if ancestry_proof.len() > base_number.as_() { return }
 
 		// hack because we can't convert usize -> N, only vice-versa.
 		// hopefully LLVM can optimize.
 		//
 		// TODO: Add TryFrom to `BlockNumberOps`.
 		let new_number = {
-			let mut new_number = self.base_number;
+			let mut new_number = base_number;
 			for _ in 0..ancestry_proof.len() {
 				new_number = new_number - N::one();
 			}
@@ -144,22 +626,166 @@ impl<H, N, V> VoteGraph<H, N, V> where
 		};
 
 		let entry = {
-			let old_entry = self.entries.get_mut(&self.base)
-				.expect("base hash entry always exists; qed");
-
-			old_entry.ancestors.extend(ancestry_proof.iter().cloned());
+			let old_entry = &mut self.entries[self.base];
+			old_entry.ancestors.append(Ancestry::from_vec(ancestry_proof.to_vec()));
+			self.ancestor_hashes += ancestry_proof.len();
 
 			Entry {
+				hash: new_hash.clone(),
 				number: new_number,
-				ancestors: Vec::new(),
-				descendents: vec![self.base.clone()],
+				ancestors: Ancestry::new(),
+				descendents: vec![self.base],
 				cumulative_vote: old_entry.cumulative_vote.clone(),
 			}
 		};
 
-		self.entries.insert(new_hash.clone(), entry);
-		self.base = new_hash.clone();
-		self.base_number = new_number;
+		self.base = self.push_entry(entry);
+	}
+
+	/// Raise the base of the graph to a descendent of the current base,
+	/// discarding all vote-nodes which aren't equal to or descendents of the
+	/// new base, along with their votes. Ancestor edges are truncated at the
+	/// new base, so that the graph doesn't keep growing in long-running rounds.
+	///
+	/// Does nothing if the new base isn't higher than the current one.
+	pub fn raise_base(&mut self, hash: H, number: N) {
+		if number <= self.entries[self.base].number { return }
+
+		// the vote-nodes which will be directly below the new base.
+		let children: Vec<usize> = self.entries.iter()
+			.enumerate()
+			.filter(|(_, entry)| entry.in_direct_ancestry(&hash, number) == Some(true))
+			.map(|(idx, _)| idx)
+			.collect();
+
+		let mut cumulative_vote = V::default();
+		for &child in &children {
+			let entry = &mut self.entries[child];
+			let offset: usize = (entry.number - number - N::one()).as_();
+			entry.ancestors.truncate(offset + 1);
+			cumulative_vote += entry.cumulative_vote.clone();
+		}
+
+		let base = match self.indices.get(&hash) {
+			Some(&idx) => {
+				self.entries[idx].ancestors.truncate(0);
+				idx
+			}
+			None => self.push_entry(Entry {
+				hash,
+				number,
+				ancestors: Ancestry::new(),
+				descendents: children,
+				cumulative_vote,
+			}),
+		};
+
+		// keep the new base and everything reachable from it.
+		let mut keep = vec![false; self.entries.len()];
+		let mut to_visit = vec![base];
+		while let Some(next) = to_visit.pop() {
+			keep[next] = true;
+			to_visit.extend(self.entries[next].descendents.iter().cloned());
+		}
+
+		self.base = base;
+		self.retain_entries(&keep);
+
+		if self.heads.is_empty() {
+			self.heads.insert(self.base);
+		}
+	}
+
+	/// Prune all vote-nodes which are neither ancestors nor descendents of the
+	/// given block, e.g. once it has been finalized. Returns the sum of the
+	/// pruned votes.
+	///
+	/// Pruned votes still count towards the cumulative votes of the remaining
+	/// ancestors, so the results of queries along the given block's chain are
+	/// unchanged. Does nothing if the block isn't in the graph.
+	pub fn prune_before(&mut self, hash: H, number: N) -> V {
+		// the vote-nodes to keep directly above the vote-node below the block.
+		let (mut keep_above, mut below) = match self.find_containing_nodes(hash.clone(), number) {
+			None => {
+				let idx = self.indices[&hash];
+				(vec![idx], self.ancestor_index(&self.entries[idx]))
+			}
+			Some(containing) => match containing.first() {
+				None => return V::default(),
+				Some(&node) => {
+					let below = self.ancestor_index(&self.entries[node]);
+					(containing, below)
+				}
+			},
+		};
+
+		let mut keep = vec![true; self.entries.len()];
+		let mut pruned = V::default();
+		while let Some(node) = below {
+			let (kept, dropped): (Vec<_>, Vec<_>) = self.entries[node].descendents.drain(..)
+				.partition(|descendent| keep_above.contains(descendent));
+
+			self.entries[node].descendents = kept;
+
+			let mut dropped_vote = V::default();
+			for &descendent in &dropped {
+				dropped_vote += self.entries[descendent].cumulative_vote.clone();
+			}
+
+			let mut to_remove = dropped;
+			while let Some(idx) = to_remove.pop() {
+				keep[idx] = false;
+				to_remove.extend(self.entries[idx].descendents.iter().cloned());
+			}
+
+			#[cfg(any(test, feature = "fuzzing"))]
+			{
+				let hash = self.entries[node].hash.clone();
+				*self.inserted.entry(hash).or_default() += dropped_vote.clone();
+			}
+
+			pruned += dropped_vote;
+			keep_above = vec![node];
+			below = self.ancestor_index(&self.entries[node]);
+		}
+
+		if keep.contains(&false) {
+			self.retain_entries(&keep);
+		}
+
+		pruned
+	}
+
+	/// Get the cumulative votes on the given block and all of its descendents.
+	/// The block doesn't need to be a vote-node: votes on all vote-nodes whose
+	/// edges contain it are summed, and ancestors of the base get the base's
+	/// votes. Blocks which aren't in the graph have no votes.
+	pub fn cumulative_vote_at<C: Chain<H, N>>(&self, hash: H, number: N, chain: &C) -> V {
+		let base = &self.entries[self.base];
+		if number < base.number {
+			return if chain.is_equal_or_descendent_of(hash, base.hash.clone()) {
+				base.cumulative_vote.clone()
+			} else {
+				V::default()
+			};
+		}
+
+		self.votes_at(hash, number).0
+	}
+
+	// get the cumulative votes on the given block, which must not be below the
+	// base, along with the vote-nodes contributing to them: the block itself if
+	// it is a vote-node, or all vote-nodes whose edges contain it.
+	fn votes_at(&self, hash: H, number: N) -> (V, Vec<usize>) {
+		let nodes = self.find_containing_nodes(hash.clone(), number)
+			.unwrap_or_else(|| vec![self.indices[&hash]]);
+
+		let mut cumulative_vote = V::default();
+		for &node in &nodes {
+			cumulative_vote += self.entries[node].cumulative_vote.clone();
+		}
+
+		(cumulative_vote, nodes)
 	}
 
 	/// Insert a vote with given value into the graph at given hash and number.
@@ -174,51 +800,65 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			// this entry already exists
 		}
 
+		#[cfg(any(test, feature = "fuzzing"))]
+		{
+			*self.inserted.entry(hash.clone()).or_default() += vote.clone();
+		}
+
 		// update cumulative vote data.
 		// NOTE: below this point, there always exists a node with the given hash and number.
-		let mut inspecting_hash = hash;
-		loop {
-			let active_entry = self.entries.get_mut(&inspecting_hash)
-				.expect("vote-node and its ancestry always exist after initial phase; qed");
+		let mut inspecting = Some(self.indices[&hash]);
+		while let Some(idx) = inspecting {
+			self.entries[idx].cumulative_vote += vote.clone();
+			inspecting = self.ancestor_index(&self.entries[idx]);
+		}
 
-			active_entry.cumulative_vote += vote.clone();
+		Ok(())
+	}
 
-			match active_entry.ancestor_node() {
-				Some(parent) => { inspecting_hash = parent },
-				None => break,
-			}
+	/// Insert a vote from the voter with the given index, e.g. its index in the
+	/// voter set. Returns `false` without changing the graph if a vote from
+	/// that voter on the same block was inserted already, so that redelivered
+	/// votes aren't counted twice.
+	pub fn insert_by_voter<C: Chain<H, N>>(
+		&mut self,
+		voter: usize,
+		hash: H,
+		number: N,
+		vote: V,
+		chain: &C,
+	) -> Result<bool, Error> {
+		if self.voter_votes.contains(&(voter, hash.clone())) {
+			return Ok(false);
 		}
 
-		Ok(())
+		self.insert(hash.clone(), number, vote, chain)?;
+		self.voter_votes.insert((voter, hash));
+
+		Ok(true)
 	}
 
 	/// Find the highest block which is either an ancestor of or equal to the given, which fulfills a
 	/// condition.
-	pub fn find_ancestor<'a, F>(&'a self, hash: H, number: N, condition: F) -> Option<(H, N)>
+	pub fn find_ancestor<F>(&self, hash: H, number: N, condition: F) -> Option<(H, N)>
 		where F: Fn(&V) -> bool
 	{
-		let entries = &self.entries;
-		let get_node = |hash: &_| -> &'a _ {
-			entries.get(hash)
-				.expect("node either base or referenced by other in graph; qed")
-		};
-
 		// we store two nodes with an edge between them that is the canonical
 		// chain.
 		// the `node_key` always points to the ancestor node, and the `canonical_node`
 		// points to the higher node.
 		let (mut node_key, mut canonical_node) = match self.find_containing_nodes(hash.clone(), number) {
 			None =>	{
-				let node = get_node(&hash);
+				let node = self.node(&hash).expect("find_containing_nodes only returns None for vote-nodes; qed");
 				if condition(&node.cumulative_vote) {
 					return Some((hash, number))
 				}
 
-				(node.ancestor_node()?, node)
+				(self.ancestor_index(node)?, node)
 			}
 			Some(ref x) if !x.is_empty() => {
-				let node = get_node(&x[0]);
-				let key = node.ancestor_node()
+				let node = &self.entries[x[0]];
+				let key = self.ancestor_index(node)
 					.expect("node containing block in ancestry has ancestor node; qed");
 
 				(key, node)
@@ -228,28 +868,56 @@ impl<H, N, V> VoteGraph<H, N, V> where
 
 		// search backwards until we find the first vote-node that
 		// meets the condition.
-		let mut active_node = get_node(&node_key);
+		let mut active_node = &self.entries[node_key];
 		while !condition(&active_node.cumulative_vote) {
-			node_key = match active_node.ancestor_node() {
-				Some(n) => n,
-				None => return None,
-			};
+			node_key = self.ancestor_index(active_node)?;
 
 			canonical_node = active_node;
-			active_node = get_node(&node_key);
+			active_node = &self.entries[node_key];
 		}
 
 		// find the GHOST merge-point after the active_node.
-		// constrain it to be within the canonical chain.
-		let good_subchain = self.ghost_find_merge_point(node_key, active_node, None, condition);
+		// constrain it to be within the canonical chain, and not above the
+		// given block.
+		let good_subchain = self.ghost_find_merge_point(active_node, None, condition);
+
+		// the good subchain and the canonical chain both start at the active
+		// node and agree up to where they fork, so the highest block on both
+		// can be searched for.
+		let top = std::cmp::min(
+			std::cmp::min(good_subchain.best_number, number),
+			canonical_node.number - N::one(),
+		);
+
+		// the given number may be wrong for the block.
+		if top < active_node.number { return None }
+
+		let good_number = highest_where(active_node.number, top, |n| {
+			canonical_node.ancestor_block(n) == good_subchain.block(n)
+		});
 
-		// FIXME: binding is required for some reason.
-		let mut blocks_reverse = good_subchain.blocks_reverse();
+		good_subchain.block(good_number).map(|good_hash| (good_hash.clone(), good_number))
+	}
+
+	/// Like `find_ancestor`, but also returns the cumulative votes on the found
+	/// block which fulfilled the condition and the vote-nodes contributing to
+	/// them.
+	pub fn find_ancestor_with_weight<F>(
+		&self,
+		hash: H,
+		number: N,
+		condition: F,
+	) -> Option<AncestorSearchResult<H, N, V>>
+		where F: Fn(&V) -> bool
+	{
+		let (hash, number) = self.find_ancestor(hash, number, condition)?;
+		let (cumulative_vote, nodes) = self.votes_at(hash.clone(), number);
 
-		blocks_reverse.find(|&(ref good_hash, good_number)| {
-			canonical_node
-				.in_direct_ancestry(good_hash, good_number)
-				.unwrap_or(false)
+		Some(AncestorSearchResult {
+			hash,
+			number,
+			cumulative_vote,
+			vote_nodes: nodes.into_iter().map(|node| self.entries[node].hash.clone()).collect(),
 		})
 	}
 
@@ -261,33 +929,29 @@ impl<H, N, V> VoteGraph<H, N, V> where
 	///
 	/// This assumes that the evaluation closure is one which returns true for at most a single
 	/// descendent of a block, in that only one fork of a block can be "heavy"
-	/// enough to trigger the threshold.
+	/// enough to trigger the threshold. If several are (e.g. due to equivocations), the one
+	/// with the highest number and then the lowest hash is followed, so that the result
+	/// doesn't depend on the order in which votes were imported.
 	///
 	/// Returns `None` when the given `current_best` does not fulfill the condition.
-	pub fn find_ghost<'a, F>(&'a self, current_best: Option<(H, N)>, condition: F) -> Option<(H, N)>
+	pub fn find_ghost<F>(&self, current_best: Option<(H, N)>, condition: F) -> Option<(H, N)>
 		where F: Fn(&V) -> bool
 	{
-		let entries = &self.entries;
-		let get_node = |hash: &_| -> &'a _ {
-			entries.get(hash)
-				.expect("node either base or referenced by other in graph; qed")
-		};
-
-		let (mut node_key, mut force_constrain) = current_best
+		let (node_key, mut force_constrain) = current_best
 			.clone()
 			.and_then(|(hash, number)| match self.find_containing_nodes(hash.clone(), number) {
-				None => Some((hash, false)),
+				None => Some((self.indices[&hash], false)),
 				Some(ref x) if !x.is_empty() => {
-					let ancestor = get_node(&x[0]).ancestor_node()
+					let ancestor = self.ancestor_index(&self.entries[x[0]])
 						.expect("node containing non-node in history always has ancestor; qed");
 
 					Some((ancestor, true))
 				}
 				Some(_) => None,
 			})
-			.unwrap_or_else(|| (self.base.clone(), false));
+			.unwrap_or((self.base, false));
 
-		let mut active_node = get_node(&node_key);
+		let mut active_node = &self.entries[node_key];
 
 		if !condition(&active_node.cumulative_vote) { return None }
 
@@ -295,8 +959,8 @@ impl<H, N, V> VoteGraph<H, N, V> where
 		loop {
 			let next_descendent = active_node.descendents
 				.iter()
-				.map(|d| (d.clone(), get_node(d)))
-				.filter(|&(_, node)| {
+				.map(|&d| &self.entries[d])
+				.filter(|node| {
 					// take only descendents with our block in the ancestry.
 					if let (true, Some(&(ref h, n))) = (force_constrain, current_best.as_ref()) {
 						node.in_direct_ancestry(h, n).unwrap_or(false)
@@ -304,46 +968,44 @@ impl<H, N, V> VoteGraph<H, N, V> where
 						true
 					}
 				})
-				.find(|&(_, node)| condition(&node.cumulative_vote));
+				.filter(|node| condition(&node.cumulative_vote))
+				// break ties deterministically: highest number, then lowest hash.
+				.max_by(|a, b| a.number.cmp(&b.number).then_with(|| b.hash.cmp(&a.hash)));
 
 			match next_descendent {
-				Some((key, node)) => {
+				Some(node) => {
 					// once we've made at least one hop, we don't need to constrain
 					// ancestry anymore.
 					force_constrain = false;
-					node_key = key;
 					active_node = node;
 				}
 				None => break,
 			}
 		}
 
-		// active_node and node_key now correspond to the vote-node with enough cumulative votes.
+		// active_node now corresponds to the vote-node with enough cumulative votes.
 		// its descendents comprise frontier of vote-nodes which individually don't have enough votes
 		// to pass the threshold but some subset of them join either at `active_node`'s block or at some
 		// descendent block of it, giving that block sufficient votes.
 		self.ghost_find_merge_point(
-			node_key,
 			active_node,
 			if force_constrain { current_best } else { None },
 			condition,
 		).best()
 	}
 
-	// given a key, node pair (which must correspond), assuming this node fulfills the condition,
-	// this function will find the highest point at which its descendents merge, which may be the
-	// node itself.
+	// given a node, assuming it fulfills the condition, this function will find the
+	// highest point at which its descendents merge, which may be the node itself.
 	fn ghost_find_merge_point<'a, F>(
 		&'a self,
-		node_key: H,
 		active_node: &'a Entry<H, N, V>,
 		force_constrain: Option<(H, N)>,
 		condition: F,
-	) -> Subchain<H, N>
+	) -> Subchain<'a, H, N, V>
 		where F: Fn(&V) -> bool
 	{
 		let mut descendent_nodes: Vec<_> = active_node.descendents.iter()
-			.map(|h| self.entries.get(h).expect("descendents always present in node storage; qed"))
+			.map(|&d| &self.entries[d])
 			.filter(|n| if let Some((ref h, num)) = force_constrain {
 				n.in_direct_ancestry(h, num).unwrap_or(false)
 			} else {
@@ -351,25 +1013,23 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			})
 			.collect();
 
-		let base_number = active_node.number;
 		let mut best_number = active_node.number;
 		let mut descendent_blocks = Vec::with_capacity(descendent_nodes.len());
-		let mut hashes = vec![node_key];
 
-		// TODO: for long ranges of blocks this could get inefficient
-		let mut offset = N::zero();
 		loop {
-			offset = offset + N::one();
+			let number = best_number + N::one();
 
-			let mut new_best = None;
+			// if several blocks fulfill the condition, take the lowest hash.
+			let mut new_best: Option<H> = None;
 			for d_node in &descendent_nodes {
-				if let Some(d_block) = d_node.ancestor_block(base_number + offset) {
+				if let Some(d_block) = d_node.ancestor_block(number) {
 					match descendent_blocks.binary_search_by_key(&d_block, |&(ref x, _)| x) {
 						Ok(idx) => {
 							descendent_blocks[idx].1 += d_node.cumulative_vote.clone();
-							if condition(&descendent_blocks[idx].1) {
+							if condition(&descendent_blocks[idx].1) &&
+								new_best.as_ref().is_none_or(|best| d_block < best)
+							{
 								new_best = Some(d_block.clone());
-								break
 							}
 						}
 						Err(idx) => descendent_blocks.insert(idx, (
@@ -382,32 +1042,43 @@ impl<H, N, V> VoteGraph<H, N, V> where
 
 			match new_best {
 				Some(new_best) => {
-					best_number = best_number + N::one();
-
 					descendent_blocks.clear();
 					descendent_nodes.retain(
-						|n| n.in_direct_ancestry(&new_best, best_number).unwrap_or(false)
+						|n| n.in_direct_ancestry(&new_best, number).unwrap_or(false)
 					);
 
-					hashes.push(new_best);
+					// the votes on the new best block are those of the remaining
+					// descendents, and so are the votes on every block above it
+					// which they all have in common. skip to the highest of those.
+					let first = descendent_nodes[0];
+					let top = descendent_nodes.iter()
+						.map(|n| n.number)
+						.min()
+						.expect("the new best block is in the ancestry of a descendent; qed") - N::one();
+
+					best_number = highest_where(number, top, |n| {
+						let block = first.ancestor_block(n);
+						descendent_nodes.iter().all(|d_node| d_node.ancestor_block(n) == block)
+					});
 				}
 				None => break,
 			}
 		}
 
 		Subchain {
-			hashes,
+			node: active_node,
+			through: descendent_nodes.first().cloned(),
 			best_number,
 		}
 	}
 
-	// attempts to find the containing node keys for the given hash and number.
+	// attempts to find the containing node indices for the given hash and number.
 	//
 	// returns `None` if there is a node by that key already, and a vector
 	// (potentially empty) of nodes with the given block in its ancestor-edge
 	// otherwise.
-	fn find_containing_nodes(&self, hash: H, number: N) -> Option<Vec<H>> {
-		if self.entries.contains_key(&hash) {
+	fn find_containing_nodes(&self, hash: H, number: N) -> Option<Vec<usize>> {
+		if self.indices.contains_key(&hash) {
 			return None
 		}
 
@@ -417,24 +1088,18 @@ impl<H, N, V> VoteGraph<H, N, V> where
 		// iterate vote-heads and their ancestry backwards until we find the one with
 		// this target hash in that chain.
 		for mut head in self.heads.iter().cloned() {
-			let mut active_entry;
-
 			loop {
-				active_entry = match self.entries.get(&head) {
-					Some(e) => e,
-					None => break,
-				};
-
 				// if node has been checked already, break
-				if !visited.insert(head.clone()) { break }
+				if !visited.insert(head) { break }
 
+				let active_entry = &self.entries[head];
 				match active_entry.in_direct_ancestry(&hash, number) {
 					Some(true) => {
 						// set containing node and continue search.
-						containing_keys.push(head.clone());
+						containing_keys.push(head);
 					}
 					Some(false) => {}, // nothing in this branch. continue search.
-					None => if let Some(prev) = active_entry.ancestor_node() {
+					None => if let Some(prev) = self.ancestor_index(active_entry) {
 						head = prev;
 						continue // iterate backwards
 					},
@@ -454,37 +1119,43 @@ impl<H, N, V> VoteGraph<H, N, V> where
 	// This function panics if any member of `descendents` is not a vote-node
 	// or does not have ancestor with given hash and number OR if `ancestor_hash`
 	// is already a known entry.
-	fn introduce_branch(&mut self, descendents: Vec<H>, ancestor_hash: H, ancestor_number: N) {
+	fn introduce_branch(&mut self, descendents: Vec<usize>, ancestor_hash: H, ancestor_number: N) {
+		let new_idx = self.entries.len();
+		let mut released = Vec::new();
 		let produced_entry = descendents.into_iter().fold(None, |mut maybe_entry, descendent| {
-			let entry = self.entries.get_mut(&descendent)
-				.expect("this function only invoked with keys of vote-nodes; qed");
+			let entry = &mut self.entries[descendent];
 
 			debug_assert!(entry.in_direct_ancestry(&ancestor_hash, ancestor_number).unwrap());
 
 			// example: splitting number 10 at ancestor 4
 			// before: [9 8 7 6 5 4 3 2 1]
 			// after: [9 8 7 6 5 4], [3 2 1]
-			// we ensure the `entry.ancestors` is drained regardless of whether
-			// the `new_entry` has already been constructed.
+			// we ensure the `entry.ancestors` is split regardless of whether
+			// the `new_entry` has already been constructed. splitting shares
+			// the storage, so no blocks are copied.
 			{
-				let prev_ancestor = entry.ancestor_node();
+				let prev_ancestor = entry.ancestor_node().cloned();
 				let offset_usize: usize = if ancestor_number > entry.number {
 					panic!("this function only invoked with direct ancestors; qed")
 				} else {
 					(entry.number - ancestor_number).as_()
 				};
-				let new_ancestors = entry.ancestors.drain(offset_usize..);
+				let new_ancestors = entry.ancestors.split_off(offset_usize);
 
-				let &mut (ref mut new_entry, _) = maybe_entry.get_or_insert_with(move || {
-					let new_entry = Entry {
+				let (new_entry, _) = match maybe_entry {
+					Some(ref mut produced) => {
+						// the blocks are in the new vote-node's edge already.
+						released.push(new_ancestors);
+						produced
+					}
+					None => maybe_entry.get_or_insert((Entry {
+						hash: ancestor_hash.clone(),
 						number: ancestor_number,
-						ancestors: new_ancestors.collect(),
+						ancestors: new_ancestors,
 						descendents: vec![],
 						cumulative_vote: V::default(),
-					};
-
-					(new_entry, prev_ancestor)
-				});
+					}, prev_ancestor)),
+				};
 
 				new_entry.descendents.push(descendent);
 				new_entry.cumulative_vote += entry.cumulative_vote.clone();
@@ -493,61 +1164,213 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			maybe_entry
 		});
 
+		for ancestry in released {
+			self.release(ancestry);
+		}
+
 		if let Some((new_entry, prev_ancestor)) = produced_entry {
 			if let Some(prev_ancestor) = prev_ancestor {
-				let prev_ancestor_node = self.entries.get_mut(&prev_ancestor)
-					.expect("Prior ancestor is referenced from a node; qed");
+				let prev_ancestor_node = &mut self.entries[self.indices[&prev_ancestor]];
 
-				prev_ancestor_node.descendents.retain(|h| !new_entry.descendents.contains(h));
-				prev_ancestor_node.descendents.push(ancestor_hash.clone());
+				prev_ancestor_node.descendents.retain(|d| !new_entry.descendents.contains(d));
+				prev_ancestor_node.descendents.push(new_idx);
 			}
 
-			assert!(
-				self.entries.insert(ancestor_hash, new_entry).is_none(),
-				"thus function is only invoked when there is no entry for the ancestor already; qed",
-			)
+			self.push_entry(new_entry);
 		}
 	}
 
 	// append a vote-node onto the chain-tree. This should only be called if
 	// no node in the tree keeps the target anyway.
 	fn append<C: Chain<H, N>>(&mut self, hash: H, number: N, chain: &C) -> Result<(), Error> {
-		let mut ancestry = chain.ancestry(self.base.clone(), hash.clone())?;
-		ancestry.push(self.base.clone()); // ancestry doesn't include base.
-
-		let mut ancestor_index = None;
-		for (i, ancestor) in ancestry.iter().chain(::std::iter::once(&self.base)).enumerate() {
-			if let Some(entry) = self.entries.get_mut(ancestor) {
-				entry.descendents.push(hash.clone());
-				ancestor_index = Some(i);
-				break;
-			}
+		let base = &self.entries[self.base];
+		let (base_hash, base_number) = (base.hash.clone(), base.number);
+		let mut ancestry = chain.ancestry(base_hash.clone(), hash.clone())?;
+
+		// votes may claim any number for their block, which would corrupt the
+		// ancestor edges.
+		if number <= base_number || (number - base_number).as_() != ancestry.len() + 1 {
+			return Err(Error::InvalidBlockNumber);
 		}
 
-		let ancestor_index = ancestor_index.expect("base is kept; \
+		ancestry.push(base_hash); // ancestry doesn't include base.
+
+		let new_idx = self.entries.len();
+		let ancestor = ancestry.iter()
+			.enumerate()
+			.find_map(|(i, ancestor_hash)| self.indices.get(ancestor_hash).map(|&idx| (i, idx)));
+
+		let (ancestor_offset, ancestor_idx) = ancestor.expect("base is kept; \
 			chain returns ancestry only if the block is a descendent of base; qed");
 
-		let ancestor_hash = ancestry[ancestor_index].clone();
-		ancestry.truncate(ancestor_index + 1);
+		// the new edge may fork off the edge of a descendent of the ancestor
+		// vote-node at a block which isn't a vote-node. find the descendent
+		// with the most blocks in common, whose storage is shared for them.
+		let ancestor_number = self.entries[ancestor_idx].number;
+		let shared = self.entries[ancestor_idx].descendents.iter()
+			.map(|&d| &self.entries[d])
+			.map(|descendent| {
+				let top = std::cmp::min(number, descendent.number) - N::one();
+				let fork = highest_where(ancestor_number, top, |n| {
+					let offset: usize = (number - n - N::one()).as_();
+					descendent.ancestor_block(n) == Some(&ancestry[offset])
+				});
 
-		self.entries.insert(hash.clone(), Entry {
+				(fork, descendent)
+			})
+			.max_by_key(|&(fork, _)| fork)
+			.filter(|&(fork, _)| fork > ancestor_number);
+
+		let ancestors = match shared {
+			Some((fork, descendent)) => {
+				ancestry.truncate((number - fork - N::one()).as_());
+				let rest = descendent.ancestors.clone().split_off((descendent.number - fork - N::one()).as_());
+
+				self.ancestor_hashes += ancestry.len();
+				let mut ancestors = Ancestry::from_vec(ancestry);
+				ancestors.append(rest);
+				ancestors
+			}
+			None => {
+				ancestry.truncate(ancestor_offset + 1);
+				self.ancestor_hashes += ancestry.len();
+				Ancestry::from_vec(ancestry)
+			}
+		};
+
+		self.entries[ancestor_idx].descendents.push(new_idx);
+		self.push_entry(Entry {
+			hash,
 			number,
-			ancestors: ancestry,
+			ancestors,
 			descendents: Vec::new(),
 			cumulative_vote: V::default(),
 		});
 
-		self.heads.remove(&ancestor_hash);
-		self.heads.insert(hash);
+		self.heads.remove(&ancestor_idx);
+		self.heads.insert(new_idx);
 
 		Ok(())
 	}
+
+	// check that the edges of the graph are consistent: every descendent link
+	// is matched by an ancestor edge of the right length, and the heads are
+	// exactly the nodes without descendents. panics otherwise.
+	#[cfg(any(test, feature = "fuzzing"))]
+	fn check_edges(&self) {
+		assert_eq!(self.indices.len(), self.entries.len(), "every vote-node is indexed");
+		for (idx, entry) in self.entries.iter().enumerate() {
+			assert_eq!(self.indices.get(&entry.hash), Some(&idx), "{:?} is indexed", entry.hash);
+		}
+
+		let base = &self.entries[self.base];
+		assert_eq!(base.ancestors.len(), 0, "base entry has no ancestors");
+
+		let storage: HashMap<_, _> = self.entries.iter()
+			.flat_map(|entry| entry.ancestors.spans())
+			.map(|span| (span.storage(), span.blocks.len()))
+			.collect();
+
+		assert_eq!(self.ancestor_hashes, storage.values().sum::<usize>(), "stored ancestor hashes are counted");
+
+		for (idx, entry) in self.entries.iter().enumerate() {
+			let hash = &entry.hash;
+			for &descendent_idx in &entry.descendents {
+				let descendent = self.entries.get(descendent_idx)
+					.unwrap_or_else(|| panic!("descendent {} of {:?} is a vote-node", descendent_idx, hash));
+
+				assert_eq!(
+					descendent.ancestor_node(), Some(hash),
+					"descendent {:?} of {:?} links back to it", descendent.hash, hash,
+				);
+				assert!(
+					descendent.number > entry.number &&
+						(descendent.number - entry.number).as_() == descendent.ancestors.len(),
+					"ancestor edge of {:?} spans exactly the blocks down to {:?}", descendent.hash, hash,
+				);
+			}
+
+			if idx != self.base {
+				let ancestor = entry.ancestor_node()
+					.and_then(|ancestor| self.node(ancestor))
+					.unwrap_or_else(|| panic!("ancestor of {:?} is a vote-node", hash));
+
+				assert!(
+					ancestor.descendents.contains(&idx),
+					"ancestor of {:?} lists it as a descendent", hash,
+				);
+			}
+
+			assert_eq!(
+				entry.descendents.is_empty(), self.heads.contains(&idx),
+				"{:?} is a head if and only if it has no descendents", hash,
+			);
+		}
+
+		assert!(
+			self.heads.iter().all(|&head| head < self.entries.len()),
+			"all heads are vote-nodes",
+		);
+	}
+}
+
+#[cfg(any(test, feature = "fuzzing"))]
+impl<H, N, V> VoteGraph<H, N, V> where
+	H: Hash + Eq + Clone + Ord + Debug,
+	V: AddAssign + Default + Clone + Debug + PartialEq,
+	N: Copy + Debug + BlockNumberOps,
+{
+	/// Check the consistency of the graph, panicking if it's violated. Every
+	/// descendent link must be matched by an ancestor edge of the right length,
+	/// the heads must be exactly the vote-nodes without descendents, and the
+	/// cumulative vote of each vote-node must equal the votes inserted at it
+	/// plus the cumulative votes of its descendents, unless the graph was
+	/// restored from a snapshot.
+	///
+	/// This walks the whole graph, so it is never run implicitly. Only
+	/// available with the `fuzzing` feature.
+	pub fn check_invariants(&self) {
+		self.check_edges();
+
+		// the votes inserted before restoring aren't known.
+		if self.restored { return }
+
+		for entry in &self.entries {
+			let mut expected = self.inserted.get(&entry.hash).cloned().unwrap_or_default();
+			for &descendent in &entry.descendents {
+				expected += self.entries[descendent].cumulative_vote.clone();
+			}
+
+			assert_eq!(entry.cumulative_vote, expected, "cumulative vote of {:?} is consistent", entry.hash);
+		}
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::testing::chain::{GENESIS_HASH, DummyChain};
+	use crate::testing::reference;
+	use proptest::prelude::*;
+
+	impl VoteGraph<&'static str, u32, u32> {
+		fn entry(&self, hash: &'static str) -> &Entry<&'static str, u32, u32> {
+			&self.entries[self.indices[&hash]]
+		}
+
+		fn entry_mut(&mut self, hash: &'static str) -> &mut Entry<&'static str, u32, u32> {
+			let idx = self.indices[&hash];
+			&mut self.entries[idx]
+		}
+
+		fn descendents(&self, hash: &'static str) -> Vec<&'static str> {
+			self.entry(hash).descendents.iter().map(|&d| self.entries[d].hash).collect()
+		}
+
+		fn is_head(&self, hash: &'static str) -> bool {
+			self.heads().any(|head| head == &hash)
+		}
+	}
 
 	#[test]
 	fn graph_fork_not_at_node() {
@@ -562,21 +1385,21 @@ mod tests {
 		tracker.insert("E1", 6, 100, &chain).unwrap();
 		tracker.insert("F2", 7, 100, &chain).unwrap();
 
-		assert!(tracker.heads.contains("E1"));
-		assert!(tracker.heads.contains("F2"));
-		assert!(!tracker.heads.contains("A"));
+		assert!(tracker.is_head("E1"));
+		assert!(tracker.is_head("F2"));
+		assert!(!tracker.is_head("A"));
 
-		let a_entry = tracker.entries.get("A").unwrap();
-		assert_eq!(a_entry.descendents, vec!["E1", "F2"]);
+		let a_entry = tracker.entry("A");
+		assert_eq!(tracker.descendents("A"), vec!["E1", "F2"]);
 		assert_eq!(a_entry.cumulative_vote, 300);
 
 
-		let e_entry = tracker.entries.get("E1").unwrap();
-		assert_eq!(e_entry.ancestor_node().unwrap(), "A");
+		let e_entry = tracker.entry("E1");
+		assert_eq!(e_entry.ancestor_node(), Some(&"A"));
 		assert_eq!(e_entry.cumulative_vote, 100);
 
-		let f_entry = tracker.entries.get("F2").unwrap();
-		assert_eq!(f_entry.ancestor_node().unwrap(), "A");
+		let f_entry = tracker.entry("F2");
+		assert_eq!(f_entry.ancestor_node(), Some(&"A"));
 		assert_eq!(f_entry.cumulative_vote, 100);
 	}
 
@@ -599,22 +1422,22 @@ mod tests {
 		tracker2.insert("C", 4, 100, &chain).unwrap();
 
 		for tracker in &[&tracker2] {
-			assert!(tracker.heads.contains("E1"));
-			assert!(tracker.heads.contains("F2"));
-			assert!(!tracker.heads.contains("C"));
-
-			let c_entry = tracker.entries.get("C").unwrap();
-			assert!(c_entry.descendents.contains(&"E1"));
-			assert!(c_entry.descendents.contains(&"F2"));
-			assert_eq!(c_entry.ancestor_node().unwrap(), GENESIS_HASH);
+			assert!(tracker.is_head("E1"));
+			assert!(tracker.is_head("F2"));
+			assert!(!tracker.is_head("C"));
+
+			let c_entry = tracker.entry("C");
+			assert!(tracker.descendents("C").contains(&"E1"));
+			assert!(tracker.descendents("C").contains(&"F2"));
+			assert_eq!(c_entry.ancestor_node(), Some(&GENESIS_HASH));
 			assert_eq!(c_entry.cumulative_vote, 300);
 
-			let e_entry = tracker.entries.get("E1").unwrap();
-			assert_eq!(e_entry.ancestor_node().unwrap(), "C");
+			let e_entry = tracker.entry("E1");
+			assert_eq!(e_entry.ancestor_node(), Some(&"C"));
 			assert_eq!(e_entry.cumulative_vote, 100);
 
-			let f_entry = tracker.entries.get("F2").unwrap();
-			assert_eq!(f_entry.ancestor_node().unwrap(), "C");
+			let f_entry = tracker.entry("F2");
+			assert_eq!(f_entry.ancestor_node(), Some(&"C"));
 			assert_eq!(f_entry.cumulative_vote, 100);
 		}
 	}
@@ -671,13 +1494,13 @@ mod tests {
 
 		assert_eq!(tracker.find_ghost(None, |&x| x >= 10), Some(("E", 6)));
 
-		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().descendents, vec!["FC", "ED"]);
+		assert_eq!(tracker.descendents(GENESIS_HASH), vec!["FC", "ED"]);
 
 		// introduce a branch in the middle.
 		tracker.insert("E", 6, 3, &chain).unwrap();
 
-		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().descendents, vec!["E"]);
-		let descendents = &tracker.entries.get("E").unwrap().descendents;
+		assert_eq!(tracker.descendents(GENESIS_HASH), vec!["E"]);
+		let descendents = tracker.descendents("E");
 		assert_eq!(descendents.len(), 2);
 		assert!(descendents.contains(&"ED"));
 		assert!(descendents.contains(&"FC"));
@@ -798,10 +1621,540 @@ mod tests {
 		tracker.adjust_base(&[GENESIS_HASH]);
 		assert_eq!(tracker.base(), (GENESIS_HASH, 1));
 
-		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().cumulative_vote, 12);
+		assert_eq!(tracker.entry(GENESIS_HASH).cumulative_vote, 12);
 
 		tracker.insert("5", 5, 3, &chain).unwrap();
 
-		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().cumulative_vote, 15);
+		assert_eq!(tracker.entry(GENESIS_HASH).cumulative_vote, 15);
+	}
+
+	#[test]
+	fn raise_base_discards_lower_nodes() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("A", &["B1", "C1"]);
+		chain.push_blocks("C", &["D2", "E2"]);
+
+		tracker.insert("C1", 4, 1u32, &chain).unwrap();
+		tracker.insert("E", 6, 5, &chain).unwrap();
+		tracker.insert("E2", 6, 3, &chain).unwrap();
+		tracker.insert("C", 4, 2, &chain).unwrap();
+
+		// raise to a block between vote-nodes.
+		tracker.raise_base("B", 3);
+		assert_eq!(tracker.base(), ("B", 3));
+		assert!(!tracker.indices.contains_key(GENESIS_HASH));
+		assert!(!tracker.indices.contains_key("C1"));
+		assert!(!tracker.is_head("C1"));
+		assert_eq!(tracker.entry("B").cumulative_vote, 10);
+		assert_eq!(tracker.entry("C").ancestors.to_vec(), vec!["B"]);
+
+		// raise to an existing vote-node.
+		tracker.raise_base("C", 4);
+		assert_eq!(tracker.base(), ("C", 4));
+		assert_eq!(tracker.entry("C").ancestors.len(), 0);
+		assert_eq!(tracker.entries.len(), 3);
+
+		// raise past a fork.
+		tracker.raise_base("D", 5);
+		assert_eq!(tracker.base(), ("D", 5));
+		assert_eq!(tracker.entry("D").cumulative_vote, 5);
+		assert_eq!(tracker.heads().collect::<Vec<_>>(), vec![&"E"]);
+
+		// lowering is ignored.
+		tracker.raise_base("B", 3);
+		assert_eq!(tracker.base(), ("D", 5));
+
+		tracker.check_invariants();
+	}
+
+	#[test]
+	fn forks_share_the_blocks_they_have_in_common() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+
+		tracker.insert("A", 2, 100u32, &chain).unwrap();
+		tracker.insert("E1", 6, 100, &chain).unwrap();
+		tracker.insert("F2", 7, 100, &chain).unwrap();
+
+		// only the blocks above the fork are stored for the second edge.
+		let storage = |hash| tracker.entry(hash).ancestors.spans().map(|span| span.storage()).collect::<Vec<_>>();
+		assert_eq!(tracker.entry("F2").ancestors.to_vec(), vec!["E2", "D2", "C", "B", "A"]);
+		assert!(storage("F2").contains(&storage("E1")[0]));
+		assert_eq!(tracker.ancestor_hashes, 1 + 4 + 2);
+
+		// splitting the edges at the fork keeps sharing the storage.
+		tracker.insert("C", 4, 0, &chain).unwrap();
+		assert_eq!(tracker.entry("C").ancestors.to_vec(), vec!["B", "A"]);
+		assert_eq!(tracker.entry("E1").ancestors.to_vec(), vec!["D1", "C"]);
+		assert_eq!(tracker.entry("F2").ancestors.to_vec(), vec!["E2", "D2", "C"]);
+		assert_eq!(tracker.ancestor_hashes, 1 + 4 + 2);
+		assert_eq!(tracker.find_ghost(None, |&x| x >= 200), Some(("C", 4)));
+
+		// storage which is only partly used anymore is freed.
+		tracker.raise_base("D2", 5);
+		assert_eq!(tracker.entry("F2").ancestors.to_vec(), vec!["E2", "D2"]);
+		assert_eq!(tracker.ancestor_hashes, 2);
+
+		tracker.check_invariants();
+	}
+
+	#[test]
+	fn merge_point_search_skips_common_blocks() {
+		use std::cell::Cell;
+
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		let blocks: Vec<&'static str> = (0..10_000)
+			.map(|i| &*Box::leak(format!("B{}", i).into_boxed_str()))
+			.collect();
+
+		let last = *blocks.last().unwrap();
+		chain.push_blocks(GENESIS_HASH, &blocks);
+		chain.push_blocks(last, &["X"]);
+		chain.push_blocks(last, &["Y"]);
+
+		tracker.insert("X", 10_002, 1u32, &chain).unwrap();
+		tracker.insert("Y", 10_002, 1, &chain).unwrap();
+		assert_eq!(tracker.ancestor_hashes, 10_001);
+
+		let evaluated = Cell::new(0);
+		let condition = |&x: &u32| {
+			evaluated.set(evaluated.get() + 1);
+			x >= 2
+		};
+
+		assert_eq!(tracker.find_ghost(None, condition), Some((last, 10_001)));
+		assert_eq!(tracker.find_ancestor("X", 10_002, condition), Some((last, 10_001)));
+		assert!(evaluated.get() < 100);
+	}
+
+	#[test]
+	fn iterates_vote_nodes() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B1"]);
+
+		tracker.insert("C", 4, 5u32, &chain).unwrap();
+		tracker.insert("B1", 3, 3, &chain).unwrap();
+
+		let mut nodes = tracker.iter().collect::<Vec<_>>();
+		nodes.sort_by_key(|node| *node.hash);
+
+		assert_eq!(nodes.len(), 3);
+		assert_eq!(nodes[0].hash, &"B1");
+		assert_eq!(nodes[0].cumulative_vote, &3);
+		assert_eq!(nodes[1].hash, &"C");
+		assert_eq!(nodes[1].number, 4);
+		assert_eq!(nodes[2].hash, &GENESIS_HASH);
+		assert_eq!(nodes[2].cumulative_vote, &8);
+		assert_eq!(nodes[2].descendents.len(), 2);
+
+		let mut heads = tracker.heads().cloned().collect::<Vec<_>>();
+		heads.sort();
+		assert_eq!(heads, vec!["B1", "C"]);
+	}
+
+	#[test]
+	fn checks_ancestry_of_vote_nodes() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+		chain.push_blocks("B", &["C1"]);
+
+		tracker.insert("B", 3, 1u32, &chain).unwrap();
+		tracker.insert("D", 5, 1, &chain).unwrap();
+		tracker.insert("C1", 4, 1, &chain).unwrap();
+
+		// across vote-nodes and within edges.
+		assert_eq!(tracker.is_equal_or_descendent_of((&"A", 2), &"D"), Some(true));
+		assert_eq!(tracker.is_equal_or_descendent_of((&"B", 3), &"C1"), Some(true));
+		assert_eq!(tracker.is_equal_or_descendent_of((&"C", 4), &"D"), Some(true));
+		assert_eq!(tracker.is_equal_or_descendent_of((&"D", 5), &"D"), Some(true));
+
+		// other forks and higher blocks.
+		assert_eq!(tracker.is_equal_or_descendent_of((&"C", 4), &"C1"), Some(false));
+		assert_eq!(tracker.is_equal_or_descendent_of((&"D", 5), &"B"), Some(false));
+
+		// not a vote-node.
+		assert_eq!(tracker.is_equal_or_descendent_of((&"A", 2), &"C"), None);
+	}
+
+	#[test]
+	fn cumulative_vote_at_blocks_within_edges() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new("A", 2);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+		chain.push_blocks("B", &["C1", "D1"]);
+		chain.push_blocks("A", &["B2"]);
+
+		tracker.insert("D", 5, 5u32, &chain).unwrap();
+		tracker.insert("D1", 5, 3, &chain).unwrap();
+		tracker.insert("B2", 3, 1, &chain).unwrap();
+
+		// vote-nodes.
+		assert_eq!(tracker.cumulative_vote_at("A", 2, &chain), 9);
+		assert_eq!(tracker.cumulative_vote_at("D", 5, &chain), 5);
+
+		// within edges.
+		assert_eq!(tracker.cumulative_vote_at("B", 3, &chain), 8);
+		assert_eq!(tracker.cumulative_vote_at("C1", 4, &chain), 3);
+
+		// below the base.
+		assert_eq!(tracker.cumulative_vote_at(GENESIS_HASH, 1, &chain), 9);
+
+		// not in the graph.
+		assert_eq!(tracker.cumulative_vote_at("E", 6, &chain), 0);
+	}
+
+	#[test]
+	fn ghost_ties_are_broken_deterministically() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B"]);
+		chain.push_blocks("A", &["B1", "C1"]);
+		chain.push_blocks("A", &["B2"]);
+		chain.push_blocks("B", &["C", "D"]);
+		chain.push_blocks("B", &["C3", "D3"]);
+		chain.push_blocks("C", &["DX"]);
+		chain.push_blocks("C3", &["DY"]);
+
+		let ghost = |votes: &[(&'static str, u32)]| {
+			let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+			for &(hash, number) in votes {
+				tracker.insert(hash, number, 5u32, &chain).unwrap();
+			}
+
+			tracker.find_ghost(None, |&x| x >= 5)
+		};
+
+		// the highest vote-node.
+		assert_eq!(ghost(&[("B2", 3), ("C1", 4)]), Some(("C1", 4)));
+		assert_eq!(ghost(&[("C1", 4), ("B2", 3)]), Some(("C1", 4)));
+
+		// the lowest hash at the same number.
+		assert_eq!(ghost(&[("C1", 4), ("C", 4)]), Some(("C", 4)));
+		assert_eq!(ghost(&[("C", 4), ("C1", 4)]), Some(("C", 4)));
+
+		// within the merge-point search.
+		let merge_ghost = |votes: &[(&'static str, u32)]| {
+			let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+			for &(hash, number) in votes {
+				tracker.insert(hash, number, 3u32, &chain).unwrap();
+			}
+
+			tracker.find_ghost(None, |&x| x >= 6)
+		};
+
+		assert_eq!(merge_ghost(&[("D", 5), ("DX", 5), ("D3", 5), ("DY", 5)]), Some(("C", 4)));
+		assert_eq!(merge_ghost(&[("D3", 5), ("DY", 5), ("D", 5), ("DX", 5)]), Some(("C", 4)));
+	}
+
+	#[test]
+	fn votes_by_voter_are_only_counted_once() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		assert!(tracker.insert_by_voter(0, "C", 4, 5u32, &chain).unwrap());
+		assert!(!tracker.insert_by_voter(0, "C", 4, 5, &chain).unwrap());
+		assert!(tracker.insert_by_voter(1, "C", 4, 5, &chain).unwrap());
+		assert!(tracker.insert_by_voter(0, "B", 3, 5, &chain).unwrap());
+
+		assert_eq!(tracker.entry("C").cumulative_vote, 10);
+		assert_eq!(tracker.entry(GENESIS_HASH).cumulative_vote, 15);
+
+		// unknown blocks aren't recorded.
+		assert!(tracker.insert_by_voter(2, "D", 5, 5, &chain).is_err());
+		chain.push_blocks("C", &["D"]);
+		assert!(tracker.insert_by_voter(2, "D", 5, 5, &chain).unwrap());
+	}
+
+	#[test]
+	fn restores_from_snapshot() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+		chain.push_blocks("B", &["C1", "D1"]);
+		chain.push_blocks("D", &["E"]);
+
+		tracker.insert("D", 5, 5u32, &chain).unwrap();
+		tracker.insert("D1", 5, 3, &chain).unwrap();
+		tracker.insert_by_voter(0, "B", 3, 1, &chain).unwrap();
+
+		let snapshot = tracker.to_snapshot();
+		let mut restored = VoteGraph::from_snapshot(snapshot.clone()).unwrap();
+		assert_eq!(restored.to_snapshot(), snapshot);
+		assert_eq!(restored.find_ghost(None, |&x| x >= 9), Some(("B", 3)));
+		assert_eq!(restored.find_ghost(None, |&x| x >= 5), Some(("D", 5)));
+		assert!(!restored.insert_by_voter(0, "B", 3, 1, &chain).unwrap());
+
+		// the restored graph keeps accepting votes.
+		restored.insert("E", 6, 2, &chain).unwrap();
+		tracker.insert("E", 6, 2, &chain).unwrap();
+		assert_eq!(restored.cumulative_vote_at("C", 4, &chain), 7);
+		assert_eq!(restored.find_ghost(None, |&x| x >= 7), tracker.find_ghost(None, |&x| x >= 7));
+
+		// inconsistent snapshots are rejected.
+		let mut corrupted = snapshot.clone();
+		corrupted.base = 10;
+		assert!(VoteGraph::from_snapshot(corrupted).is_none());
+
+		let mut corrupted = snapshot.clone();
+		corrupted.nodes[0].descendents.pop();
+		assert!(VoteGraph::from_snapshot(corrupted).is_none());
+
+		let mut corrupted = snapshot;
+		corrupted.nodes[1].ancestors.pop();
+		assert!(VoteGraph::from_snapshot(corrupted).is_none());
+	}
+
+	#[test]
+	fn find_ancestor_reports_justifying_weight() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("B", &["C1", "D1"]);
+
+		tracker.insert("E", 6, 5u32, &chain).unwrap();
+		tracker.insert("D1", 5, 3, &chain).unwrap();
+		tracker.insert("A", 2, 1, &chain).unwrap();
+
+		// within an edge.
+		let result = tracker.find_ancestor_with_weight("D", 5, |&x| x >= 8).unwrap();
+		assert_eq!((result.hash, result.number), ("B", 3));
+		assert_eq!(result.cumulative_vote, 8);
+		let mut vote_nodes = result.vote_nodes;
+		vote_nodes.sort();
+		assert_eq!(vote_nodes, vec!["D1", "E"]);
+
+		// at a vote-node.
+		let result = tracker.find_ancestor_with_weight("D", 5, |&x| x >= 9).unwrap();
+		assert_eq!(result, AncestorSearchResult {
+			hash: "A",
+			number: 2,
+			cumulative_vote: 9,
+			vote_nodes: vec!["A"],
+		});
+
+		assert_eq!(tracker.find_ancestor("D", 5, |&x| x >= 9), Some(("A", 2)));
+		assert!(tracker.find_ancestor_with_weight("D", 5, |&x| x >= 10).is_none());
+	}
+
+	#[test]
+	fn prune_before_keeps_finalized_chain() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("A", &["B1", "C1"]);
+		chain.push_blocks("C", &["D2", "E2"]);
+
+		tracker.insert("C1", 4, 1u32, &chain).unwrap();
+		tracker.insert("E", 6, 5, &chain).unwrap();
+		tracker.insert("E2", 6, 3, &chain).unwrap();
+		tracker.insert("C", 4, 2, &chain).unwrap();
+
+		// not in the graph.
+		assert_eq!(tracker.prune_before("X", 5), 0);
+		assert_eq!(tracker.entries.len(), 5);
+
+		assert_eq!(tracker.prune_before("D", 5), 4);
+		assert_eq!(tracker.base(), (GENESIS_HASH, 1));
+		assert!(!tracker.indices.contains_key("C1"));
+		assert!(!tracker.indices.contains_key("E2"));
+		assert_eq!(tracker.heads().collect::<Vec<_>>(), vec![&"E"]);
+
+		// pruned votes still count towards the remaining ancestors.
+		assert_eq!(tracker.entry(GENESIS_HASH).cumulative_vote, 11);
+		assert_eq!(tracker.entry("C").cumulative_vote, 10);
+
+		tracker.check_invariants();
+	}
+
+	#[test]
+	fn invariants_hold_after_mutations() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new("C", 4);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+		chain.push_blocks("E1", &["FA", "FB"]);
+
+		tracker.insert("F1", 7, 5u32, &chain).unwrap();
+		tracker.insert("F2", 7, 3, &chain).unwrap();
+		tracker.check_invariants();
+
+		// introduces a branch at E1.
+		tracker.insert("FB", 8, 2, &chain).unwrap();
+		tracker.check_invariants();
+
+		// vote directly on an existing node.
+		tracker.insert("E1", 6, 4, &chain).unwrap();
+		tracker.check_invariants();
+
+		tracker.adjust_base(&["B", "A"]);
+		tracker.check_invariants();
+
+		assert_eq!(tracker.entry("A").cumulative_vote, 14);
+	}
+
+	#[test]
+	#[should_panic(expected = "cumulative vote")]
+	fn invariant_check_detects_bad_cumulative_vote() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		tracker.insert("C", 4, 5u32, &chain).unwrap();
+
+		tracker.entry_mut("C").cumulative_vote = 3;
+		tracker.check_invariants();
+	}
+
+	#[test]
+	fn find_ancestor_is_never_above_the_block() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("B", &["C1"]);
+
+		tracker.insert("C", 4, 10u32, &chain).unwrap();
+		tracker.insert("C1", 4, 10, &chain).unwrap();
+
+		// "B" fulfills the condition as well, but it's a descendent of "A".
+		assert_eq!(tracker.find_ancestor("A", 2, |&x| x > 15), Some(("A", 2)));
+		assert_eq!(tracker.find_ancestor("B", 3, |&x| x > 15), Some(("B", 3)));
+	}
+
+	#[test]
+	fn rejects_votes_with_wrong_block_number() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		assert_eq!(tracker.insert("C", 7, 10u32, &chain), Err(Error::InvalidBlockNumber));
+		assert_eq!(tracker.insert("C", 1, 10, &chain), Err(Error::InvalidBlockNumber));
+		assert!(tracker.is_head(GENESIS_HASH));
+
+		tracker.insert("C", 4, 10, &chain).unwrap();
+		tracker.check_invariants();
+	}
+
+	// the blocks of random trees, which descend from the genesis.
+	const TREE_BLOCKS: [&str; 24] = [
+		"A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L",
+		"M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X",
+	];
+
+	// a random tree, given by the parent of each block as an index into the
+	// genesis followed by the blocks before it, and random votes by the index
+	// of the block.
+	fn tree_and_votes() -> impl Strategy<Value = (Vec<usize>, Vec<(usize, u64)>)> {
+		(1..=TREE_BLOCKS.len()).prop_flat_map(|len| (
+			(0..len).map(|i| 0..=i).collect::<Vec<_>>(),
+			prop::collection::vec((0..=len, 1..100u64), 1..24),
+		))
+	}
+
+	// build the tree, returning the chain and all of its blocks.
+	fn build_tree(parents: &[usize]) -> (DummyChain, Vec<(&'static str, u32)>) {
+		let mut chain = DummyChain::new();
+		let mut blocks = vec![(GENESIS_HASH, 1)];
+
+		for (&hash, &parent) in TREE_BLOCKS.iter().zip(parents) {
+			chain.push_blocks(blocks[parent].0, &[hash]);
+			blocks.push((hash, chain.number(hash)));
+		}
+
+		(chain, blocks)
+	}
+
+	fn votes_by_hash(blocks: &[(&'static str, u32)], votes: &[(usize, u64)]) -> Vec<(&'static str, u64)> {
+		votes.iter().map(|&(block, weight)| (blocks[block].0, weight)).collect()
+	}
+
+	fn graph_with_votes(chain: &DummyChain, votes: &[(&'static str, u64)]) -> VoteGraph<&'static str, u32, u64> {
+		let mut graph = VoteGraph::new(GENESIS_HASH, 1);
+		for &(hash, weight) in votes {
+			graph.insert(hash, chain.number(hash), weight, chain).unwrap();
+		}
+
+		graph
+	}
+
+	proptest! {
+		#[test]
+		fn cumulative_votes_are_order_independent(
+			(parents, votes, shuffled) in tree_and_votes().prop_flat_map(|(parents, votes)| {
+				let shuffled = Just(votes.clone()).prop_shuffle();
+				(Just(parents), Just(votes), shuffled)
+			}),
+		) {
+			let (chain, blocks) = build_tree(&parents);
+			let votes = votes_by_hash(&blocks, &votes);
+			let graph = graph_with_votes(&chain, &votes);
+			let shuffled = graph_with_votes(&chain, &votes_by_hash(&blocks, &shuffled));
+
+			for &(hash, number) in &blocks {
+				let expected = reference::cumulative_vote(&chain, &hash, &votes);
+				prop_assert_eq!(graph.cumulative_vote_at(hash, number, &chain), expected);
+				prop_assert_eq!(shuffled.cumulative_vote_at(hash, number, &chain), expected);
+			}
+		}
+
+		#[test]
+		fn find_ghost_matches_reference((parents, votes) in tree_and_votes(), percent in 50..100u64) {
+			let (chain, blocks) = build_tree(&parents);
+			let votes = votes_by_hash(&blocks, &votes);
+			let graph = graph_with_votes(&chain, &votes);
+
+			// a strict majority, so that at most one child of a block fulfills it.
+			let total: u64 = votes.iter().map(|&(_, weight)| weight).sum();
+			let condition = |weight: u64| weight * 100 > total * percent;
+
+			prop_assert_eq!(
+				graph.find_ghost(None, |&weight| condition(weight)),
+				reference::ghost(&chain, &blocks, (GENESIS_HASH, 1), &votes, condition),
+			);
+		}
+
+		#[test]
+		fn descendent_links_stay_consistent((parents, votes) in tree_and_votes()) {
+			let (chain, blocks) = build_tree(&parents);
+			let mut graph = VoteGraph::new(GENESIS_HASH, 1);
+
+			for (hash, weight) in votes_by_hash(&blocks, &votes) {
+				graph.insert(hash, chain.number(hash), weight, &chain).unwrap();
+
+				let vote_nodes: Vec<_> = graph.iter().map(|node| *node.hash).collect();
+				for node in graph.iter() {
+					let mut descendents: Vec<_> = node.descendents.iter().map(|&&hash| hash).collect();
+					let mut expected = reference::descendent_vote_nodes(&chain, &vote_nodes, node.hash);
+					descendents.sort();
+					expected.sort();
+
+					prop_assert_eq!(descendents, expected, "descendents of {}", node.hash);
+				}
+
+				graph.check_invariants();
+			}
+		}
 	}
 }
@@ -0,0 +1,301 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A relay for commit messages which doesn't participate in any rounds.
+//!
+//! Nodes that only want to propagate finality proofs (e.g. sentries or
+//! bootnodes) can run a `CommitRelay` instead of a full `Voter`. Incoming
+//! commits are validated against a schedule of voter sets and only the best
+//! commit seen for each round is republished.
+
+use futures::prelude::*;
+use futures::ready;
+use futures::task::{Context, Poll};
+#[cfg(feature = "std")]
+use tracing::trace;
+
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::{BlockNumberOps, Chain, Commit, CommitMessage, validate_commit};
+use crate::voter_set::VoterSet;
+use super::{
+	BadCommit, Buffered, CommitProcessingOutcome, CommunicationIn, CommunicationOut, GoodCommit,
+};
+
+/// The number of most recent rounds for which relayed commits are remembered.
+/// Commits for rounds older than these are dropped.
+const TRACKED_ROUNDS: usize = 64;
+
+/// A future that validates incoming commits and republishes the best one for
+/// each round, without running any round logic.
+///
+/// A commit is better than a previously relayed one for the same round if it
+/// finalizes a higher block. Catch-up messages are ignored.
+pub struct CommitRelay<H, N, C, S, Id, CommitIn, CommitOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	Id: Clone + Hash + Eq + ::std::fmt::Debug,
+	CommitOut: Sink<CommunicationOut<H, N, S, Id>>,
+{
+	chain: Arc<C>,
+	// voter sets, keyed by the first round they're valid for.
+	voters: BTreeMap<u64, VoterSet<Id>>,
+	// number of the best block finalized by a relayed commit, per round.
+	relayed: BTreeMap<u64, N>,
+	commit_in: CommitIn,
+	commit_out: Buffered<CommitOut, CommunicationOut<H, N, S, Id>>,
+}
+
+impl<H, N, C, S, Id, E, CommitIn, CommitOut> CommitRelay<H, N, C, S, Id, CommitIn, CommitOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	C: Chain<H, N>,
+	S: Clone + Eq,
+	Id: Clone + Hash + Eq + ::std::fmt::Debug,
+	E: From<crate::Error>,
+	CommitIn: Stream<Item=Result<CommunicationIn<H, N, S, Id>, E>> + Unpin,
+	CommitOut: Sink<CommunicationOut<H, N, S, Id>, Error=E> + Unpin,
+{
+	/// Create a new `CommitRelay` validating commits of all rounds with the
+	/// given voter set.
+	///
+	/// The input stream is responsible for validating the signature data in
+	/// commit messages, and should only provide commits for known blocks.
+	pub fn new(
+		chain: Arc<C>,
+		voters: VoterSet<Id>,
+		commit_comms: (CommitIn, CommitOut),
+	) -> Self {
+		let (commit_in, commit_out) = commit_comms;
+
+		CommitRelay {
+			chain,
+			voters: std::iter::once((0, voters)).collect(),
+			relayed: BTreeMap::new(),
+			commit_in,
+			commit_out: Buffered::new(commit_out),
+		}
+	}
+
+	/// Use the given voter set to validate commits from `round` onwards, until
+	/// another voter set is scheduled for a later round.
+	pub fn schedule_voters(&mut self, round: u64, voters: VoterSet<Id>) {
+		self.voters.insert(round, voters);
+	}
+
+	fn voters_for(&self, round: u64) -> &VoterSet<Id> {
+		self.voters.range(..=round).next_back()
+			.map(|(_, voters)| voters)
+			.expect("a voter set for round 0 is always present; qed")
+	}
+
+	fn relay_commit(&mut self, round: u64, commit: Commit<H, N, S, Id>) -> Result<CommitProcessingOutcome, E> {
+		let validation_result = validate_commit(&commit, self.voters_for(round), &*self.chain)?;
+
+		let finalized_number = match validation_result.ghost() {
+			Some(&(_, number)) => number,
+			None => return Ok(CommitProcessingOutcome::Bad(BadCommit::from(validation_result))),
+		};
+
+		let too_old = self.relayed.len() >= TRACKED_ROUNDS &&
+			self.relayed.keys().next().is_some_and(|&oldest| round < oldest);
+
+		let is_better = !too_old &&
+			self.relayed.get(&round).is_none_or(|&relayed| finalized_number > relayed);
+
+		if is_better {
+			trace!(target: "afg", "Relaying commit for round {}, finalizing block #{:?}",
+				round,
+				finalized_number,
+			);
+
+			self.relayed.insert(round, finalized_number);
+			while self.relayed.len() > TRACKED_ROUNDS {
+				let oldest = *self.relayed.keys().next().expect("map is non-empty; qed");
+				self.relayed.remove(&oldest);
+			}
+
+			self.commit_out.push(CommunicationOut::Commit(CommitMessage::new(round, commit)));
+		}
+
+		Ok(CommitProcessingOutcome::Good(GoodCommit::new()))
+	}
+
+	// returns ready when the incoming stream has ended.
+	fn process_incoming(&mut self, cx: &mut Context) -> Poll<Result<(), E>> {
+		while let Some(item) = ready!(self.commit_in.poll_next_unpin(cx)) {
+			match item? {
+				CommunicationIn::Commit(CommitMessage { round_number, commit }, mut process_commit_outcome) => {
+					let outcome = self.relay_commit(round_number, commit.into())?;
+					process_commit_outcome.run(outcome);
+				}
+				CommunicationIn::CatchUp(..) => {
+					trace!(target: "afg", "Ignoring catch-up message in commit relay");
+				}
+				CommunicationIn::JustificationRequest(_, mut respond) => {
+					// relayed commits aren't kept around.
+					respond.run(None);
+				}
+			}
+		}
+
+		Poll::Ready(Ok(()))
+	}
+}
+
+// the relay is never structurally pinned.
+impl<H, N, C, S, Id, CommitIn, CommitOut> Unpin for CommitRelay<H, N, C, S, Id, CommitIn, CommitOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	Id: Clone + Hash + Eq + ::std::fmt::Debug,
+	CommitOut: Sink<CommunicationOut<H, N, S, Id>>,
+{}
+
+impl<H, N, C, S, Id, E, CommitIn, CommitOut> Future for CommitRelay<H, N, C, S, Id, CommitIn, CommitOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	C: Chain<H, N>,
+	S: Clone + Eq,
+	Id: Clone + Hash + Eq + ::std::fmt::Debug,
+	E: From<crate::Error>,
+	CommitIn: Stream<Item=Result<CommunicationIn<H, N, S, Id>, E>> + Unpin,
+	CommitOut: Sink<CommunicationOut<H, N, S, Id>, Error=E> + Unpin,
+{
+	type Output = Result<(), E>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), E>> {
+		let incoming_done = self.process_incoming(cx)?.is_ready();
+		let outgoing_done = self.commit_out.poll(cx)?.is_ready();
+
+		if incoming_done && outgoing_done {
+			Poll::Ready(Ok(()))
+		} else {
+			Poll::Pending
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::channel::mpsc;
+	use futures::executor::block_on;
+	use crate::{Error, Precommit, SignedPrecommit};
+	use crate::testing::{
+		chain::{DummyChain, GENESIS_HASH},
+		environment::{Id, Signature},
+	};
+	use crate::voter::Callback;
+
+	type TestCommit = Commit<&'static str, u32, Signature, Id>;
+
+	fn commit(target: (&'static str, u32), voters: &[u32]) -> TestCommit {
+		Commit {
+			target_hash: target.0,
+			target_number: target.1,
+			precommits: voters.iter().map(|&v| SignedPrecommit {
+				precommit: Precommit::new(target.0, target.1),
+				signature: Signature(v),
+				id: Id(v),
+			}).collect(),
+		}
+	}
+
+	fn relay(
+		voters: VoterSet<Id>,
+		schedule: Vec<(u64, VoterSet<Id>)>,
+		commits: Vec<(u64, TestCommit)>,
+	) -> (Vec<(u64, &'static str)>, Vec<CommitProcessingOutcome>) {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let (in_tx, in_rx) = mpsc::unbounded();
+		let (out_tx, out_rx) = mpsc::unbounded();
+		let (outcome_tx, outcome_rx) = mpsc::unbounded();
+
+		for (round, commit) in commits {
+			let outcome_tx = outcome_tx.clone();
+			in_tx.unbounded_send(CommunicationIn::Commit(
+				CommitMessage::new(round, commit.into()),
+				Callback::Work(Box::new(move |outcome| { let _ = outcome_tx.unbounded_send(outcome); })),
+			)).unwrap();
+		}
+		drop(in_tx);
+		drop(outcome_tx);
+
+		let mut relay = CommitRelay::new(
+			Arc::new(chain),
+			voters,
+			(
+				in_rx.map(Ok::<_, Error>),
+				out_tx.sink_map_err(|_| -> Error { panic!("receiver kept alive; qed") }),
+			),
+		);
+		for (round, voters) in schedule {
+			relay.schedule_voters(round, voters);
+		}
+
+		block_on(relay).unwrap();
+
+		let relayed = block_on(out_rx.collect::<Vec<_>>()).into_iter()
+			.map(|out| match out {
+				CommunicationOut::Commit(message) => (message.round_number, message.commit.target_hash),
+			})
+			.collect();
+
+		(relayed, block_on(outcome_rx.collect()))
+	}
+
+	#[test]
+	fn relays_only_best_commit_per_round() {
+		let voters: VoterSet<_> = (0..4).map(|i| (Id(i), 1)).collect();
+
+		let (relayed, outcomes) = relay(voters, Vec::new(), vec![
+			(1, commit(("B", 3), &[0, 1, 2])),
+			// duplicate.
+			(1, commit(("B", 3), &[0, 1, 2])),
+			// finalizes a lower block.
+			(1, commit(("A", 2), &[1, 2, 3])),
+			// finalizes a higher block.
+			(1, commit(("D", 5), &[0, 1, 3])),
+			(2, commit(("E", 6), &[0, 1, 2, 3])),
+		]);
+
+		assert_eq!(relayed, vec![(1, "B"), (1, "D"), (2, "E")]);
+		assert_eq!(outcomes, vec![CommitProcessingOutcome::good(); 5]);
+	}
+
+	#[test]
+	fn validates_against_voter_schedule() {
+		let voters: VoterSet<_> = (0..4).map(|i| (Id(i), 1)).collect();
+		let next_voters: VoterSet<_> = (4..8).map(|i| (Id(i), 1)).collect();
+
+		let (relayed, outcomes) = relay(voters, vec![(5, next_voters)], vec![
+			(4, commit(("B", 3), &[0, 1, 2])),
+			// old voters are no longer valid from round 5.
+			(5, commit(("C", 4), &[0, 1, 2])),
+			(5, commit(("D", 5), &[4, 5, 6])),
+		]);
+
+		assert_eq!(relayed, vec![(4, "B"), (5, "D")]);
+		assert_eq!(outcomes.iter().map(|o| match o {
+			CommitProcessingOutcome::Good(_) => true,
+			CommitProcessingOutcome::Bad(_) | CommitProcessingOutcome::Useless => false,
+		}).collect::<Vec<_>>(), vec![true, false, true]);
+		assert_eq!(outcomes[1].misbehavior(), Some(crate::misbehavior::Misbehavior::InvalidCommit(3)));
+	}
+}
@@ -0,0 +1,125 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Events emitted by a voter while it runs.
+//!
+//! These are meant for telemetry and user interfaces, and don't need to be
+//! consumed. Anything affecting the chain is still handed to the
+//! `Environment`.
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+
+use crate::round::State as RoundState;
+use crate::{Equivocation, Prevote, Precommit};
+
+/// An event in the life of a voter. See `Voter::events`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoterEvent<H, N, S, Id> {
+	/// We started voting in the given round.
+	RoundStarted(u64),
+	/// We cast a prevote in the given round.
+	PrevoteCast(u64, Prevote<H, N>),
+	/// We cast a precommit in the given round.
+	PrecommitCast(u64, Precommit<H, N>),
+	/// The given round became completable, with its state at that point.
+	RoundCompletable(u64, RoundState<H, N>),
+	/// A block was finalized by the given round.
+	Finalized(u64, (H, N)),
+	/// We broadcast a commit for the given round and block.
+	CommitBroadcast(u64, (H, N)),
+	/// A valid commit for the given round and block was imported.
+	CommitImported(u64, (H, N)),
+	/// A voter cast two different prevotes in the given round.
+	PrevoteEquivocation(u64, Equivocation<Id, Prevote<H, N>, S>),
+	/// A voter cast two different precommits in the given round.
+	PrecommitEquivocation(u64, Equivocation<Id, Precommit<H, N>, S>),
+}
+
+/// A stream of voter events. See `Voter::events`.
+pub type VoterEvents<H, N, S, Id> = UnboundedReceiver<VoterEvent<H, N, S, Id>>;
+
+/// A stream of the states of rounds whenever they change, along with the
+/// round number. See `Voter::round_state_updates`.
+pub type RoundStateUpdates<H, N> = UnboundedReceiver<(u64, RoundState<H, N>)>;
+
+// subscribers to round-state updates, of a single round or of all rounds.
+type RoundStateSubscribers<H, N> = Vec<(Option<u64>, UnboundedSender<(u64, RoundState<H, N>)>)>;
+
+// subscribers to voter events.
+type EventSubscribers<H, N, S, Id> = Vec<UnboundedSender<VoterEvent<H, N, S, Id>>>;
+
+/// Emits events to all subscribers. Shared by the voter and its rounds.
+pub(super) struct EventSender<H, N, S, Id> {
+	subscribers: Arc<Mutex<EventSubscribers<H, N, S, Id>>>,
+	round_state_subscribers: Arc<Mutex<RoundStateSubscribers<H, N>>>,
+}
+
+impl<H, N, S, Id> Clone for EventSender<H, N, S, Id> {
+	fn clone(&self) -> Self {
+		EventSender {
+			subscribers: self.subscribers.clone(),
+			round_state_subscribers: self.round_state_subscribers.clone(),
+		}
+	}
+}
+
+impl<H: Clone, N: Clone, S: Clone, Id: Clone> EventSender<H, N, S, Id> {
+	pub(super) fn new() -> Self {
+		EventSender {
+			subscribers: Arc::new(Mutex::new(Vec::new())),
+			round_state_subscribers: Arc::new(Mutex::new(Vec::new())),
+		}
+	}
+
+	/// Subscribe to the states of the given round, or of all rounds if `None`,
+	/// whenever they change from now on.
+	pub(super) fn subscribe_round_states(&self, round: Option<u64>) -> RoundStateUpdates<H, N> {
+		let (tx, rx) = mpsc::unbounded();
+		self.round_state_subscribers.lock().push((round, tx));
+		rx
+	}
+
+	/// Emit the new state of a round, dropping subscribers that went away.
+	pub(super) fn emit_round_state(&self, round: u64, state: &RoundState<H, N>) {
+		self.round_state_subscribers.lock().retain(|(subscribed, subscriber)| {
+			if subscribed.is_some() && *subscribed != Some(round) {
+				return !subscriber.is_closed();
+			}
+
+			subscriber.unbounded_send((round, state.clone())).is_ok()
+		});
+	}
+
+	/// Subscribe to all events emitted from now on.
+	pub(super) fn subscribe(&self) -> VoterEvents<H, N, S, Id> {
+		let (tx, rx) = mpsc::unbounded();
+		self.subscribers.lock().push(tx);
+		rx
+	}
+
+	/// Emit an event, dropping subscribers that went away. The event is only
+	/// constructed if there are any subscribers.
+	pub(super) fn emit<F: FnOnce() -> VoterEvent<H, N, S, Id>>(&self, event: F) {
+		let mut subscribers = self.subscribers.lock();
+		if subscribers.is_empty() {
+			return;
+		}
+
+		let event = event();
+		subscribers.retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+	}
+}
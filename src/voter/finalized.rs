@@ -0,0 +1,109 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finality notifications from voting rounds to the voter.
+//!
+//! Only the highest finalized block matters to the voter, so rather than
+//! queueing every notification this keeps a single slot holding the highest
+//! one not yet taken. Rounds never block on sending and memory stays bounded
+//! no matter how far behind the voter falls in draining notifications.
+
+use futures::task::{AtomicWaker, Context, Poll};
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+
+struct Shared<H, N, C> {
+	latest: Mutex<Option<(H, N, u64, C)>>,
+	waker: AtomicWaker,
+}
+
+/// Create a new finality notification slot.
+pub(super) fn channel<H, N, C>() -> (FinalizedSender<H, N, C>, FinalizedReceiver<H, N, C>) {
+	let shared = Arc::new(Shared {
+		latest: Mutex::new(None),
+		waker: AtomicWaker::new(),
+	});
+
+	(FinalizedSender { shared: shared.clone() }, FinalizedReceiver { shared })
+}
+
+/// The sending half, held by voting rounds.
+pub(super) struct FinalizedSender<H, N, C> {
+	shared: Arc<Shared<H, N, C>>,
+}
+
+impl<H, N, C> Clone for FinalizedSender<H, N, C> {
+	fn clone(&self) -> Self {
+		FinalizedSender { shared: self.shared.clone() }
+	}
+}
+
+impl<H, N: PartialOrd, C> FinalizedSender<H, N, C> {
+	/// Notify of a finalized block. Replaces a pending notification for a
+	/// lower block and is dropped if one for the same or a higher block is
+	/// already pending.
+	pub(super) fn notify(&self, finalized: (H, N, u64, C)) {
+		{
+			let mut latest = self.shared.latest.lock();
+			if latest.as_ref().is_some_and(|pending| pending.1 >= finalized.1) {
+				return;
+			}
+
+			*latest = Some(finalized);
+		}
+
+		self.shared.waker.wake();
+	}
+}
+
+/// The receiving half, held by the voter.
+pub(super) struct FinalizedReceiver<H, N, C> {
+	shared: Arc<Shared<H, N, C>>,
+}
+
+impl<H, N, C> FinalizedReceiver<H, N, C> {
+	/// Take the pending notification, if any. Schedules the current task to
+	/// be woken up by the next notification otherwise.
+	pub(super) fn poll(&mut self, cx: &mut Context) -> Poll<(H, N, u64, C)> {
+		self.shared.waker.register(cx.waker());
+
+		match self.shared.latest.lock().take() {
+			Some(finalized) => Poll::Ready(finalized),
+			None => Poll::Pending,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::task::noop_waker_ref;
+
+	#[test]
+	fn keeps_only_highest_pending_notification() {
+		let mut cx = Context::from_waker(noop_waker_ref());
+		let (sender, mut receiver) = channel::<&'static str, u32, ()>();
+		assert_eq!(receiver.poll(&mut cx), Poll::Pending);
+
+		sender.notify(("B", 3, 1, ()));
+		sender.clone().notify(("D", 5, 2, ()));
+		sender.notify(("C", 4, 3, ()));
+		assert_eq!(receiver.poll(&mut cx), Poll::Ready(("D", 5, 2, ())));
+		assert_eq!(receiver.poll(&mut cx), Poll::Pending);
+
+		sender.notify(("C", 4, 3, ()));
+		assert_eq!(receiver.poll(&mut cx), Poll::Ready(("C", 4, 3, ())));
+	}
+}
@@ -0,0 +1,222 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A handle to a running voter.
+//!
+//! The voter itself is a future which is usually spawned onto an executor. A
+//! `VoterHandle` can be obtained before that and used from anywhere to inspect
+//! the voter while it's running.
+
+use futures::task::Waker;
+use parking_lot::RwLock;
+
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::round::State as RoundState;
+use crate::voter_set::VoterSet;
+
+/// Data about the last round completed by a voter. This is exactly what is
+/// needed to start a fresh `Voter` which continues from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletedRound<H, N, Id: Hash + Eq> {
+	/// The round number.
+	pub number: u64,
+	/// The state of the round when it was completed.
+	pub state: RoundState<H, N>,
+	/// The base block of the round.
+	pub base: (H, N),
+	/// The voters of the round.
+	pub voters: VoterSet<Id>,
+}
+
+/// A snapshot of the state of a single round.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundReport<H, N> {
+	/// The round number.
+	pub number: u64,
+	/// The total weight of all voters.
+	pub total_weight: u64,
+	/// The weight required to reach supermajority.
+	pub threshold_weight: u64,
+	/// The weight and number of voters that have prevoted.
+	pub prevote_participation: (u64, usize),
+	/// The weight and number of voters that have precommitted.
+	pub precommit_participation: (u64, usize),
+	/// The state of the round, including the current estimate.
+	pub state: RoundState<H, N>,
+}
+
+/// A snapshot of the state of all rounds run by a voter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoterState<H, N> {
+	/// The round being voted in.
+	pub best_round: RoundReport<H, N>,
+	/// Past rounds still running in the background, ordered by round number.
+	pub background_rounds: Vec<RoundReport<H, N>>,
+}
+
+/// A summary of whether finality is making progress and whether we take part
+/// in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoterHealth {
+	/// Time since a block was last finalized, or since the voter was started if
+	/// it hasn't finalized any block yet.
+	pub since_last_finalized: Duration,
+	/// Time since we last cast a prevote or precommit. `None` if we haven't
+	/// voted since the voter was started.
+	pub since_last_vote: Option<Duration>,
+	/// Time since the best round was started.
+	pub round_age: Duration,
+	/// The number of past rounds still running in the background.
+	pub background_rounds: usize,
+	/// Whether we vote in the best round. `false` if we only observe it, e.g.
+	/// because we aren't part of the voter set.
+	pub voting: bool,
+	/// How many rounds the best round is behind the latest round that saw
+	/// activity, as reported by `Environment::round_activity`.
+	pub rounds_behind: u64,
+}
+
+struct SharedState<H, N, Id: Hash + Eq> {
+	last_completed_round: Option<CompletedRound<H, N, Id>>,
+	round_sizes: Vec<(u64, usize)>,
+	voter_state: Option<VoterState<H, N>>,
+	started_at: Instant,
+	last_finalized_at: Option<Instant>,
+	last_vote_at: Option<Instant>,
+	// start time of the best round, whether we vote in it and how many rounds
+	// it is behind.
+	best_round: Option<(Instant, bool, u64)>,
+	voting_enabled: bool,
+	// the task driving the voter, woken up when voting is enabled or disabled.
+	waker: Option<Waker>,
+}
+
+/// A cloneable handle to a running voter, giving access to its state.
+pub struct VoterHandle<H, N, Id: Hash + Eq> {
+	shared: Arc<RwLock<SharedState<H, N, Id>>>,
+}
+
+impl<H, N, Id: Hash + Eq> Clone for VoterHandle<H, N, Id> {
+	fn clone(&self) -> Self {
+		VoterHandle { shared: self.shared.clone() }
+	}
+}
+
+impl<H: Clone, N: Clone, Id: Hash + Eq + Clone> VoterHandle<H, N, Id> {
+	pub(super) fn new() -> Self {
+		VoterHandle {
+			shared: Arc::new(RwLock::new(SharedState {
+				last_completed_round: None,
+				round_sizes: Vec::new(),
+				voter_state: None,
+				started_at: Instant::now(),
+				last_finalized_at: None,
+				last_vote_at: None,
+				best_round: None,
+				voting_enabled: true,
+				waker: None,
+			})),
+		}
+	}
+
+	/// The last round completed by the voter, if any round was completed since
+	/// the voter was started.
+	pub fn last_completed_round(&self) -> Option<CompletedRound<H, N, Id>> {
+		self.shared.read().last_completed_round.clone()
+	}
+
+	/// The approximate memory used by each round the voter is running, in
+	/// bytes, ordered by round number. The last entry is the best round.
+	pub fn round_sizes(&self) -> Vec<(u64, usize)> {
+		self.shared.read().round_sizes.clone()
+	}
+
+	/// A snapshot of the voter's rounds, taken the last time the voter was
+	/// polled. `None` if it hasn't been polled yet.
+	pub fn voter_state(&self) -> Option<VoterState<H, N>> {
+		self.shared.read().voter_state.clone()
+	}
+
+	/// A summary of the voter's liveness, as of the last time the voter was
+	/// polled. `None` if it hasn't been polled yet.
+	pub fn health(&self) -> Option<VoterHealth> {
+		let shared = self.shared.read();
+		let (round_started_at, voting, rounds_behind) = shared.best_round?;
+
+		Some(VoterHealth {
+			since_last_finalized: shared.last_finalized_at.unwrap_or(shared.started_at).elapsed(),
+			since_last_vote: shared.last_vote_at.map(|at| at.elapsed()),
+			round_age: round_started_at.elapsed(),
+			background_rounds: shared.voter_state.as_ref().map_or(0, |state| state.background_rounds.len()),
+			voting,
+			rounds_behind,
+		})
+	}
+
+	/// Enable or disable casting votes, e.g. during maintenance or while the
+	/// node is syncing. While disabled the voter keeps importing votes and
+	/// following rounds as an observer would. Enabled by default.
+	pub fn set_voting_enabled(&self, enabled: bool) {
+		let mut shared = self.shared.write();
+		shared.voting_enabled = enabled;
+
+		if let Some(ref waker) = shared.waker {
+			waker.wake_by_ref();
+		}
+	}
+
+	/// Whether casting votes is enabled.
+	pub fn voting_enabled(&self) -> bool {
+		self.shared.read().voting_enabled
+	}
+
+	pub(super) fn set_waker(&self, waker: &Waker) {
+		let mut shared = self.shared.write();
+		if !shared.waker.as_ref().is_some_and(|current| current.will_wake(waker)) {
+			shared.waker = Some(waker.clone());
+		}
+	}
+
+	pub(super) fn note_finalized(&self) {
+		self.shared.write().last_finalized_at = Some(Instant::now());
+	}
+
+	pub(super) fn note_vote(&self, vote_at: Option<Instant>) {
+		let mut shared = self.shared.write();
+		if vote_at > shared.last_vote_at {
+			shared.last_vote_at = vote_at;
+		}
+	}
+
+	pub(super) fn set_best_round(&self, started_at: Instant, voting: bool, rounds_behind: u64) {
+		self.shared.write().best_round = Some((started_at, voting, rounds_behind));
+	}
+
+	pub(super) fn set_voter_state(&self, voter_state: VoterState<H, N>) {
+		self.shared.write().voter_state = Some(voter_state);
+	}
+
+	// the sizes are set on every poll, but rarely change.
+	pub(super) fn set_round_sizes(&self, sizes: Vec<(u64, usize)>) {
+		if self.shared.read().round_sizes == sizes { return }
+		self.shared.write().round_sizes = sizes;
+	}
+
+	pub(super) fn set_last_completed_round(&self, round: CompletedRound<H, N, Id>) {
+		self.shared.write().last_completed_round = Some(round);
+	}
+}
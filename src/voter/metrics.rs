@@ -0,0 +1,54 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics recorded by a voter.
+//!
+//! The `Environment` can provide a `Metrics` implementation backed by e.g.
+//! prometheus counters, gauges and histograms. All methods do nothing by
+//! default, so implementations only need to record what they're interested in.
+
+use std::time::Duration;
+
+/// Receives metrics from the voter and its rounds.
+pub trait Metrics: Send + Sync {
+	/// Counter: a vote was imported into a round.
+	fn message_imported(&self) {}
+
+	/// Counter: a vote was dropped without being imported, e.g. because it
+	/// targets a block below the round base.
+	fn message_dropped(&self) {}
+
+	/// Counter: a voter equivocated in a round.
+	fn equivocation(&self) {}
+
+	/// Counter: a commit was validated successfully.
+	fn commit_validated(&self) {}
+
+	/// Counter: a commit failed validation.
+	fn commit_rejected(&self) {}
+
+	/// Gauge: the number of the best round.
+	fn current_round(&self, _round: u64) {}
+
+	/// Gauge: the number of past rounds running in the background.
+	fn background_rounds(&self, _rounds: usize) {}
+
+	/// Gauge: the number of messages waiting to be sent by the voter and its
+	/// best round.
+	fn buffered_messages(&self, _messages: usize) {}
+
+	/// Histogram: the time from starting a round until we moved on to the
+	/// next one.
+	fn round_duration(&self, _duration: Duration) {}
+}
@@ -24,38 +24,72 @@
 //!  round. If no local id is defined or if it's not part of the voter set then
 //!  votes will not be pushed to the sink. The protocol state machine still
 //!  transitions state as if the votes had been pushed out.
+//!
+//! Which votes to cast in a round, and when, is decided by the futures-free
+//! `round_logic::RoundMachine`. Everything else, from round transitions to
+//! commits and finality, is driven by the `Voter` future.
+//!
+//! The voter doesn't depend on any particular executor. It never spawns tasks
+//! and round timers are provided by the `Environment`, so the `Voter` future
+//! can be driven by any executor able to run `std::future::Future`s, as long
+//! as the environment's timers work on it. Retries of failed sends wait on
+//! `futures-timer`, which runs its own timer thread, and internal channels
+//! are executor-independent. The crate's `async_std` and `smol` examples run
+//! voters on either.
 
 use futures::prelude::*;
-use futures::sync::mpsc::{self, UnboundedReceiver};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::task::{Context, Poll};
+use futures_timer::Delay;
 #[cfg(feature = "std")]
-use log::trace;
+use tracing::{debug, debug_span, error, trace, warn};
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::hash::Hash;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::misbehavior::Misbehavior;
 use crate::round::State as RoundState;
 use crate::{
-	CatchUp, Chain, Commit, CompactCommit, Equivocation, Message, Prevote, Precommit,
-	PrimaryPropose, SignedMessage, BlockNumberOps, validate_commit, CommitValidationResult,
+	CatchUp, Chain, Commit, CommitMessage, CompactCommit, Equivocation, Message, Prevote, Precommit,
+	PrimaryPropose, SignedMessage, BlockNumberOps, validate_commit, CommitValidationResult, InvalidCommit,
 	HistoricalVotes,
 };
 use crate::voter_set::VoterSet;
 use past_rounds::PastRounds;
-use voting_round::{VotingRound, State as VotingRoundState};
-
+use voting_round::VotingRound;
+use round_logic::Step;
+
+pub use commit_relay::CommitRelay;
+pub use events::{RoundStateUpdates, VoterEvent, VoterEvents};
+pub use metrics::Metrics;
+pub use handle::{CompletedRound, RoundReport, VoterHandle, VoterHealth, VoterState};
+pub use voting_rule::{BeforeBestBlockBy, VotingRule};
+
+mod commit_relay;
+mod events;
+mod finalized;
+mod handle;
+mod metrics;
 mod past_rounds;
+pub mod round_logic;
 mod voting_round;
+mod voting_rule;
 
 /// Necessary environment for a voter.
 ///
 /// This encapsulates the database and networking layers of the chain.
 pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
-	type Timer: Future<Item=(),Error=Self::Error>;
+	type Timer: Future<Output=Result<(), Self::Error>> + Unpin;
+	type Finalize: Future<Output=Result<(), Self::Error>> + Unpin;
+	type BestChain: Future<Output=Result<Option<(H, N)>, Self::Error>> + Unpin;
+	type Ancestry: Future<Output=Result<Option<Vec<H>>, Self::Error>> + Unpin;
 	type Id: Hash + Clone + Eq + ::std::fmt::Debug;
 	type Signature: Eq + Clone;
-	type In: Stream<Item=SignedMessage<H, N, Self::Signature, Self::Id>, Error=Self::Error>;
-	type Out: Sink<SinkItem=Message<H, N>, SinkError=Self::Error>;
+	type In: Stream<Item=Result<SignedMessage<H, N, Self::Signature, Self::Id>, Self::Error>> + Unpin;
+	type Out: Sink<Message<H, N>, Error=Self::Error> + Unpin;
 	type Error: From<crate::Error> + ::std::error::Error;
 
 	/// Produce data necessary to start a round of voting.
@@ -75,13 +109,37 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	///
 	/// Furthermore, this means that actual logic of creating and verifying
 	/// signatures is flexible and can be maintained outside this crate.
-	fn round_data(&self, round: u64) -> RoundData<
+	///
+	/// Alternatively, environments may sign our votes as they are cast with
+	/// `sign_message`, in which case they don't need to be returned by the
+	/// `In` stream.
+	///
+	/// `gossip_duration_hint` suggests how long to wait for votes to propagate
+	/// before the prevote and precommit timers fire, based on how long votes
+	/// took to reach us in recent rounds. It is only given when adaptive
+	/// timing is enabled with `Voter::set_adaptive_timing`, and environments
+	/// should bound it to sensible limits.
+	fn round_data(&self, round: u64, gossip_duration_hint: Option<Duration>) -> RoundData<
 		Self::Id,
 		Self::Timer,
 		Self::In,
 		Self::Out,
 	>;
 
+	/// Look up the best chain containing the given block, like
+	/// `Chain::best_chain_containing`, without blocking. The voter suspends
+	/// casting its prevote until the lookup completes.
+	fn best_chain_lookup(&self, base: H) -> Self::BestChain;
+
+	/// Look up the ancestry of a block, like `Chain::ancestry`, without
+	/// blocking. Resolves to `None` if the block isn't a descendent of `base`.
+	///
+	/// Incoming votes are only imported once the ancestry of their target
+	/// from the round base has been looked up, so that inserting them into
+	/// the vote-graph doesn't block. Other queries still go through the
+	/// synchronous `Chain` methods, which should be answerable from a cache.
+	fn ancestry_lookup(&self, base: H, block: H) -> Self::Ancestry;
+
 	/// Return a timer that will be used to delay the broadcast of a commit
 	/// message. This delay should not be static to minimize the amount of
 	/// commit messages that are sent (e.g. random value in [0, 1] seconds).
@@ -106,21 +164,253 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 		votes: &HistoricalVotes<H, N, Self::Signature, Self::Id>,
 	) -> Result<(), Self::Error>;
 
-	/// Called when a block should be finalized.
-	// TODO: make this a future that resolves when it's e.g. written to disk?
-	fn finalize_block(&self, hash: H, number: N, round: u64, commit: Commit<H, N, Self::Signature, Self::Id>) -> Result<(), Self::Error>;
+	/// Called when a block should be finalized. The returned future should
+	/// resolve once the finalization is durable, e.g. written to disk.
+	///
+	/// Until then the voter doesn't advertise the block as finalized: it
+	/// doesn't broadcast commits for it (or higher blocks) and doesn't notify
+	/// listeners of `Voter::finalized_stream`. Futures are polled in the order
+	/// the blocks were finalized in, and an error is treated like any other
+	/// error from the environment.
+	fn finalize_block(&self, hash: H, number: N, round: u64, commit: Commit<H, N, Self::Signature, Self::Id>) -> Self::Finalize;
 
 	// Note that an equivocation in prevotes has occurred.s
 	fn prevote_equivocation(&self, round: u64, equivocation: Equivocation<Self::Id, Prevote<H, N>, Self::Signature>);
 	// Note that an equivocation in precommits has occurred.
 	fn precommit_equivocation(&self, round: u64, equivocation: Equivocation<Self::Id, Precommit<H, N>, Self::Signature>);
+
+	/// Return the voter set to use in the given round, if the voter weights
+	/// have changed (e.g. due to stake adjustments). Weights changed in some
+	/// round apply to every later round until they change again, so the set
+	/// must also be returned for those rounds.
+	///
+	/// This is called at the start of every round, and to validate commits
+	/// and catch-ups for rounds other than the ones running. The returned set
+	/// must contain exactly the same voters as the current one, only their
+	/// weights may differ; otherwise it is ignored. Rounds that are already
+	/// running (including background rounds) keep the weights they were
+	/// started with.
+	///
+	/// Returns `None` by default, i.e. weights never change.
+	fn round_voters(&self, _round: u64) -> Option<VoterSet<Self::Id>> {
+		None
+	}
+
+	/// Note that a vote signed with our local id was received which we haven't
+	/// cast in the given round. This may indicate that our key is being used
+	/// by another node. The vote is still imported as usual.
+	///
+	/// Does nothing by default.
+	fn unexpected_own_vote(&self, _round: u64, _message: Message<H, N>) {}
+
+	/// Sign a message we cast in the given round with the key of our local
+	/// voter id.
+	///
+	/// If a signature is returned, the message is imported into the round
+	/// right away. It is still pushed into the round's `Out` sink to be
+	/// multicast to peers, but needn't be returned by the `In` stream; it is
+	/// ignored if it is.
+	///
+	/// Returns `None` by default, i.e. the `Out` sink signs our votes and
+	/// schedules them to be returned by the `In` stream.
+	fn sign_message(&self, _round: u64, _id: &Self::Id, _message: &Message<H, N>) -> Option<Self::Signature> {
+		None
+	}
+
+	/// Decide whether a message we cast in the given round is broadcast, just
+	/// before it is pushed into the round's `Out` sink. This is also called
+	/// when our votes are sent again, e.g. on rebroadcast or after a restart.
+	///
+	/// A vetoed message is neither sent nor imported locally, but the vote
+	/// still counts as cast: we won't cast a different one in the round, so
+	/// that vetoing can't make us equivocate. This allows holding back votes
+	/// during a known-unsafe period, or delaying them until a later
+	/// rebroadcast.
+	///
+	/// Returns `VoteDecision::Broadcast` by default.
+	fn pre_broadcast(&self, _round: u64, _message: &Message<H, N>) -> VoteDecision {
+		VoteDecision::Broadcast
+	}
+
+	/// Return the votes we've already cast in the given round, e.g. before a
+	/// restart. This is called together with `round_data`, and any votes
+	/// returned are re-broadcast instead of constructing new ones, which could
+	/// conflict with them.
+	///
+	/// Returns `HasVoted::No` by default.
+	fn has_voted(&self, _round: u64) -> HasVoted<H, N> {
+		HasVoted::No
+	}
+
+	/// Note the votes imported in a round since the last call for it,
+	/// including our own votes, along with the votes we've cast. This is
+	/// called for the best round and any background rounds whenever new votes
+	/// have been imported or cast. Applying the updates of a round to a
+	/// `RoundSnapshot` in order gives the round's state, to be restored with
+	/// `Voter::resume` after a restart.
+	///
+	/// Does nothing by default.
+	fn persist_round_votes(
+		&self,
+		_update: RoundVotesUpdate<H, N, Self::Signature, Self::Id>,
+	) -> Result<(), Self::Error> {
+		Ok(())
+	}
+	/// Called after `completed` with a report of how the round went, e.g. to
+	/// tune the gossip duration. Not called for rounds that were skipped
+	/// because of a commit from a later round.
+	///
+	/// Does nothing by default.
+	fn completed_with_report(&self, _report: CompletionReport<H, N>) {}
+
+	/// Return a rule restricting the blocks we prevote for, e.g. to avoid
+	/// voting for blocks that were just authored.
+	///
+	/// Returns `None` by default, i.e. votes are not restricted.
+	fn voting_rule(&self) -> Option<&dyn VotingRule<H, N>> {
+		None
+	}
+
+	/// Return how far ahead of a round's base incoming votes may target
+	/// blocks, see `Round::set_max_vote_distance`. Votes further ahead are
+	/// dropped without looking up their ancestry, and reported with
+	/// `vote_rejected`.
+	///
+	/// Returns `None` by default, i.e. votes are not limited.
+	fn max_vote_distance(&self) -> Option<N> {
+		None
+	}
+
+	/// Note that an incoming vote in the given round was rejected, e.g. so
+	/// that the gossip layer can penalize the peer which sent it.
+	///
+	/// Does nothing by default.
+	fn vote_rejected(
+		&self,
+		_round: u64,
+		_vote: &SignedMessage<H, N, Self::Signature, Self::Id>,
+		_misbehavior: Misbehavior,
+	) {}
+
+	/// Return a sink for metrics about the voter, e.g. backed by prometheus.
+	///
+	/// Returns `None` by default, i.e. no metrics are recorded.
+	fn metrics(&self) -> Option<&dyn Metrics> {
+		None
+	}
+
+	/// Note that a valid commit finalized a block which conflicts with a block
+	/// we finalized before. The voter fails with `Error::SafetyViolation`
+	/// right after this is called.
+	///
+	/// Does nothing by default.
+	fn finality_violation(&self, _violation: FinalityViolation<H, N, Self::Signature, Self::Id>) {}
+
+	/// Return a stream of notifications for newly imported blocks. When given,
+	/// incoming commits for a target which `is_block_imported` doesn't know
+	/// yet are held back until it has been imported, so every imported block
+	/// must be notified. This is called once when the voter is created.
+	///
+	/// Returns `None` by default, i.e. incoming commits must only reference
+	/// known blocks.
+	fn block_imported(&self) -> Option<UnboundedReceiver<(H, N)>> {
+		None
+	}
+
+	/// Whether the given block has been imported, on any fork. Environments
+	/// providing `block_imported` notifications must override this, it
+	/// decides which commits are held back.
+	///
+	/// Returns `true` by default, i.e. all blocks are assumed to be imported.
+	fn is_block_imported(&self, _block: H) -> bool {
+		true
+	}
+
+	/// Return a stream of round numbers for which messages were received, e.g.
+	/// by the network before they are handed to the streams of the round. This
+	/// wakes up the voter when later rounds see activity, even if the best
+	/// round is idle, and is reported as `VoterHealth::rounds_behind`. This is
+	/// called once when the voter is created.
+	///
+	/// Returns `None` by default, i.e. the voter is only woken up by the
+	/// streams of the rounds it runs.
+	fn round_activity(&self) -> Option<UnboundedReceiver<u64>> {
+		None
+	}
+
+	/// Classify an error raised by the environment, or by the streams and
+	/// sinks it provides. The voter logs transient errors and keeps running,
+	/// retrying the failed work the next time it is woken up, while fatal
+	/// errors stop it. Safety violations should always be fatal.
+	///
+	/// Returns `ErrorSeverity::Fatal` by default, i.e. every error stops the
+	/// voter.
+	fn error_severity(&self, _error: &Self::Error) -> ErrorSeverity {
+		ErrorSeverity::Fatal
+	}
+
+	/// Return how messages are sent to the sinks of the voter and of each
+	/// round, e.g. to retry when they fail. This is called whenever a sink is
+	/// set up.
+	///
+	/// Returns `SendPolicy::default()` by default, i.e. the first failure is
+	/// returned as an error and messages aren't limited.
+	fn send_policy(&self) -> SendPolicy {
+		SendPolicy::default()
+	}
+
+	/// Return a timer after which the given round is considered stalled if it
+	/// isn't completable yet. This is called when the round is started.
+	///
+	/// Returns `None` by default, i.e. stalls aren't detected.
+	fn round_stall_timer(&self, _round: u64) -> Option<Self::Timer> {
+		None
+	}
+
+	/// Note that a round isn't completable after its stall timer fired, e.g.
+	/// because too many voters are offline. `missing_weight` is the weight of
+	/// votes still missing to reach the threshold, which is zero if enough
+	/// voters voted but the votes are split. This is only for alerting and
+	/// called at most once per round, voting carries on as usual.
+	///
+	/// Does nothing by default.
+	fn round_stalled(&self, _round: u64, _state: RoundState<H, N>, _missing_weight: u64) {}
+
+	/// Note that the estimate of the given round changed. Blocks are only
+	/// finalized on top of the estimate of the latest rounds, so block
+	/// authors may follow it to build on a chain GRANDPA is likely to
+	/// finalize.
+	///
+	/// Does nothing by default.
+	fn round_estimate_updated(&self, _round: u64, _estimate: (H, N)) {}
+}
+
+/// How severe an error is. See `Environment::error_severity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSeverity {
+	/// The error is temporary, e.g. a failure to send a message.
+	Transient,
+	/// The voter can't continue.
+	Fatal,
 }
 
 /// Communication between nodes that is not round-localized.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommunicationOut<H, N, S, Id> {
 	/// A commit message.
-	Commit(u64, Commit<H, N, S, Id>),
+	Commit(CommitMessage<Commit<H, N, S, Id>>),
+}
+
+/// Evidence that two conflicting blocks were finalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalityViolation<H, N, S, Id> {
+	/// The block we finalized before.
+	pub finalized: (H, N),
+	/// The commit which finalized `finalized`. This is `None` if the block was
+	/// finalized before the voter was started.
+	pub finalized_commit: Option<CommitMessage<Commit<H, N, S, Id>>>,
+	/// The commit which finalized a block on another fork.
+	pub conflicting_commit: CommitMessage<Commit<H, N, S, Id>>,
 }
 
 /// The outcome of processing a commit.
@@ -130,6 +420,20 @@ pub enum CommitProcessingOutcome {
 	Good(GoodCommit),
 	/// It wasn't beneficial to process this commit. We wasted resources.
 	Bad(BadCommit),
+	/// The commit wasn't processed, e.g. because it references blocks we
+	/// don't know and too many such commits are held back already.
+	Useless,
+}
+
+impl CommitProcessingOutcome {
+	/// The misbehavior of the peer which sent the commit, if any. Useless
+	/// commits are not considered misbehavior.
+	pub fn misbehavior(&self) -> Option<Misbehavior> {
+		match *self {
+			CommitProcessingOutcome::Good(_) | CommitProcessingOutcome::Useless => None,
+			CommitProcessingOutcome::Bad(ref bad) => Some(Misbehavior::InvalidCommit(bad.num_precommits())),
+		}
+	}
 }
 
 #[cfg(any(test, feature = "test-helpers"))]
@@ -165,6 +469,7 @@ pub struct BadCommit {
 	num_duplicated_precommits: usize,
 	num_equivocations: usize,
 	num_invalid_voters: usize,
+	reason: Option<InvalidCommit>,
 }
 
 impl BadCommit {
@@ -187,15 +492,21 @@ impl BadCommit {
 	pub fn num_invalid_voters(&self) -> usize {
 		self.num_invalid_voters
 	}
+
+	/// Get why the commit is invalid, if known.
+	pub fn reason(&self) -> Option<InvalidCommit> {
+		self.reason
+	}
 }
 
 impl<H, N> From<CommitValidationResult<H, N>> for BadCommit {
 	fn from(r: CommitValidationResult<H, N>) -> Self {
 		BadCommit {
-			num_precommits: r.num_precommits,
-			num_duplicated_precommits: r.num_duplicated_precommits,
-			num_equivocations: r.num_equivocations,
-			num_invalid_voters: r.num_invalid_voters,
+			num_precommits: r.num_precommits(),
+			num_duplicated_precommits: r.num_duplicated_precommits(),
+			num_equivocations: r.num_equivocations(),
+			num_invalid_voters: r.num_invalid_voters(),
+			reason: r.invalid_reason(),
 			_priv: (),
 		}
 	}
@@ -214,6 +525,17 @@ pub enum CatchUpProcessingOutcome {
 	Useless,
 }
 
+impl CatchUpProcessingOutcome {
+	/// The misbehavior of the peer which sent the catch up, if any. Useless
+	/// catch ups are not considered misbehavior.
+	pub fn misbehavior(&self) -> Option<Misbehavior> {
+		match *self {
+			CatchUpProcessingOutcome::Bad(_) => Some(Misbehavior::InvalidCatchUp),
+			CatchUpProcessingOutcome::Good(_) | CatchUpProcessingOutcome::Useless => None,
+		}
+	}
+}
+
 #[cfg(any(test, feature = "test-helpers"))]
 impl CatchUpProcessingOutcome {
 	/// Returns a `Bad` instance of catch up processing outcome's opaque type. Useful for testing.
@@ -261,7 +583,7 @@ pub enum Callback<O> {
 	Work(Box<dyn FnMut(O) + Send>),
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-helpers"))]
 impl<O> Clone for Callback<O> {
 	fn clone(&self) -> Self {
 		Callback::Blank
@@ -279,12 +601,17 @@ impl<O> Callback<O> {
 }
 
 /// Communication between nodes that is not round-localized.
-#[cfg_attr(test, derive(Clone))]
+#[cfg_attr(any(test, feature = "test-helpers"), derive(Clone))]
 pub enum CommunicationIn<H, N, S, Id> {
 	/// A commit message.
-	Commit(u64, CompactCommit<H, N, S, Id>, Callback<CommitProcessingOutcome>),
+	Commit(CommitMessage<CompactCommit<H, N, S, Id>>, Callback<CommitProcessingOutcome>),
 	/// A catch up message.
 	CatchUp(CatchUp<H, N, S, Id>, Callback<CatchUpProcessingOutcome>),
+	/// A request for a commit proving the finality of the given block. The
+	/// callback is given a commit for the block or one of its descendents,
+	/// assembled from a running round, or `None` if no running round
+	/// finalized it.
+	JustificationRequest((H, N), Callback<Option<Justification<H, N, S, Id>>>),
 }
 
 /// Data necessary to participate in a round.
@@ -296,70 +623,411 @@ pub struct RoundData<Id, Timer, Input, Output> {
 	pub prevote_timer: Timer,
 	/// Timer before precommits can be cast. This should be Start + 4T
 	pub precommit_timer: Timer,
+	/// Creates timers after which our votes are sent again while the round
+	/// isn't completable, in case the network lost them. A new timer is
+	/// created each time one fires, so they should all fire after the same
+	/// interval. No votes are sent again if `None`.
+	pub rebroadcast_timer: Option<Box<dyn FnMut() -> Timer + Send>>,
 	/// Incoming messages.
 	pub incoming: Input,
 	/// Outgoing messages.
 	pub outgoing: Output,
 }
 
-struct Buffered<S: Sink> {
+/// Whether a message we cast should be broadcast, see
+/// `Environment::pre_broadcast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteDecision {
+	/// Push the message into the round's outgoing sink.
+	Broadcast,
+	/// Don't broadcast the message this time.
+	Veto,
+}
+
+/// The votes cast by the local voter in a round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HasVoted<H, N> {
+	/// Nothing was cast yet.
+	No,
+	/// A primary proposal was cast.
+	Proposed(PrimaryPropose<H, N>),
+	/// A prevote was cast, with the primary proposal (if any) cast before it.
+	Prevoted(Option<PrimaryPropose<H, N>>, Prevote<H, N>),
+	/// A precommit was cast, with the primary proposal and prevote (if any)
+	/// cast before it. The prevote may be missing if we weren't voting, or
+	/// couldn't construct one, when the round reached the prevote stage.
+	Precommitted(Option<PrimaryPropose<H, N>>, Option<Prevote<H, N>>, Precommit<H, N>),
+}
+
+impl<H, N> HasVoted<H, N> {
+	/// The primary proposal that was cast, if any.
+	pub fn propose(&self) -> Option<&PrimaryPropose<H, N>> {
+		match self {
+			HasVoted::Proposed(propose) => Some(propose),
+			HasVoted::Prevoted(propose, _) | HasVoted::Precommitted(propose, _, _) => propose.as_ref(),
+			HasVoted::No => None,
+		}
+	}
+
+	/// The prevote that was cast, if any.
+	pub fn prevote(&self) -> Option<&Prevote<H, N>> {
+		match self {
+			HasVoted::Prevoted(_, prevote) => Some(prevote),
+			HasVoted::Precommitted(_, prevote, _) => prevote.as_ref(),
+			_ => None,
+		}
+	}
+
+	/// The precommit that was cast, if any.
+	pub fn precommit(&self) -> Option<&Precommit<H, N>> {
+		match self {
+			HasVoted::Precommitted(_, _, precommit) => Some(precommit),
+			_ => None,
+		}
+	}
+
+	// the votes cast according to either this or the other, preferring ours.
+	fn or(&self, other: &HasVoted<H, N>) -> HasVoted<H, N> where H: Clone, N: Clone {
+		let propose = self.propose().or_else(|| other.propose()).cloned();
+		let prevote = self.prevote().or_else(|| other.prevote()).cloned();
+		match (self.precommit().or_else(|| other.precommit()).cloned(), prevote, propose) {
+			(Some(precommit), prevote, propose) => HasVoted::Precommitted(propose, prevote, precommit),
+			(None, Some(prevote), propose) => HasVoted::Prevoted(propose, prevote),
+			(None, None, Some(propose)) => HasVoted::Proposed(propose),
+			(None, None, None) => HasVoted::No,
+		}
+	}
+}
+
+/// Votes of a single round, as persisted through `Environment::persist_round_votes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundSnapshot<H, N, S, Id> {
+	/// The round number.
+	pub round_number: u64,
+	/// The base block of the round.
+	pub base: (H, N),
+	/// All votes imported in the round, in order.
+	pub votes: HistoricalVotes<H, N, S, Id>,
+	/// The votes we cast in the round. These are sent again after resuming
+	/// instead of casting new ones, even if they weren't imported.
+	pub has_voted: HasVoted<H, N>,
+}
+
+impl<H: Clone, N: Clone, S: Clone, Id: Clone> RoundSnapshot<H, N, S, Id> {
+	/// Create a snapshot of a round without any votes.
+	pub fn new(round_number: u64, base: (H, N)) -> Self {
+		RoundSnapshot { round_number, base, votes: HistoricalVotes::new(), has_voted: HasVoted::No }
+	}
+
+	/// Apply an update handed to `Environment::persist_round_votes`. Updates
+	/// must be applied in the order they were handed over; votes which the
+	/// snapshot has already are skipped.
+	pub fn apply(&mut self, update: &RoundVotesUpdate<H, N, S, Id>) {
+		let set_indices = |votes: &mut HistoricalVotes<H, N, S, Id>| {
+			let idx = Some(votes.seen().len() as u64);
+			if votes.prevote_idx().is_none() && update.prevote_idx == idx { votes.set_prevoted_idx(); }
+			if votes.precommit_idx().is_none() && update.precommit_idx == idx { votes.set_precommited_idx(); }
+		};
+
+		let known = self.votes.seen().len().saturating_sub(update.first_vote);
+		for vote in update.new_votes.iter().skip(known) {
+			set_indices(&mut self.votes);
+			self.votes.push_vote(vote.clone());
+		}
+		set_indices(&mut self.votes);
+
+		self.has_voted = update.has_voted.clone();
+	}
+}
+
+/// The votes of a round which changed since they were last handed to
+/// `Environment::persist_round_votes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundVotesUpdate<'a, H, N, S, Id> {
+	/// The round number.
+	pub round_number: u64,
+	/// The base block of the round.
+	pub base: (H, N),
+	/// The number of votes handed over in earlier updates.
+	pub first_vote: usize,
+	/// The votes imported since the last update, in order.
+	pub new_votes: &'a [SignedMessage<H, N, S, Id>],
+	/// The number of votes imported before we prevoted, if we did.
+	pub prevote_idx: Option<u64>,
+	/// The number of votes imported before we precommitted, if we did.
+	pub precommit_idx: Option<u64>,
+	/// The votes we've cast in the round.
+	pub has_voted: &'a HasVoted<H, N>,
+}
+
+/// How a round we took part in went, as reported through
+/// `Environment::completed_with_report`. All times are measured from when the
+/// round was started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionReport<H, N> {
+	/// The round number.
+	pub round_number: u64,
+	/// The state of the round when it was completed.
+	pub state: RoundState<H, N>,
+	/// The base block of the round.
+	pub base: (H, N),
+	/// When we cast our prevote, if we did.
+	pub prevoted_after: Option<Duration>,
+	/// When we cast our precommit, if we did.
+	pub precommitted_after: Option<Duration>,
+	/// When the round became completable.
+	pub completable_after: Option<Duration>,
+	/// When the round was completed and we moved on to the next one.
+	pub duration: Duration,
+	/// The number of prevotes imported, including our own.
+	pub prevotes: usize,
+	/// The number of precommits imported, including our own.
+	pub precommits: usize,
+}
+
+/// In-flight voter state to resume from after a restart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoterSnapshot<H, N, S, Id> {
+	/// The round the voter was voting in.
+	pub best_round: RoundSnapshot<H, N, S, Id>,
+	/// Any past rounds that were still running in the background.
+	pub background_rounds: Vec<RoundSnapshot<H, N, S, Id>>,
+	/// The state of the round prior to the best round. Only used if that round
+	/// is not part of `background_rounds`.
+	pub last_round_state: RoundState<H, N>,
+	/// The last finalized block.
+	pub last_finalized: (H, N),
+}
+
+// the number of rounds the best round can be behind the highest round we've
+// seen a commit for before commits are prioritized over round messages.
+const FAR_BEHIND_ROUNDS: u64 = 2;
+
+// the number of recently completed rounds whose gossip latency is considered
+// when suggesting a gossip duration.
+const ADAPTIVE_TIMING_ROUNDS: usize = 8;
+
+// the maximum number of commits for unknown blocks we hold back. Beyond that,
+// those for the lowest rounds are dropped.
+const MAX_PENDING_COMMITS: usize = 64;
+
+// a commit for a target that hasn't been imported yet.
+type PendingCommit<H, N, S, Id> = (u64, Commit<H, N, S, Id>, Callback<CommitProcessingOutcome>);
+
+// a commit message proving the finality of a block, as given in response to
+// a justification request.
+type Justification<H, N, S, Id> = CommitMessage<Commit<H, N, S, Id>>;
+
+// a block being finalized by the environment, with the round and commit that
+// finalized it.
+type PendingFinalization<H, N, E> = (
+	H,
+	N,
+	u64,
+	Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
+	<E as Environment<H, N>>::Finalize,
+);
+
+/// How messages are sent to the sinks provided by the environment. See
+/// `Environment::send_policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendPolicy {
+	/// How many times sending is retried after the sink fails before the
+	/// error is returned.
+	pub max_retries: u32,
+	/// The delay before the first retry, which doubles with every further
+	/// failure. The voter is woken up to retry once the delay has passed.
+	pub initial_backoff: Duration,
+	/// The longest delay between retries.
+	pub max_backoff: Duration,
+	/// The most messages waiting to be sent, `None` for no limit.
+	pub max_buffered: Option<usize>,
+	/// What to do with messages beyond `max_buffered`.
+	pub overflow: OverflowPolicy,
+}
+
+impl Default for SendPolicy {
+	fn default() -> Self {
+		SendPolicy {
+			max_retries: 0,
+			initial_backoff: Duration::from_millis(100),
+			max_backoff: Duration::from_secs(5),
+			max_buffered: None,
+			overflow: OverflowPolicy::DropOldest,
+		}
+	}
+}
+
+/// What to do with a message when too many are waiting to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+	/// Drop the oldest waiting message to make room for the new one.
+	DropOldest,
+	/// Drop the new message.
+	DropNewest,
+}
+
+struct Buffered<S, I> {
 	inner: S,
-	buffer: VecDeque<S::SinkItem>,
+	buffer: VecDeque<I>,
+	policy: SendPolicy,
+	// the number of failures since the last successful send.
+	failures: u32,
+	// when to retry sending after a failure, and a timer waking us up then.
+	retry: Option<(Instant, Delay)>,
 }
 
-impl<S: Sink> Buffered<S> {
-	fn new(inner: S) -> Buffered<S> {
+impl<S: Sink<I> + Unpin, I: Clone> Buffered<S, I> {
+	fn new(inner: S) -> Buffered<S, I> {
+		Buffered::with_policy(inner, SendPolicy::default())
+	}
+
+	fn with_policy(inner: S, policy: SendPolicy) -> Buffered<S, I> {
 		Buffered {
 			buffer: VecDeque::new(),
-			inner
+			inner,
+			policy,
+			failures: 0,
+			retry: None,
 		}
 	}
 
+	// the number of items waiting to be sent.
+	fn len(&self) -> usize {
+		self.buffer.len()
+	}
+
 	// push an item into the buffered sink.
 	// the sink _must_ be driven to completion with `poll` afterwards.
-	fn push(&mut self, item: S::SinkItem) {
+	fn push(&mut self, item: I) {
+		if self.policy.max_buffered.is_some_and(|max| self.buffer.len() >= max) {
+			debug!(target: "afg", "Too many messages waiting to be sent, dropping the {}",
+				if self.policy.overflow == OverflowPolicy::DropOldest { "oldest" } else { "newest" });
+
+			match self.policy.overflow {
+				OverflowPolicy::DropOldest => { self.buffer.pop_front(); },
+				OverflowPolicy::DropNewest => return,
+			}
+		}
+
 		self.buffer.push_back(item);
 	}
 
+	// the items waiting to be sent, oldest first.
+	fn queued(&self) -> impl Iterator<Item=&I> {
+		self.buffer.iter()
+	}
+
+	// drop the items waiting to be sent which don't satisfy the predicate.
+	fn retain<F: FnMut(&I) -> bool>(&mut self, f: F) {
+		self.buffer.retain(f);
+	}
+
 	// returns ready when the sink and the buffer are completely flushed.
-	fn poll(&mut self) -> Poll<(), S::SinkError> {
-		let polled = self.schedule_all()?;
-
-		match polled {
-			Async::Ready(()) => self.inner.poll_complete(),
-			Async::NotReady => {
-				self.inner.poll_complete()?;
-				Ok(Async::NotReady)
+	fn poll(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+		if let Some((at, ref mut delay)) = self.retry {
+			if Instant::now() < at && delay.poll_unpin(cx).is_pending() {
+				return Poll::Pending;
 			}
 		}
+		self.retry = None;
+
+		let scheduled = match self.schedule_all(cx) {
+			Ok(scheduled) => scheduled,
+			Err(e) => return self.failed(e, cx),
+		};
+
+		let flushed = match self.inner.poll_flush_unpin(cx) {
+			Poll::Ready(Ok(())) => true,
+			Poll::Ready(Err(e)) => return self.failed(e, cx),
+			Poll::Pending => false,
+		};
+
+		if scheduled && flushed {
+			Poll::Ready(Ok(()))
+		} else {
+			Poll::Pending
+		}
 	}
 
-	fn schedule_all(&mut self) -> Poll<(), S::SinkError> {
-		while let Some(front) = self.buffer.pop_front() {
-			match self.inner.start_send(front) {
-				Ok(AsyncSink::Ready) => continue,
-				Ok(AsyncSink::NotReady(front)) => {
-					self.buffer.push_front(front);
-					break;
+	// start sending all buffered items, returning whether the sink accepted
+	// all of them.
+	fn schedule_all(&mut self, cx: &mut Context) -> Result<bool, S::Error> {
+		while !self.buffer.is_empty() {
+			if self.inner.poll_ready_unpin(cx)?.is_pending() {
+				return Ok(false);
+			}
+
+			let front = self.buffer.pop_front().expect("buffer is non-empty; qed");
+			// keep a copy to retry with, since a failing sink drops the item.
+			let retry = if self.policy.max_retries > 0 { Some(front.clone()) } else { None };
+			if let Err(e) = self.inner.start_send_unpin(front) {
+				if let Some(retry) = retry {
+					self.buffer.push_front(retry);
 				}
-				Err(e) => return Err(e),
+				return Err(e);
 			}
+
+			self.failures = 0;
 		}
 
-		if self.buffer.is_empty() {
-			Ok(Async::Ready(()))
-		} else {
-			Ok(Async::NotReady)
+		Ok(true)
+	}
+
+	// back off after the sink failed, or return the error once out of retries.
+	fn failed(&mut self, error: S::Error, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+		if self.failures >= self.policy.max_retries {
+			self.failures = 0;
+			return Poll::Ready(Err(error));
+		}
+
+		let backoff = self.policy.initial_backoff
+			.checked_mul(1 << self.failures.min(16))
+			.map_or(self.policy.max_backoff, |backoff| backoff.min(self.policy.max_backoff));
+
+		self.failures += 1;
+
+		// make sure we are woken up to retry, even if nothing else happens.
+		let mut delay = Delay::new(backoff);
+		if delay.poll_unpin(cx).is_ready() {
+			cx.waker().wake_by_ref();
 		}
+		self.retry = Some((Instant::now() + backoff, delay));
+		trace!(target: "afg", "Sending failed, retrying in {:?} ({} of {})",
+			backoff,
+			self.failures,
+			self.policy.max_retries,
+		);
+
+		Poll::Pending
 	}
 }
 
-type FinalizedNotification<H, N, E> = (
+/// A block finalized by the voter, with the round and commit that finalized it.
+pub type FinalizedBlock<H, N, S, Id> = (H, N, u64, Commit<H, N, S, Id>);
+
+type FinalizedListener<H, N, E> = UnboundedSender<
+	FinalizedBlock<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
+>;
+
+type FinalizedSender<H, N, E> = finalized::FinalizedSender<
 	H,
 	N,
-	u64,
 	Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
-);
+>;
+
+type Events<H, N, E> = events::EventSender<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+type FinalizedReceiver<H, N, E> = finalized::FinalizedReceiver<
+	H,
+	N,
+	Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
+>;
 
 /// A future that maintains and multiplexes between different rounds,
 /// and caches votes.
@@ -382,28 +1050,56 @@ type FinalizedNotification<H, N, E> = (
 pub struct Voter<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=CommunicationIn<H, N, E::Signature, E::Id>, Error=E::Error>,
-	GlobalOut: Sink<SinkItem=CommunicationOut<H, N, E::Signature, E::Id>, SinkError=E::Error>,
+	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
 {
 	env: Arc<E>,
 	voters: VoterSet<E::Id>,
+	// the voter sets rounds were started with, by the round they apply from.
+	voter_schedule: BTreeMap<u64, VoterSet<E::Id>>,
 	best_round: VotingRound<H, N, E>,
 	past_rounds: PastRounds<H, N, E>,
-	finalized_notifications: UnboundedReceiver<FinalizedNotification<H, N, E>>,
-	last_finalized_number: N,
+	finalized_notifications: FinalizedReceiver<H, N, E>,
+	last_finalized: (H, N),
+	// the round and commit which finalized `last_finalized`, if known.
+	last_finalized_commit: Option<Justification<H, N, E::Signature, E::Id>>,
 	global_in: GlobalIn,
-	global_out: Buffered<GlobalOut>,
+	global_out: Buffered<GlobalOut, CommunicationOut<H, N, E::Signature, E::Id>>,
 	// the commit protocol might finalize further than the current round (if we're
 	// behind), we keep track of last finalized in round so we don't violate any
 	// assumptions from round-to-round.
 	last_finalized_in_rounds: (H, N),
+	handle: VoterHandle<H, N, E::Id>,
+	memory_budget: Option<usize>,
+	max_background_rounds: Option<usize>,
+	adaptive_timing: bool,
+	finalized_listeners: Vec<FinalizedListener<H, N, E>>,
+	events: Events<H, N, E>,
+	// how long it took for precommits to reach us in recently completed rounds.
+	gossip_latencies: VecDeque<Duration>,
+	// the highest round we've seen a commit message for.
+	highest_commit_round: u64,
+	block_imported: Option<UnboundedReceiver<(H, N)>>,
+	round_activity: Option<UnboundedReceiver<u64>>,
+	// the highest round seen through `round_activity`.
+	highest_active_round: u64,
+	// the highest block we've broadcast a commit for.
+	last_broadcast_commit: Option<N>,
+	// commits held back until their target is imported.
+	pending_commits: VecDeque<PendingCommit<H, N, E::Signature, E::Id>>,
+	// finalizations the environment hasn't completed yet, in order.
+	pending_finalizations: VecDeque<PendingFinalization<H, N, E>>,
+	// the highest block the environment has completed finalizing.
+	durably_finalized: N,
+	// commits held back until their target is durably finalized.
+	commits_awaiting_finality: Vec<Justification<H, N, E::Signature, E::Id>>,
 }
 
 impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, GlobalOut> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=CommunicationIn<H, N, E::Signature, E::Id>, Error=E::Error>,
-	GlobalOut: Sink<SinkItem=CommunicationOut<H, N, E::Signature, E::Id>, SinkError=E::Error>,
+	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
 {
 	/// Create new `Voter` tracker with given round number and base block.
 	///
@@ -412,8 +1108,10 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 	/// should be provided.
 	///
 	/// The input stream for commit messages should provide commits which
-	/// correspond to known blocks only (including all its precommits). It
-	/// is also responsible for validating the signature data in commit
+	/// correspond to known blocks only (including all its precommits). If the
+	/// environment provides `Environment::block_imported` notifications,
+	/// commits for a target it doesn't know yet are held back instead.
+	/// It is also responsible for validating the signature data in commit
 	/// messages.
 	pub fn new(
 		env: Arc<E>,
@@ -423,127 +1121,519 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 		last_round_state: RoundState<H, N>,
 		last_finalized: (H, N),
 	) -> Self {
-		let (finalized_sender, finalized_notifications) = mpsc::unbounded();
-		let last_finalized_number = last_finalized.1;
+		let (finalized_sender, finalized_notifications) = finalized::channel();
 		let (_, last_round_state) = crate::bridge_state::bridge_state(last_round_state);
+		let mut voter_schedule = std::iter::once((0, voters.clone())).collect();
+		let mut voters = voters;
+		update_voters(&*env, last_round_number + 1, &mut voters);
+		schedule_voters(&mut voter_schedule, last_round_number + 1, &voters);
 
+		let events = Events::<H, N, E>::new();
 		let best_round = VotingRound::new(
 			last_round_number + 1,
 			voters.clone(),
 			last_finalized.clone(),
 			Some(last_round_state),
 			finalized_sender,
+			events.clone(),
+			None,
 			env.clone(),
 		);
 
 		let (global_in, global_out) = global_comms;
-
-		// TODO: load last round (or more), re-process all votes from them,
-		// and background until irrelevant
+		let block_imported = env.block_imported();
+		let round_activity = env.round_activity();
+		let send_policy = env.send_policy();
 
 		Voter {
 			env,
 			voters,
+			voter_schedule,
 			best_round,
 			past_rounds: PastRounds::new(),
 			finalized_notifications,
-			last_finalized_number,
+			last_finalized: last_finalized.clone(),
+			durably_finalized: last_finalized.1,
+			last_finalized_commit: None,
 			last_finalized_in_rounds: last_finalized,
 			global_in,
-			global_out: Buffered::new(global_out),
+			global_out: Buffered::with_policy(global_out, send_policy),
+			handle: VoterHandle::new(),
+			memory_budget: None,
+			max_background_rounds: None,
+			adaptive_timing: false,
+			finalized_listeners: Vec::new(),
+			events,
+			gossip_latencies: VecDeque::new(),
+			highest_commit_round: 0,
+			block_imported,
+			round_activity,
+			highest_active_round: 0,
+			pending_commits: VecDeque::new(),
+			last_broadcast_commit: None,
+			pending_finalizations: VecDeque::new(),
+			commits_awaiting_finality: Vec::new(),
 		}
 	}
 
-	fn prune_background_rounds(&mut self) -> Result<(), E::Error> {
-		// Do work on all background rounds, broadcasting any commits generated.
-		while let Async::Ready(Some((number, commit))) = self.past_rounds.poll()? {
-			self.global_out.push(CommunicationOut::Commit(number, commit));
+	/// Create a new `Voter` which continues from a snapshot of in-flight round
+	/// state, e.g. after a crash.
+	///
+	/// The votes of the best round and of any background rounds are re-imported
+	/// and the votes we cast before the restart are sent again instead of
+	/// casting new ones, as with `Environment::has_voted`, so that we never
+	/// equivocate with them.
+	///
+	/// `voters` is the voter set before the oldest restored round. The weights
+	/// returned by `Environment::round_voters` for each restored round are
+	/// applied in order, as when the rounds were started.
+	pub fn resume(
+		env: Arc<E>,
+		voters: VoterSet<E::Id>,
+		global_comms: (GlobalIn, GlobalOut),
+		snapshot: VoterSnapshot<H, N, E::Signature, E::Id>,
+	) -> Result<Self, E::Error> {
+		let (finalized_sender, finalized_notifications) = finalized::channel();
+		let VoterSnapshot { best_round, mut background_rounds, last_round_state, last_finalized } = snapshot;
+		let best_round_number = best_round.round_number;
+
+		background_rounds.sort_by_key(|round| round.round_number);
+
+		let round_numbers = background_rounds.iter().map(|round| round.round_number);
+		let mut previous = None;
+		for round_number in round_numbers.chain(std::iter::once(best_round_number)) {
+			if previous == Some(round_number) {
+				return Err(crate::Error::DuplicateRound(round_number).into());
+			}
+			previous = Some(round_number);
 		}
 
-		while let Async::Ready(res) = self.finalized_notifications.poll()
-			.expect("unbounded receivers do not have spurious errors; qed")
-		{
-			let (f_hash, f_num, round, commit) =
-				res.expect("one sender always kept alive in self.best_round; qed");
-
+		let events = Events::<H, N, E>::new();
+		let mut voter_schedule = std::iter::once((0, voters.clone())).collect();
+		let mut voters = voters;
+		let mut past_rounds = Vec::with_capacity(background_rounds.len());
+		for round in background_rounds {
+			update_voters(&*env, round.round_number, &mut voters);
+			schedule_voters(&mut voter_schedule, round.round_number, &voters);
+			past_rounds.push(VotingRound::restore(
+				round,
+				voters.clone(),
+				None,
+				finalized_sender.clone(),
+				events.clone(),
+				env.clone(),
+			)?);
+		}
 
-			self.past_rounds.update_finalized(f_num);
+		let last_round_state = match past_rounds.last_mut() {
+			Some(round) if round.round_number() + 1 == best_round_number => round.bridge_state(),
+			_ => crate::bridge_state::bridge_state(last_round_state).1,
+		};
 
-			if self.set_last_finalized_number(f_num) {
-				self.env.finalize_block(f_hash.clone(), f_num, round, commit)?;
-			}
+		update_voters(&*env, best_round_number, &mut voters);
+		schedule_voters(&mut voter_schedule, best_round_number, &voters);
+		let best_round = VotingRound::restore(
+			best_round,
+			voters.clone(),
+			Some(last_round_state),
+			finalized_sender,
+			events.clone(),
+			env.clone(),
+		)?;
 
-			if f_num > self.last_finalized_in_rounds.1 {
-				self.last_finalized_in_rounds = (f_hash, f_num);
-			}
+		let mut background = PastRounds::new();
+		for round in past_rounds {
+			background.push(&*env, round, last_finalized.1);
 		}
 
-		Ok(())
+		let (global_in, global_out) = global_comms;
+		let block_imported = env.block_imported();
+		let round_activity = env.round_activity();
+		let send_policy = env.send_policy();
+
+		Ok(Voter {
+			env,
+			voters,
+			voter_schedule,
+			best_round,
+			past_rounds: background,
+			finalized_notifications,
+			last_finalized: last_finalized.clone(),
+			durably_finalized: last_finalized.1,
+			last_finalized_commit: None,
+			last_finalized_in_rounds: last_finalized,
+			global_in,
+			global_out: Buffered::with_policy(global_out, send_policy),
+			handle: VoterHandle::new(),
+			memory_budget: None,
+			max_background_rounds: None,
+			adaptive_timing: false,
+			finalized_listeners: Vec::new(),
+			events,
+			gossip_latencies: VecDeque::new(),
+			highest_commit_round: 0,
+			block_imported,
+			round_activity,
+			highest_active_round: 0,
+			pending_commits: VecDeque::new(),
+			last_broadcast_commit: None,
+			pending_finalizations: VecDeque::new(),
+			commits_awaiting_finality: Vec::new(),
+		})
 	}
 
-	/// Process all incoming messages from other nodes.
+	/// Limit the approximate memory used by all rounds to the given number of
+	/// bytes. When the budget is exceeded the oldest background rounds are
+	/// dropped, even if they are still relevant. The best round is always kept.
 	///
-	/// Commit messages are handled with extra care. If a commit message references
-	/// a currently backgrounded round, we send it to that round so that when we commit
-	/// on that round, our commit message will be informed by those that we've seen.
+	/// By default there is no limit.
+	pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+		self.memory_budget = budget;
+	}
+
+	/// Limit the number of past rounds kept running in the background. When
+	/// there are more, the oldest are dropped even if they are still relevant.
 	///
-	/// Otherwise, we will simply handle the commit and issue a finalization command
-	/// to the environment.
-	fn process_incoming(&mut self) -> Result<(), E::Error> {
-		while let Async::Ready(Some(item)) = self.global_in.poll()? {
-			match item {
-				CommunicationIn::Commit(round_number, commit, mut process_commit_outcome) => {
-					trace!(target: "afg", "Got commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
-						round_number,
-						commit.target_number,
-						commit.target_hash,
-					);
+	/// By default there is no limit.
+	pub fn set_max_background_rounds(&mut self, max_rounds: Option<usize>) {
+		self.max_background_rounds = max_rounds;
+	}
 
-					let commit: Commit<_, _, _, _> = commit.into();
+	/// Measure how long it takes for votes to reach us in the rounds we vote
+	/// in and suggest a matching gossip duration to `Environment::round_data`,
+	/// so the environment can shorten its timers on well-connected networks.
+	///
+	/// Disabled by default.
+	pub fn set_adaptive_timing(&mut self, enabled: bool) {
+		self.adaptive_timing = enabled;
+	}
 
-					// if the commit is for a background round dispatch to round committer.
-					// that returns Some if there wasn't one.
-					if let Some(commit) = self.past_rounds.import_commit(round_number, commit) {
-						// otherwise validate the commit and signal the finalized block
-						// (if any) to the environment
-						let validation_result = validate_commit(&commit, &self.voters, &*self.env)?;
-
-						if let Some((finalized_hash, finalized_number)) = validation_result.ghost {
-							// this can't be moved to a function because the compiler
-							// will complain about getting two mutable borrows to self
-							// (due to the call to `self.rounds.get_mut`).
-							let last_finalized_number = &mut self.last_finalized_number;
-
-							if finalized_number > *last_finalized_number {
-								*last_finalized_number = finalized_number;
-								self.env.finalize_block(finalized_hash, finalized_number, round_number, commit)?;
-							}
-							process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
-						} else {
-							// Failing validation of a commit is bad.
-							process_commit_outcome.run(
-								CommitProcessingOutcome::Bad(BadCommit::from(validation_result)),
-							);
-						}
-					} else {
-						// Import to backgrounded round is good.
-						process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
-					}
-				}
-				CommunicationIn::CatchUp(catch_up, mut process_catch_up_outcome) => {
-					trace!(target: "afg", "Got catch-up message for round {}", catch_up.round_number);
+	/// Get a stream of the blocks finalized by the voter, with the round and
+	/// commit that finalized them. Blocks are only notified once
+	/// `Environment::finalize_block` succeeded.
+	pub fn finalized_stream(&mut self) -> UnboundedReceiver<FinalizedBlock<H, N, E::Signature, E::Id>> {
+		let (tx, rx) = mpsc::unbounded();
+		self.finalized_listeners.push(tx);
+		rx
+	}
 
-					let round = if let Some(round) = validate_catch_up(
-						catch_up,
-						&*self.env,
-						&self.voters,
-						self.best_round.round_number(),
-					) {
-						round
-					} else {
-						process_catch_up_outcome.run(CatchUpProcessingOutcome::Bad(BadCatchUp::new()));
-						return Ok(());
-					};
+	/// Get a stream of events from the voter and its rounds, e.g. for
+	/// telemetry. Only events emitted after subscribing are received.
+	pub fn events(&self) -> VoterEvents<H, N, E::Signature, E::Id> {
+		self.events.subscribe()
+	}
+
+	/// Get a stream of the state of every round run by the voter, whenever
+	/// its prevote-GHOST, estimate, finalized block or completability change.
+	/// Only changes after subscribing are received.
+	pub fn round_state_updates(&self) -> RoundStateUpdates<H, N> {
+		self.events.subscribe_round_states(None)
+	}
+
+	/// Like `round_state_updates`, but only for the given round.
+	pub fn round_state_updates_for(&self, round: u64) -> RoundStateUpdates<H, N> {
+		self.events.subscribe_round_states(Some(round))
+	}
+
+	/// The number of past rounds currently running in the background.
+	pub fn background_rounds(&self) -> usize {
+		self.past_rounds.len()
+	}
+
+	/// Get a handle to this voter, which can be used to inspect it while running.
+	pub fn handle(&self) -> VoterHandle<H, N, E::Id> {
+		self.handle.clone()
+	}
+
+	/// Stop the voter. The returned future waits for pending finalizations,
+	/// flushes all messages waiting to be sent, notes the best round to the
+	/// environment as completed if it is completable and resolves
+	/// to a snapshot of the in-flight rounds, which can be passed to
+	/// `Voter::resume` later on.
+	///
+	/// The environment can treat a completable best round like any other
+	/// completed round, so that a voter created with `Voter::new` from it
+	/// starts at the next round without equivocating. A best round which isn't
+	/// completable isn't noted, since the next round couldn't be started from
+	/// it; it is only kept in the snapshot.
+	///
+	/// To stop a voter on some signal, select between the voter and the signal
+	/// (e.g. with `Future::select2`) and shut down the voter that is returned.
+	pub fn shutdown(self) -> Shutdown<H, N, E, GlobalIn, GlobalOut> {
+		Shutdown { voter: Some(self) }
+	}
+
+	// queue a commit to be broadcast, unless we've already broadcast one for
+	// the same or a higher block. A commit for the same block from a higher
+	// round replaces the queued one, and queued commits for lower blocks are
+	// dropped since the new one supersedes them. commits for blocks which
+	// aren't durably finalized yet are held back until they are.
+	fn broadcast_commit(&mut self, round: u64, commit: Commit<H, N, E::Signature, E::Id>) {
+		let number = commit.target_number;
+		if number > self.durably_finalized {
+			trace!(target: "afg", "Holding back commit for round {} until block #{:?} is finalized",
+				round,
+				number,
+			);
+
+			self.commits_awaiting_finality.push(CommitMessage::new(round, commit));
+			return;
+		}
+
+		let replaces_queued = || self.global_out.queued().any(|CommunicationOut::Commit(queued)|
+			queued.commit.target_number == number && queued.round_number < round
+		);
+
+		if let Some(last) = self.last_broadcast_commit {
+			if number < last || (number == last && !replaces_queued()) {
+				trace!(target: "afg", "Not broadcasting commit for round {}, block #{:?} was already committed",
+					round,
+					number,
+				);
+				return;
+			}
+		}
+
+		self.global_out.retain(|CommunicationOut::Commit(queued)|
+			queued.commit.target_number > number ||
+				(queued.commit.target_number == number && queued.round_number >= round)
+		);
+		let target = (commit.target_hash.clone(), number);
+		self.events.emit(|| VoterEvent::CommitBroadcast(round, target));
+		self.global_out.push(CommunicationOut::Commit(CommitMessage::new(round, commit)));
+		self.last_broadcast_commit = Some(number);
+	}
+
+	fn prune_background_rounds(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		// Do work on all background rounds, broadcasting any commits generated.
+		while let Poll::Ready(Some(item)) = self.past_rounds.poll_next_unpin(cx) {
+			let (number, commit) = item?;
+			self.broadcast_commit(number, commit);
+		}
+
+		while let Poll::Ready((f_hash, f_num, round, commit)) = self.finalized_notifications.poll(cx) {
+			self.past_rounds.update_finalized(f_num);
+
+			if self.set_last_finalized(f_hash.clone(), f_num, round, &commit)? {
+				self.finalize_block(f_hash.clone(), f_num, round, commit);
+			}
+
+			if f_num > self.last_finalized_in_rounds.1 {
+				self.last_finalized_in_rounds = (f_hash, f_num);
+			}
+		}
+
+		Ok(())
+	}
+
+	// whether to hold back a commit until its target is imported. only commits
+	// which would finalize a new block and are signed by voters of the round
+	// with enough weight are worth waiting for, others are processed (and
+	// rejected) right away.
+	fn should_hold_back(&self, round_number: u64, commit: &Commit<H, N, E::Signature, E::Id>) -> bool {
+		if commit.target_number <= self.last_finalized.1 ||
+			self.env.is_block_imported(commit.target_hash.clone())
+		{
+			return false;
+		}
+
+		let voters = self.voters_for(round_number);
+		let mut signers = HashSet::new();
+		let mut weight = 0;
+		for signed in &commit.precommits {
+			match voters.info(&signed.id) {
+				Some(info) => if signers.insert(&signed.id) { weight += info.weight() },
+				None => return false,
+			}
+		}
+
+		weight >= voters.threshold()
+	}
+
+	// hold back a commit until its target is imported, dropping the one for
+	// the lowest round if too many are held back.
+	fn hold_back(
+		&mut self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		process_commit_outcome: Callback<CommitProcessingOutcome>,
+	) {
+		trace!(target: "afg", "Holding back commit for round {} until its target is imported", round_number);
+		self.pending_commits.push_back((round_number, commit, process_commit_outcome));
+
+		if self.pending_commits.len() > MAX_PENDING_COMMITS {
+			let lowest = self.pending_commits.iter().enumerate()
+				.min_by_key(|(_, &(round_number, _, _))| round_number)
+				.map(|(idx, _)| idx)
+				.expect("more than MAX_PENDING_COMMITS are held back; qed");
+
+			let (round_number, _, mut process_commit_outcome) = self.pending_commits.remove(lowest)
+				.expect("index is in bounds; qed");
+
+			debug!(target: "afg", "Too many commits for unknown blocks, dropping commit for round {}",
+				round_number);
+			process_commit_outcome.run(CommitProcessingOutcome::Useless);
+		}
+	}
+
+	// dispatch a commit to its background round, or validate it and signal
+	// the finalized block (if any) to the environment.
+	fn process_commit(
+		&mut self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		process_commit_outcome: Callback<CommitProcessingOutcome>,
+	) -> Result<(), E::Error> {
+		let span = debug_span!(target: "afg", "commit",
+			round = round_number,
+			target_number = ?commit.target_number,
+			target_hash = ?commit.target_hash,
+		);
+		let _enter = span.enter();
+
+		// if the commit is for a background round dispatch to round committer.
+		// that returns Some if there wasn't one.
+		let imported = self.past_rounds.import_commit(round_number, commit, process_commit_outcome);
+		if let Some((commit, mut process_commit_outcome)) = imported {
+			let voters = self.voters_for(round_number);
+			let validation_result = validate_commit(&commit, &voters, &*self.env)?;
+
+			if let Some(metrics) = self.env.metrics() {
+				if validation_result.ghost().is_some() {
+					metrics.commit_validated();
+				} else {
+					metrics.commit_rejected();
+				}
+			}
+
+			if let Some((finalized_hash, finalized_number)) = validation_result.ghost().cloned() {
+				// only valid commits count, so that peers can't hold back our
+				// rounds with junk commits for far-future rounds.
+				self.highest_commit_round = std::cmp::max(self.highest_commit_round, round_number);
+
+				// the commit proves that a later round has completed, no need
+				// to go through the rounds in between.
+				if round_number > self.best_round.round_number() {
+					self.skip_to_round(round_number, voters, &commit, (finalized_hash.clone(), finalized_number))?;
+				}
+
+				if self.set_last_finalized(finalized_hash.clone(), finalized_number, round_number, &commit)? {
+					self.finalize_block(finalized_hash, finalized_number, round_number, commit);
+				}
+				process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
+			} else {
+				// Failing validation of a commit is bad.
+				process_commit_outcome.run(
+					CommitProcessingOutcome::Bad(BadCommit::from(validation_result)),
+				);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Process commits that were held back because their target was unknown,
+	/// once it has been imported. If the environment stops notifying us of
+	/// imported blocks all of them are processed as they are. Commits which
+	/// can't finalize a new block anymore are dropped.
+	///
+	/// Returns whether any commit messages were processed.
+	fn process_pending_commits(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let last_finalized = self.last_finalized.1;
+		for (round_number, commit, process_commit_outcome) in self.pending_commits.iter_mut() {
+			if commit.target_number <= last_finalized {
+				trace!(target: "afg", "Dropping held back commit for round {}, its target is finalized already",
+					round_number);
+				process_commit_outcome.run(CommitProcessingOutcome::Useless);
+			}
+		}
+		self.pending_commits.retain(|(_, commit, _)| commit.target_number > last_finalized);
+
+		let mut imported = false;
+		let mut ended = false;
+		if let Some(ref mut block_imported) = self.block_imported {
+			loop {
+				match block_imported.poll_next_unpin(cx) {
+					Poll::Ready(Some(_)) => imported = true,
+					Poll::Ready(None) => {
+						ended = true;
+						break;
+					}
+					Poll::Pending => break,
+				}
+			}
+		}
+
+		if ended {
+			self.block_imported = None;
+		}
+
+		if (!imported && !ended) || self.pending_commits.is_empty() {
+			return Ok(false);
+		}
+
+		let mut processed = false;
+		for (round_number, commit, process_commit_outcome) in std::mem::take(&mut self.pending_commits) {
+			if !ended && !self.env.is_block_imported(commit.target_hash.clone()) {
+				self.pending_commits.push_back((round_number, commit, process_commit_outcome));
+			} else {
+				processed = true;
+				self.process_commit(round_number, commit, process_commit_outcome)?;
+			}
+		}
+
+		Ok(processed)
+	}
+
+	/// Process all incoming messages from other nodes.
+	///
+	/// Commit messages are handled with extra care. If a commit message references
+	/// a currently backgrounded round, we send it to that round so that when we commit
+	/// on that round, our commit message will be informed by those that we've seen.
+	///
+	/// Otherwise, we will simply handle the commit and issue a finalization command
+	/// to the environment.
+	///
+	/// Commits for a target we haven't imported yet are held back until it is,
+	/// if the environment notifies us of imported blocks.
+	///
+	/// Returns whether any commit messages were processed.
+	fn process_incoming(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let mut imported_commits = false;
+		while let Poll::Ready(Some(item)) = self.global_in.poll_next_unpin(cx) {
+			match item? {
+				CommunicationIn::Commit(CommitMessage { round_number, commit }, process_commit_outcome) => {
+					trace!(target: "afg", "Got commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
+						round_number,
+						commit.target_number,
+						commit.target_hash,
+					);
+
+					imported_commits = true;
+
+					let commit: Commit<_, _, _, _> = commit.into();
+
+					if self.block_imported.is_some() && self.should_hold_back(round_number, &commit) {
+						self.hold_back(round_number, commit, process_commit_outcome);
+					} else {
+						self.process_commit(round_number, commit, process_commit_outcome)?;
+					}
+				}
+				CommunicationIn::CatchUp(catch_up, mut process_catch_up_outcome) => {
+					trace!(target: "afg", "Got catch-up message for round {}", catch_up.round_number);
+
+					let voters = self.voters_for(catch_up.round_number);
+					let round = if let Some(round) = validate_catch_up(
+						catch_up,
+						&*self.env,
+						&voters,
+						self.best_round.round_number(),
+					) {
+						round
+					} else {
+						process_catch_up_outcome.run(CatchUpProcessingOutcome::Bad(BadCatchUp::new()));
+						return Ok(imported_commits);
+					};
 
 					let state = round.state();
 
@@ -552,15 +1642,20 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 					let mut just_completed = VotingRound::completed(
 						round,
 						self.best_round.finalized_sender(),
+						self.events.clone(),
 						self.env.clone(),
 					);
 
+					self.set_voters(just_completed.round_number(), voters);
+
 					let new_best = VotingRound::new(
 						just_completed.round_number() + 1,
 						self.voters.clone(),
 						self.last_finalized_in_rounds.clone(),
 						Some(just_completed.bridge_state()),
 						self.best_round.finalized_sender(),
+						self.events.clone(),
+						self.gossip_duration_hint(),
 						self.env.clone(),
 					);
 
@@ -572,47 +1667,218 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 						}
 					}
 
-					self.env.completed(
-						just_completed.round_number(),
-						just_completed.round_state(),
-						just_completed.dag_base(),
-						just_completed.historical_votes(),
-					)?;
+					self.completed_round(&just_completed)?;
 
-					self.past_rounds.push(&*self.env, just_completed);
+					self.past_rounds.push(&*self.env, just_completed, self.last_finalized.1);
 
-					self.past_rounds.push(
-						&*self.env,
-						std::mem::replace(&mut self.best_round, new_best),
-					);
+					let old_best = self.replace_best_round(new_best);
+					self.past_rounds.push(&*self.env, old_best, self.last_finalized.1);
 
 					process_catch_up_outcome.run(CatchUpProcessingOutcome::Good(GoodCatchUp::new()));
 				},
+				CommunicationIn::JustificationRequest((hash, number), mut respond) => {
+					trace!(target: "afg", "Got justification request for block {:?}", (&hash, number));
+
+					respond.run(self.justify(&hash, number));
+				}
+			}
+		}
+
+		Ok(imported_commits)
+	}
+
+	// assemble a commit for the given block, or one of its descendents, from
+	// the lowest running round which finalized it.
+	fn justify(&mut self, hash: &H, number: N) -> Option<Justification<H, N, E::Signature, E::Id>> {
+		if let Some(justification) = self.past_rounds.justify(&*self.env, hash, number) {
+			return Some(justification);
+		}
+
+		let finalizes = self.best_round.finalized().is_some_and(|(f_hash, f_number)|
+			*f_number >= number && self.env.is_equal_or_descendent_of(hash.clone(), f_hash.clone())
+		);
+
+		if finalizes {
+			let round_number = self.best_round.round_number();
+			self.best_round.assemble_commit().map(|commit| CommitMessage::new(round_number, commit))
+		} else {
+			None
+		}
+	}
+
+	// log and swallow transient errors, returning `None` in their place.
+	fn recover<T>(&self, result: Result<T, E::Error>) -> Result<Option<T>, E::Error> {
+		match result {
+			Ok(value) => Ok(Some(value)),
+			Err(e) => match self.env.error_severity(&e) {
+				ErrorSeverity::Transient => {
+					warn!(target: "afg", "Transient error in voter: {:?}", e);
+					Ok(None)
+				}
+				ErrorSeverity::Fatal => Err(e),
+			},
+		}
+	}
+
+	// note the rounds which saw activity. Activity beyond the best round means
+	// the network has moved on without us.
+	fn process_round_activity(&mut self, cx: &mut Context) {
+		let best_round_number = self.best_round.round_number();
+		let mut ended = false;
+		if let Some(ref mut round_activity) = self.round_activity {
+			loop {
+				match round_activity.poll_next_unpin(cx) {
+					Poll::Ready(Some(round)) => if round > self.highest_active_round {
+						if round > best_round_number {
+							trace!(target: "afg", "Seen activity in round {} while at round {}",
+								round,
+								best_round_number,
+							);
+						}
+
+						self.highest_active_round = round;
+					},
+					Poll::Ready(None) => {
+						ended = true;
+						break;
+					}
+					Poll::Pending => break,
+				}
 			}
 		}
 
+		if ended {
+			self.round_activity = None;
+		}
+	}
+
+	// the gossip duration to suggest for new rounds: the highest recently
+	// measured latency, if adaptive timing is enabled.
+	fn gossip_duration_hint(&self) -> Option<Duration> {
+		if !self.adaptive_timing {
+			return None;
+		}
+
+		self.gossip_latencies.iter().max().cloned()
+	}
+
+	// the voter set to validate messages of the given round with. background
+	// rounds have their own, for other rounds the environment's weights for the
+	// round apply, if any, or else those the round was started with (or would
+	// be, for rounds we haven't reached).
+	fn voters_for(&self, round_number: u64) -> VoterSet<E::Id> {
+		if round_number == self.best_round.round_number() {
+			return self.voters.clone();
+		}
+
+		let mut voters = self.voter_schedule.range(..=round_number).next_back()
+			.map(|(_, voters)| voters.clone())
+			.expect("a voter set for round 0 is always present; qed");
+		update_voters(&*self.env, round_number, &mut voters);
+
+		voters
+	}
+
+	// use the given voter set for the given completed round, and the
+	// environment's weights, if any, for the round after it.
+	fn set_voters(&mut self, round_number: u64, voters: VoterSet<E::Id>) {
+		schedule_voters(&mut self.voter_schedule, round_number, &voters);
+		self.voters = voters;
+		update_voters(&*self.env, round_number + 1, &mut self.voters);
+		schedule_voters(&mut self.voter_schedule, round_number + 1, &self.voters);
+	}
+
+	// whether commits were seen for rounds far ahead of the best round.
+	fn is_far_behind(&self) -> bool {
+		self.highest_commit_round > self.best_round.round_number() + FAR_BEHIND_ROUNDS
+	}
+
+	// complete the given later round, which the commit finalized a block in,
+	// and start voting in the round after it. The best round keeps running in
+	// the background.
+	fn skip_to_round(
+		&mut self,
+		round_number: u64,
+		voters: VoterSet<E::Id>,
+		commit: &Commit<H, N, E::Signature, E::Id>,
+		finalized: (H, N),
+	) -> Result<(), E::Error> {
+		debug!(target: "afg", "Skipping from round {} to round {} after commit finalizing {:?}",
+			self.best_round.round_number(),
+			round_number + 1,
+			finalized,
+		);
+
+		// we only know what the commit tells us: the finalized block is also
+		// the most conservative estimate of the round.
+		let state = RoundState {
+			prevote_ghost: Some(finalized.clone()),
+			finalized: Some(finalized.clone()),
+			estimate: Some(finalized.clone()),
+			completable: true,
+		};
+
+		let votes = HistoricalVotes::new_with(
+			commit.precommits.iter().map(|signed| SignedMessage {
+				message: Message::Precommit(signed.precommit.clone()),
+				signature: signed.signature.clone(),
+				id: signed.id.clone(),
+			}).collect(),
+			None,
+			None,
+		);
+
+		self.env.completed(round_number, state.clone(), finalized.clone(), &votes)?;
+		self.handle.set_last_completed_round(CompletedRound {
+			number: round_number,
+			state: state.clone(),
+			base: finalized.clone(),
+			voters: voters.clone(),
+		});
+
+		self.set_voters(round_number, voters);
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(state);
+		let new_best = VotingRound::new(
+			round_number + 1,
+			self.voters.clone(),
+			self.last_finalized_in_rounds.clone(),
+			Some(last_round_state),
+			self.best_round.finalized_sender(),
+			self.events.clone(),
+			self.gossip_duration_hint(),
+			self.env.clone(),
+		);
+
+		// update last-finalized in rounds _after_ starting new round.
+		// otherwise the base could be too eagerly set forward.
+		if finalized.1 > self.last_finalized_in_rounds.1 {
+			self.last_finalized_in_rounds = finalized;
+		}
+
+		let old_best = self.replace_best_round(new_best);
+		self.past_rounds.push(&*self.env, old_best, self.last_finalized.1);
+
 		Ok(())
 	}
 
 	// process the logic of the best round.
-	fn process_best_round(&mut self) -> Poll<(), E::Error> {
+	fn process_best_round(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
 		// If the current `best_round` is completable and we've already precommitted,
 		// we start a new round at `best_round + 1`.
 		let should_start_next = {
-			let completable = match self.best_round.poll()? {
-				Async::Ready(()) => true,
-				Async::NotReady => false,
+			let completable = match self.best_round.poll(cx)? {
+				Poll::Ready(()) => true,
+				Poll::Pending => false,
 			};
 
-			let precommitted = match self.best_round.state() {
-				Some(&VotingRoundState::Precommitted) => true, // start when we've cast all votes.
-				_ => false,
-			};
+			// start when we've cast all votes.
+			let precommitted = self.best_round.step() == Some(Step::Precommitted);
 
 			completable && precommitted
 		};
 
-		if !should_start_next { return Ok(Async::NotReady) }
+		if !should_start_next { return Poll::Pending }
 
 		trace!(target: "afg", "Best round at {} has become completable. Starting new best round at {}",
 			self.best_round.round_number(),
@@ -622,18 +1888,47 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 		self.completed_best_round()?;
 
 		// round has been updated. so we need to re-poll.
-		self.poll()
+		Future::poll(Pin::new(self), cx)
 	}
 
-	fn completed_best_round(&mut self) -> Result<(), E::Error> {
+	// note a completed round to the environment and the voter handle.
+	fn completed_round(&self, round: &VotingRound<H, N, E>) -> Result<(), E::Error> {
 		self.env.completed(
-			self.best_round.round_number(),
-			self.best_round.round_state(),
-			self.best_round.dag_base(),
-			self.best_round.historical_votes(),
+			round.round_number(),
+			round.round_state(),
+			round.dag_base(),
+			round.historical_votes(),
 		)?;
+		self.env.completed_with_report(round.completion_report());
+
+		self.handle.note_vote(round.last_vote_at());
+		self.handle.set_last_completed_round(CompletedRound {
+			number: round.round_number(),
+			state: round.round_state(),
+			base: round.dag_base(),
+			voters: round.voters().clone(),
+		});
+
+		Ok(())
+	}
+
+	fn completed_best_round(&mut self) -> Result<(), E::Error> {
+		self.completed_round(&self.best_round)?;
+
+		if let Some(metrics) = self.env.metrics() {
+			metrics.round_duration(self.best_round.started_at().elapsed());
+		}
+
+		if let Some(latency) = self.best_round.gossip_latency() {
+			self.gossip_latencies.push_back(latency);
+			if self.gossip_latencies.len() > ADAPTIVE_TIMING_ROUNDS {
+				self.gossip_latencies.pop_front();
+			}
+		}
 
 		let old_round_number = self.best_round.round_number();
+		update_voters(&*self.env, old_round_number + 1, &mut self.voters);
+		schedule_voters(&mut self.voter_schedule, old_round_number + 1, &self.voters);
 
 		let next_round = VotingRound::new(
 			old_round_number + 1,
@@ -641,85 +1936,400 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 			self.last_finalized_in_rounds.clone(),
 			Some(self.best_round.bridge_state()),
 			self.best_round.finalized_sender(),
+			self.events.clone(),
+			self.gossip_duration_hint(),
 			self.env.clone(),
 		);
 
-		let old_round = ::std::mem::replace(&mut self.best_round, next_round);
-		self.past_rounds.push(&*self.env, old_round);
+		let old_round = self.replace_best_round(next_round);
+		self.past_rounds.push(&*self.env, old_round, self.last_finalized.1);
 		Ok(())
 	}
 
-	fn set_last_finalized_number(&mut self, finalized_number: N) -> bool {
-		let last_finalized_number = &mut self.last_finalized_number;
-		if finalized_number > *last_finalized_number {
-			*last_finalized_number = finalized_number;
-			return true;
+	// account the memory used by all rounds and evict the oldest background
+	// rounds if we're over budget or keep too many of them.
+	fn enforce_round_limits(&mut self) {
+		let best_round_size = self.best_round.approximate_size();
+		let mut background_sizes = self.past_rounds.approximate_sizes();
+		background_sizes.sort();
+
+		if let Some(max_rounds) = self.max_background_rounds {
+			let excess = background_sizes.len().saturating_sub(max_rounds);
+			for &(round_number, _) in &background_sizes[..excess] {
+				trace!(target: "afg", "More than {} background rounds, evicting round {}",
+					max_rounds,
+					round_number,
+				);
+
+				self.past_rounds.evict(round_number);
+			}
+
+			background_sizes.drain(..excess);
 		}
-		false
-	}
-}
 
-impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Future for Voter<H, N, E, GlobalIn, GlobalOut> where
-	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
-	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=CommunicationIn<H, N, E::Signature, E::Id>, Error=E::Error>,
-	GlobalOut: Sink<SinkItem=CommunicationOut<H, N, E::Signature, E::Id>, SinkError=E::Error>,
-{
-	type Item = ();
-	type Error = E::Error;
+		if let Some(budget) = self.memory_budget {
+			let mut total: usize = best_round_size +
+				background_sizes.iter().map(|&(_, size)| size).sum::<usize>();
+
+			let mut evict = 0;
+			while total > budget && evict < background_sizes.len() {
+				let (round_number, size) = background_sizes[evict];
+				trace!(target: "afg", "Memory budget of {} bytes exceeded ({} bytes used), evicting round {}",
+					budget,
+					total,
+					round_number,
+				);
 
-	fn poll(&mut self) -> Poll<(), E::Error> {
-		self.process_incoming()?;
-		self.prune_background_rounds()?;
-		self.global_out.poll()?;
+				self.past_rounds.evict(round_number);
+				total -= size;
+				evict += 1;
+			}
 
-		self.process_best_round()
-	}
-}
+			background_sizes.drain(..evict);
+		}
 
-/// Validate the given catch up and return a completed round with all prevotes
-/// and precommits from the catch up imported. If the catch up is invalid `None`
-/// is returned instead.
-fn validate_catch_up<H, N, S, I, E>(
-	catch_up: CatchUp<H, N, S, I>,
-	env: &E,
-	voters: &VoterSet<I>,
-	best_round_number: u64,
-) -> Option<crate::round::Round<I, H, N, S>> where
-	H: Clone + Eq + Ord + std::fmt::Debug + std::hash::Hash,
-	N: BlockNumberOps + std::fmt::Debug,
-	S: Clone + Eq,
-	I: Clone + Eq + std::fmt::Debug + std::hash::Hash,
-	E: Environment<H, N>,
-{
-	if catch_up.round_number <= best_round_number {
-		trace!(target: "afg", "Ignoring because best round number is {}",
-			   best_round_number);
+		background_sizes.push((self.best_round.round_number(), best_round_size));
+		self.handle.set_round_sizes(background_sizes);
+	}
 
-		return None;
+	fn snapshot(&mut self) -> VoterSnapshot<H, N, E::Signature, E::Id> {
+		VoterSnapshot {
+			best_round: self.best_round.snapshot(),
+			background_rounds: self.past_rounds.snapshots(),
+			last_round_state: self.best_round.last_round_state()
+				.expect("the best round is always started with the state of the prior round; qed"),
+			last_finalized: self.last_finalized_in_rounds.clone(),
+		}
 	}
 
-	// check threshold support in prevotes and precommits.
-	{
-		let mut map = std::collections::HashMap::new();
+	// note a newly finalized block, returning whether it is higher than the
+	// last finalized block. fails if the block is on a different fork, which
+	// means safety has been violated, after reporting both commits to the
+	// environment. a block at or below the last finalized one must be one of
+	// its ancestors. failed ancestry lookups are passed on as errors.
+	fn set_last_finalized(
+		&mut self,
+		hash: H,
+		number: N,
+		round: u64,
+		commit: &Commit<H, N, E::Signature, E::Id>,
+	) -> Result<bool, E::Error> {
+		let higher = number > self.last_finalized.1;
+		let consistent = if higher {
+			self.env.check_descendent_of(self.last_finalized.0.clone(), hash.clone())?
+		} else {
+			self.env.check_descendent_of(hash.clone(), self.last_finalized.0.clone())?
+		};
 
-		for prevote in &catch_up.prevotes {
-			if !voters.contains_key(&prevote.id) {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, invalid voter: {:?}",
-					   prevote.id,
-				);
+		if !consistent {
+			error!(target: "afg", "Safety violation: finalized block {:?} is not a descendent of \
+				previously finalized block {:?}",
+				(&hash, number),
+				self.last_finalized,
+			);
 
-				return None;
-			}
+			self.env.finality_violation(FinalityViolation {
+				finalized: self.last_finalized.clone(),
+				finalized_commit: self.last_finalized_commit.clone(),
+				conflicting_commit: CommitMessage::new(round, commit.clone()),
+			});
 
-			map.entry(prevote.id.clone()).or_insert((false, false)).0 = true;
+			return Err(crate::Error::SafetyViolation.into());
 		}
 
-		for precommit in &catch_up.precommits {
-			if !voters.contains_key(&precommit.id) {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, invalid voter: {:?}",
+		if !higher {
+			return Ok(false);
+		}
+
+		self.last_finalized = (hash, number);
+		self.last_finalized_commit = Some(CommitMessage::new(round, commit.clone()));
+		Ok(true)
+	}
+
+	// start finalizing the block with the environment. listeners are notified
+	// once it has completed, see `process_finalizations`.
+	fn finalize_block(
+		&mut self,
+		hash: H,
+		number: N,
+		round: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+	) {
+		let finalize = self.env.finalize_block(hash.clone(), number, round, commit.clone());
+		self.pending_finalizations.push_back((hash, number, round, commit, finalize));
+	}
+
+	// drive the environment's finalizations in order, notifying listeners of
+	// completed ones and broadcasting the commits held back for them.
+	fn process_finalizations(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		while let Some((_, _, _, _, finalize)) = self.pending_finalizations.front_mut() {
+			let result = match finalize.poll_unpin(cx) {
+				Poll::Ready(result) => result,
+				Poll::Pending => break,
+			};
+
+			let (hash, number, round, commit, _) = self.pending_finalizations.pop_front()
+				.expect("front was just polled; qed");
+			result?;
+
+			self.durably_finalized = std::cmp::max(self.durably_finalized, number);
+			self.finalized_listeners.retain(|listener|
+				listener.unbounded_send((hash.clone(), number, round, commit.clone())).is_ok()
+			);
+
+			self.events.emit(|| VoterEvent::Finalized(round, (hash, number)));
+			self.handle.note_finalized();
+		}
+
+		let durably_finalized = self.durably_finalized;
+		let (ready, waiting) = std::mem::take(&mut self.commits_awaiting_finality)
+			.into_iter()
+			.partition(|message| message.commit.target_number <= durably_finalized);
+		self.commits_awaiting_finality = waiting;
+
+		for CommitMessage { round_number, commit } in ready {
+			self.broadcast_commit(round_number, commit);
+		}
+
+		Ok(())
+	}
+
+	// start voting in the given round, returning the previous best round.
+	fn replace_best_round(&mut self, round: VotingRound<H, N, E>) -> VotingRound<H, N, E> {
+		let number = round.round_number();
+		self.events.emit(|| VoterEvent::RoundStarted(number));
+
+		std::mem::replace(&mut self.best_round, round)
+	}
+}
+
+// the voter is never structurally pinned: every field is polled by `&mut`.
+impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Unpin for Voter<H, N, E, GlobalIn, GlobalOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
+{}
+
+impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Future for Voter<H, N, E, GlobalIn, GlobalOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
+{
+	type Output = Result<(), E::Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		self.handle.set_waker(cx.waker());
+
+		let pending = self.process_pending_commits(cx);
+		let mut imported_commits = self.recover(pending)?.unwrap_or(false);
+		let incoming = self.process_incoming(cx);
+		imported_commits |= self.recover(incoming)?.unwrap_or(false);
+
+		self.process_round_activity(cx);
+
+		let paused = !self.handle.voting_enabled();
+		self.best_round.set_paused(paused);
+		self.past_rounds.set_paused(paused);
+
+		let pruned = self.prune_background_rounds(cx);
+		self.recover(pruned)?;
+		let finalized = self.process_finalizations(cx);
+		self.recover(finalized)?;
+		self.enforce_round_limits();
+		let flushed = match self.global_out.poll(cx) {
+			Poll::Ready(Err(e)) => Err(e),
+			_ => Ok(()),
+		};
+		self.recover(flushed)?;
+
+		// when far behind, commits finalize much more than our own rounds can,
+		// so round messages are only imported once no more commits are pending.
+		let res = if imported_commits && self.is_far_behind() {
+			trace!(target: "afg", "Best round {} is far behind commits for round {}, deferring round messages",
+				self.best_round.round_number(),
+				self.highest_commit_round,
+			);
+
+			cx.waker().wake_by_ref();
+			Poll::Pending
+		} else {
+			match self.process_best_round(cx) {
+				Poll::Ready(Err(e)) => match self.recover::<()>(Err(e)) {
+					Ok(_) => Poll::Pending,
+					Err(e) => Poll::Ready(Err(e)),
+				},
+				processed => processed,
+			}
+		};
+		let voter_state = VoterState {
+			best_round: self.best_round.report(),
+			background_rounds: self.past_rounds.reports(),
+		};
+		self.handle.set_voter_state(voter_state);
+		self.handle.note_vote(self.best_round.last_vote_at());
+		self.handle.set_best_round(
+			self.best_round.started_at(),
+			self.best_round.is_voting(),
+			self.highest_active_round.saturating_sub(self.best_round.round_number()),
+		);
+
+		if let Some(metrics) = self.env.metrics() {
+			metrics.current_round(self.best_round.round_number());
+			metrics.background_rounds(self.past_rounds.len());
+			metrics.buffered_messages(self.global_out.len() + self.best_round.buffered());
+		}
+
+		res
+	}
+}
+
+/// A future which stops a voter, resolving to a snapshot of its rounds once all
+/// pending messages have been sent. See `Voter::shutdown`.
+pub struct Shutdown<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
+{
+	voter: Option<Voter<H, N, E, GlobalIn, GlobalOut>>,
+}
+
+impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Future for Shutdown<H, N, E, GlobalIn, GlobalOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
+{
+	type Output = Result<VoterSnapshot<H, N, E::Signature, E::Id>, E::Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		{
+			let voter = self.voter.as_mut().expect("not polled after completion; qed");
+
+			// commits are only sent once their blocks are durably finalized.
+			voter.process_finalizations(cx)?;
+			if !voter.pending_finalizations.is_empty() {
+				return Poll::Pending;
+			}
+
+			// poll all sinks, even if some are not flushed yet.
+			let global_flushed = voter.global_out.poll(cx)?.is_ready();
+			let best_flushed = voter.best_round.flush(cx)?.is_ready();
+			let background_flushed = voter.past_rounds.flush(cx)?.is_ready();
+
+			if !(global_flushed && best_flushed && background_flushed) {
+				return Poll::Pending;
+			}
+		}
+
+		let mut voter = self.voter.take().expect("checked above; qed");
+
+		debug!(target: "afg", "Shutting down voter at round {}", voter.best_round.round_number());
+
+		if voter.best_round.round_state().completable {
+			voter.completed_round(&voter.best_round)?;
+		}
+
+		Poll::Ready(Ok(voter.snapshot()))
+	}
+}
+
+/// Update the voter weights to be used starting at the given round, if the
+/// environment provides new ones. Sets with different voters are ignored.
+fn update_voters<H, N, E>(env: &E, round_number: u64, voters: &mut VoterSet<E::Id>) where
+	H: Eq,
+	N: BlockNumberOps,
+	E: Environment<H, N>,
+{
+	let new_voters = match env.round_voters(round_number) {
+		Some(new_voters) => new_voters,
+		None => return,
+	};
+
+	if !new_voters.has_same_voters(voters) {
+		warn!(target: "afg", "Ignoring voter weights for round {}: voter set differs from current one",
+			round_number);
+		return;
+	}
+
+	if new_voters == *voters {
+		return;
+	}
+
+	if new_voters.total_weight() == 0 {
+		warn!(target: "afg", "Ignoring voter weights for round {}: total weight is zero", round_number);
+		return;
+	}
+
+	trace!(target: "afg", "Updating voter weights at round {}: total weight {} -> {}, threshold {} -> {}",
+		round_number,
+		voters.total_weight(),
+		new_voters.total_weight(),
+		voters.threshold(),
+		new_voters.threshold(),
+	);
+
+	*voters = new_voters;
+}
+
+/// Record that the given voter set is used from the given round onwards,
+/// unless it is already in effect by then.
+fn schedule_voters<Id: Hash + Eq + Clone>(
+	schedule: &mut BTreeMap<u64, VoterSet<Id>>,
+	round_number: u64,
+	voters: &VoterSet<Id>,
+) {
+	let in_effect = schedule.range(..=round_number).next_back().map(|(_, voters)| voters);
+	if in_effect != Some(voters) {
+		schedule.insert(round_number, voters.clone());
+	}
+}
+
+/// Validate the given catch up and return a completed round with all prevotes
+/// and precommits from the catch up imported. If the catch up is invalid `None`
+/// is returned instead.
+fn validate_catch_up<H, N, S, I, E>(
+	catch_up: CatchUp<H, N, S, I>,
+	env: &E,
+	voters: &VoterSet<I>,
+	best_round_number: u64,
+) -> Option<crate::round::Round<I, H, N, S>> where
+	H: Clone + Eq + Ord + std::fmt::Debug + std::hash::Hash,
+	N: BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	I: Clone + Eq + std::fmt::Debug + std::hash::Hash,
+	E: Environment<H, N>,
+{
+	if catch_up.round_number <= best_round_number {
+		trace!(target: "afg", "Ignoring because best round number is {}",
+			   best_round_number);
+
+		return None;
+	}
+
+	// check threshold support in prevotes and precommits.
+	{
+		let mut map = std::collections::HashMap::new();
+
+		for prevote in &catch_up.prevotes {
+			if !voters.contains_key(&prevote.id) {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, invalid voter: {:?}",
+					   prevote.id,
+				);
+
+				return None;
+			}
+
+			map.entry(prevote.id.clone()).or_insert((false, false)).0 = true;
+		}
+
+		for precommit in &catch_up.precommits {
+			if !voters.contains_key(&precommit.id) {
+				trace!(target: "afg",
+					   "Ignoring invalid catch up, invalid voter: {:?}",
 					   precommit.id,
 				);
 
@@ -762,34 +2372,22 @@ fn validate_catch_up<H, N, S, I, E>(
 		base: (catch_up.base_hash.clone(), catch_up.base_number),
 	});
 
-	// import prevotes first.
-	for crate::SignedPrevote { prevote, id, signature } in catch_up.prevotes {
-		match round.import_prevote(env, prevote, id, signature) {
-			Ok(_) => {},
-			Err(e) => {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, error importing prevote: {:?}",
-					   e,
-				);
+	// import prevotes first, then precommits.
+	let prevotes = catch_up.prevotes.into_iter().map(|crate::SignedPrevote { prevote, id, signature }| {
+		SignedMessage { message: Message::Prevote(prevote), signature, id }
+	});
 
-				return None;
-			},
-		}
-	}
+	let precommits = catch_up.precommits.into_iter().map(|crate::SignedPrecommit { precommit, id, signature }| {
+		SignedMessage { message: Message::Precommit(precommit), signature, id }
+	});
 
-	// then precommits.
-	for crate::SignedPrecommit { precommit, id, signature } in catch_up.precommits {
-		match round.import_precommit(env, precommit, id, signature) {
-			Ok(_) => {},
-			Err(e) => {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, error importing precommit: {:?}",
-					   e,
-				);
+	if let Err(e) = round.import_votes(env, prevotes.chain(precommits)) {
+		trace!(target: "afg",
+			   "Ignoring invalid catch up, error importing votes: {:?}",
+			   e,
+		);
 
-				return None;
-			},
-		}
+		return None;
 	}
 
 	let state = round.state();
@@ -803,369 +2401,3855 @@ fn validate_catch_up<H, N, S, I, E>(
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use crate::SignedPrecommit;
+	use crate::{Error, SignedPrecommit, Precommit};
 	use crate::testing::{
 		self,
+		byzantine::{byzantine_voter, Behavior},
 		chain::GENESIS_HASH,
-		environment::{Environment, Id, Signature},
+		environment::{Environment, Id, LinkLatency, Network, Signature},
+		simulation::{block_on_virtual, Simulation},
 	};
+	use futures::executor::{block_on, LocalPool, LocalSpawner};
+	use futures::future;
+	use futures::stream;
+	use futures::task::{noop_waker_ref, LocalSpawnExt};
 	use std::time::Duration;
-	use tokio::prelude::FutureExt;
-	use tokio::runtime::current_thread;
 
-	#[test]
-	fn talking_to_myself() {
-		let local_id = Id(5);
-		let voters = std::iter::once((local_id, 100)).collect();
+	// a sink which fails the given number of times before accepting items.
+	struct FlakySink {
+		failures: usize,
+		sent: Vec<u32>,
+	}
+
+	impl Sink<u32> for FlakySink {
+		type Error = ();
+
+		fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), ()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn start_send(mut self: Pin<&mut Self>, item: u32) -> Result<(), ()> {
+			if self.failures > 0 {
+				self.failures -= 1;
+				return Err(());
+			}
+
+			self.sent.push(item);
+			Ok(())
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), ()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), ()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	// a sink which fails to send any item.
+	struct BrokenSink;
+
+	impl<T> Sink<T> for BrokenSink {
+		type Error = Error;
+
+		fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn start_send(self: Pin<&mut Self>, _: T) -> Result<(), Error> {
+			Err(Error::NotDescendent)
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), Error>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	// run the future created by `f` on a single-threaded executor, along with
+	// the tasks it spawns, until it resolves.
+	fn block_on_all<F: Future>(f: impl FnOnce(&LocalSpawner) -> F) -> F::Output {
+		let mut pool = LocalPool::new();
+		let future = f(&pool.spawner());
+		pool.run_until(future)
+	}
+
+	// run the given number of voters out of 10 until they all finalize, on a
+	// network with the given latency. returns the virtual time taken.
+	fn finalize_with_latency(
+		online: u32,
+		latency: LinkLatency,
+		seed: u64,
+		configure: impl FnOnce(&Network),
+	) -> Duration {
+		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(latency, seed);
+		let clock = network.clock().unwrap();
+		configure(&network);
+
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let finalized_streams = (0..online).map(move |i| {
+				let local_id = Id(i);
+				let env = Arc::new(Environment::new(network.clone(), local_id));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms(),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					last_finalized,
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 6))
+					.for_each(|_| future::ready(()))
+			});
+
+			future::join_all(finalized_streams).map(|_| { let _ = signal.fire(); })
+		});
+
+		clock.now()
+	}
+
+	// run 10 voters in virtual time, the last of which misbehave as given,
+	// until the honest ones all finalize. the chain has a fork at "B", which
+	// honest voters don't build on. returns the honest environments.
+	fn finalize_with_byzantine(behaviors: Vec<Behavior>) -> Vec<Arc<Environment>> {
+		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+		let honest = 10 - behaviors.len() as u32;
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 6);
+		let clock = network.clock().unwrap();
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			for (i, behavior) in behaviors.into_iter().enumerate() {
+				spawner.spawn_local(byzantine_voter(&network, Id(honest + i as u32), behavior, 3)).unwrap();
+			}
+
+			let (envs, finalized_streams): (Vec<_>, Vec<_>) = (0..honest).map(|i| {
+				let local_id = Id(i);
+				let env = Arc::new(Environment::new(network.clone(), local_id)
+					.with_equivocation_reports()
+					.with_metrics());
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.push_blocks("B", &["F1", "F2"]);
+					chain.last_finalized()
+				});
+
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms_for(local_id),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					last_finalized,
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				let finalized = finalized
+					.take_while(|&(_, n, _)| future::ready(n < 6))
+					.for_each(|_| future::ready(()));
+
+				(env, finalized)
+			}).unzip();
+
+			future::join_all(finalized_streams).map(move |_| {
+				let _ = signal.fire();
+				envs
+			})
+		})
+	}
+
+	// run 10 voters on a network partitioned into the given groups for ten
+	// seconds of virtual time, passing the blocks finalized by then to
+	// `check`. the partition is then healed and the blocks finalized once
+	// all voters finalize are returned.
+	fn finalize_after_partition(
+		groups: &[&[Id]],
+		check: impl FnOnce(Vec<(&'static str, u32)>) + 'static,
+	) -> Vec<(&'static str, u32)> {
+		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 5);
+		let clock = network.clock().unwrap();
+		network.partition(groups);
 
-		let (network, routing_task) = testing::environment::make_network();
 		let (signal, exit) = ::exit_future::signal();
 
-		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		current_thread::block_on_all(::futures::future::lazy(move || {
-			// initialize chain
+		let partition_clock = clock.clone();
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let (envs, finalized_streams): (Vec<_>, Vec<_>) = (0..10).map(|i| {
+				let local_id = Id(i);
+				let env = Arc::new(Environment::new(network.clone(), local_id));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms_for(local_id),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					last_finalized,
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				(env, finalized)
+			}).unzip();
+
+			let last_finalized = move || envs.iter()
+				.map(|env| env.with_chain(|chain| chain.last_finalized()))
+				.collect::<Vec<_>>();
+
+			partition_clock.delay(Duration::from_secs(10))
+				.then(move |_| {
+					check(last_finalized());
+					network.heal();
+
+					let finalized = finalized_streams.into_iter().map(|finalized| finalized
+						.take_while(|&(_, n, _)| future::ready(n < 6))
+						.for_each(|_| future::ready(()))
+					);
+
+					future::join_all(finalized).map(move |_| last_finalized())
+				})
+				.map(move |finalized| {
+					let _ = signal.fire();
+					finalized
+				})
+		})
+	}
+
+	#[test]
+	fn buffered_sink_retries_and_limits() {
+		let policy = SendPolicy {
+			max_retries: 2,
+			initial_backoff: Duration::from_millis(0),
+			..SendPolicy::default()
+		};
+		let mut cx = Context::from_waker(noop_waker_ref());
+
+		// recovers within the retries without losing messages.
+		let mut buffered = Buffered::with_policy(FlakySink { failures: 2, sent: Vec::new() }, policy.clone());
+		buffered.push(1);
+		buffered.push(2);
+		assert_eq!(buffered.poll(&mut cx), Poll::Pending);
+		assert_eq!(buffered.poll(&mut cx), Poll::Pending);
+		assert_eq!(buffered.poll(&mut cx), Poll::Ready(Ok(())));
+		assert_eq!(buffered.inner.sent, vec![1, 2]);
+
+		// fails once out of retries.
+		let mut buffered = Buffered::with_policy(FlakySink { failures: 3, sent: Vec::new() }, policy.clone());
+		buffered.push(1);
+		assert_eq!(buffered.poll(&mut cx), Poll::Pending);
+		assert_eq!(buffered.poll(&mut cx), Poll::Pending);
+		assert_eq!(buffered.poll(&mut cx), Poll::Ready(Err(())));
+
+		// waits for the backoff before retrying.
+		let mut buffered = Buffered::with_policy(FlakySink { failures: 1, sent: Vec::new() }, SendPolicy {
+			initial_backoff: Duration::from_secs(60),
+			..policy.clone()
+		});
+		buffered.push(1);
+		assert_eq!(buffered.poll(&mut cx), Poll::Pending);
+		buffered.inner.failures = 0;
+		assert_eq!(buffered.poll(&mut cx), Poll::Pending);
+		assert!(buffered.inner.sent.is_empty());
+
+		// overflowing messages are dropped.
+		for &(overflow, kept) in &[(OverflowPolicy::DropOldest, [2, 3]), (OverflowPolicy::DropNewest, [1, 2])] {
+			let mut buffered = Buffered::with_policy(FlakySink { failures: 0, sent: Vec::new() }, SendPolicy {
+				max_buffered: Some(2),
+				overflow,
+				..policy.clone()
+			});
+			buffered.push(1);
+			buffered.push(2);
+			buffered.push(3);
+			assert_eq!(buffered.poll(&mut cx), Poll::Ready(Ok(())));
+			assert_eq!(buffered.inner.sent, kept);
+		}
+	}
+
+	#[test]
+	fn buffered_sink_wakes_up_to_retry() {
+		let mut buffered = Buffered::with_policy(FlakySink { failures: 1, sent: Vec::new() }, SendPolicy {
+			max_retries: 1,
+			initial_backoff: Duration::from_millis(50),
+			..SendPolicy::default()
+		});
+		buffered.push(1);
+
+		// nothing but the backoff wakes the task up to retry.
+		block_on(future::poll_fn(|cx| buffered.poll(cx))).unwrap();
+		assert_eq!(buffered.inner.sent, vec![1]);
+	}
+
+	#[test]
+	fn talking_to_myself() {
+		let local_id = Id(5);
+		let voters = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// wait for the best block to finalize.
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn imports_votes_signed_locally() {
+		let local_id = Id(5);
+		let voters = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// our votes never come back from the network, so we only finalize
+		// anything if we import them as we sign them.
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		env.set_sign_locally();
+		let task_env = env.clone();
+		block_on_all(move |spawner| {
+			let last_finalized = task_env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = task_env.finalized_stream();
+			let voter = Voter::new(
+				task_env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+
+		// the votes were still broadcast.
+		assert_eq!(network.sent_messages(1, local_id), vec![
+			Message::Prevote(Prevote::new("E", 6)),
+			Message::Precommit(Precommit::new("E", 6)),
+		]);
+		assert!(env.unexpected_own_votes().is_empty());
+	}
+
+	#[test]
+	fn rejects_distant_votes() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 1)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// a vote for an unknown block far ahead, which we don't look up.
+		let distant = Message::Prevote(Prevote::new("Z", 1_000_000));
+		network.inject(1, Id(6), distant.clone());
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		env.set_max_vote_distance(1000);
+		let task_env = env.clone();
+		block_on_all(move |spawner| {
+			let last_finalized = task_env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = task_env.finalized_stream();
+			let voter = Voter::new(
+				task_env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+
+		assert_eq!(env.rejected_votes(), vec![(
+			1,
+			SignedMessage { message: distant, signature: Signature(6), id: Id(6) },
+			crate::misbehavior::Misbehavior::DistantVote,
+		)]);
+	}
+
+	#[test]
+	fn rejects_votes_from_non_voters() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 1)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let unknown = Message::Prevote(Prevote::new("C", 4));
+		network.inject(1, Id(7), unknown.clone());
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		let task_env = env.clone();
+		block_on_all(move |spawner| {
+			let last_finalized = task_env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = task_env.finalized_stream();
+			let voter = Voter::new(
+				task_env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+
+		assert_eq!(env.rejected_votes(), vec![(
+			1,
+			SignedMessage { message: unknown, signature: Signature(7), id: Id(7) },
+			crate::misbehavior::Misbehavior::UnknownVoter,
+		)]);
+	}
+
+	#[test]
+	fn rejects_votes_with_wrong_block_numbers() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 1)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// "C" is block 4. the voter keeps going after rejecting the vote.
+		let wrong_number = Message::Prevote(Prevote::new("C", 9));
+		network.inject(1, Id(6), wrong_number.clone());
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		let task_env = env.clone();
+		block_on_all(move |spawner| {
+			let last_finalized = task_env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = task_env.finalized_stream();
+			let voter = Voter::new(
+				task_env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+
+		assert_eq!(env.rejected_votes(), vec![(
+			1,
+			SignedMessage { message: wrong_number, signature: Signature(6), id: Id(6) },
+			crate::misbehavior::Misbehavior::InvalidBlockNumber,
+		)]);
+	}
+
+	#[test]
+	fn vetoed_votes_are_not_broadcast() {
+		let voters: VoterSet<_> = (0..4).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let envs: Vec<_> = (0..4).map(|i| {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)));
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+			env
+		}).collect();
+
+		// the first voter holds back its precommits, the others still make up
+		// a supermajority.
+		envs[0].set_broadcast_veto(|_, message| matches!(*message, Message::Precommit(_)));
+
+		let task_envs = envs.clone();
+		let task_network = network.clone();
+		block_on_all(move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let finalized_streams = task_envs.into_iter().enumerate().map(|(i, env)| {
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env,
+					voters.clone(),
+					task_network.make_global_comms_for(Id(i as u32)),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					(GENESIS_HASH, 1),
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 6))
+					.for_each(|_| future::ready(()))
+			}).collect::<Vec<_>>();
+
+			future::join_all(finalized_streams).map(|_| signal.fire())
+		}).unwrap();
+
+		assert_eq!(network.sent_messages(1, Id(0)), vec![Message::Prevote(Prevote::new("E", 6))]);
+		assert_eq!(network.sent_messages(1, Id(1)), vec![
+			Message::Prevote(Prevote::new("E", 6)),
+			Message::Precommit(Precommit::new("E", 6)),
+		]);
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+		}
+	}
+
+	#[test]
+	fn tapped_round_observes_votes() {
+		let local_id = Id(5);
+		let voters = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let round_messages = network.tap_round(1);
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		let precommit = block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// our own precommit is seen by the tap.
+			round_messages
+				.filter_map(|signed| future::ready(match signed.message {
+					Message::Precommit(precommit) => Some((signed.id, precommit)),
+					_ => None,
+				}))
+				.into_future()
+				.map(move |(precommit, _)| {
+					let _ = signal.fire();
+					precommit
+				})
+		});
+
+		assert_eq!(precommit, Some((local_id, Precommit::new("E", 6))));
+	}
+
+	#[test]
+	fn suggests_gossip_duration_from_measured_latency() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			voter.set_adaptive_timing(true);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				if voter.best_round.round_number() == 3 {
+					// nothing was measured before the first round completed,
+					// after which votes only have to reach ourselves.
+					let hints = env.gossip_duration_hints();
+					assert_eq!(hints[0], (1, None));
+					assert_eq!(hints.iter().map(|&(round, _)| round).collect::<Vec<_>>(), vec![1, 2, 3]);
+					assert!(hints[1..].iter().all(|&(_, hint)| hint.is_some_and(|hint| hint < Duration::from_millis(500))));
+
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn finalized_stream_notifies_finalized_blocks() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let finalized = voter.finalized_stream();
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized.into_future()
+				.map(move |(finalized, _)| {
+					let (hash, number, round, commit) = finalized.unwrap();
+					assert_eq!((hash, number, round), ("E", 6, 1));
+					assert_eq!((commit.target_hash, commit.target_number), ("E", 6));
+					assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn emits_events() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let events = voter.events();
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			events
+				.take_while(|event| future::ready(*event != VoterEvent::RoundStarted(2)))
+				.collect::<Vec<_>>()
+				.map(move |events| {
+					let position = |f: &dyn Fn(&VoterEvent<_, _, _, _>) -> bool| events.iter()
+						.position(f)
+						.unwrap_or_else(|| panic!("missing event in {:?}", events));
+
+					let prevoted = position(&|event| *event == VoterEvent::PrevoteCast(1, Prevote::new("E", 6)));
+					let precommitted = position(&|event| *event == VoterEvent::PrecommitCast(1, Precommit::new("E", 6)));
+					let completable = position(&|event| match event {
+						VoterEvent::RoundCompletable(1, state) => state.finalized == Some(("E", 6)),
+						_ => false,
+					});
+
+					assert!(prevoted < precommitted);
+					assert!(precommitted < completable);
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn streams_round_state_updates() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let first_round = voter.round_state_updates_for(1);
+			let all_rounds = voter.round_state_updates();
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			let finalized_in_first_round = RoundState {
+				prevote_ghost: Some(("E", 6)),
+				finalized: Some(("E", 6)),
+				estimate: Some(("E", 6)),
+				completable: true,
+			};
+
+			let first_round = first_round
+				.take_while(|(_, state)| future::ready(state.finalized.is_none()))
+				.collect::<Vec<_>>();
+			let all_rounds = all_rounds
+				.take_while(|&(round, _)| future::ready(round == 1))
+				.collect::<Vec<_>>();
+
+			future::join(first_round, all_rounds).map(move |(first_round, all_rounds)| {
+				// the round changes state once its prevote-GHOST is found, and
+				// the aggregated stream sees the same updates.
+				assert!(first_round.iter().all(|(round, state)| *round == 1 && state.prevote_ghost.is_some()));
+				assert_eq!(all_rounds.last(), Some(&(1, finalized_in_first_round)));
+				assert_eq!(&all_rounds[..first_round.len()], &first_round[..]);
+
+				signal.fire()
+			})
+		}).unwrap();
+	}
+
+	#[test]
+	fn notifies_round_estimate_updates() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		let task_env = env.clone();
+		block_on_all(move |spawner| {
+			let last_finalized = task_env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = task_env.finalized_stream();
+			let voter = Voter::new(
+				task_env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+
+		// the estimate is the prevote-GHOST as soon as it is found, before
+		// anything is finalized.
+		assert_eq!(env.estimate_updates().first(), Some(&(1, ("E", 6))));
+	}
+
+	#[test]
+	fn records_metrics() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id).with_metrics());
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let events = voter.events();
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			events
+				.filter(|event| future::ready(*event == VoterEvent::RoundStarted(3)))
+				.into_future()
+				.map(move |_| {
+					let metrics = env.recorded_metrics();
+					assert!(metrics.imported >= 2);
+					assert!(metrics.completed_rounds >= 2);
+					assert!(metrics.current_round >= 2);
+					assert_eq!(metrics.equivocations, 0);
+					assert_eq!(metrics.commits_rejected, 0);
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn reports_how_rounds_went() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let events = voter.events();
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			events
+				.filter(|event| future::ready(*event == VoterEvent::RoundStarted(2)))
+				.into_future()
+				.map(move |_| {
+					let reports = env.completion_reports();
+					let report = &reports[0];
+					assert_eq!(report.round_number, 1);
+					assert_eq!(report.base, (GENESIS_HASH, 1));
+					assert_eq!(report.state.finalized, Some(("E", 6)));
+					assert_eq!((report.prevotes, report.precommits), (1, 1));
+
+					let prevoted = report.prevoted_after.unwrap();
+					let precommitted = report.precommitted_after.unwrap();
+					let completable = report.completable_after.unwrap();
+					assert!(prevoted <= precommitted);
+					assert!(precommitted <= completable);
+					assert!(completable <= report.duration);
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn handle_exposes_last_completed_round() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			let handle = voter.handle();
+			assert!(handle.last_completed_round().is_none());
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(move |_| {
+					let completed = handle.last_completed_round().unwrap();
+					assert_eq!(completed.number, 1);
+					assert_eq!(completed.base, (GENESIS_HASH, 1));
+					assert_eq!(completed.state.finalized, Some(("E", 6)));
+					assert_eq!(completed.voters, voters);
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn voter_weights_change_at_round_boundaries() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+
+		// a set with different voters is ignored.
+		env.set_round_voters(2, [(local_id, 100), (Id(6), 100)].iter().cloned().collect());
+		env.set_round_voters(3, std::iter::once((local_id, 300)).collect());
+
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+				match voter.best_round.round_number() {
+					2 => assert_eq!(voter.best_round.voters().total_weight(), 100),
+					3 => {
+						assert_eq!(voter.voters.total_weight(), 300);
+						assert_eq!(voter.best_round.voters().total_weight(), 300);
+						assert_eq!(voter.best_round.voters().threshold(), 201);
+						return Poll::Ready(());
+					}
+					_ => {}
+				}
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn resumed_voter_does_not_vote_again() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+
+		// before the restart we had prevoted for "C" in round 1. prevoting
+		// for the best chain ("E") again would equivocate and panic.
+		let prevote = Prevote::new("C", 4);
+		let snapshot = VoterSnapshot {
+			best_round: RoundSnapshot {
+				round_number: 1,
+				base: (GENESIS_HASH, 1),
+				votes: HistoricalVotes::new_with(
+					vec![SignedMessage {
+						message: Message::Prevote(prevote.clone()),
+						signature: Signature(5),
+						id: local_id,
+					}],
+					Some(0),
+					None,
+				),
+				has_voted: HasVoted::Prevoted(None, prevote.clone()),
+			},
+			background_rounds: Vec::new(),
+			last_round_state: RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized: (GENESIS_HASH, 1),
+		};
+		env.set_persisted_votes(snapshot.best_round.clone());
+
+		block_on_all(move |spawner| {
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+			let finalized = env.finalized_stream();
+			let voter = Voter::resume(
+				env.clone(),
+				voters,
+				global_comms,
+				snapshot,
+			).unwrap();
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(move |_| {
+					let persisted = env.persisted_votes(1).unwrap().votes;
+					assert_eq!(persisted.seen()[0].message, Message::Prevote(prevote));
+					assert_eq!(persisted.seen()[1].message, Message::Precommit(Precommit::new("C", 4)));
+					assert_eq!(persisted.prevote_idx(), Some(0));
+					assert_eq!(persisted.precommit_idx(), Some(1));
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn resumed_voter_applies_weights_of_restored_rounds() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+		env.set_round_voters(2, std::iter::once((local_id, 200)).collect());
+		env.set_round_voters(3, std::iter::once((local_id, 300)).collect());
+
+		let snapshot = VoterSnapshot {
+			best_round: RoundSnapshot::new(3, (GENESIS_HASH, 1)),
+			background_rounds: vec![RoundSnapshot::new(2, (GENESIS_HASH, 1))],
+			last_round_state: RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized: (GENESIS_HASH, 1),
+		};
+
+		let mut voter = Voter::resume(env, voters, network.make_global_comms(), snapshot).unwrap();
+		let background = voter.past_rounds.reports();
+		assert_eq!((background[0].number, background[0].total_weight), (2, 200));
+		assert_eq!(voter.best_round.voters().total_weight(), 300);
+		assert_eq!(voter.voters.total_weight(), 300);
+	}
+
+	#[test]
+	fn recast_votes_keep_their_restored_index() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 100)].into_iter().collect();
+
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+		// we prevoted before seeing the prevote of the other voter.
+		let prevote = Prevote::new("C", 4);
+		let signed = |id: Id| SignedMessage {
+			message: Message::Prevote(prevote.clone()),
+			signature: Signature(id.0),
+			id,
+		};
+		let snapshot = VoterSnapshot {
+			best_round: RoundSnapshot {
+				round_number: 1,
+				base: (GENESIS_HASH, 1),
+				votes: HistoricalVotes::new_with(vec![signed(local_id), signed(Id(6))], Some(0), None),
+				has_voted: HasVoted::Prevoted(None, prevote.clone()),
+			},
+			background_rounds: Vec::new(),
+			last_round_state: RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized: (GENESIS_HASH, 1),
+		};
+		env.set_persisted_votes(snapshot.best_round.clone());
+
+		let mut voter = Voter::resume(env.clone(), voters, network.make_global_comms(), snapshot).unwrap();
+		// resuming recasts our prevote.
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = voter.poll_unpin(cx);
+			Poll::Ready(())
+		}));
+
+		assert_eq!(env.persisted_votes(1).unwrap().votes.prevote_idx(), Some(0));
+		assert_eq!(voter.best_round.historical_votes().prevote_idx(), Some(0));
+	}
+
+	#[test]
+	fn rejects_snapshots_with_duplicate_rounds() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+		let round = |round_number| RoundSnapshot::new(round_number, (GENESIS_HASH, 1));
+		let snapshot = |best_round, background_rounds| VoterSnapshot {
+			best_round,
+			background_rounds,
+			last_round_state: RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized: (GENESIS_HASH, 1),
+		};
+
+		for &(best, background, duplicate) in &[(3, &[2, 1, 2][..], 2), (2, &[1, 2][..], 2)] {
+			let resumed = Voter::resume(
+				env.clone(),
+				voters.clone(),
+				network.make_global_comms(),
+				snapshot(round(best), background.iter().cloned().map(round).collect()),
+			);
+			assert_eq!(resumed.err(), Some(Error::DuplicateRound(duplicate)));
+		}
+	}
+
+	#[test]
+	fn recasts_votes_from_before_restart() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+
+		// we would now vote for "E", but had already voted before the restart.
+		env.set_has_voted(1, HasVoted::Precommitted(None, Some(Prevote::new("C", 4)), Precommit::new("B", 3)));
+
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized.into_future()
+				.map(move |(first, _)| {
+					let (hash, number, _) = first.unwrap();
+					assert_eq!((hash, number), ("B", 3));
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn voter_restarted_mid_round_neither_equivocates_nor_stalls() {
+		// all three voters are needed to finalize.
+		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 7);
+		let clock = network.clock().unwrap();
+		let (signal, exit) = ::exit_future::signal();
+
+		let envs: Vec<_> = (0..3).map(|i| {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)).with_equivocation_reports());
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+			env
+		}).collect();
+
+		let task_clock = clock.clone();
+		let task_envs = envs.clone();
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let mut crash = None;
+			let finalized_streams: Vec<_> = task_envs.iter().enumerate().map(|(i, env)| {
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms_for(Id(i as u32)),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					(GENESIS_HASH, 1),
+				);
+
+				let (voter, handle) = future::abortable(voter);
+				if i == 0 {
+					crash = Some(handle);
+				}
+
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.map(|res| res.expect("Error voting")))).map(|_| ())).unwrap();
+
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 8))
+					.for_each(|_| future::ready(()))
+			}).collect();
+
+			let restart_clock = task_clock.clone();
+			let spawner = spawner.clone();
+			let envs = task_envs.clone();
+
+			// crash voter 0 after it prevoted in round 1, but before it
+			// precommitted.
+			task_clock.delay(Duration::from_millis(700))
+				.then(move |_| {
+					crash.unwrap().abort();
+
+					// voting for the new best block "G" would equivocate.
+					for env in &envs {
+						env.with_chain(|chain| chain.push_blocks("E", &["F", "G"]));
+					}
+
+					restart_clock.delay(Duration::from_secs(2)).map(move |_| envs)
+				})
+				.then(move |envs| {
+					let sent = network.sent_messages(1, Id(0));
+					assert_eq!(sent, vec![Message::Prevote(Prevote::new("E", 6))]);
+					assert!(envs.iter().all(|env| env.with_chain(|chain| chain.last_finalized()) == (GENESIS_HASH, 1)));
+
+					let voter = Voter::resume(
+						envs[0].clone(),
+						voters,
+						network.make_global_comms_for(Id(0)),
+						envs[0].crash_snapshot().unwrap(),
+					).unwrap();
+
+					spawner.spawn_local(exit.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+					// peers gossip our votes from before the restart back to us.
+					for message in sent {
+						network.inject(1, Id(0), message);
+					}
+
+					future::join_all(finalized_streams)
+				})
+				.map(move |_| { let _ = signal.fire(); })
+		});
+
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("G", 8));
+			assert_eq!(env.recorded_equivocations(), Default::default());
+			assert!(env.unexpected_own_votes().is_empty(), "{:?}", env.unexpected_own_votes());
+		}
+	}
+
+	#[test]
+	fn prevotes_follow_reorged_best_chain() {
+		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 3);
+		let clock = network.clock().unwrap();
+		let votes = network.tap_round(1);
+		let (signal, exit) = ::exit_future::signal();
+
+		let envs: Vec<_> = (0..3).map(|i| {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)));
+			env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				// the best chain moved to a shorter fork.
+				chain.push_blocks("B", &["F1", "F2"]);
+				chain.set_best_block(Some("F2"));
+			});
+			env
+		}).collect();
+
+		let task_envs = envs.clone();
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let finalized_streams = task_envs.iter().enumerate().map(|(i, env)| {
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms_for(Id(i as u32)),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					(GENESIS_HASH, 1),
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 5))
+					.for_each(|_| future::ready(()))
+			});
+
+			future::join_all(finalized_streams).map(move |_| { let _ = signal.fire(); })
+		});
+
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("F2", 5));
+		}
+
+		let prevotes: Vec<_> = futures::executor::block_on_stream(votes)
+			.filter_map(|signed| match signed.message {
+				Message::Prevote(prevote) => Some(prevote),
+				_ => None,
+			})
+			.take(3)
+			.collect();
+		assert_eq!(prevotes, vec![Prevote::new("F2", 5); 3]);
+	}
+
+	#[test]
+	fn reorg_after_prevoting_does_not_change_votes() {
+		// all three voters are needed to finalize.
+		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 7);
+		let clock = network.clock().unwrap();
+		let (signal, exit) = ::exit_future::signal();
+
+		let envs: Vec<_> = (0..3).map(|i| {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)).with_equivocation_reports());
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+			env
+		}).collect();
+
+		let task_clock = clock.clone();
+		let task_envs = envs.clone();
+		let task_network = network.clone();
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let finalized_streams: Vec<_> = task_envs.iter().enumerate().map(|(i, env)| {
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					task_network.make_global_comms_for(Id(i as u32)),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					(GENESIS_HASH, 1),
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 6))
+					.for_each(|_| future::ready(()))
+			}).collect();
+
+			let envs = task_envs.clone();
+
+			// re-org after voter 0 prevoted in round 1, but before it
+			// precommitted.
+			task_clock.delay(Duration::from_millis(700))
+				.then(move |_| {
+					assert_eq!(task_network.sent_messages(1, Id(0)), vec![Message::Prevote(Prevote::new("E", 6))]);
+
+					for env in &envs {
+						env.with_chain(|chain| {
+							chain.push_blocks("B", &["F1", "F2"]);
+							chain.set_best_block(Some("F2"));
+						});
+						assert_eq!(env.with_chain(|chain| chain.best_chain_containing(GENESIS_HASH)), Some(("F2", 5)));
+					}
+
+					future::join_all(finalized_streams)
+				})
+				.map(move |_| { let _ = signal.fire(); })
+		});
+
+		assert_eq!(network.sent_messages(1, Id(0)), vec![
+			Message::Prevote(Prevote::new("E", 6)),
+			Message::Precommit(Precommit::new("E", 6)),
+		]);
+
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+			assert_eq!(env.recorded_equivocations(), Default::default());
+		}
+	}
+
+	#[test]
+	fn prevotes_wait_for_best_chain_lookup() {
+		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 7);
+		let clock = network.clock().unwrap();
+		let (signal, exit) = ::exit_future::signal();
+
+		let envs: Vec<_> = (0..3).map(|i| {
+			let env = Arc::new(Environment::new(network.clone(), Id(i)));
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+			env.set_chain_lookup_delay(Duration::from_millis(400));
+			env
+		}).collect();
+
+		let task_clock = clock.clone();
+		let task_envs = envs.clone();
+		let task_network = network.clone();
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			let finalized_streams: Vec<_> = task_envs.iter().enumerate().map(|(i, env)| {
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					task_network.make_global_comms_for(Id(i as u32)),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					(GENESIS_HASH, 1),
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 8))
+					.for_each(|_| future::ready(()))
+			}).collect();
+
+			let envs = task_envs.clone();
+
+			// the prevote timer has fired, but the best chain is still being
+			// looked up when the chain grows.
+			task_clock.delay(Duration::from_millis(700))
+				.then(move |_| {
+					assert_eq!(task_network.sent_messages(1, Id(0)), vec![]);
+
+					for env in &envs {
+						env.with_chain(|chain| chain.push_blocks("E", &["F", "G"]));
+					}
+
+					future::join_all(finalized_streams)
+				})
+				.map(move |_| { let _ = signal.fire(); })
+		});
+
+		assert_eq!(network.sent_messages(1, Id(0))[0], Message::Prevote(Prevote::new("G", 8)));
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("G", 8));
+		}
+	}
+
+	#[test]
+	fn votes_are_imported_as_their_lookups_complete() {
+		let voters: VoterSet<_> = (5..8).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network_with_latency(JITTERY_LINKS, 7);
+		let clock = network.clock().unwrap();
+
+		// the lookup for "E" is slow, and its vote is delivered twice.
+		let slow = Message::Prevote(Prevote::new("E", 6));
+		network.inject(1, Id(5), slow.clone());
+		network.inject(1, Id(5), slow.clone());
+		network.inject(1, Id(6), Message::Prevote(Prevote::new("D", 5)));
+		network.inject(1, Id(7), Message::Prevote(Prevote::new("D", 5)));
+
+		// we only observe.
+		let env = Arc::new(Environment::new(network.clone(), Id(9)));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+		env.set_ancestry_lookup_delay("E", Duration::from_secs(10));
+
+		let (_, global_out) = network.make_global_comms();
+		let (_commits_in, global_in) = mpsc::unbounded();
+		let task_clock = clock.clone();
+		let task_env = env.clone();
+		block_on_virtual(&clock, move |spawner| {
+			spawner.spawn_local(routing_task.map(|_| ())).unwrap();
+
+			let mut voter = Voter::new(
+				task_env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				(GENESIS_HASH, 1),
+			);
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+				let seen = voter.best_round.historical_votes().seen().len();
+				if seen < 2 {
+					return Poll::Pending;
+				}
+
+				// the votes for "D" didn't wait for the one for "E".
+				assert!(task_clock.now() < Duration::from_secs(10));
+				assert!(voter.best_round.historical_votes().seen().iter().all(|vote| vote.message != slow));
+				Poll::Ready(())
+			})
+		});
+
+		// the redelivered vote wasn't looked up again.
+		assert_eq!(env.ancestry_lookups().iter().filter(|&&block| block == "E").count(), 1);
+	}
+
+	#[test]
+	fn detects_own_votes_we_have_not_cast() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// someone else is signing with our key. since the genesis estimate is
+		// finalized we never send a primary proposal ourselves.
+		let (_, mut round_sink) = network.make_round_comms(1, local_id);
+		let propose = Message::PrimaryPropose(PrimaryPropose { target_hash: "C", target_number: 4 });
+		block_on(round_sink.send(propose.clone())).unwrap();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(move |_| {
+					// our own prevote and precommit were echoed back as well.
+					assert_eq!(env.unexpected_own_votes(), vec![(1, propose)]);
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn prevotes_using_primary_block_hint() {
+		let local_id = Id(5);
+		let primary_id = Id(6);
+		let voters: VoterSet<_> = [(local_id, 1), (primary_id, 1)].iter().cloned().collect();
+		assert_eq!(voters.voter_by_index(1).0, primary_id);
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// the (offline) primary of round 1 proposes "C", the last prevote-GHOST.
+		let (_, mut primary_sink) = network.make_round_comms(1, primary_id);
+		let propose = PrimaryPropose { target_hash: "C", target_number: 4 };
+		block_on(primary_sink.send(Message::PrimaryPropose(propose))).unwrap();
+
+		let (round_stream, _) = network.make_round_comms(1, Id(7));
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				// a longer fork, which we would vote for without the hint.
+				chain.push_blocks("B", &["F", "G", "H", "I", "J"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState {
+				prevote_ghost: Some(("C", 4)),
+				finalized: Some((GENESIS_HASH, 1)),
+				estimate: Some((GENESIS_HASH, 1)),
+				completable: true,
+			};
+
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			round_stream
+				.filter_map(move |signed| future::ready(match signed {
+					Ok(SignedMessage { message: Message::Prevote(prevote), id, .. }) if id == local_id => Some(prevote),
+					_ => None,
+				}))
+				.into_future()
+				.map(move |(prevote, _)| {
+					assert_eq!(prevote, Some(Prevote::new("E", 6)));
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn background_rounds_evicted_over_memory_budget() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			voter.set_memory_budget(Some(0));
+			let handle = voter.handle();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				// only the best round is ever kept around.
+				let sizes = handle.round_sizes();
+				assert_eq!(sizes.len(), 1);
+				assert_eq!(sizes[0].0, voter.best_round.round_number());
+				assert!(sizes[0].1 > 0);
+
+				if voter.best_round.round_number() == 3 {
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn survives_transient_errors() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// sending commits always fails, with an error standing in for a
+		// network failure.
+		let (global_in, _) = network.make_global_comms();
+		let global_out = BrokenSink;
+
+		let env = Arc::new(Environment::new(network, local_id));
+		env.set_transient(Error::NotDescendent);
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in, global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// background rounds keep committing into the broken sink.
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.unwrap_or_else(|e| panic!("voter failed: {:?}", e)));
+				if voter.best_round.round_number() == 4 {
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn background_rounds_limited() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			voter.set_max_background_rounds(Some(1));
+			assert_eq!(voter.background_rounds(), 0);
+			let handle = voter.handle();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				// evicted rounds are dropped the next time the voter is polled.
+				let background_rounds = handle.voter_state().unwrap().background_rounds;
+				assert!(background_rounds.len() <= 1);
+				assert!(voter.background_rounds() <= 2);
+
+				if voter.best_round.round_number() == 4 {
+					assert_eq!(background_rounds[0].number, 3);
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	const JITTERY_LINKS: LinkLatency = LinkLatency {
+		delay: Duration::from_millis(500),
+		jitter: Duration::from_millis(200),
+	};
+
+	#[test]
+	fn finalizes_with_link_latency() {
+		let elapsed = finalize_with_latency(10, JITTERY_LINKS, 1, |_| {});
+
+		// prevotes and precommits each take at least one hop.
+		assert!(elapsed >= Duration::from_millis(600), "finalized after {:?}", elapsed);
+	}
+
+	#[test]
+	fn finalizing_at_fault_threshold_with_link_latency() {
+		finalize_with_latency(7, JITTERY_LINKS, 2, |_| {});
+	}
+
+	#[test]
+	fn finalizes_with_slow_voter() {
+		let slow = LinkLatency { delay: Duration::from_secs(30), jitter: Duration::from_millis(0) };
+		let elapsed = finalize_with_latency(10, JITTERY_LINKS, 3, |network| {
+			for i in 1..10 {
+				network.set_link_latency(Id(0), Id(i), slow);
+			}
+		});
+
+		// the others don't wait for the slow voter's votes.
+		assert!(elapsed < Duration::from_secs(30), "finalized after {:?}", elapsed);
+	}
+
+	#[test]
+	fn even_partition_finalizes_after_healing() {
+		let left: Vec<_> = (0..5).map(Id).collect();
+		let right: Vec<_> = (5..10).map(Id).collect();
+
+		let finalized = finalize_after_partition(&[&left, &right], |finalized| {
+			// neither side has a supermajority.
+			assert!(finalized.iter().all(|&block| block == (GENESIS_HASH, 1)), "{:?}", finalized);
+		});
+
+		assert!(finalized.iter().all(|&block| block == ("E", 6)), "{:?}", finalized);
+	}
+
+	#[test]
+	fn partitioned_minority_catches_up_after_healing() {
+		let majority: Vec<_> = (0..7).map(Id).collect();
+		let minority: Vec<_> = (7..10).map(Id).collect();
+
+		let finalized = finalize_after_partition(&[&majority, &minority], |finalized| {
+			assert!(finalized[..7].iter().all(|&block| block == ("E", 6)), "{:?}", finalized);
+			assert!(finalized[7..].iter().all(|&block| block == (GENESIS_HASH, 1)), "{:?}", finalized);
+		});
+
+		assert!(finalized.iter().all(|&block| block == ("E", 6)), "{:?}", finalized);
+	}
+
+	#[test]
+	fn equivocations_are_reported() {
+		let envs = finalize_with_byzantine(vec![Behavior::Equivocate(("E", 6), ("F2", 5))]);
+
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+
+			let equivocations = env.recorded_equivocations();
+			assert!(equivocations.prevotes.iter().any(|(round, equivocation)|
+				*round == 1 && equivocation.identity == Id(9)
+			));
+			assert!(equivocations.precommits.iter().any(|(round, equivocation)|
+				*round == 1 && equivocation.identity == Id(9)
+			));
+		}
+	}
+
+	#[test]
+	fn finalizes_despite_byzantine_voters() {
+		let envs = finalize_with_byzantine(vec![
+			Behavior::VoteFor(("F2", 5)),
+			Behavior::WithholdPrecommits(("E", 6)),
+			Behavior::JunkCommits(("F2", 5)),
+		]);
+
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+			assert!(env.finality_violations().is_empty());
+			assert_eq!(env.recorded_equivocations(), Default::default());
+			assert!(env.recorded_metrics().commits_rejected > 0);
+		}
+	}
+
+	#[test]
+	fn simulated_schedules_are_safe_and_live() {
+		let simulation = Simulation::default();
+		for seed in 0..1000 {
+			if let Err(violation) = simulation.check(seed) {
+				panic!("{}", violation);
+			}
+		}
+	}
+
+	#[test]
+	fn simulations_are_reproducible() {
+		let simulation = Simulation { voters: 7, ..Simulation::default() };
+		for seed in 0..10 {
+			assert_eq!(simulation.run(seed), simulation.run(seed));
+		}
+	}
+
+	#[test]
+	fn link_latency_is_deterministic() {
+		let run = |seed| finalize_with_latency(10, JITTERY_LINKS, seed, |_| {});
+		assert_eq!(run(4), run(4));
+	}
+
+	#[test]
+	fn finalizing_at_fault_threshold() {
+		// 10 voters
+		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_all(move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			// 3 voters offline.
+			let finalized_streams = (0..7).map(move |i| {
+				let local_id = Id(i);
+				// initialize chain
+				let env = Arc::new(Environment::new(network.clone(), local_id));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+				// run voter in background. scheduling it to shut down at the end.
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms(),
+					0,
+					last_round_state,
+					last_finalized,
+				);
+				spawner.spawn_local(exit.clone()
+					.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+				// wait for the best block to be finalized by all honest voters
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 6))
+					.for_each(|_| future::ready(()))
+			});
+
+			::futures::future::join_all(finalized_streams).map(|_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn broadcast_commit() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits, _) = network.make_global_comms();
+
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// wait for the node to broadcast a commit message
+			commits.take(1).for_each(|_| future::ready(())).map(|_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn commits_wait_for_durable_finalization() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits, _) = network.make_global_comms();
+
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		env.set_finalization_delay(Duration::from_millis(500));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let mut finalized = voter.finalized_stream();
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// the commit timer fires long before the finalization is durable,
+			// but the commit is only sent afterwards, as are notifications.
+			commits.take(1).for_each(move |commit| {
+				let commit = match commit.unwrap() {
+					CommunicationIn::Commit(message, _) => message.commit,
+					_ => panic!("expected a commit"),
+				};
+
+				assert_eq!(env.durably_finalized(), ("E", 6));
+				assert_eq!((commit.target_hash, commit.target_number), ("E", 6));
+				let notified = finalized.next().now_or_never().flatten();
+				assert_eq!(notified.map(|(hash, number, ..)| (hash, number)), Some(("E", 6)));
+				future::ready(())
+			}).map(|_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn broadcast_commit_only_if_newer() {
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters: VoterSet<_> = [
+			(local_id, 100),
+			(test_id, 201),
+		].iter().cloned().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits_stream, commits_sink) = network.make_global_comms();
+		let (round_stream, round_sink) = network.make_round_comms(1, test_id);
+
+		let prevote = Message::Prevote(Prevote {
+			target_hash: "E",
+			target_number: 6,
+		});
+
+		let precommit = Message::Precommit(Precommit {
+			target_hash: "E",
+			target_number: 6,
+		});
+
+		let commit = (1, Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash: "E", target_number: 6 },
+				signature: Signature(test_id.0),
+				id: test_id
+			}],
+		});
+
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.unwrap_or_else(|e| panic!("Error voting: {:?}", e)))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(
+				round_stream.into_future()
+					.then(|(value, stream)| { // wait for a prevote
+						assert!(match value {
+							Some(Ok(SignedMessage { message: Message::Prevote(_), id: Id(5), .. })) => true,
+							_ => false,
+						});
+						let votes = vec![prevote, precommit].into_iter().map(Ok);
+						stream::iter(votes).forward(round_sink).map(|_| stream) // send our prevote
+					})
+					.then(|stream| {
+						stream.take_while(|value| future::ready(match value { // wait for a precommit
+							Ok(SignedMessage { message: Message::Precommit(_), id: Id(5), .. }) => false,
+							_ => true,
+						})).for_each(|_| future::ready(()))
+					})
+					.then(|_| {
+						// send our commit
+						stream::iter(Some(Ok(CommunicationOut::Commit(commit.into())))).forward(commits_sink)
+					})
+			).map(|_| ())).unwrap();
+
+			// wait for the first commit (ours)
+			commits_stream.into_future()
+				.then(|(_, stream)| {
+					future::select(
+						stream.take(1).for_each(|_| future::ready(())), // the second commit should never arrive
+						Delay::new(Duration::from_millis(500)),
+					)
+				})
+				.map(|res| {
+					assert!(match res { // so the previous future times out
+						future::Either::Right(_) => true,
+						future::Either::Left(_) => false,
+					});
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn import_commit_for_any_round() {
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters: VoterSet<_> = [
+			(local_id, 100),
+			(test_id, 201),
+		].iter().cloned().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (_, mut commits_sink) = network.make_global_comms();
+
+		let (signal, exit) = ::exit_future::signal();
+
+		// this is a commit for a previous round
+		let commit = (0, Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash: "E", target_number: 6 },
+				signature: Signature(test_id.0),
+				id: test_id
+			}],
+		});
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				1,
+				last_round_state,
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			commits_sink.send(CommunicationOut::Commit(commit.into())).now_or_never().unwrap().unwrap();
+
+			// wait for the commit message to be processed which finalized block 6
+			env.finalized_stream()
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(|_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn skips_to_latest_round_after_catch_up() {
+		// 3 voters
+		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_all(move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			// initialize unsynced voter at round 0
+			let mut unsynced_voter = {
+				let local_id = Id(4);
+
+				let env = Arc::new(Environment::new(network.clone(), local_id));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+				Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms(),
+					0,
+					last_round_state,
+					last_finalized,
+				)
+			};
+
+			let pv = |id| crate::SignedPrevote {
+				prevote: crate::Prevote { target_hash: "C", target_number: 4 },
+				id: Id(id),
+				signature: Signature(99),
+			};
+
+			let pc = |id| crate::SignedPrecommit {
+				precommit: crate::Precommit { target_hash: "C", target_number: 4 },
+				id: Id(id),
+				signature: Signature(99),
+			};
+
+			// send in a catch-up message for round 5.
+			network.send_message(CommunicationIn::CatchUp(
+				CatchUp {
+					base_number: 1,
+					base_hash: GENESIS_HASH,
+					round_number: 5,
+					prevotes: vec![pv(0), pv(1), pv(2)],
+					precommits: vec![pc(0), pc(1), pc(2)],
+				},
+				Callback::Blank,
+			));
+
+			// poll until it's caught up.
+			// should skip to round 6
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = unsynced_voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+				if unsynced_voter.best_round.round_number() == 6 {
+					Poll::Ready(())
+				} else {
+					poll
+				}
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	// run voters with the given ids on the network until each of them has
+	// finalized block #6, resolving to the highest round in which any of them
+	// finalized it.
+	fn run_until_finalized(
+		spawner: &LocalSpawner,
+		network: &testing::environment::Network,
+		voters: &VoterSet<Id>,
+		online: impl IntoIterator<Item=Id>,
+		exit: ::exit_future::Exit,
+	) -> impl Future<Output=u64> {
+		let finalized = online.into_iter().map(|local_id| {
+			let env = Arc::new(Environment::new(network.clone(), local_id));
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				network.make_global_comms(),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
+
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+				.map(move |_| env.last_finalized_round())
+		}).collect::<Vec<_>>();
+
+		::futures::future::join_all(finalized).map(|rounds| rounds.into_iter().max().unwrap_or(0))
+	}
+
+	#[test]
+	fn liveness_after_asynchronous_period() {
+		let voters: VoterSet<_> = (0..4).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_all(move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			// nothing is delivered until well after all round timers fired.
+			network.pause();
+			let synchronous = network.clone();
+			spawner.spawn_local(Delay::new(Duration::from_secs(3))
+				.map(move |_| synchronous.resume())).unwrap();
+
+			run_until_finalized(spawner, &network, &voters, (0..4).map(Id), exit)
+				.map(|round| {
+					assert!(round <= 2, "finalized in round {}", round);
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn liveness_with_one_third_offline() {
+		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_all(move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			run_until_finalized(spawner, &network, &voters, (0..7).map(Id), exit)
+				.map(|round| {
+					assert!(round <= 2, "finalized in round {}", round);
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn liveness_of_late_joiner() {
+		let voters: VoterSet<_> = (0..4).map(|i| (Id(i), 1)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		block_on_all(move |spawner| {
+			spawner.spawn_local(exit.clone().until(routing_task).map(|_| ())).unwrap();
+
+			// the last voter only starts after the others finalized.
+			let late_spawner = spawner.clone();
+			run_until_finalized(spawner, &network, &voters, (0..3).map(Id), exit.clone())
+				.then(move |round| {
+					run_until_finalized(&late_spawner, &network, &voters, std::iter::once(Id(3)), exit)
+						.map(move |late_round| assert!(late_round <= round))
+				})
+				.map(|_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn voter_state_reports_rounds() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+			assert!(handle.voter_state().is_none());
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				let voter_state = handle.voter_state().unwrap();
+				assert_eq!(voter_state.best_round.number, voter.best_round.round_number());
+				assert_eq!(voter_state.best_round.total_weight, 100);
+				assert_eq!(voter_state.best_round.threshold_weight, 67);
+
+				if voter_state.best_round.number == 2 {
+					let round_one = &voter_state.background_rounds[0];
+					assert_eq!(round_one.number, 1);
+					assert_eq!(round_one.prevote_participation, (100, 1));
+					assert_eq!(round_one.precommit_participation, (100, 1));
+					assert_eq!(round_one.state.estimate, Some(("E", 6)));
+
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn shutdown_resolves_to_snapshot() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Some(Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			));
+			let handle = voter.as_ref().unwrap().handle();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// run the voter until it starts round 2.
+			future::poll_fn(move |cx| {
+				let poll = voter.as_mut().unwrap().poll_unpin(cx)?;
+				if voter.as_ref().unwrap().best_round.round_number() == 2 {
+					return Poll::Ready(Ok(voter.take().unwrap()));
+				}
+
+				poll.map(|_| panic!("voter does not conclude"))
+			})
+				.and_then(|voter| voter.shutdown())
+				.map(move |snapshot| {
+					let snapshot = snapshot.unwrap();
+					assert_eq!(snapshot.best_round.round_number, 2);
+					assert_eq!(snapshot.last_round_state.finalized, Some(("E", 6)));
+					assert_eq!(snapshot.last_finalized, ("E", 6));
+
+					// round 2 has just started and isn't completable yet, so
+					// round 1 is still the last completed one.
+					assert_eq!(handle.last_completed_round().unwrap().number, 1);
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn shutdown_does_not_complete_incompletable_best_round() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 100)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Some(Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			));
+			let handle = voter.as_ref().unwrap().handle();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// without the other voter round 1 can't become completable.
+			future::poll_fn(move |cx| {
+				let _ = voter.as_mut().unwrap().poll_unpin(cx)?;
+				Poll::Ready(Ok(voter.take().unwrap()))
+			})
+				.and_then(|voter| voter.shutdown())
+				.map(move |snapshot| {
+					let snapshot = snapshot.unwrap();
+					assert_eq!(snapshot.best_round.round_number, 1);
+
+					// a voter started after it would rely on an estimate for
+					// round 1 which doesn't exist.
+					assert!(handle.last_completed_round().is_none());
+
+					signal.fire()
+				})
+		}).unwrap();
+	}
+
+	#[test]
+	fn health_reports_liveness() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let observer_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let observer_env = Arc::new(Environment::new(network, Id(6)));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+			observer_env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+			assert!(handle.health().is_none());
+
+			// a voter whose id isn't in the voter set only observes.
+			let mut observer = Voter::new(
+				observer_env,
+				voters,
+				observer_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				(GENESIS_HASH, 1),
+			);
+			let _ = observer.poll_unpin(&mut Context::from_waker(noop_waker_ref())).map(Result::unwrap);
+			let observer_health = observer.handle().health().unwrap();
+			assert!(!observer_health.voting);
+			assert_eq!(observer_health.since_last_vote, None);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				let health = handle.health().unwrap();
+				assert!(health.voting);
+
+				if voter.best_round.round_number() == 2 {
+					// we prevoted and precommitted in round 1.
+					assert!(health.since_last_vote.is_some());
+					assert_eq!(health.background_rounds, handle.voter_state().unwrap().background_rounds.len());
+
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn woken_up_by_activity_in_later_rounds() {
+		let local_id = Id(5);
+		// the other voter is offline, so the best round can't complete.
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 100)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let round_activity = env.notify_round_activity();
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+			spawner.spawn_local(Delay::new(Duration::from_millis(100))
+				.map(move |_| round_activity.unbounded_send(4).unwrap())).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+				assert_eq!(voter.best_round.round_number(), 1);
+
+				if handle.health().unwrap().rounds_behind == 3 {
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn notes_stalled_rounds() {
+		let local_id = Id(5);
+		// the other voter is offline, so the round can't complete.
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 100)].into_iter().collect();
+		let threshold = voters.threshold();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		env.set_stall_timeout(Duration::from_millis(100));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// the stall timer fires before the prevote timer.
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				let stalled = env.stalled_rounds();
+				if !stalled.is_empty() {
+					assert_eq!(stalled.len(), 1);
+					assert_eq!(stalled[0].0, 1);
+					assert!(!stalled[0].1.completable);
+					assert_eq!(stalled[0].2, threshold);
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn rebroadcasts_votes_until_completable() {
+		let local_id = Id(5);
+		// the other voter is offline, so the round can't complete.
+		let voters: VoterSet<_> = vec![(local_id, 100), (Id(6), 100)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		env.set_rebroadcast_interval(Duration::from_millis(100));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			// listen to the round as the other voter.
+			let (mut round_in, _round_out) = network.make_round_comms(1, Id(6));
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// without a supermajority of prevotes we never precommit.
+			let mut prevotes = 0;
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				while let Poll::Ready(Some(Ok(message))) = round_in.poll_next_unpin(cx) {
+					assert_eq!(message.id, local_id);
+					match message.message {
+						Message::Prevote(_) => prevotes += 1,
+						Message::Precommit(_) => panic!("precommitted without prevote supermajority"),
+						Message::PrimaryPropose(_) => {}
+					}
+				}
+
+				if prevotes >= 3 {
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn disabled_voting_casts_no_votes() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+			handle.set_voting_enabled(false);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// the prevote timer fires without us prevoting.
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+				assert!(!handle.health().unwrap().voting);
+
+				if voter.best_round.step() == Some(Step::Prevoted) {
+					assert!(voter.best_round.historical_votes().seen().is_empty());
+					assert_eq!(env.persisted_votes(1), None);
+
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn precommit_without_prevote_is_recorded() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = vec![(local_id, 1), (Id(6), 3)].into_iter().collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		// Id(6) alone is a supermajority.
+		network.inject(1, Id(6), Message::Prevote(Prevote::new("E", 6)));
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+			handle.set_voting_enabled(false);
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// we skip the prevote, but are voting again by the time we
+			// precommit.
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				match voter.best_round.step() {
+					Some(Step::Prevoted) if !handle.voting_enabled() =>
+						handle.set_voting_enabled(true),
+					// wait for the echo of our precommit.
+					Some(Step::Precommitted)
+						if voter.best_round.historical_votes().seen().len() == 2 => return Poll::Ready(()),
+					_ => {}
+				}
+
+				poll
+			}).map(move |_| {
+				// the precommit is known to the round and after a restart, so
+				// its echo isn't unexpected and it is re-cast.
+				assert!(env.unexpected_own_votes().is_empty());
+				let has_voted = super::Environment::has_voted(&*env, 1);
+				assert_eq!(has_voted.prevote(), None);
+				assert_eq!(has_voted.precommit(), Some(&Precommit::new("E", 6)));
+
+				signal.fire()
+			})
+		}).unwrap();
+	}
+
+	#[test]
+	fn skips_rounds_after_commit_from_far_ahead() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		// we only observe.
+		let env = Arc::new(Environment::new(network.clone(), Id(6)));
+		block_on_all(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+
+			// a prevote in our round and a commit from far ahead.
+			let (_, mut round_out) = network.make_round_comms(1, voter_id);
+			round_out.send(Message::Prevote(Prevote::new("E", 6))).now_or_never().unwrap().unwrap();
+
+			let commit = Commit {
+				target_hash: "E",
+				target_number: 6,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("E", 6),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+			commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(10, commit.into()), Callback::Blank)).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+
+				// the commit was imported and we skipped past its round, leaving
+				// our round with the prevote in the background.
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+				assert_eq!(voter.best_round.round_number(), 11);
+				assert!(voter.best_round.historical_votes().seen().is_empty());
+				assert_eq!(handle.last_completed_round().unwrap().number, 10);
+				assert_eq!(voter.past_rounds.reports()[0].number, 1);
+
+				Poll::Ready(())
+			})
+		});
+	}
+
+	#[test]
+	fn validates_commits_with_the_weights_of_their_round() {
+		let voters: VoterSet<_> = vec![(Id(5), 100), (Id(6), 1)].into_iter().collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		// we only observe. by round 10, Id(6) alone is a supermajority.
+		let env = Arc::new(Environment::new(network.clone(), Id(7)));
+		env.set_round_voters(10, vec![(Id(5), 1), (Id(6), 100)].into_iter().collect());
+		block_on_all(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			let handle = voter.handle();
+
+			let commit = Commit {
+				target_hash: "E",
+				target_number: 6,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("E", 6),
+					signature: Signature(6),
+					id: Id(6),
+				}],
+			};
+			commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(10, commit.into()), Callback::Blank)).unwrap();
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+
+				// the commit is only valid with the weights of round 10.
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+				assert_eq!(voter.best_round.round_number(), 11);
+				assert_eq!(handle.last_completed_round().unwrap().voters.total_weight(), 101);
+				assert_eq!(voter.best_round.voters().info(&Id(6)).map(|info| info.weight()), Some(100));
+
+				Poll::Ready(())
+			})
+		});
+	}
+
+	#[test]
+	fn validates_commits_with_the_weights_in_effect_in_their_round() {
+		let voters: VoterSet<_> = vec![(Id(5), 100), (Id(6), 1)].into_iter().collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+		let (outcome_tx, outcome_rx) = mpsc::unbounded();
+
+		// we only observe. from round 11 onwards, Id(6) alone is a supermajority.
+		let env = Arc::new(Environment::new(network.clone(), Id(7)));
+		env.set_round_voters(11, vec![(Id(5), 1), (Id(6), 100)].into_iter().collect());
+		block_on_all(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			let commit = |id: Id, target_hash, target_number| Commit {
+				target_hash,
+				target_number,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new(target_hash, target_number),
+					signature: Signature(id.0),
+					id,
+				}],
+			};
+
+			// the commit for round 12 skips the voter past round 11, but the
+			// one for round 3 still counts with the weights before the change.
+			for &(round, id, target_hash, target_number) in &[(12, Id(6), "E", 6), (3, Id(5), "D", 5)] {
+				let outcome_tx = outcome_tx.clone();
+				commits_in.unbounded_send(CommunicationIn::Commit(
+					CommitMessage::new(round, commit(id, target_hash, target_number).into()),
+					Callback::Work(Box::new(move |outcome| { let _ = outcome_tx.unbounded_send(outcome); })),
+				)).unwrap();
+			}
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+				assert_eq!(voter.best_round.round_number(), 13);
+				Poll::Ready(())
+			}).then(move |_| outcome_rx.take(2).collect::<Vec<_>>()).map(|outcomes| {
+				assert_eq!(outcomes, vec![CommitProcessingOutcome::good(), CommitProcessingOutcome::good()]);
+			})
+		});
+	}
+
+	#[test]
+	fn votes_in_round_after_skipped_one() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+		let (signal, exit) = ::exit_future::signal();
+
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			let commit = Commit {
+				target_hash: "C",
+				target_number: 4,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("C", 4),
+					signature: Signature(5),
+					id: local_id,
+				}],
+			};
+			commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(5, commit.into()), Callback::Blank)).unwrap();
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// we start voting in round 6 and finalize the rest of the chain.
+			future::poll_fn(move |cx| -> Poll<()> {
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+
+				if env.with_chain(|chain| chain.last_finalized()) == ("E", 6) {
+					assert_eq!(voter.best_round.round_number(), 7);
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn invalid_far_future_commits_dont_hold_back_rounds() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+		let (signal, exit) = ::exit_future::signal();
+
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			// signed by a non-voter.
+			let junk = Commit {
+				target_hash: "E",
+				target_number: 6,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("E", 6),
+					signature: Signature(7),
+					id: Id(7),
+				}],
+			};
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			// a junk commit arrives before every poll, we still finalize the chain.
+			future::poll_fn(move |cx| -> Poll<()> {
+				commits_in.unbounded_send(
+					CommunicationIn::Commit(CommitMessage::new(1000, junk.clone().into()), Callback::Blank),
+				).unwrap();
+
+				let poll = voter.poll_unpin(cx).map(|res| res.expect("Error voting"));
+				assert_eq!(voter.highest_commit_round, 0);
+
+				if env.with_chain(|chain| chain.last_finalized()) == ("E", 6) {
+					return Poll::Ready(());
+				}
+
+				poll
+			}).map(move |_| signal.fire())
+		}).unwrap();
+	}
+
+	#[test]
+	fn reports_outcome_of_commits_for_background_rounds() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		let env = Arc::new(Environment::new(network, local_id));
+		block_on_all(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			let (outcome_tx, outcome_rx) = mpsc::unbounded();
+			let mut outcome_rx = outcome_rx;
+			let mut sent = false;
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+
+				if !sent {
+					let round = match voter.past_rounds.approximate_sizes().first() {
+						Some(&(round, _)) => round,
+						None => return Poll::Pending,
+					};
+
+					// signed by a voter which isn't in the set.
+					let commit = Commit {
+						target_hash: "E",
+						target_number: 6,
+						precommits: vec![SignedPrecommit {
+							precommit: Precommit::new("E", 6),
+							signature: Signature(7),
+							id: Id(7),
+						}],
+					};
+					let outcome_tx = outcome_tx.clone();
+					commits_in.unbounded_send(CommunicationIn::Commit(
+						CommitMessage::new(round, commit.into()),
+						Callback::Work(Box::new(move |outcome| { let _ = outcome_tx.unbounded_send(outcome); })),
+					)).unwrap();
+					sent = true;
+
+					let _ = voter.poll_unpin(cx).map(Result::unwrap);
+				}
+
+				// the background round checked the commit rather than
+				// accepting it on sight.
+				let outcome = outcome_rx.try_recv().unwrap();
+				assert_eq!(outcome.misbehavior(), Some(crate::misbehavior::Misbehavior::InvalidCommit(1)));
+
+				Poll::Ready(())
+			})
+		});
+	}
+
+	#[test]
+	fn does_not_finalize_backwards_from_old_commits() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		// we only observe.
+		let env = Arc::new(Environment::new(network, Id(6)));
+		block_on_all(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			let commit = |hash, number| Commit {
+				target_hash: hash,
+				target_number: number,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new(hash, number),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+
+			let (outcome_tx, outcome_rx) = mpsc::unbounded();
+			for &(round, target) in &[(10, ("E", 6)), (8, ("C", 4))] {
+				let outcome_tx = outcome_tx.clone();
+				commits_in.unbounded_send(CommunicationIn::Commit(
+					CommitMessage::new(round, commit(target.0, target.1).into()),
+					Callback::Work(Box::new(move |outcome| { let _ = outcome_tx.unbounded_send(outcome); })),
+				)).unwrap();
+			}
+			drop(outcome_tx);
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+				Poll::Ready(())
+			}).then(move |_| {
+				// the older commit is still acknowledged as valid but doesn't
+				// move finality backwards.
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+				outcome_rx.collect::<Vec<_>>().map(|outcomes| {
+					assert_eq!(outcomes, vec![CommitProcessingOutcome::good(); 2]);
+				})
+			})
+		});
+	}
+
+	#[test]
+	fn holds_back_commits_for_unknown_blocks() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		// we only observe.
+		let env = Arc::new(Environment::new(network, Id(6)));
+		let block_imported = env.notify_block_imports();
+		block_on_all(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			let commit = Commit {
+				target_hash: "F",
+				target_number: 7,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("F", 7),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+			let (outcome_tx, outcome_rx) = mpsc::unbounded();
+			commits_in.unbounded_send(CommunicationIn::Commit(
+				CommitMessage::new(10, commit.into()),
+				Callback::Work(Box::new(move |outcome| { let _ = outcome_tx.unbounded_send(outcome); })),
+			)).unwrap();
+			let mut outcome_rx = outcome_rx;
+
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+
+				// "F" is unknown so the commit is neither processed nor rejected.
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), (GENESIS_HASH, 1));
+				assert_eq!(voter.pending_commits.len(), 1);
+
+				env.with_chain(|chain| chain.push_blocks("E", &["F"]));
+				block_imported.unbounded_send(("F", 7)).unwrap();
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("F", 7));
+				assert!(voter.pending_commits.is_empty());
+				assert_eq!(outcome_rx.try_recv().unwrap(), CommitProcessingOutcome::good());
+
+				Poll::Ready(())
+			})
+		});
+	}
+
+	#[test]
+	fn held_back_commits_are_bounded() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		// we only observe.
+		let env = Arc::new(Environment::new(network, Id(6)));
+		let block_imported = env.notify_block_imports();
+		block_on_all(move |_| {
 			let last_finalized = env.with_chain(|chain| {
 				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
 				chain.last_finalized()
 			});
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
-
-			// run voter in background. scheduling it to shut down at the end.
-			let finalized = env.finalized_stream();
-			let voter = Voter::new(
+			let mut voter = Voter::new(
 				env.clone(),
 				voters,
-				global_comms,
+				(global_in.map(Ok::<_, Error>), global_out),
 				0,
-				last_round_state,
+				RoundState::genesis((GENESIS_HASH, 1)),
 				last_finalized,
 			);
-			::tokio::spawn(exit.clone()
-				.until(voter.map_err(|_| panic!("Error voting"))).map(|_| ()));
 
-			::tokio::spawn(exit.until(routing_task).map(|_| ()));
+			let commit = |id: Id, target_hash, target_number| Commit {
+				target_hash,
+				target_number,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new(target_hash, target_number),
+					signature: Signature(id.0),
+					id,
+				}],
+			};
 
-			// wait for the best block to finalize.
-			finalized
-				.take_while(|&(_, n, _)| Ok(n < 6))
-				.for_each(|_| Ok(()))
-				.map(|_| signal.fire())
-		})).unwrap();
-	}
+			// "H" on another fork is never imported, and the commit signed by a
+			// non-voter isn't worth waiting for.
+			let commits = std::iter::once((200, commit(voter_id, "H", 7)))
+				.chain((10..10 + MAX_PENDING_COMMITS as u64).map(|round| (round, commit(voter_id, "F", 7))))
+				.chain(std::iter::once((100, commit(Id(9), "G", 8))));
+
+			let (outcome_tx, mut outcome_rx) = mpsc::unbounded();
+			for (round, commit) in commits {
+				let outcome_tx = outcome_tx.clone();
+				commits_in.unbounded_send(CommunicationIn::Commit(
+					CommitMessage::new(round, commit.into()),
+					Callback::Work(Box::new(move |outcome| { let _ = outcome_tx.unbounded_send((round, outcome)); })),
+				)).unwrap();
+			}
 
-	#[test]
-	fn finalizing_at_fault_threshold() {
-		// 10 voters
-		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (signal, exit) = ::exit_future::signal();
+				// the commit for the lowest round makes room for the others.
+				assert_eq!(voter.pending_commits.len(), MAX_PENDING_COMMITS);
+				assert_eq!(outcome_rx.try_recv().unwrap(), (10, CommitProcessingOutcome::Useless));
+				let (round, outcome) = outcome_rx.try_recv().unwrap();
+				assert_eq!(round, 100);
+				assert!(outcome.misbehavior().is_some());
 
-		current_thread::block_on_all(::futures::future::lazy(move || {
-			::tokio::spawn(exit.clone().until(routing_task).map(|_| ()));
+				env.with_chain(|chain| chain.push_blocks("E", &["F"]));
+				block_imported.unbounded_send(("F", 7)).unwrap();
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
 
-			// 3 voters offline.
-			let finalized_streams = (0..7).map(move |i| {
-				let local_id = Id(i);
-				// initialize chain
-				let env = Arc::new(Environment::new(network.clone(), local_id));
-				let last_finalized = env.with_chain(|chain| {
-					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-					chain.last_finalized()
-				});
+				assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("F", 7));
+				assert_eq!(voter.pending_commits.len(), 1);
 
-				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+				// the commit for "H" can't finalize anything anymore.
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
+				assert!(voter.pending_commits.is_empty());
+				let last = std::iter::from_fn(|| outcome_rx.try_recv().ok()).last();
+				assert_eq!(last, Some((200, CommitProcessingOutcome::Useless)));
 
-				// run voter in background. scheduling it to shut down at the end.
-				let finalized = env.finalized_stream();
-				let voter = Voter::new(
-					env.clone(),
-					voters.clone(),
-					network.make_global_comms(),
-					0,
-					last_round_state,
-					last_finalized,
-				);
-				::tokio::spawn(exit.clone()
-					.until(voter.map_err(|_| panic!("Error voting"))).map(|_| ()));
+				Poll::Ready(())
+			})
+		});
+	}
 
-				// wait for the best block to be finalized by all honest voters
-				finalized
-					.take_while(|&(_, n, _)| Ok(n < 6))
-					.for_each(|_| Ok(()))
+	#[test]
+	fn deduplicates_outgoing_commits() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (signal, exit) = ::exit_future::signal();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, Id(6)));
+		block_on_all(move |spawner| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
 			});
 
-			::futures::future::join_all(finalized_streams).map(|_| signal.fire())
-		})).unwrap();
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			// as if all blocks had been finalized, so commits aren't held back.
+			voter.durably_finalized = 6;
+
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
+
+			let commit = |hash, number| Commit {
+				target_hash: hash,
+				target_number: number,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new(hash, number),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+			let queued = |voter: &Voter<_, _, _, _, _>| voter.global_out.queued()
+				.map(|CommunicationOut::Commit(message)| (message.round_number, message.commit.target_hash))
+				.collect::<Vec<_>>();
+
+			voter.broadcast_commit(1, commit("C", 4));
+			voter.broadcast_commit(2, commit("E", 6));
+			assert_eq!(queued(&voter), vec![(2, "E")]);
+
+			// lower blocks are skipped and higher rounds replace queued commits.
+			voter.broadcast_commit(3, commit("D", 5));
+			voter.broadcast_commit(4, commit("E", 6));
+			assert_eq!(queued(&voter), vec![(4, "E")]);
+
+			// once sent, the same block isn't committed again.
+			let _ = voter.global_out.poll(&mut Context::from_waker(noop_waker_ref())).map(Result::unwrap);
+			assert_eq!(voter.global_out.len(), 0);
+			voter.broadcast_commit(5, commit("E", 6));
+			assert_eq!(voter.global_out.len(), 0);
+
+			future::ready(signal.fire())
+		}).unwrap();
 	}
 
 	#[test]
-	fn broadcast_commit() {
+	fn answers_justification_requests() {
 		let local_id = Id(5);
 		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (commits, _) = network.make_global_comms();
-
-		let (signal, exit) = ::exit_future::signal();
+		let (network, mut routing_task) = testing::environment::make_network();
+		let (requests_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
 
-		let global_comms = network.make_global_comms();
 		let env = Arc::new(Environment::new(network, local_id));
-		current_thread::block_on_all(::futures::future::lazy(move || {
-			// initialize chain
+		block_on_all(move |_| {
 			let last_finalized = env.with_chain(|chain| {
 				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
 				chain.last_finalized()
 			});
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
-
-			// run voter in background. scheduling it to shut down at the end.
-			let voter = Voter::new(
+			let mut voter = Voter::new(
 				env.clone(),
 				voters.clone(),
-				global_comms,
+				(global_in.map(Ok::<_, Error>), global_out),
 				0,
-				last_round_state,
+				RoundState::genesis((GENESIS_HASH, 1)),
 				last_finalized,
 			);
-			::tokio::spawn(exit.clone()
-				.until(voter.map_err(|_| panic!("Error voting"))).map(|_| ()));
 
-			::tokio::spawn(exit.until(routing_task).map(|_| ()));
+			let (response_tx, response_rx) = mpsc::unbounded();
+			let mut response_rx = response_rx;
+			let request = move |block| {
+				let response_tx = response_tx.clone();
+				requests_in.unbounded_send(CommunicationIn::JustificationRequest(
+					block,
+					Callback::Work(Box::new(move |response| { let _ = response_tx.unbounded_send(response); })),
+				)).unwrap();
+			};
 
-			// wait for the node to broadcast a commit message
-			commits.take(1).for_each(|_| Ok(())).map(|_| signal.fire())
-		})).unwrap();
-	}
+			future::poll_fn(move |cx| -> Poll<()> {
+				let _ = routing_task.poll_unpin(cx);
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
 
-	#[test]
-	fn broadcast_commit_only_if_newer() {
-		let local_id = Id(5);
-		let test_id = Id(42);
-		let voters: VoterSet<_> = [
-			(local_id, 100),
-			(test_id, 201),
-		].iter().cloned().collect();
+				if voter.background_rounds() == 0 {
+					return Poll::Pending;
+				}
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (commits_stream, commits_sink) = network.make_global_comms();
-		let (round_stream, round_sink) = network.make_round_comms(1, test_id);
+				// a descendent of "C" was finalized in the first round.
+				request(("C", 4));
+				request(("F", 7));
+				let _ = voter.poll_unpin(cx).map(Result::unwrap);
 
-		let prevote = Message::Prevote(Prevote {
-			target_hash: "E",
-			target_number: 6,
-		});
+				let CommitMessage { round_number, commit } = response_rx.try_recv().unwrap().unwrap();
+				assert_eq!(round_number, 1);
+				assert_eq!((commit.target_hash, commit.target_number), ("E", 6));
+				assert!(validate_commit(&commit, &voters, &*env).unwrap().is_valid());
 
-		let precommit = Message::Precommit(Precommit {
-			target_hash: "E",
-			target_number: 6,
-		});
+				assert!(response_rx.try_recv().unwrap().is_none());
 
-		let commit = (1, Commit {
-			target_hash: "E",
-			target_number: 6,
-			precommits: vec![SignedPrecommit {
-				precommit: Precommit { target_hash: "E", target_number: 6 },
-				signature: Signature(test_id.0),
-				id: test_id
-			}],
+				Poll::Ready(())
+			})
 		});
+	}
+
+	#[test]
+	fn prevotes_restricted_by_voting_rule() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
 
+		let (network, routing_task) = testing::environment::make_network();
 		let (signal, exit) = ::exit_future::signal();
 
 		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		current_thread::block_on_all(::futures::future::lazy(move || {
-			// initialize chain
+		let env = Arc::new(Environment::new(network, local_id).with_voting_rule(BeforeBestBlockBy(2)));
+		block_on_all(move |spawner| {
 			let last_finalized = env.with_chain(|chain| {
 				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
 				chain.last_finalized()
 			});
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
-
-			// run voter in background. scheduling it to shut down at the end.
+			let finalized = env.finalized_stream();
 			let voter = Voter::new(
 				env.clone(),
-				voters.clone(),
+				voters,
 				global_comms,
 				0,
-				last_round_state,
+				RoundState::genesis((GENESIS_HASH, 1)),
 				last_finalized,
 			);
-			::tokio::spawn(exit.clone()
-				.until(voter.map_err(|e| panic!("Error voting: {:?}", e))).map(|_| ()));
-
-			::tokio::spawn(exit.clone().until(routing_task).map(|_| ()));
+			spawner.spawn_local(exit.clone()
+				.until(voter.map(|res| res.expect("Error voting"))).map(|_| ())).unwrap();
 
-			::tokio::spawn(exit.until(::futures::future::lazy(|| {
-				round_stream.into_future().map_err(|(e, _)| e)
-					.and_then(|(value, stream)| { // wait for a prevote
-						assert!(match value {
-							Some(SignedMessage { message: Message::Prevote(_), id: Id(5), .. }) => true,
-							_ => false,
-						});
-						let votes = vec![prevote, precommit].into_iter().map(Result::Ok);
-						round_sink.send_all(futures::stream::iter_result(votes)).map(|_| stream) // send our prevote
-					})
-					.and_then(|stream| {
-						stream.take_while(|value| match value { // wait for a precommit
-							SignedMessage { message: Message::Precommit(_), id: Id(5), .. } => Ok(false),
-							_ => Ok(true),
-						}).for_each(|_| Ok(()))
-					})
-					.and_then(|_| {
-						// send our commit
-						commits_sink.send(CommunicationOut::Commit(commit.0, commit.1))
-					})
-					.map_err(|_| ())
-			})).map(|_| ()));
+			spawner.spawn_local(exit.until(routing_task).map(|_| ())).unwrap();
 
-			// wait for the first commit (ours)
-			commits_stream.into_future().map_err(|_| ())
-				.and_then(|(_, stream)| {
-					stream.take(1).for_each(|_| Ok(())) // the second commit should never arrive
-						.timeout(Duration::from_millis(500)).map_err(|_| ())
-				})
-				.then(|res| {
-					assert!(res.is_err()); // so the previous future times out
-					signal.fire();
-					futures::future::ok::<(), ()>(())
+			// we prevote two blocks behind the best block, which is finalized.
+			finalized
+				.into_future()
+				.map(move |(finalized, _)| {
+					assert_eq!(finalized.map(|(hash, number, _)| (hash, number)), Some(("C", 4)));
+					signal.fire()
 				})
-		})).unwrap();
+		}).unwrap();
 	}
 
 	#[test]
-	fn import_commit_for_any_round() {
-		let local_id = Id(5);
-		let test_id = Id(42);
-		let voters: VoterSet<_> = [
-			(local_id, 100),
-			(test_id, 201),
-		].iter().cloned().collect();
+	fn conflicting_finality_is_a_safety_violation() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, _) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		let env = Arc::new(Environment::new(network, Id(6)));
+		env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.push_blocks("A", &["B1", "C1"]);
+		});
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (_, commits_sink) = network.make_global_comms();
+		let voter_env = env.clone();
+		block_on_all(move |_| {
+			// we have already finalized B.
+			let mut voter = Voter::new(
+				voter_env,
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				1,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				("B", 3),
+			);
 
-		let (signal, exit) = ::exit_future::signal();
+			let commit = Commit {
+				target_hash: "C1",
+				target_number: 4,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("C1", 4),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+			commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(2, commit.into()), Callback::Blank)).unwrap();
 
-		// this is a commit for a previous round
-		let commit = (0, Commit {
-			target_hash: "E",
-			target_number: 6,
-			precommits: vec![SignedPrecommit {
-				precommit: Precommit { target_hash: "E", target_number: 6 },
-				signature: Signature(test_id.0),
-				id: test_id
-			}],
+			future::poll_fn(move |cx| -> Poll<()> {
+				match voter.poll_unpin(cx) {
+					Poll::Ready(Err(e)) => assert_eq!(e, Error::SafetyViolation),
+					_ => panic!("conflicting commit was imported"),
+				}
+
+				Poll::Ready(())
+			})
 		});
 
-		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		current_thread::block_on_all(::futures::future::lazy(move || {
-			// initialize chain
-			let last_finalized = env.with_chain(|chain| {
-				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-				chain.last_finalized()
-			});
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), (GENESIS_HASH, 1));
+	}
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+	#[test]
+	fn conflicting_finality_at_the_same_height_is_a_safety_violation() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, _) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		let env = Arc::new(Environment::new(network, Id(6)));
+		env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.push_blocks("A", &["B1", "C1"]);
+		});
 
-			// run voter in background. scheduling it to shut down at the end.
-			let voter = Voter::new(
-				env.clone(),
-				voters.clone(),
-				global_comms,
+		let voter_env = env.clone();
+		block_on_all(move |_| {
+			// we have already finalized B.
+			let mut voter = Voter::new(
+				voter_env,
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
 				1,
-				last_round_state,
-				last_finalized,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				("B", 3),
 			);
-			::tokio::spawn(exit.clone()
-				.until(voter.map_err(|_| panic!("Error voting"))).map(|_| ()));
 
-			::tokio::spawn(exit.until(routing_task).map(|_| ()));
+			// B1 is at the same height as B.
+			let commit = Commit {
+				target_hash: "B1",
+				target_number: 3,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("B1", 3),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+			commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(2, commit.into()), Callback::Blank)).unwrap();
 
-			::tokio::spawn(commits_sink.send(CommunicationOut::Commit(commit.0, commit.1))
-				.map_err(|_| ()).map(|_| ()));
+			future::poll_fn(move |cx| -> Poll<()> {
+				match voter.poll_unpin(cx) {
+					Poll::Ready(Err(e)) => assert_eq!(e, Error::SafetyViolation),
+					_ => panic!("conflicting commit was imported"),
+				}
 
-			// wait for the commit message to be processed which finalized block 6
-			env.finalized_stream()
-				.take_while(|&(_, n, _)| Ok(n < 6))
-				.for_each(|_| Ok(()))
-				.map(|_| signal.fire())
-		})).unwrap();
+				Poll::Ready(())
+			})
+		});
+
+		assert_eq!(env.finality_violations().len(), 1);
 	}
 
 	#[test]
-	fn skips_to_latest_round_after_catch_up() {
-		// 3 voters
-		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+	fn unavailable_ancestry_is_not_a_safety_violation() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, _) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		let env = Arc::new(Environment::new(network, Id(6)));
+		env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.push_blocks("A", &["B1", "C1"]);
+			chain.set_ancestry_unavailable("C");
+		});
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (signal, exit) = ::exit_future::signal();
+		let voter_env = env.clone();
+		block_on_all(move |_| {
+			// we have already finalized B.
+			let mut voter = Voter::new(
+				voter_env,
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				1,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				("B", 3),
+			);
 
-		current_thread::block_on_all(::futures::future::lazy(move || {
-			::tokio::spawn(exit.clone().until(routing_task).map(|_| ()));
+			// C descends from B, but we can't tell.
+			let commit = Commit {
+				target_hash: "C",
+				target_number: 4,
+				precommits: vec![SignedPrecommit {
+					precommit: Precommit::new("C", 4),
+					signature: Signature(5),
+					id: voter_id,
+				}],
+			};
+			commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(2, commit.into()), Callback::Blank)).unwrap();
 
-			// initialize unsynced voter at round 0
-			let mut unsynced_voter = {
-				let local_id = Id(4);
+			future::poll_fn(move |cx| -> Poll<()> {
+				match voter.poll_unpin(cx) {
+					Poll::Ready(Err(e)) => assert_eq!(e, Error::AncestryUnavailable),
+					_ => panic!("commit was imported"),
+				}
 
-				let env = Arc::new(Environment::new(network.clone(), local_id));
-				let last_finalized = env.with_chain(|chain| {
-					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-					chain.last_finalized()
-				});
+				Poll::Ready(())
+			})
+		});
 
-				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+		assert!(env.finality_violations().is_empty());
+	}
 
-				Voter::new(
-					env.clone(),
-					voters.clone(),
-					network.make_global_comms(),
-					0,
-					last_round_state,
-					last_finalized,
-				)
-			};
+	#[test]
+	fn reports_conflicting_commits() {
+		let voter_id = Id(5);
+		let voters: VoterSet<_> = std::iter::once((voter_id, 100)).collect();
+
+		let (network, _) = testing::environment::make_network();
+		let (commits_in, global_in) = mpsc::unbounded();
+		let (_, global_out) = network.make_global_comms();
+
+		let env = Arc::new(Environment::new(network, Id(6)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.push_blocks("A", &["B1", "C1"]);
+			chain.last_finalized()
+		});
 
-			let pv = |id| crate::SignedPrevote {
-				prevote: crate::Prevote { target_hash: "C", target_number: 4 },
-				id: Id(id),
-				signature: Signature(99),
-			};
+		let make_commit = |hash, number| Commit {
+			target_hash: hash,
+			target_number: number,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit::new(hash, number),
+				signature: Signature(5),
+				id: voter_id,
+			}],
+		};
 
-			let pc = |id| crate::SignedPrecommit {
-				precommit: crate::Precommit { target_hash: "C", target_number: 4 },
-				id: Id(id),
-				signature: Signature(99),
-			};
+		let finalized = make_commit("B", 3);
+		let conflicting = make_commit("C1", 4);
+		commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(1, finalized.clone().into()), Callback::Blank)).unwrap();
+		commits_in.unbounded_send(CommunicationIn::Commit(CommitMessage::new(2, conflicting.clone().into()), Callback::Blank)).unwrap();
 
-			// send in a catch-up message for round 5.
-			network.send_message(CommunicationIn::CatchUp(
-				CatchUp {
-					base_number: 1,
-					base_hash: GENESIS_HASH,
-					round_number: 5,
-					prevotes: vec![pv(0), pv(1), pv(2)],
-					precommits: vec![pc(0), pc(1), pc(2)],
-				},
-				Callback::Blank,
-			));
+		let voter_env = env.clone();
+		block_on_all(move |_| {
+			let mut voter = Voter::new(
+				voter_env,
+				voters,
+				(global_in.map(Ok::<_, Error>), global_out),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
 
-			// poll until it's caught up.
-			// should skip to round 6
-			::futures::future::poll_fn(move || -> Poll<(), ()> {
-				let poll = unsynced_voter.poll().map_err(|_| ())?;
-				if unsynced_voter.best_round.round_number() == 6 {
-					Ok(Async::Ready(()))
-				} else {
-					Ok(poll)
-				}
-			}).map(move |_| signal.fire())
-		})).unwrap();
+			future::poll_fn(move |cx| -> Poll<()> {
+				assert_eq!(voter.poll_unpin(cx), Poll::Ready(Err(Error::SafetyViolation)));
+				Poll::Ready(())
+			})
+		});
+
+		assert_eq!(env.finality_violations(), vec![FinalityViolation {
+			finalized: ("B", 3),
+			finalized_commit: Some(CommitMessage::new(1, finalized)),
+			conflicting_commit: CommitMessage::new(2, conflicting),
+		}]);
 	}
 }
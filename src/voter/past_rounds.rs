@@ -21,21 +21,24 @@
 //!   - Informing it of any new finalized block heights
 //!   - Passing it any validated commits (so backgrounded rounds don't produce conflicting ones)
 
-#[cfg(feature = "std")]
-use futures::try_ready;
 use futures::prelude::*;
-use futures::stream::{self, futures_unordered::FuturesUnordered};
-use futures::task;
-use futures::sync::mpsc;
+use futures::ready;
+use futures::channel::mpsc;
+use futures::stream::{self, FuturesUnordered};
+use futures::task::{Context, Poll, Waker};
 #[cfg(feature = "std")]
-use log::{trace, debug};
+use tracing::{trace, debug, warn};
 
 use std::cmp;
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::pin::Pin;
 
-use crate::{Commit, BlockNumberOps};
-use super::Environment;
+use crate::{Commit, CommitMessage, BlockNumberOps};
+use super::{
+	BadCommit, Callback, CommitProcessingOutcome, Environment, GoodCommit, Justification, RoundReport,
+	RoundSnapshot,
+};
 use super::voting_round::VotingRound;
 
 // wraps a voting round with a new future that resolves when the round can
@@ -47,9 +50,10 @@ struct BackgroundRound<H, N, E: Environment<H, N>> where
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 	inner: VotingRound<H, N, E>,
-	task: Option<task::Task>,
+	waker: Option<Waker>,
 	finalized_number: N,
 	round_committer: Option<RoundCommitter<H, N, E>>,
+	evicted: bool,
 }
 
 impl<H, N, E: Environment<H, N>> BackgroundRound<H, N, E> where
@@ -67,8 +71,18 @@ impl<H, N, E: Environment<H, N>> BackgroundRound<H, N, E> where
 		//   - rounds are not backgrounded when incomplete unless we've skipped forward
 		//   - if we skipped forward we may never complete this round and we don't need
 		//     to keep it forever.
-		self.round_committer.is_none() && self.inner.round_state().estimate
-			.map_or(true, |x| x.1 <= self.finalized_number)
+		//
+		// evicted rounds are dropped regardless.
+		self.evicted || self.round_committer.is_none() && self.inner.round_state().estimate
+			.is_none_or(|x| x.1 <= self.finalized_number)
+	}
+
+	fn evict(&mut self) {
+		self.evicted = true;
+
+		if let Some(ref waker) = self.waker {
+			waker.wake_by_ref();
+		}
 	}
 
 	fn update_finalized(&mut self, new_finalized: N) {
@@ -76,8 +90,8 @@ impl<H, N, E: Environment<H, N>> BackgroundRound<H, N, E> where
 
 		// wake up the future to be polled if done.
 		if self.is_done() {
-			if let Some(ref task) = self.task {
-				task.notify();
+			if let Some(ref waker) = self.waker {
+				waker.wake_by_ref();
 			}
 		}
 	}
@@ -94,45 +108,81 @@ enum BackgroundRoundChange<H, N, E: Environment<H, N>> where
 	Committed(Commit<H, N, E::Signature, E::Id>),
 }
 
+// background rounds are never structurally pinned.
+impl<H, N, E: Environment<H, N>> Unpin for BackgroundRound<H, N, E> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+{}
+
 impl<H, N, E: Environment<H, N>> Future for BackgroundRound<H, N, E> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
-	type Item = BackgroundRoundChange<H, N, E>;
-	type Error = E::Error;
+	type Output = Result<BackgroundRoundChange<H, N, E>, E::Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		match self.waker {
+			Some(ref waker) if waker.will_wake(cx.waker()) => {}
+			_ => self.waker = Some(cx.waker().clone()),
+		}
 
-	fn poll(&mut self) -> Poll<Self::Item, E::Error> {
-		self.task = Some(::futures::task::current());
+		if self.evicted {
+			return Poll::Ready(Ok(BackgroundRoundChange::Irrelevant(self.round_number())));
+		}
 
-		self.inner.poll()?;
+		if let Poll::Ready(Err(e)) = self.inner.poll(cx) {
+			if !self.inner.is_transient(&e) {
+				return Poll::Ready(Err(e));
+			}
+
+			warn!(target: "afg", "Transient error in background round {}: {:?}", self.round_number(), e);
+		}
 
-		self.round_committer = match self.round_committer.take() {
+		let this = &mut *self;
+		this.round_committer = match this.round_committer.take() {
 			None => None,
-			Some(mut committer) => match committer.commit(&mut self.inner)? {
-				Async::Ready(None) => None,
-				Async::Ready(Some(commit)) => return Ok(Async::Ready(
+			Some(mut committer) => match committer.commit(cx, &mut this.inner) {
+				Poll::Ready(Ok(None)) => None,
+				Poll::Ready(Ok(Some(commit))) => return Poll::Ready(Ok(
 					BackgroundRoundChange::Committed(commit)
 				)),
-				Async::NotReady => Some(committer),
+				Poll::Pending => Some(committer),
+				Poll::Ready(Err(e)) => {
+					if !this.inner.is_transient(&e) {
+						return Poll::Ready(Err(e));
+					}
+
+					warn!(target: "afg", "Transient error committing round {}: {:?}", this.round_number(), e);
+					Some(committer)
+				}
 			}
 		};
 
 		if self.is_done() {
 			// if this is fully done (has committed _and_ estimate finalized)
 			// we bail for real.
-			Ok(Async::Ready(BackgroundRoundChange::Irrelevant(self.round_number())))
+			Poll::Ready(Ok(BackgroundRoundChange::Irrelevant(self.round_number())))
 		} else {
-			Ok(Async::NotReady)
+			Poll::Pending
 		}
 	}
 }
 
+// A commit signed by the voters of the environment.
+type EnvCommit<H, N, E> = Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>;
+
+// A commit for a backgrounded round and the callback to run once it's checked.
+type IncomingCommit<H, N, E> = (EnvCommit<H, N, E>, Callback<CommitProcessingOutcome>);
+
+// The commit made for a round, if any, once it's time to commit.
+type RoundCommit<H, N, E> = Result<Option<EnvCommit<H, N, E>>, <E as Environment<H, N>>::Error>;
+
 struct RoundCommitter<H, N, E: Environment<H, N>> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 	commit_timer: E::Timer,
-	import_commits: stream::Fuse<mpsc::UnboundedReceiver<Commit<H, N, E::Signature, E::Id>>>,
+	import_commits: stream::Fuse<mpsc::UnboundedReceiver<IncomingCommit<H, N, E>>>,
 	last_commit: Option<Commit<H, N, E::Signature, E::Id>>,
 }
 
@@ -142,7 +192,7 @@ impl<H, N, E: Environment<H, N>> RoundCommitter<H, N, E> where
 {
 	fn new(
 		commit_timer: E::Timer,
-		commit_receiver: mpsc::UnboundedReceiver<Commit<H, N, E::Signature, E::Id>>,
+		commit_receiver: mpsc::UnboundedReceiver<IncomingCommit<H, N, E>>,
 	) -> Self {
 		RoundCommitter {
 			commit_timer,
@@ -155,41 +205,52 @@ impl<H, N, E: Environment<H, N>> RoundCommitter<H, N, E> where
 		&mut self,
 		voting_round: &mut VotingRound<H, N, E>,
 		commit: Commit<H, N, E::Signature, E::Id>,
-	) -> Result<bool, E::Error> {
+	) -> Result<CommitProcessingOutcome, E::Error> {
 		// ignore commits for a block lower than we already finalized
 		if commit.target_number < voting_round.finalized().map_or_else(N::zero, |(_, n)| *n) {
-			return Ok(true);
+			return Ok(CommitProcessingOutcome::Good(GoodCommit::new()));
 		}
 
-		if voting_round.check_and_import_from_commit(&commit)?.is_none() {
-			return Ok(false)
+		let validation_result = voting_round.check_and_import_from_commit(&commit)?;
+		if validation_result.ghost().is_none() {
+			if let Some(metrics) = voting_round.metrics() {
+				metrics.commit_rejected();
+			}
+			return Ok(CommitProcessingOutcome::Bad(BadCommit::from(validation_result)))
+		}
+
+		if let Some(metrics) = voting_round.metrics() {
+			metrics.commit_validated();
 		}
 
 		self.last_commit = Some(commit);
 
-		Ok(true)
+		Ok(CommitProcessingOutcome::Good(GoodCommit::new()))
 	}
 
-	fn commit(&mut self, voting_round: &mut VotingRound<H, N, E>)
-		-> Poll<Option<Commit<H, N, E::Signature, E::Id>>, E::Error>
+	fn commit(&mut self, cx: &mut Context, voting_round: &mut VotingRound<H, N, E>)
+		-> Poll<RoundCommit<H, N, E>>
 	{
-		while let Ok(Async::Ready(Some(commit))) = self.import_commits.poll() {
-			if !self.import_commit(voting_round, commit)? {
+		while let Poll::Ready(Some((commit, mut process_commit_outcome))) = self.import_commits.poll_next_unpin(cx) {
+			let outcome = self.import_commit(voting_round, commit)?;
+			if let CommitProcessingOutcome::Bad(_) = outcome {
 				trace!(target: "afg", "Ignoring invalid commit");
 			}
+
+			process_commit_outcome.run(outcome);
 		}
 
-		try_ready!(self.commit_timer.poll());
+		ready!(self.commit_timer.poll_unpin(cx))?;
 
 		match (self.last_commit.take(), voting_round.finalized()) {
 			(None, Some(_)) => {
-				Ok(Async::Ready(voting_round.finalizing_commit().cloned()))
+				Poll::Ready(Ok(voting_round.finalizing_commit().cloned()))
 			},
 			(Some(Commit { target_number, .. }), Some((_, finalized_number))) if target_number < *finalized_number => {
-				Ok(Async::Ready(voting_round.finalizing_commit().cloned()))
+				Poll::Ready(Ok(voting_round.finalizing_commit().cloned()))
 			},
 			_ => {
-				Ok(Async::Ready(None))
+				Poll::Ready(Ok(None))
 			},
 		}
 	}
@@ -213,18 +274,17 @@ impl<F> SelfReturningFuture<F> {
 	}
 }
 
-impl<F: Future> Future for SelfReturningFuture<F> {
-	type Item = (F::Item, F);
-	type Error = F::Error;
+impl<F: Future + Unpin> Future for SelfReturningFuture<F> {
+	type Output = (F::Output, F);
 
-	fn poll(&mut self) -> Poll<Self::Item, F::Error> {
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
 		match self.inner.take() {
 			None => panic!("poll after return is not done in this module; qed"),
-			Some(mut f) => match f.poll()? {
-				Async::Ready(item) => Ok(Async::Ready((item, f))),
-				Async::NotReady => {
+			Some(mut f) => match f.poll_unpin(cx) {
+				Poll::Ready(item) => Poll::Ready((item, f)),
+				Poll::Pending => {
 					self.inner = Some(f);
-					Ok(Async::NotReady)
+					Poll::Pending
 				}
 			}
 		}
@@ -238,7 +298,7 @@ pub(super) struct PastRounds<H, N, E: Environment<H, N>> where
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 	past_rounds: FuturesUnordered<SelfReturningFuture<BackgroundRound<H, N, E>>>,
-	commit_senders: HashMap<u64, mpsc::UnboundedSender<Commit<H, N, E::Signature, E::Id>>>,
+	commit_senders: HashMap<u64, mpsc::UnboundedSender<IncomingCommit<H, N, E>>>,
 }
 
 impl<H, N, E: Environment<H, N>> PastRounds<H, N, E> where
@@ -253,24 +313,31 @@ impl<H, N, E: Environment<H, N>> PastRounds<H, N, E> where
 		}
 	}
 
-	// push an old voting round onto this stream.
-	pub(super) fn push(&mut self, env: &E, round: VotingRound<H, N, E>) {
+	// push an old voting round onto this stream, given the number of the last
+	// finalized block.
+	pub(super) fn push(&mut self, env: &E, round: VotingRound<H, N, E>, finalized_number: N) {
 		let round_number = round.round_number();
 		let (tx, rx) = mpsc::unbounded();
 		let background = BackgroundRound {
 			inner: round,
-			task: None,
-			// https://github.com/paritytech/finality-grandpa/issues/50
-			finalized_number: N::zero(),
+			waker: None,
+			finalized_number,
 			round_committer: Some(RoundCommitter::new(
 				env.round_commit_timer(),
 				rx,
 			)),
+			evicted: false,
 		};
 		self.past_rounds.push(background.into());
 		self.commit_senders.insert(round_number, tx);
 	}
 
+	/// the number of background rounds, including any evicted rounds which
+	/// haven't been dropped yet.
+	pub(super) fn len(&self) -> usize {
+		self.past_rounds.len()
+	}
+
 	/// update the last finalized block. this will lead to
 	/// any irrelevant background rounds being pruned.
 	pub(super) fn update_finalized(&mut self, f_num: N) {
@@ -281,15 +348,116 @@ impl<H, N, E: Environment<H, N>> PastRounds<H, N, E> where
 		}
 	}
 
-	// import the commit into the given backgrounded round. If not possible,
-	// just return and process the commit.
-	pub(super) fn import_commit(&self, round_number: u64, commit: Commit<H, N, E::Signature, E::Id>)
-		-> Option<Commit<H, N, E::Signature, E::Id>>
+	/// the approximate memory used by each background round which hasn't been
+	/// evicted, by round number.
+	pub(super) fn approximate_sizes(&mut self) -> Vec<(u64, usize)> {
+		let mut sizes = Vec::new();
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| if !f.evicted {
+				sizes.push((f.round_number(), f.inner.approximate_size()));
+			});
+		}
+
+		sizes
+	}
+
+	/// a snapshot of the state of each background round which hasn't been
+	/// evicted, ordered by round number.
+	pub(super) fn reports(&mut self) -> Vec<RoundReport<H, N>> {
+		let mut reports = Vec::new();
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| if !f.evicted {
+				reports.push(f.inner.report());
+			});
+		}
+
+		reports.sort_by_key(|report| report.number);
+		reports
+	}
+
+	/// the votes of each background round which hasn't been evicted, ordered
+	/// by round number.
+	pub(super) fn snapshots(&mut self) -> Vec<RoundSnapshot<H, N, E::Signature, E::Id>> {
+		let mut snapshots = Vec::new();
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| if !f.evicted {
+				snapshots.push(f.inner.snapshot());
+			});
+		}
+
+		snapshots.sort_by_key(|snapshot| snapshot.round_number);
+		snapshots
+	}
+
+	/// assemble a commit for the given block, or one of its descendents, from
+	/// the lowest background round which finalized it.
+	pub(super) fn justify(&mut self, chain: &E, hash: &H, number: N)
+		-> Option<Justification<H, N, E::Signature, E::Id>>
 	{
+		let mut justifications = Vec::new();
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| if !f.evicted {
+				let finalizes = f.inner.finalized().is_some_and(|(f_hash, f_number)|
+					*f_number >= number && chain.is_equal_or_descendent_of(hash.clone(), f_hash.clone())
+				);
+
+				if finalizes {
+					if let Some(commit) = f.inner.assemble_commit() {
+						justifications.push(CommitMessage::new(f.round_number(), commit));
+					}
+				}
+			});
+		}
+
+		justifications.into_iter().min_by_key(|justification| justification.round_number)
+	}
+
+	/// flush the buffered votes of all background rounds. ready when every
+	/// round is flushed.
+	pub(super) fn flush(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		let mut result = Poll::Ready(Ok(()));
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| match (f.inner.flush(cx), &result) {
+				(Poll::Ready(Err(e)), _) => result = Poll::Ready(Err(e)),
+				(Poll::Pending, Poll::Ready(Ok(_))) => result = Poll::Pending,
+				_ => {}
+			});
+		}
+
+		result
+	}
+
+	/// stop or resume casting votes in all background rounds.
+	pub(super) fn set_paused(&mut self, paused: bool) {
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| f.inner.set_paused(paused));
+		}
+	}
+
+	/// evict a background round. it will be dropped without waiting for its
+	/// estimate to be finalized or for it to commit.
+	pub(super) fn evict(&mut self, round_number: u64) {
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|f| if f.round_number() == round_number {
+				debug!(target: "afg", "Evicting background round {}", round_number);
+				f.evict();
+			});
+		}
+	}
+
+	// import the commit into the given backgrounded round, which runs the
+	// callback once it has checked the commit. If not possible, just return
+	// and process the commit.
+	pub(super) fn import_commit(
+		&self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		process_commit_outcome: Callback<CommitProcessingOutcome>,
+	) -> Option<IncomingCommit<H, N, E>> {
 		if let Some(sender) = self.commit_senders.get(&round_number) {
-			sender.unbounded_send(commit).map_err(|e| e.into_inner()).err()
+			sender.unbounded_send((commit, process_commit_outcome)).map_err(|e| e.into_inner()).err()
 		} else {
-			Some(commit)
+			Some((commit, process_commit_outcome))
 		}
 	}
 }
@@ -298,16 +466,16 @@ impl<H, N, E: Environment<H, N>> Stream for PastRounds<H, N, E> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
-	type Item = (u64, Commit<H, N, E::Signature, E::Id>);
-	type Error = E::Error;
+	type Item = Result<(u64, Commit<H, N, E::Signature, E::Id>), E::Error>;
 
-	fn poll(&mut self) -> Poll<Option<Self::Item>, E::Error> {
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
 		loop {
-			match self.past_rounds.poll()? {
-				Async::Ready(Some((BackgroundRoundChange::Irrelevant(number), _))) => {
+			match self.past_rounds.poll_next_unpin(cx) {
+				Poll::Ready(Some((Err(e), _))) => return Poll::Ready(Some(Err(e))),
+				Poll::Ready(Some((Ok(BackgroundRoundChange::Irrelevant(number)), _))) => {
 					self.commit_senders.remove(&number);
 				}
-				Async::Ready(Some((BackgroundRoundChange::Committed(commit), round))) => {
+				Poll::Ready(Some((Ok(BackgroundRoundChange::Committed(commit)), round))) => {
 					let number = round.round_number();
 
 					// reschedule until irrelevant.
@@ -321,10 +489,10 @@ impl<H, N, E: Environment<H, N>> Stream for PastRounds<H, N, E> where
 						commit.target_hash,
 					);
 
-					return Ok(Async::Ready(Some((number, commit))));
+					return Poll::Ready(Some(Ok((number, commit))));
 				}
-				Async::Ready(None) => return Ok(Async::Ready(None)),
-				Async::NotReady => return Ok(Async::NotReady),
+				Poll::Ready(None) => return Poll::Ready(None),
+				Poll::Pending => return Poll::Pending,
 			}
 		}
 	}
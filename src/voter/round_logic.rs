@@ -0,0 +1,597 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The decisions a voter makes within a round, independent of futures, timers
+//! and networking.
+//!
+//! `RoundMachine` steps through a round as inputs are handed to it (votes
+//! tallied, timers firing, lookups completing) and returns what should be done
+//! in response, such as casting a vote. Everything here is a function of its
+//! inputs and the chain, so that it can be tested deterministically and reused
+//! outside of the `Voter` future. `VotingRound` drives the machine with timers
+//! and message streams.
+//!
+//! Only our votes within a round are decided here. Managing rounds (starting
+//! and completing them, keeping background rounds, skipping ahead after
+//! commits or catch-ups) and processing commits and finality are still done
+//! by the `Voter` future itself and aren't available without it.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::round::State as RoundState;
+use crate::{BlockNumberOps, Chain, Message, Precommit, Prevote, PrimaryPropose};
+use super::HasVoted;
+
+/// The block hint to send as primary, given the state of the prior round.
+///
+/// A hint is only sent if the prior round's estimate hasn't been finalized.
+pub fn primary_proposal<H: Clone, N: Copy + BlockNumberOps>(
+	last_round_state: &RoundState<H, N>,
+) -> Option<PrimaryPropose<H, N>> {
+	let (hash, number) = last_round_state.estimate.clone()?;
+	let finalized = last_round_state.finalized.as_ref();
+
+	if finalized.is_none_or(|f| number > f.1) {
+		Some(PrimaryPropose { target_hash: hash, target_number: number })
+	} else {
+		None
+	}
+}
+
+/// The block our prevote must build on: we prevote for the best chain
+/// containing it.
+///
+/// This is the prior round's estimate, unless the primary's block hint is a
+/// descendent of that estimate and included in the prior round's
+/// prevote-GHOST.
+///
+/// Panics if the prior round isn't completable, i.e. has no estimate or
+/// prevote-GHOST.
+pub fn prevote_base<H, N, C>(
+	chain: &C,
+	last_round_state: &RoundState<H, N>,
+	primary_block: Option<&(H, N)>,
+) -> (H, N) where
+	H: Clone + Eq,
+	N: Copy + BlockNumberOps,
+	C: Chain<H, N> + ?Sized,
+{
+	let last_round_estimate = last_round_state.estimate.clone()
+		.expect("Rounds only started when prior round completable; qed");
+
+	let primary_block = match primary_block {
+		// vote for best chain containing prior round-estimate.
+		None => return last_round_estimate,
+		Some(primary_block) => primary_block,
+	};
+
+	// we will vote for the best chain containing `p_hash` iff
+	// the last round's prevote-GHOST included that block and
+	// that block is a strict descendent of the last round-estimate that we are
+	// aware of.
+	let last_prevote_g = last_round_state.prevote_ghost.clone()
+		.expect("Rounds only started when prior round completable; qed");
+
+	// if the blocks are equal, we don't check ancestry.
+	if primary_block == &last_prevote_g {
+		return primary_block.clone();
+	}
+
+	if primary_block.1 >= last_prevote_g.1 {
+		return last_round_estimate;
+	}
+
+	// from this point onwards, the number of the primary-broadcasted
+	// block is less than the last prevote-GHOST's number.
+	// if the primary block is in the ancestry of p-G we vote for the
+	// best chain containing it.
+	let &(ref p_hash, p_num) = primary_block;
+	match chain.ancestry(last_round_estimate.0.clone(), last_prevote_g.0) {
+		Ok(ancestry) => {
+			let to_sub = p_num + N::one();
+
+			let offset: usize = if last_prevote_g.1 < to_sub {
+				0
+			} else {
+				(last_prevote_g.1 - to_sub).as_()
+			};
+
+			if ancestry.get(offset).is_some_and(|b| b == p_hash) {
+				(p_hash.clone(), p_num)
+			} else {
+				last_round_estimate
+			}
+		}
+		Err(_) => last_round_estimate,
+	}
+}
+
+/// Whether the round's prevote-GHOST allows us to precommit: it must be equal
+/// to or a descendent of the prior round's estimate.
+pub fn can_precommit<H, N, C>(
+	chain: &C,
+	last_round_estimate: &(H, N),
+	prevote_ghost: Option<&(H, N)>,
+) -> bool where
+	H: Clone + Eq,
+	N: Copy + BlockNumberOps,
+	C: Chain<H, N> + ?Sized,
+{
+	prevote_ghost.is_some_and(|p_g| {
+		p_g == last_round_estimate ||
+			chain.is_equal_or_descendent_of(last_round_estimate.0.clone(), p_g.0.clone())
+	})
+}
+
+/// The block to precommit for: the round's prevote-GHOST, or the round's base
+/// if there is none.
+pub fn precommit_target<H: Clone, N: Copy>(round_state: &RoundState<H, N>, base: (H, N)) -> (H, N) {
+	round_state.prevote_ghost.clone().unwrap_or(base)
+}
+
+/// The step a voter is at within a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+	/// Waiting to prevote, before a primary block hint was sent.
+	Start,
+	/// Waiting to prevote, after a primary block hint was sent.
+	Proposed,
+	/// Waiting for the best chain to prevote on.
+	Prevoting,
+	/// Waiting to precommit.
+	Prevoted,
+	/// Done casting votes.
+	Precommitted,
+}
+
+/// Something a `RoundMachine` is told about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input<H, N> {
+	/// The state of the prior round, whenever it changes.
+	LastRoundState(RoundState<H, N>),
+	/// The state of the round, whenever votes were imported.
+	RoundState(RoundState<H, N>),
+	/// The block hint sent by the round's primary.
+	PrimaryBlock((H, N)),
+	/// The prevote timer fired.
+	PrevoteTimer,
+	/// The precommit timer fired.
+	PrecommitTimer,
+	/// The answer to `Output::LookupBestChain`: the block to prevote for, if
+	/// the base is still known.
+	BestChain(Option<(H, N)>),
+	/// Casting votes was stopped or resumed.
+	Paused(bool),
+}
+
+/// Something a `RoundMachine` asks its driver to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output<H, N> {
+	/// Send a primary block hint. It is already recorded as cast.
+	Propose(PrimaryPropose<H, N>),
+	/// Cast a prevote. It is already recorded as cast.
+	Prevote(Prevote<H, N>),
+	/// Cast a precommit. It is already recorded as cast.
+	Precommit(Precommit<H, N>),
+	/// Send a message cast before, e.g. prior to a restart.
+	Recast(Message<H, N>),
+	/// Look up the best chain containing the given block and answer with
+	/// `Input::BestChain`.
+	LookupBestChain((H, N)),
+}
+
+/// The steps of a voter through a single round.
+///
+/// The machine doesn't advance until the state of the prior round is known,
+/// and doesn't advance at all for a round that was completed without us.
+pub struct RoundMachine<H, N, C> {
+	chain: Arc<C>,
+	base: (H, N),
+	step: Option<Step>,
+	voter: bool,
+	primary: bool,
+	paused: bool,
+	has_voted: HasVoted<H, N>,
+	last_round_state: Option<RoundState<H, N>>,
+	round_state: RoundState<H, N>,
+	primary_block: Option<(H, N)>,
+	prevote_timer_fired: bool,
+	precommit_timer_fired: bool,
+	best_chain_base: Option<(H, N)>, // the base of a pending best chain lookup.
+	prevoted_at: Option<Instant>,
+	precommitted_at: Option<Instant>,
+}
+
+impl<H, N, C> RoundMachine<H, N, C> where
+	H: Clone + Eq,
+	N: Copy + BlockNumberOps,
+	C: Chain<H, N>,
+{
+	/// Create a machine for a round with the given base, resuming after the
+	/// votes we've already cast.
+	pub fn new(chain: Arc<C>, base: (H, N), voter: bool, primary: bool, has_voted: HasVoted<H, N>) -> Self {
+		RoundMachine {
+			round_state: RoundState {
+				prevote_ghost: None,
+				finalized: None,
+				estimate: None,
+				completable: false,
+			},
+			chain,
+			base,
+			step: Some(Step::Start),
+			voter,
+			primary,
+			paused: false,
+			has_voted,
+			last_round_state: None,
+			primary_block: None,
+			prevote_timer_fired: false,
+			precommit_timer_fired: false,
+			best_chain_base: None,
+			prevoted_at: None,
+			precommitted_at: None,
+		}
+	}
+
+	/// Create a machine for a round which was completed without us. It never
+	/// advances.
+	pub fn completed(chain: Arc<C>, base: (H, N)) -> Self {
+		RoundMachine { step: None, ..RoundMachine::new(chain, base, false, false, HasVoted::No) }
+	}
+
+	/// Note votes cast before a restart, e.g. found in the round's persisted
+	/// votes, besides those the machine was created with. Like those, they
+	/// are cast again instead of new ones. Must be called before any input is
+	/// handled.
+	pub fn resume(&mut self, has_voted: &HasVoted<H, N>) {
+		self.has_voted = self.has_voted.or(has_voted);
+	}
+
+	/// Handle an input, returning what should be done in response.
+	pub fn handle(&mut self, input: Input<H, N>, now: Instant) -> Vec<Output<H, N>> {
+		match input {
+			Input::LastRoundState(state) => self.last_round_state = Some(state),
+			Input::RoundState(state) => self.round_state = state,
+			Input::PrimaryBlock(block) => self.primary_block = Some(block),
+			Input::PrevoteTimer => self.prevote_timer_fired = true,
+			Input::PrecommitTimer => self.precommit_timer_fired = true,
+			Input::BestChain(target) => return self.prevote(target, now),
+			Input::Paused(paused) => self.paused = paused,
+		}
+
+		self.advance(now)
+	}
+
+	/// The step we're at, if we take part in the round.
+	pub fn step(&self) -> Option<Step> {
+		self.step
+	}
+
+	/// The votes we've cast in the round.
+	pub fn has_voted(&self) -> &HasVoted<H, N> {
+		&self.has_voted
+	}
+
+	/// Whether we cast votes in the round.
+	pub fn is_voting(&self) -> bool {
+		self.voter && !self.paused
+	}
+
+	/// Whether we send a primary block hint in the round.
+	pub fn is_primary(&self) -> bool {
+		self.primary && !self.paused
+	}
+
+	/// Whether a best chain lookup is pending.
+	pub fn is_prevoting(&self) -> bool {
+		self.best_chain_base.is_some()
+	}
+
+	/// When we cast our prevote, if we did.
+	pub fn prevoted_at(&self) -> Option<Instant> {
+		self.prevoted_at
+	}
+
+	/// When we cast our precommit, if we did.
+	pub fn precommitted_at(&self) -> Option<Instant> {
+		self.precommitted_at
+	}
+
+	// take all steps that the inputs so far allow.
+	fn advance(&mut self, now: Instant) -> Vec<Output<H, N>> {
+		let mut outputs = Vec::new();
+		let last_round_state = match self.last_round_state {
+			Some(ref state) => state.clone(),
+			None => return outputs,
+		};
+
+		loop {
+			let next = match self.step {
+				Some(Step::Start) => self.propose(&last_round_state, &mut outputs)
+					.or_else(|| self.should_prevote()),
+				Some(Step::Proposed) => self.should_prevote(),
+				Some(Step::Prevoting) => self.request_best_chain(&last_round_state, &mut outputs),
+				Some(Step::Prevoted) => self.precommit(&last_round_state, now, &mut outputs),
+				Some(Step::Precommitted) | None => None,
+			};
+
+			match next {
+				Some(step) => self.step = Some(step),
+				None => return outputs,
+			}
+		}
+	}
+
+	fn propose(&mut self, last_round_state: &RoundState<H, N>, outputs: &mut Vec<Output<H, N>>) -> Option<Step> {
+		if !self.is_primary() {
+			return None;
+		}
+
+		if let Some(propose) = self.has_voted.propose() {
+			outputs.push(Output::Recast(Message::PrimaryPropose(propose.clone())));
+			return Some(Step::Proposed);
+		}
+
+		let propose = primary_proposal(last_round_state)?;
+		self.has_voted = HasVoted::Proposed(propose.clone());
+		outputs.push(Output::Propose(propose));
+
+		Some(Step::Proposed)
+	}
+
+	fn should_prevote(&self) -> Option<Step> {
+		let should_prevote = self.prevote_timer_fired ||
+			self.round_state.completable ||
+			self.has_voted.prevote().is_some();
+
+		if should_prevote { Some(Step::Prevoting) } else { None }
+	}
+
+	fn request_best_chain(
+		&mut self,
+		last_round_state: &RoundState<H, N>,
+		outputs: &mut Vec<Output<H, N>>,
+	) -> Option<Step> {
+		if !self.is_voting() {
+			self.best_chain_base = None;
+			return Some(Step::Prevoted);
+		}
+
+		if let Some(prevote) = self.has_voted.prevote() {
+			outputs.push(Output::Recast(Message::Prevote(prevote.clone())));
+			return Some(Step::Prevoted);
+		}
+
+		if self.best_chain_base.is_none() {
+			let base = prevote_base(&*self.chain, last_round_state, self.primary_block.as_ref());
+			self.best_chain_base = Some(base.clone());
+			outputs.push(Output::LookupBestChain(base));
+		}
+
+		None
+	}
+
+	// cast our prevote once the best chain has been looked up.
+	fn prevote(&mut self, target: Option<(H, N)>, now: Instant) -> Vec<Output<H, N>> {
+		if self.step != Some(Step::Prevoting) || self.best_chain_base.take().is_none() {
+			return Vec::new();
+		}
+
+		let mut outputs = Vec::new();
+		if let Some((target_hash, target_number)) = target {
+			let prevote = Prevote { target_hash, target_number };
+			self.has_voted = HasVoted::Prevoted(self.has_voted.propose().cloned(), prevote.clone());
+			self.prevoted_at = Some(now);
+			outputs.push(Output::Prevote(prevote));
+		}
+
+		self.step = Some(Step::Prevoted);
+		outputs.extend(self.advance(now));
+		outputs
+	}
+
+	fn precommit(
+		&mut self,
+		last_round_state: &RoundState<H, N>,
+		now: Instant,
+		outputs: &mut Vec<Output<H, N>>,
+	) -> Option<Step> {
+		let last_round_estimate = last_round_state.estimate.as_ref()
+			.expect("Rounds only started when prior round completable; qed");
+
+		// we wait for the last round's estimate to be equal to or
+		// the ancestor of the current round's p-Ghost before precommitting.
+		// a precommit we already cast is sent again right away.
+		let should_precommit = self.has_voted.precommit().is_some() || (can_precommit(
+			&*self.chain,
+			last_round_estimate,
+			self.round_state.prevote_ghost.as_ref(),
+		) && (self.precommit_timer_fired || self.round_state.completable));
+
+		if !should_precommit {
+			return None;
+		}
+
+		if !self.is_voting() {
+			return Some(Step::Precommitted);
+		}
+
+		if let Some(precommit) = self.has_voted.precommit() {
+			outputs.push(Output::Recast(Message::Precommit(precommit.clone())));
+			return Some(Step::Precommitted);
+		}
+
+		let (target_hash, target_number) = precommit_target(&self.round_state, self.base.clone());
+		let precommit = Precommit { target_hash, target_number };
+		self.has_voted = HasVoted::Precommitted(
+			self.has_voted.propose().cloned(),
+			self.has_voted.prevote().cloned(),
+			precommit.clone(),
+		);
+		self.precommitted_at = Some(now);
+		outputs.push(Output::Precommit(precommit));
+
+		Some(Step::Precommitted)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+
+	fn state(
+		prevote_ghost: Option<(&'static str, u32)>,
+		finalized: Option<(&'static str, u32)>,
+		estimate: Option<(&'static str, u32)>,
+	) -> RoundState<&'static str, u32> {
+		RoundState { prevote_ghost, finalized, estimate, completable: true }
+	}
+
+	#[test]
+	fn primary_proposes_unfinalized_estimate() {
+		assert_eq!(
+			primary_proposal(&state(Some(("C", 4)), Some(("A", 2)), Some(("B", 3)))),
+			Some(PrimaryPropose { target_hash: "B", target_number: 3 }),
+		);
+		assert_eq!(primary_proposal(&state(Some(("C", 4)), Some(("B", 3)), Some(("B", 3)))), None);
+		assert_eq!(primary_proposal(&state(None, None, None)), None);
+	}
+
+	#[test]
+	fn prevote_base_follows_primary_within_ghost() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("B", &["C1", "D1"]);
+
+		let last_round_state = state(Some(("D", 5)), None, Some(("A", 2)));
+
+		// no hint.
+		assert_eq!(prevote_base(&chain, &last_round_state, None), ("A", 2));
+		// hint equal to the prevote-GHOST.
+		assert_eq!(prevote_base(&chain, &last_round_state, Some(&("D", 5))), ("D", 5));
+		// hint in the ancestry of the prevote-GHOST.
+		assert_eq!(prevote_base(&chain, &last_round_state, Some(&("C", 4))), ("C", 4));
+		// hint on another fork.
+		assert_eq!(prevote_base(&chain, &last_round_state, Some(&("C1", 4))), ("A", 2));
+		// hint beyond the prevote-GHOST.
+		assert_eq!(prevote_base(&chain, &last_round_state, Some(&("E", 6))), ("A", 2));
+	}
+
+	#[test]
+	fn precommits_wait_for_ghost_descending_from_estimate() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B1"]);
+
+		assert!(can_precommit(&chain, &("A", 2), Some(&("A", 2))));
+		assert!(can_precommit(&chain, &("A", 2), Some(&("C", 4))));
+		assert!(!can_precommit(&chain, &("B", 3), Some(&("B1", 3))));
+		assert!(!can_precommit(&chain, &("A", 2), None));
+
+		assert_eq!(precommit_target(&state(Some(("C", 4)), None, None), ("A", 2)), ("C", 4));
+		assert_eq!(precommit_target(&state(None, None, None), ("A", 2)), ("A", 2));
+	}
+
+	fn machine(primary: bool, has_voted: HasVoted<&'static str, u32>) -> RoundMachine<&'static str, u32, DummyChain> {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		RoundMachine::new(Arc::new(chain), (GENESIS_HASH, 1), true, primary, has_voted)
+	}
+
+	#[test]
+	fn machine_casts_votes_as_inputs_arrive() {
+		let now = Instant::now();
+		let mut machine = machine(false, HasVoted::No);
+
+		// nothing happens until the prior round is known.
+		assert_eq!(machine.handle(Input::PrevoteTimer, now), vec![]);
+		assert_eq!(machine.step(), Some(Step::Start));
+
+		assert_eq!(
+			machine.handle(Input::LastRoundState(RoundState::genesis((GENESIS_HASH, 1))), now),
+			vec![Output::LookupBestChain((GENESIS_HASH, 1))],
+		);
+		assert_eq!(machine.step(), Some(Step::Prevoting));
+
+		assert_eq!(
+			machine.handle(Input::BestChain(Some(("C", 4))), now),
+			vec![Output::Prevote(Prevote { target_hash: "C", target_number: 4 })],
+		);
+		assert_eq!(machine.step(), Some(Step::Prevoted));
+		assert_eq!(machine.prevoted_at(), Some(now));
+
+		// we wait for the precommit timer unless the round is completable.
+		let round_state = RoundState {
+			prevote_ghost: Some(("B", 3)),
+			finalized: None,
+			estimate: Some(("B", 3)),
+			completable: false,
+		};
+		assert_eq!(machine.handle(Input::RoundState(round_state), now), vec![]);
+		assert_eq!(
+			machine.handle(Input::PrecommitTimer, now),
+			vec![Output::Precommit(Precommit { target_hash: "B", target_number: 3 })],
+		);
+		assert_eq!(machine.step(), Some(Step::Precommitted));
+		assert_eq!(machine.has_voted().precommit(), Some(&Precommit { target_hash: "B", target_number: 3 }));
+	}
+
+	#[test]
+	fn machine_recasts_votes_and_honors_pause() {
+		let now = Instant::now();
+		let last_round_state = state(Some(("B", 3)), Some(("A", 2)), Some(("B", 3)));
+
+		// the primary sends its block hint right away.
+		let mut primary = machine(true, HasVoted::No);
+		assert_eq!(
+			primary.handle(Input::LastRoundState(last_round_state.clone()), now),
+			vec![Output::Propose(PrimaryPropose { target_hash: "B", target_number: 3 })],
+		);
+		assert_eq!(primary.step(), Some(Step::Proposed));
+
+		// after a restart, the prevote we cast before is sent again.
+		let prevote = Prevote { target_hash: "C", target_number: 4 };
+		let mut restarted = machine(false, HasVoted::Prevoted(None, prevote.clone()));
+		assert_eq!(
+			restarted.handle(Input::LastRoundState(last_round_state.clone()), now),
+			vec![Output::Recast(Message::Prevote(prevote.clone()))],
+		);
+		assert_eq!(restarted.step(), Some(Step::Prevoted));
+		assert_eq!(restarted.prevoted_at(), None);
+
+		// votes found in persisted votes fill in those the environment didn't
+		// report.
+		let precommit = Precommit { target_hash: "B", target_number: 3 };
+		let mut resumed = machine(false, HasVoted::Prevoted(None, prevote.clone()));
+		resumed.resume(&HasVoted::Precommitted(None, None, precommit.clone()));
+		assert_eq!(
+			resumed.handle(Input::LastRoundState(last_round_state.clone()), now),
+			vec![Output::Recast(Message::Prevote(prevote)), Output::Recast(Message::Precommit(precommit))],
+		);
+		assert_eq!(resumed.step(), Some(Step::Precommitted));
+
+		// a paused voter passes through the round without voting.
+		let mut paused = machine(true, HasVoted::No);
+		assert_eq!(paused.handle(Input::Paused(true), now), vec![]);
+		assert!(!paused.is_voting());
+		assert_eq!(paused.handle(Input::LastRoundState(last_round_state), now), vec![]);
+		assert_eq!(paused.handle(Input::PrevoteTimer, now), vec![]);
+		assert_eq!(paused.handle(Input::PrecommitTimer, now), vec![]);
+		assert_eq!(paused.step(), Some(Step::Prevoted));
+	}
+}
@@ -15,40 +15,123 @@
 //! Logic for voting and handling messages within a single round.
 
 #[cfg(feature = "std")]
-use futures::try_ready;
 use futures::prelude::*;
-use futures::sync::mpsc::UnboundedSender;
+use futures::ready;
+use futures::stream::FuturesUnordered;
+use futures::task::{Context, Poll};
 #[cfg(feature = "std")]
-use log::{trace, warn, debug};
+use tracing::{trace, warn, debug, debug_span, Span};
 
 use std::hash::Hash;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::round::{Round, State as RoundState};
+use crate::misbehavior::Misbehavior;
+use crate::round::{ImportResult, Round, State as RoundState};
 use crate::{
-	Commit, Message, Prevote, Precommit, PrimaryPropose, SignedMessage,
-	SignedPrecommit, BlockNumberOps, validate_commit, ImportResult,
+	Chain, Commit, Message, SignedMessage,
+	SignedPrecommit, BlockNumberOps, validate_commit, CommitValidationResult,
 	HistoricalVotes,
 };
 use crate::voter_set::VoterSet;
-use super::{Environment, Buffered, FinalizedNotification};
-
-/// The state of a voting round.
-pub(super) enum State<T> {
-	Start(T, T),
-	Proposed(T, T),
-	Prevoted(T),
-	Precommitted,
+use super::{CompletionReport, Environment, ErrorSeverity, Buffered, Events, FinalizedSender, HasVoted, Metrics, VoterEvent, RoundReport, VoteDecision, RoundSnapshot, RoundVotesUpdate};
+use super::round_logic::{Input, Output, RoundMachine, Step};
+
+// re-arming timer for sending our votes again.
+struct Rebroadcast<T> {
+	new_timer: Box<dyn FnMut() -> T + Send>,
+	timer: T,
 }
 
-impl<T> std::fmt::Debug for State<T> {
-	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		match self {
-			State::Start(..) => write!(f, "Start"),
-			State::Proposed(..) => write!(f, "Proposed"),
-			State::Prevoted(_) => write!(f, "Prevoted"),
-			State::Precommitted => write!(f, "Precommitted"),
+impl<T: Future<Output=Result<(), E>> + Unpin, E> Rebroadcast<T> {
+	// whether the timer fired, in which case it is re-armed.
+	fn poll(&mut self, cx: &mut Context) -> Result<bool, E> {
+		if self.timer.poll_unpin(cx)?.is_pending() {
+			return Ok(false);
 		}
+
+		// the new timer is polled the next time we are.
+		self.timer = (self.new_timer)();
+		cx.waker().wake_by_ref();
+
+		Ok(true)
+	}
+}
+
+// the maximum number of incoming messages waiting for chain lookups. Further
+// messages are left in the incoming stream until lookups complete.
+const MAX_PENDING_INCOMING: usize = 256;
+
+type Incoming<H, N, E> = SignedMessage<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>;
+
+// an incoming message waiting for the ancestry of its target from the round
+// base to be looked up. resolves to the message, the base and the ancestry.
+struct PendingMessage<H: Eq, N: BlockNumberOps, E: Environment<H, N>> {
+	incoming: Option<(Incoming<H, N, E>, H)>,
+	lookup: E::Ancestry,
+}
+
+impl<H: Eq, N: BlockNumberOps, E: Environment<H, N>> PendingMessage<H, N, E> {
+	fn is_for(&self, incoming: &Incoming<H, N, E>) -> bool {
+		self.incoming.as_ref().is_some_and(|(pending, _)| pending == incoming)
+	}
+}
+
+// only the lookup is polled, which is `Unpin` itself.
+impl<H: Eq, N: BlockNumberOps, E: Environment<H, N>> Unpin for PendingMessage<H, N, E> {}
+
+impl<H: Eq, N: BlockNumberOps, E: Environment<H, N>> Future for PendingMessage<H, N, E> {
+	type Output = (Incoming<H, N, E>, H, Result<Option<Vec<H>>, E::Error>);
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let this = self.get_mut();
+		let ancestry = ready!(this.lookup.poll_unpin(cx));
+		let (incoming, base) = this.incoming.take().expect("not polled after completion; qed");
+		Poll::Ready((incoming, base, ancestry))
+	}
+}
+
+// the base of our prevote and the lookup of the best chain containing it.
+type BestChainLookup<H, N, E> = ((H, N), <E as Environment<H, N>>::BestChain);
+
+// the block to prevote for, or `None` if the best chain lookup came up empty.
+type PrevoteTarget<H, N> = Option<(H, N)>;
+
+// poll a timer until it fires, after which it is dropped.
+fn poll_timer<T: Future<Output=Result<(), E>> + Unpin, E>(timer: &mut Option<T>, cx: &mut Context) -> Result<bool, E> {
+	let fired = match timer {
+		Some(ref mut timer) => timer.poll_unpin(cx)?.is_ready(),
+		None => false,
+	};
+
+	if fired {
+		*timer = None;
+	}
+
+	Ok(fired)
+}
+
+// a chain which answers the ancestry of a single block from a prior lookup,
+// and defers everything else to the environment.
+struct Prefetched<'a, H, C> {
+	chain: &'a C,
+	base: H,
+	block: H,
+	ancestry: Option<Vec<H>>,
+}
+
+impl<'a, H: Clone + Eq, N: BlockNumberOps, C: Chain<H, N>> Chain<H, N> for Prefetched<'a, H, C> {
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, crate::Error> {
+		if base == self.base && block == self.block {
+			return self.ancestry.clone().ok_or(crate::Error::NotDescendent);
+		}
+
+		self.chain.ancestry(base, block)
+	}
+
+	fn best_chain_containing(&self, base: H) -> Option<(H, N)> {
+		self.chain.best_chain_containing(base)
 	}
 }
 
@@ -58,45 +141,30 @@ pub(super) struct VotingRound<H, N, E: Environment<H, N>> where
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 	env: Arc<E>,
-	voting: Voting,
 	votes: Round<E::Id, H, N, E::Signature>,
 	incoming: E::In,
-	outgoing: Buffered<E::Out>,
-	state: Option<State<E::Timer>>, // state machine driving votes.
+	pending_incoming: FuturesUnordered<PendingMessage<H, N, E>>, // incoming messages waiting for chain lookups.
+	outgoing: Buffered<E::Out, Message<H, N>>,
+	machine: RoundMachine<H, N, E>, // state machine deciding on our votes.
+	outputs: Vec<Output<H, N>>, // outputs of the machine to carry out when next polled.
+	prevote_timer: Option<E::Timer>,
+	precommit_timer: Option<E::Timer>,
 	bridged_round_state: Option<crate::bridge_state::PriorView<H, N>>, // updates to later round
 	last_round_state: Option<crate::bridge_state::LatterView<H, N>>, // updates from prior round
-	primary_block: Option<(H, N)>, // a block posted by primary as a hint.
-	finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
+	best_chain_lookup: Option<BestChainLookup<H, N, E>>, // pending while we're about to prevote.
+	voter_id: Option<E::Id>, // our local id, if any.
+	imported_own: Vec<Message<H, N>>, // our own votes that we've already imported.
+	finalized_sender: FinalizedSender<H, N, E>,
+	events: Events<H, N, E>,
 	best_finalized: Option<Commit<H, N, E::Signature, E::Id>>,
-}
-
-/// Whether we should vote in the current round (i.e. push votes to the sink.)
-enum Voting {
-	/// Voting is disabled for the current round.
-	No,
-	/// Voting is enabled for the current round (prevotes and precommits.)
-	Yes,
-	/// Voting is enabled for the current round and we are the primary proposer
-	/// (we can also push primary propose messages).
-	Primary,
-}
-
-impl Voting {
-	/// Whether the voter should cast round votes (prevotes and precommits.)
-	fn is_active(&self) -> bool {
-		match self {
-			Voting::Yes | Voting::Primary => true,
-			_ => false,
-		}
-	}
-
-	/// Whether the voter is the primary proposer.
-	fn is_primary(&self) -> bool {
-		match self {
-			Voting::Primary => true,
-			_ => false,
-		}
-	}
+	// number of seen votes, our vote indices and the votes we had cast when
+	// votes were last persisted.
+	persisted: (usize, Option<u64>, Option<u64>, HasVoted<H, N>),
+	started_at: Instant,
+	completable_at: Option<Instant>, // when the round first became completable.
+	stall_timer: Option<E::Timer>, // fires when the round is considered stalled.
+	rebroadcast: Option<Rebroadcast<E::Timer>>, // fires when we should send our votes again.
+	span: Span, // carries the round number and our id into everything logged by the round.
 }
 
 impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
@@ -109,90 +177,194 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		voters: VoterSet<E::Id>,
 		base: (H, N),
 		last_round_state: Option<crate::bridge_state::LatterView<H, N>>,
-		finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
+		finalized_sender: FinalizedSender<H, N, E>,
+		events: Events<H, N, E>,
+		gossip_duration_hint: Option<Duration>,
 		env: Arc<E>,
 	) -> VotingRound<H, N, E> {
-		let round_data = env.round_data(round_number);
+		let round_data = env.round_data(round_number, gossip_duration_hint);
+		let has_voted = env.has_voted(round_number);
+		let stall_timer = env.round_stall_timer(round_number);
 		let round_params = crate::round::RoundParams {
 			voters,
 			base,
 			round_number,
 		};
 
-		let votes = Round::new(round_params);
+		let mut votes = Round::new(round_params);
+		votes.set_max_vote_distance(env.max_vote_distance());
+		let span = debug_span!(target: "afg", "round", round = round_number, voter = ?round_data.voter_id);
 
-		let voting = if round_data.voter_id.as_ref() == Some(&votes.primary_voter().0) {
-			Voting::Primary
-		} else if round_data.voter_id
+		// we vote if we're in the voter set, and send a primary block hint if
+		// we're the round's primary.
+		let primary = round_data.voter_id.as_ref() == Some(&votes.voters().primary_voter(round_number).0);
+		let voter = round_data.voter_id
 			.as_ref()
-			.map_or(false, |id| votes.voters().contains_key(id))
-		{
-			Voting::Yes
-		} else {
-			Voting::No
-		};
+			.is_some_and(|id| votes.voters().contains_key(id));
 
 		VotingRound {
+			machine: RoundMachine::new(env.clone(), votes.base(), voter, primary, has_voted),
 			votes,
-			voting,
 			incoming: round_data.incoming,
-			outgoing: Buffered::new(round_data.outgoing),
-			state: Some(
-				State::Start(round_data.prevote_timer, round_data.precommit_timer)
-			),
+			pending_incoming: FuturesUnordered::new(),
+			outgoing: Buffered::with_policy(round_data.outgoing, env.send_policy()),
+			outputs: Vec::new(),
+			prevote_timer: Some(round_data.prevote_timer),
+			precommit_timer: Some(round_data.precommit_timer),
 			bridged_round_state: None,
-			primary_block: None,
+			best_chain_lookup: None,
+			voter_id: round_data.voter_id,
+			imported_own: Vec::new(),
 			best_finalized: None,
 			env,
 			last_round_state,
 			finalized_sender,
+			events,
+			persisted: (0, None, None, HasVoted::No),
+			started_at: Instant::now(),
+			completable_at: None,
+			stall_timer,
+			rebroadcast: round_data.rebroadcast_timer.map(|mut new_timer| Rebroadcast {
+				timer: new_timer(),
+				new_timer,
+			}),
+			span,
 		}
 	}
 
+	/// Restore a voting round from previously persisted votes. All votes are
+	/// re-imported in their original order, and the votes we've cast are sent
+	/// again instead of casting new ones, so that we never vote twice.
+	pub (super) fn restore(
+		snapshot: RoundSnapshot<H, N, E::Signature, E::Id>,
+		voters: VoterSet<E::Id>,
+		last_round_state: Option<crate::bridge_state::LatterView<H, N>>,
+		finalized_sender: FinalizedSender<H, N, E>,
+		events: Events<H, N, E>,
+		env: Arc<E>,
+	) -> Result<VotingRound<H, N, E>, E::Error> {
+		let RoundSnapshot { round_number, base, votes, has_voted } = snapshot;
+		let mut round = VotingRound::new(
+			round_number,
+			voters,
+			base,
+			last_round_state,
+			finalized_sender,
+			events,
+			None,
+			env,
+		);
+
+		let pre_state = round.votes.state();
+		let prevote_idx = votes.prevote_idx();
+		let precommit_idx = votes.precommit_idx();
+		let set_indices = |round: &mut Self, idx: u64| {
+			if prevote_idx == Some(idx) { round.votes.set_prevoted_index(); }
+			if precommit_idx == Some(idx) { round.votes.set_precommited_index(); }
+		};
+
+		// equivocations were already reported before the votes were persisted.
+		for (idx, SignedMessage { message, signature, id }) in votes.seen().iter().cloned().enumerate() {
+			set_indices(&mut round, idx as u64);
+			if round.voter_id.as_ref() == Some(&id) {
+				round.imported_own.push(message.clone());
+			}
+			match message {
+				Message::Prevote(prevote) => {
+					round.votes.import_prevote(&*round.env, prevote, id, signature)?;
+				}
+				Message::Precommit(precommit) => {
+					round.votes.import_precommit(&*round.env, precommit, id, signature)?;
+				}
+				Message::PrimaryPropose(_) => {}
+			}
+		}
+		set_indices(&mut round, votes.seen().len() as u64);
+
+		round.machine.resume(&has_voted);
+		round.persisted = (votes.seen().len(), prevote_idx, precommit_idx, has_voted);
+
+		let post_state = round.votes.state();
+		round.notify(pre_state, post_state);
+
+		Ok(round)
+	}
+
 	/// Create a voting round from a completed `Round`. We will not vote further
 	/// in this round.
 	pub (super) fn completed(
-		votes: Round<E::Id, H, N, E::Signature>,
-		finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
+		mut votes: Round<E::Id, H, N, E::Signature>,
+		finalized_sender: FinalizedSender<H, N, E>,
+		events: Events<H, N, E>,
 		env: Arc<E>,
 	) -> VotingRound<H, N, E> {
+		votes.set_max_vote_distance(env.max_vote_distance());
 
-		let round_data = env.round_data(votes.number());
+		let round_data = env.round_data(votes.number(), None);
+		let span = debug_span!(target: "afg", "round", round = votes.number(), voter = ?round_data.voter_id);
 
 		VotingRound {
+			machine: RoundMachine::completed(env.clone(), votes.base()),
 			votes,
-			voting: Voting::No,
 			incoming: round_data.incoming,
-			outgoing: Buffered::new(round_data.outgoing),
-			state: None,
+			pending_incoming: FuturesUnordered::new(),
+			outgoing: Buffered::with_policy(round_data.outgoing, env.send_policy()),
+			outputs: Vec::new(),
+			prevote_timer: None,
+			precommit_timer: None,
 			bridged_round_state: None,
-			primary_block: None,
+			best_chain_lookup: None,
+			voter_id: round_data.voter_id,
+			imported_own: Vec::new(),
 			env,
 			last_round_state: None,
 			finalized_sender,
+			events,
 			best_finalized: None,
+			persisted: (0, None, None, HasVoted::No),
+			started_at: Instant::now(),
+			completable_at: None,
+			stall_timer: None,
+			rebroadcast: None,
+			span,
 		}
 	}
 
-	/// Poll the round. When the round is completable and messages have been flushed, it will return `Async::Ready` but
+	/// Poll the round. When the round is completable and messages have been flushed, it will return `Poll::Ready` but
 	/// can continue to be polled.
-	pub(super) fn poll(&mut self) -> Poll<(), E::Error> {
-		trace!(target: "afg", "Polling round {}, state = {:?}, step = {:?}", self.votes.number(), self.votes.state(), self.state);
+	pub(super) fn poll(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		let span = self.span.clone();
+		let _enter = span.enter();
+
+		trace!(target: "afg", "Polling round {}, state = {:?}, step = {:?}", self.votes.number(), self.votes.state(), self.machine.step());
 		let pre_state = self.votes.state();
-		self.process_incoming()?;
+		let pre_step = self.machine.step();
+		self.process_incoming(cx)?;
 
 		// we only cast votes when we have access to the previous round state.
 		// we might have started this round as a prospect "future" round to
 		// check whether the voter is lagging behind the current round.
-		let last_round_state = self.last_round_state.as_ref().map(|s| s.get().clone());
+		let last_round_state = self.last_round_state.as_ref().map(|s| s.get(cx.waker()).clone());
 		if let Some(ref last_round_state) = last_round_state {
-			self.primary_propose(last_round_state)?;
-			self.prevote(last_round_state)?;
-			self.precommit(last_round_state)?;
+			self.drive(cx, last_round_state.clone())?;
+		}
+
+		let post_step = self.machine.step();
+		if pre_step != post_step {
+			debug!(target: "afg", from = ?pre_step, to = ?post_step, "Round step changed");
+		}
+		self.persist_votes()?;
+		if self.completable_at.is_none() && self.votes.completable() {
+			self.completable_at = Some(Instant::now());
+			let (round, state) = (self.votes.number(), self.votes.state());
+			self.events.emit(|| VoterEvent::RoundCompletable(round, state));
 		}
+		self.check_stalled(cx)?;
+		self.rebroadcast(cx)?;
 
-		try_ready!(self.outgoing.poll());
-		self.process_incoming()?; // in case we got a new message signed locally.
+		ready!(self.outgoing.poll(cx))?;
+		self.process_incoming(cx)?; // in case we got a new message signed locally.
+		self.persist_votes()?;
 
 		// broadcast finality notifications after attempting to cast votes
 		let post_state = self.votes.state();
@@ -200,7 +372,7 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 
 		// early exit if the current round is not completable
 		if !self.votes.completable() {
-			return Ok(Async::NotReady);
+			return Poll::Pending;
 		}
 
 		// make sure that the previous round estimate has been finalized
@@ -232,16 +404,16 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 
 		// the previous round estimate must be finalized
 		if !last_round_estimate_finalized {
-			return Ok(Async::NotReady);
+			return Poll::Pending;
 		}
 
 		// both exit conditions verified, we can complete this round
-		Ok(Async::Ready(()))
+		Poll::Ready(Ok(()))
 	}
 
-	/// Inspect the state of this round.
-	pub(super) fn state(&self) -> Option<&State<E::Timer>> {
-		self.state.as_ref()
+	/// Inspect the step we're at in this round, if we take part in it.
+	pub(super) fn step(&self) -> Option<Step> {
+		self.machine.step()
 	}
 
 	/// Get the round number.
@@ -259,6 +431,106 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.votes.base()
 	}
 
+	/// Get the approximate memory used by this round, in bytes.
+	pub(super) fn approximate_size(&self) -> usize {
+		self.votes.approximate_size() +
+			self.outgoing.len() * std::mem::size_of::<Message<H, N>>()
+	}
+
+	/// Get a snapshot of the state of this round.
+	pub(super) fn report(&self) -> RoundReport<H, N> {
+		RoundReport {
+			number: self.votes.number(),
+			total_weight: self.votes.voters().total_weight(),
+			threshold_weight: self.votes.threshold(),
+			prevote_participation: self.votes.prevote_participation(),
+			precommit_participation: self.votes.precommit_participation(),
+			state: self.votes.state(),
+		}
+	}
+
+	/// Get the votes of this round, to be resumed from later.
+	pub(super) fn snapshot(&self) -> RoundSnapshot<H, N, E::Signature, E::Id> {
+		RoundSnapshot {
+			round_number: self.votes.number(),
+			base: self.votes.base(),
+			votes: self.votes.historical_votes().clone(),
+			has_voted: self.machine.has_voted().clone(),
+		}
+	}
+
+	/// Get the latest state of the prior round, if this round builds on it.
+	pub(super) fn last_round_state(&self) -> Option<RoundState<H, N>> {
+		self.last_round_state.as_ref().map(|state| state.read().clone())
+	}
+
+	/// Send any votes which are still buffered. Ready when all of them were
+	/// flushed to the outgoing sink.
+	pub(super) fn flush(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		self.outgoing.poll(cx)
+	}
+
+	/// Get the metrics provided by the environment, if any.
+	pub(super) fn metrics(&self) -> Option<&dyn Metrics> {
+		self.env.metrics()
+	}
+
+	/// Get the number of messages waiting to be sent.
+	pub(super) fn buffered(&self) -> usize {
+		self.outgoing.len()
+	}
+
+	/// Get the time this round was started at.
+	pub(super) fn started_at(&self) -> Instant {
+		self.started_at
+	}
+
+	/// Get the time we last cast a vote in this round, if any.
+	pub(super) fn last_vote_at(&self) -> Option<Instant> {
+		self.machine.precommitted_at().or_else(|| self.machine.prevoted_at())
+	}
+
+	/// Get the time it took from our precommit until the round became
+	/// completable, i.e. for the other precommits to reach us. `None` if we
+	/// didn't precommit or the round was completable before we did.
+	pub(super) fn gossip_latency(&self) -> Option<Duration> {
+		match (self.machine.precommitted_at(), self.completable_at) {
+			(Some(precommitted_at), Some(completable_at))
+				if completable_at >= precommitted_at => Some(completable_at - precommitted_at),
+			_ => None,
+		}
+	}
+
+	/// Report how this round went, for `Environment::completed_with_report`.
+	pub(super) fn completion_report(&self) -> CompletionReport<H, N> {
+		let since_start = |at: Option<Instant>| at.map(|at| at.duration_since(self.started_at));
+		let seen = self.votes.historical_votes().seen();
+		let count = |f: fn(&Message<H, N>) -> bool| seen.iter().filter(|signed| f(&signed.message)).count();
+
+		CompletionReport {
+			round_number: self.votes.number(),
+			state: self.votes.state(),
+			base: self.votes.base(),
+			prevoted_after: since_start(self.machine.prevoted_at()),
+			precommitted_after: since_start(self.machine.precommitted_at()),
+			completable_after: since_start(self.completable_at),
+			duration: self.started_at.elapsed(),
+			prevotes: count(|message| matches!(message, Message::Prevote(_))),
+			precommits: count(|message| matches!(message, Message::Precommit(_))),
+		}
+	}
+
+	/// Whether we cast votes in this round.
+	pub(super) fn is_voting(&self) -> bool {
+		self.machine.is_voting()
+	}
+
+	/// Stop or resume casting votes in this round. Incoming votes are still
+	/// imported and the round progresses as if we weren't a voter.
+	pub(super) fn set_paused(&mut self, paused: bool) {
+		self.handle(Input::Paused(paused));
+	}
+
 	/// Get the voters in this round.
 	pub(super) fn voters(&self) -> &VoterSet<E::Id> {
 		self.votes.voters()
@@ -270,40 +542,56 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 	}
 
 	/// Check a commit. If it's valid, import all the votes into the round as well.
-	/// Returns the finalized base if it checks out.
+	/// Returns the result of validating the commit, whose ghost is the finalized
+	/// base if it checks out.
 	pub(super) fn check_and_import_from_commit(
 		&mut self,
 		commit: &Commit<H, N, E::Signature, E::Id>
-	) -> Result<Option<(H, N)>, E::Error> {
-		let base = validate_commit(commit, self.voters(), &*self.env)?.ghost;
-		if base.is_none() { return Ok(None) }
+	) -> Result<CommitValidationResult<H, N>, E::Error> {
+		let validation_result = validate_commit(commit, self.voters(), &*self.env)?;
+		if validation_result.ghost().is_none() { return Ok(validation_result) }
 
+		let round = self.round_number();
 		for SignedPrecommit { precommit, signature, id } in commit.precommits.iter().cloned() {
 			let import_result = self.votes.import_precommit(&*self.env, precommit, id, signature)?;
-			if let ImportResult { equivocation: Some(e), .. } = import_result {
-				self.env.precommit_equivocation(self.round_number(), e);
+			self.note_import(&import_result);
+			if let ImportResult::Equivocation(e) = import_result {
+				self.events.emit(|| VoterEvent::PrecommitEquivocation(round, e.clone()));
+				self.env.precommit_equivocation(round, e);
 			}
 		}
 
-		Ok(base)
+		let target = (commit.target_hash.clone(), commit.target_number);
+		self.events.emit(|| VoterEvent::CommitImported(round, target));
+
+		Ok(validation_result)
+	}
+
+	/// Whether the environment considers the given error transient.
+	pub(super) fn is_transient(&self, error: &E::Error) -> bool {
+		self.env.error_severity(error) == ErrorSeverity::Transient
 	}
 
 	/// Get a clone of the finalized sender.
-	pub(super) fn finalized_sender(&self) -> UnboundedSender<FinalizedNotification<H, N, E>> {
+	pub(super) fn finalized_sender(&self) -> FinalizedSender<H, N, E> {
 		self.finalized_sender.clone()
 	}
 
 	// call this when we build on top of a given round in order to get a handle
-	// to updates to the latest round-state.
+	// to updates to the latest round-state. may be called any number of times,
+	// all handles receive the same updates.
 	pub(super) fn bridge_state(&mut self) -> crate::bridge_state::LatterView<H, N> {
-		let (prior_view, latter_view) = crate::bridge_state::bridge_state(self.votes.state());
-		if self.bridged_round_state.is_some() {
-			warn!(target: "afg", "Bridged state from round {} more than once.",
-				self.votes.number());
+		match self.bridged_round_state {
+			Some(ref prior_view) => {
+				prior_view.update(self.votes.state());
+				prior_view.latter_view()
+			}
+			None => {
+				let (prior_view, latter_view) = crate::bridge_state::bridge_state(self.votes.state());
+				self.bridged_round_state = Some(prior_view);
+				latter_view
+			}
 		}
-
-		self.bridged_round_state = Some(prior_view);
-		latter_view
 	}
 
 	/// Get a commit justifying the best finalized block.
@@ -311,6 +599,15 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.best_finalized.as_ref()
 	}
 
+	/// Assemble a commit for the block finalized in this round from the
+	/// precommits imported so far, even if we didn't precommit ourselves.
+	pub(super) fn assemble_commit(&mut self) -> Option<Commit<H, N, E::Signature, E::Id>> {
+		let (target_hash, target_number) = self.votes.finalized()?.clone();
+		let precommits = self.votes.finalizing_precommits(&*self.env)?.collect();
+
+		Some(Commit { target_hash, target_number, precommits })
+	}
+
 	/// Return all votes for the round (prevotes and precommits), sorted by
 	/// imported order and indicating the indices where we voted. At most two
 	/// prevotes and two precommits per voter are present, further equivocations
@@ -319,244 +616,474 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.votes.historical_votes()
 	}
 
-	fn process_incoming(&mut self) -> Result<(), E::Error> {
-		while let Async::Ready(Some(incoming)) = self.incoming.poll()? {
+	fn process_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		// messages are imported as soon as the ancestry of their target has
+		// been looked up, which needn't be in the order they were received.
+		loop {
+			self.queue_incoming(cx)?;
+			let full = self.pending_incoming.len() >= MAX_PENDING_INCOMING;
+
+			let mut imported = false;
+			while let Poll::Ready(Some((incoming, base, ancestry))) = self.pending_incoming.poll_next_unpin(cx) {
+				self.import_incoming(incoming, base, ancestry?)?;
+				imported = true;
+			}
+
+			// the incoming stream is only waiting on us if the queue was full.
+			if !(full && imported) {
+				return Ok(());
+			}
+		}
+	}
+
+	// start looking up the ancestry of incoming messages, until the queue of
+	// pending lookups is full.
+	fn queue_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		while self.pending_incoming.len() < MAX_PENDING_INCOMING {
+			let incoming = match self.incoming.poll_next_unpin(cx) {
+				Poll::Ready(Some(incoming)) => incoming,
+				_ => break,
+			};
+
 			trace!(target: "afg", "Got incoming message");
-			let SignedMessage { message, signature, id } = incoming;
-			if !self.env.is_equal_or_descendent_of(self.votes.base().0, message.target().0.clone()) {
-				trace!(target: "afg", "Ignoring message targeting {:?} lower than round base {:?}",
-					   message.target(),
-					   self.votes.base(),
+			let incoming = incoming?;
+			if self.votes.check_vote_distance(incoming.message.target().1).is_err() {
+				debug!(target: "afg", "Ignoring message targeting {:?}, too far ahead of round base {:?}",
+					incoming.message.target(),
+					self.votes.base(),
 				);
+				if let Some(metrics) = self.env.metrics() {
+					metrics.message_dropped();
+				}
+				self.env.vote_rejected(self.votes.number(), &incoming, Misbehavior::DistantVote);
 				continue;
 			}
 
-			match message {
-				Message::Prevote(prevote) => {
-					let import_result = self.votes.import_prevote(&*self.env, prevote, id, signature)?;
-					if let ImportResult { equivocation: Some(e), .. } = import_result {
-						self.env.prevote_equivocation(self.votes.number(), e);
-					}
-				}
-				Message::Precommit(precommit) => {
-					let import_result = self.votes.import_precommit(&*self.env, precommit, id, signature)?;
-					if let ImportResult { equivocation: Some(e), .. } = import_result {
-						self.env.precommit_equivocation(self.votes.number(), e);
-					}
+			// redelivered votes needn't be looked up again, whether they were
+			// imported already or are still waiting for their lookup.
+			let pending = self.pending_incoming.iter().any(|pending| pending.is_for(&incoming));
+			if pending || self.votes.has_vote(&incoming) {
+				trace!(target: "afg", "Ignoring duplicate of {:?} in round {}", incoming.message, self.votes.number());
+				if let Some(metrics) = self.env.metrics() {
+					metrics.message_dropped();
 				}
-				Message::PrimaryPropose(primary) => {
-					let primary_id = self.votes.primary_voter().0.clone();
-					if id == primary_id {
-						self.primary_block = Some((primary.target_hash, primary.target_number));
-					}
+				continue;
+			}
+
+			let base = self.votes.base().0;
+			let lookup = self.env.ancestry_lookup(base.clone(), incoming.message.target().0.clone());
+			self.pending_incoming.push(PendingMessage { incoming: Some((incoming, base)), lookup });
+		}
+
+		Ok(())
+	}
+
+	// import an incoming message, given the ancestry of its target.
+	fn import_incoming(
+		&mut self,
+		incoming: SignedMessage<H, N, E::Signature, E::Id>,
+		base: H,
+		ancestry: Option<Vec<H>>,
+	) -> Result<(), E::Error> {
+		let SignedMessage { message, signature, id } = incoming.clone();
+		let env = self.env.clone();
+		let chain = Prefetched {
+			chain: &*env,
+			base: base.clone(),
+			block: message.target().0.clone(),
+			ancestry,
+		};
+
+		// votes below the base are left for the round to reject.
+		let below_base = message.target().1 < self.votes.base().1;
+		if !below_base && !chain.is_equal_or_descendent_of(base, message.target().0.clone()) {
+			trace!(target: "afg", "Ignoring message targeting {:?} lower than round base {:?}",
+				   message.target(),
+				   self.votes.base(),
+			);
+			if let Some(metrics) = self.env.metrics() {
+				metrics.message_dropped();
+			}
+			return Ok(());
+		}
+
+		if self.voter_id.as_ref() == Some(&id) {
+			if self.imported_own.contains(&message) {
+				trace!(target: "afg", "Ignoring echo of our own vote in round {}", self.votes.number());
+				if let Some(metrics) = self.env.metrics() {
+					metrics.message_dropped();
 				}
+				return Ok(());
+			}
+
+			let has_voted = self.machine.has_voted();
+			let was_cast = match message {
+				Message::Prevote(ref prevote) => has_voted.prevote() == Some(prevote),
+				Message::Precommit(ref precommit) => has_voted.precommit() == Some(precommit),
+				Message::PrimaryPropose(ref primary) => has_voted.propose() == Some(primary),
 			};
+
+			if was_cast {
+				self.imported_own.push(message.clone());
+			} else {
+				warn!(target: "afg", "Received a vote signed by us which we haven't cast in round {}: {:?}",
+					self.votes.number(),
+					message,
+				);
+				self.env.unexpected_own_vote(self.votes.number(), message.clone());
+			}
 		}
 
+		match message {
+			Message::Prevote(prevote) => {
+				let import_result = self.votes.import_prevote(&chain, prevote, id, signature)?;
+				self.note_import(&import_result);
+				self.note_rejected(&incoming, &import_result);
+				if let ImportResult::Equivocation(e) = import_result {
+					let round = self.votes.number();
+					self.events.emit(|| VoterEvent::PrevoteEquivocation(round, e.clone()));
+					self.env.prevote_equivocation(round, e);
+				}
+			}
+			Message::Precommit(precommit) => {
+				let import_result = self.votes.import_precommit(&chain, precommit, id, signature)?;
+				self.note_import(&import_result);
+				self.note_rejected(&incoming, &import_result);
+				if let ImportResult::Equivocation(e) = import_result {
+					let round = self.votes.number();
+					self.events.emit(|| VoterEvent::PrecommitEquivocation(round, e.clone()));
+					self.env.precommit_equivocation(round, e);
+				}
+			}
+			Message::PrimaryPropose(primary) => {
+				let primary_id = self.votes.primary_voter().0.clone();
+				if id == primary_id {
+					self.handle(Input::PrimaryBlock((primary.target_hash, primary.target_number)));
+				}
+			}
+		};
+
 		Ok(())
 	}
 
-	fn primary_propose(&mut self, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
-		match self.state.take() {
-			Some(State::Start(prevote_timer, precommit_timer)) => {
-				let maybe_estimate = last_round_state.estimate.clone();
-
-				match (maybe_estimate, self.voting.is_primary()) {
-					(Some(last_round_estimate), true) => {
-						let maybe_finalized = last_round_state.finalized.clone();
-
-						// Last round estimate has not been finalized.
-						let should_send_primary = maybe_finalized.map_or(true, |f| last_round_estimate.1 > f.1);
-						if should_send_primary {
-							debug!(target: "afg", "Sending primary block hint for round {}", self.votes.number());
-							let primary = PrimaryPropose {
-								target_hash: last_round_estimate.0,
-								target_number: last_round_estimate.1,
-							};
-							self.env.proposed(self.round_number(), primary.clone())?;
-							self.outgoing.push(Message::PrimaryPropose(primary));
-							self.state = Some(State::Proposed(prevote_timer, precommit_timer));
-
-							return Ok(());
-						} else {
-							debug!(target: "afg", "Last round estimate has been finalized, \
-								not sending primary block hint for round {}", self.votes.number());
-						}
-					},
-					(None, true) => {
-						debug!(target: "afg", "Last round estimate does not exist, \
-							not sending primary block hint for round {}", self.votes.number());
-					},
-					_ => {},
-				}
+	// push a message we cast into the outgoing sink, unless the environment
+	// vetoes it. returns whether it was pushed.
+	fn broadcast(&mut self, message: Message<H, N>) -> bool {
+		if self.env.pre_broadcast(self.votes.number(), &message) == VoteDecision::Veto {
+			debug!(target: "afg", "Broadcast of {:?} in round {} vetoed", message, self.votes.number());
+			return false;
+		}
 
-				self.state = Some(State::Start(prevote_timer, precommit_timer));
-			},
-			x => { self.state = x; }
+		self.outgoing.push(message);
+		true
+	}
+
+	// send a message we cast, importing it right away if the environment
+	// signs it for us.
+	fn cast(&mut self, message: Message<H, N>) -> Result<(), E::Error> {
+		if !self.broadcast(message.clone()) {
+			return Ok(());
 		}
 
+		let id = match self.voter_id {
+			Some(ref id) => id.clone(),
+			None => return Ok(()),
+		};
+
+		let signature = match self.env.sign_message(self.votes.number(), &id, &message) {
+			Some(signature) => signature,
+			None => return Ok(()),
+		};
+
+		let base = self.votes.base().0;
+		let ancestry = self.env.ancestry(base.clone(), message.target().0.clone()).ok();
+		self.import_incoming(SignedMessage { message, signature, id }, base, ancestry)
+	}
+
+	// record the result of importing a vote with the environment's metrics.
+	fn note_import<V>(&self, result: &ImportResult<E::Id, V, E::Signature>) {
+		let metrics = match self.env.metrics() {
+			Some(metrics) => metrics,
+			None => return,
+		};
+
+		if result.is_new() {
+			metrics.message_imported();
+		} else {
+			metrics.message_dropped();
+		}
+
+		if let ImportResult::Equivocation(_) = *result {
+			metrics.equivocation();
+		}
+	}
+
+	// hand votes which a peer shouldn't have sent to the environment, so that
+	// it can be penalized. duplicates are left alone, as gossip may deliver
+	// votes more than once.
+	fn note_rejected<V>(
+		&self,
+		vote: &SignedMessage<H, N, E::Signature, E::Id>,
+		result: &ImportResult<E::Id, V, E::Signature>,
+	) {
+		let misbehavior = match *result {
+			ImportResult::Excess => Misbehavior::VoteSpam,
+			ImportResult::NotVoter => Misbehavior::UnknownVoter,
+			ImportResult::BelowBase => Misbehavior::VoteBelowBase,
+			ImportResult::BadNumber => Misbehavior::InvalidBlockNumber,
+			ImportResult::Imported | ImportResult::Equivocation(_) | ImportResult::Duplicate => return,
+		};
+
+		debug!(target: "afg", "Rejecting {:?} from {:?} in round {}: {:?}",
+			vote.message,
+			vote.id,
+			self.votes.number(),
+			misbehavior,
+		);
+		self.env.vote_rejected(self.votes.number(), vote, misbehavior);
+	}
+
+	// hand the votes which changed since the last time to the environment
+	// for persistence, if any.
+	fn persist_votes(&mut self) -> Result<(), E::Error> {
+		let votes = self.votes.historical_votes();
+		let has_voted = self.machine.has_voted();
+		let (persisted, prevote_idx, precommit_idx, ref persisted_voted) = self.persisted;
+		let unchanged = votes.seen().len() == persisted &&
+			votes.prevote_idx() == prevote_idx &&
+			votes.precommit_idx() == precommit_idx &&
+			has_voted == persisted_voted;
+
+		if unchanged { return Ok(()) }
+
+		self.env.persist_round_votes(RoundVotesUpdate {
+			round_number: self.votes.number(),
+			base: self.votes.base(),
+			first_vote: persisted,
+			new_votes: &votes.seen()[persisted..],
+			prevote_idx: votes.prevote_idx(),
+			precommit_idx: votes.precommit_idx(),
+			has_voted,
+		})?;
+
+		self.persisted = (votes.seen().len(), votes.prevote_idx(), votes.precommit_idx(), has_voted.clone());
+
 		Ok(())
 	}
 
-	fn prevote(&mut self, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
-		let state = self.state.take();
+	// send our votes again whenever the rebroadcast timer fires, until the
+	// round is completable.
+	fn rebroadcast(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		if self.votes.completable() {
+			self.rebroadcast = None;
+			return Ok(());
+		}
 
-		let mut handle_prevote = |mut prevote_timer: E::Timer, precommit_timer: E::Timer, proposed| {
-			let should_prevote = match prevote_timer.poll() {
-				Err(e) => return Err(e),
-				Ok(Async::Ready(())) => true,
-				Ok(Async::NotReady) => self.votes.completable(),
-			};
+		let fired = match self.rebroadcast {
+			Some(ref mut rebroadcast) => rebroadcast.poll(cx)?,
+			None => false,
+		};
 
-			if should_prevote {
-				if self.voting.is_active() {
-					if let Some(prevote) = self.construct_prevote(last_round_state)? {
-						debug!(target: "afg", "Casting prevote for round {}", self.votes.number());
-						self.env.prevoted(self.round_number(), prevote.clone())?;
-						self.votes.set_prevoted_index();
-						self.outgoing.push(Message::Prevote(prevote));
-					}
-				}
-				self.state = Some(State::Prevoted(precommit_timer));
-			} else if proposed {
-				self.state = Some(State::Proposed(prevote_timer, precommit_timer));
-			} else {
-				self.state = Some(State::Start(prevote_timer, precommit_timer));
-			}
+		if !fired || !self.is_voting() {
+			return Ok(());
+		}
+
+		if let Some(prevote) = self.machine.has_voted().prevote() {
+			debug!(target: "afg", "Re-sending prevote for round {}", self.votes.number());
+			let prevote = prevote.clone();
+			self.broadcast(Message::Prevote(prevote));
+		}
+
+		if let Some(precommit) = self.machine.has_voted().precommit() {
+			debug!(target: "afg", "Re-sending precommit for round {}", self.votes.number());
+			let precommit = precommit.clone();
+			self.broadcast(Message::Precommit(precommit));
+		}
 
-			Ok(())
+		Ok(())
+	}
+
+	// notify the environment once if the round isn't completable by the time
+	// the stall timer fires.
+	fn check_stalled(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		if self.votes.completable() {
+			self.stall_timer = None;
+			return Ok(());
+		}
+
+		let fired = match self.stall_timer {
+			Some(ref mut timer) => timer.poll_unpin(cx)?.is_ready(),
+			None => false,
 		};
 
-		match state {
-			Some(State::Start(prevote_timer, precommit_timer)) => {
-				handle_prevote(prevote_timer, precommit_timer, false)?;
-			},
-			Some(State::Proposed(prevote_timer, precommit_timer)) => {
-				handle_prevote(prevote_timer, precommit_timer, true)?;
-			},
-			x => { self.state = x; }
+		if fired {
+			self.stall_timer = None;
+
+			let (prevote_shortfall, precommit_shortfall) = self.votes.threshold_shortfall();
+			let missing_weight = std::cmp::max(prevote_shortfall, precommit_shortfall);
+			warn!(target: "afg", "Round {} stalled, missing weight {}",
+				self.votes.number(),
+				missing_weight,
+			);
+
+			self.env.round_stalled(self.votes.number(), self.votes.state(), missing_weight);
 		}
 
 		Ok(())
 	}
 
-	fn precommit(&mut self, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
-		match self.state.take() {
-			Some(State::Prevoted(mut precommit_timer)) => {
-				let last_round_estimate = last_round_state.estimate.clone()
-					.expect("Rounds only started when prior round completable; qed");
-
-				let should_precommit = {
-					// we wait for the last round's estimate to be equal to or
-					// the ancestor of the current round's p-Ghost before precommitting.
-					self.votes.state().prevote_ghost.as_ref().map_or(false, |p_g| {
-						p_g == &last_round_estimate ||
-							self.env.is_equal_or_descendent_of(last_round_estimate.0, p_g.0.clone())
-					})
-				} && match precommit_timer.poll() {
-					Err(e) => return Err(e),
-					Ok(Async::Ready(())) => true,
-					Ok(Async::NotReady) => self.votes.completable(),
-				};
+	// hand an input to the state machine. what it asks for is carried out
+	// when the round is next polled.
+	fn handle(&mut self, input: Input<H, N>) {
+		let outputs = self.machine.handle(input, Instant::now());
+		self.outputs.extend(outputs);
+	}
 
-				if should_precommit {
-					if self.voting.is_active() {
-						debug!(target: "afg", "Casting precommit for round {}", self.votes.number());
-						let precommit = self.construct_precommit();
-						self.env.precommitted(self.round_number(), precommit.clone())?;
-						self.votes.set_precommited_index();
-						self.outgoing.push(Message::Precommit(precommit));
-					}
-					self.state = Some(State::Precommitted);
-				} else {
-					self.state = Some(State::Prevoted(precommit_timer));
+	// hand the state of this and the prior round, fired timers and the best
+	// chain to prevote on to the state machine, and carry out what it asks
+	// for until it has to wait for something else.
+	fn drive(&mut self, cx: &mut Context, last_round_state: RoundState<H, N>) -> Result<(), E::Error> {
+		self.handle(Input::LastRoundState(last_round_state));
+		self.handle(Input::RoundState(self.votes.state()));
+
+		if poll_timer(&mut self.prevote_timer, cx)? {
+			self.handle(Input::PrevoteTimer);
+		}
+		if poll_timer(&mut self.precommit_timer, cx)? {
+			self.handle(Input::PrecommitTimer);
+		}
+
+		loop {
+			if self.outputs.is_empty() {
+				match self.poll_best_chain(cx) {
+					Poll::Ready(target) => self.handle(Input::BestChain(target?)),
+					Poll::Pending => break,
 				}
+				continue;
+			}
+
+			for output in std::mem::take(&mut self.outputs) {
+				self.carry_out(output)?;
 			}
-			x => { self.state = x; }
+
+			// the votes we've cast may have been imported.
+			self.handle(Input::RoundState(self.votes.state()));
+		}
+
+		if !self.machine.is_prevoting() {
+			self.best_chain_lookup = None;
 		}
 
 		Ok(())
 	}
 
-	// construct a prevote message based on local state.
-	fn construct_prevote(&self, last_round_state: &RoundState<H, N>) -> Result<Option<Prevote<H, N>>, E::Error> {
-		let last_round_estimate = last_round_state.estimate.clone()
-			.expect("Rounds only started when prior round completable; qed");
-
-		let find_descendent_of = match self.primary_block {
-			None => {
-				// vote for best chain containing prior round-estimate.
-				last_round_estimate.0
+	// carry out what the state machine asked for.
+	fn carry_out(&mut self, output: Output<H, N>) -> Result<(), E::Error> {
+		let round = self.round_number();
+		match output {
+			Output::Propose(primary) => {
+				debug!(target: "afg", "Sending primary block hint for round {}", round);
+				self.env.proposed(round, primary.clone())?;
+				self.cast(Message::PrimaryPropose(primary))
+			}
+			Output::Prevote(prevote) => {
+				debug!(target: "afg", "Casting prevote for round {}", round);
+				self.env.prevoted(round, prevote.clone())?;
+				self.events.emit(|| VoterEvent::PrevoteCast(round, prevote.clone()));
+				self.votes.set_prevoted_index();
+				self.cast(Message::Prevote(prevote))
 			}
-			Some(ref primary_block) => {
-				// we will vote for the best chain containing `p_hash` iff
-				// the last round's prevote-GHOST included that block and
-				// that block is a strict descendent of the last round-estimate that we are
-				// aware of.
-				let last_prevote_g = last_round_state.prevote_ghost.clone()
-					.expect("Rounds only started when prior round completable; qed");
-
-				// if the blocks are equal, we don't check ancestry.
-				if primary_block == &last_prevote_g {
-					primary_block.0.clone()
-				} else if primary_block.1 >= last_prevote_g.1 {
-					last_round_estimate.0
-				} else {
-					// from this point onwards, the number of the primary-broadcasted
-					// block is less than the last prevote-GHOST's number.
-					// if the primary block is in the ancestry of p-G we vote for the
-					// best chain containing it.
-					let &(ref p_hash, p_num) = primary_block;
-					match self.env.ancestry(last_round_estimate.0.clone(), last_prevote_g.0) {
-						Ok(ancestry) => {
-							let to_sub = p_num + N::one();
-
-							let offset: usize = if last_prevote_g.1 < to_sub {
-								0
-							} else {
-								(last_prevote_g.1 - to_sub).as_()
-							};
-
-							if ancestry.get(offset).map_or(false, |b| b == p_hash) {
-								p_hash.clone()
-							} else {
-								last_round_estimate.0
-							}
+			Output::Precommit(precommit) => {
+				debug!(target: "afg", "Casting precommit for round {}", round);
+				self.env.precommitted(round, precommit.clone())?;
+				self.events.emit(|| VoterEvent::PrecommitCast(round, precommit.clone()));
+				self.votes.set_precommited_index();
+				self.cast(Message::Precommit(precommit))
+			}
+			Output::Recast(message) => {
+				match message {
+					Message::PrimaryPropose(_) => {
+						debug!(target: "afg", "Re-sending primary block hint for round {}", round);
+					}
+					Message::Prevote(_) => {
+						debug!(target: "afg", "Re-casting prevote for round {}", round);
+						// a restored round keeps the index persisted with the vote.
+						if self.votes.prevoted_index().is_none() {
+							self.votes.set_prevoted_index();
+						}
+					}
+					Message::Precommit(_) => {
+						debug!(target: "afg", "Re-casting precommit for round {}", round);
+						if self.votes.precommited_index().is_none() {
+							self.votes.set_precommited_index();
 						}
-						Err(crate::Error::NotDescendent) => last_round_estimate.0,
 					}
 				}
+				self.cast(message)
 			}
-		};
-
-		let best_chain = self.env.best_chain_containing(find_descendent_of.clone());
-		debug_assert!(best_chain.is_some(), "Previously known block {:?} has disappeared from chain", find_descendent_of);
+			Output::LookupBestChain(base) => {
+				let lookup = self.env.best_chain_lookup(base.0.clone());
+				self.best_chain_lookup = Some((base, lookup));
+				Ok(())
+			}
+		}
+	}
 
-		let t = if let Some(target) = best_chain {
-			target
-		} else {
-			// If this block is considered unknown, something has gone wrong.
-			// log and handle, but skip casting a vote.
-			warn!(target: "afg", "Could not cast prevote: previously known block {:?} has disappeared", find_descendent_of);
-			return Ok(None);
+	// the block to prevote for, once the lookup of the best chain containing
+	// the base of our prevote completes.
+	fn poll_best_chain(&mut self, cx: &mut Context) -> Poll<Result<PrevoteTarget<H, N>, E::Error>> {
+		let best_chain = match self.best_chain_lookup {
+			Some((_, ref mut lookup)) => ready!(lookup.poll_unpin(cx)),
+			None => return Poll::Pending,
 		};
+		let (find_descendent_of, _) = self.best_chain_lookup.take().expect("polled above; qed");
+		let best_chain = best_chain?;
+		debug_assert!(best_chain.is_some(), "Previously known block {:?} has disappeared from chain", find_descendent_of.0);
 
-		Ok(Some(Prevote {
-			target_hash: t.0,
-			target_number: t.1,
-		}))
+		match best_chain {
+			Some(target) => Poll::Ready(Ok(Some(self.restrict_vote(find_descendent_of, target)))),
+			None => {
+				// If this block is considered unknown, something has gone wrong.
+				// log and handle, but skip casting a vote.
+				warn!(target: "afg", "Could not cast prevote: previously known block {:?} has disappeared", find_descendent_of.0);
+				Poll::Ready(Ok(None))
+			}
+		}
 	}
 
-	// construct a precommit message based on local state.
-	fn construct_precommit(&self) -> Precommit<H, N> {
-		let t = match self.votes.state().prevote_ghost {
-			Some(target) => target,
-			None => self.votes.base(),
+	// restrict the target of a prevote building on `base` according to the
+	// environment's voting rule.
+	fn restrict_vote(&self, base: (H, N), target: (H, N)) -> (H, N) {
+		let rule = match self.env.voting_rule() {
+			Some(rule) => rule,
+			None => return target,
+		};
+
+		let restricted = match rule.restrict_vote(&*self.env, base.clone(), target.clone()) {
+			Some(restricted) => restricted,
+			None => return target,
 		};
 
-		Precommit {
-			target_hash: t.0,
-			target_number: t.1,
+		let valid = base.1 <= restricted.1 && restricted.1 <= target.1 &&
+			self.env.is_equal_or_descendent_of(base.0, restricted.0.clone()) &&
+			self.env.is_equal_or_descendent_of(restricted.0.clone(), target.0.clone());
+
+		if valid {
+			debug!(target: "afg", "Restricted vote target in round {} from {:?} to {:?}",
+				self.votes.number(),
+				target,
+				restricted,
+			);
+
+			restricted
+		} else {
+			warn!(target: "afg", "Ignoring voting rule in round {}: {:?} is not between the base and {:?}",
+				self.votes.number(),
+				restricted,
+				target,
+			);
+
+			target
 		}
 	}
 
@@ -568,12 +1095,29 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 			b.update(new_state.clone());
 		}
 
+		self.events.emit_round_state(self.votes.number(), &new_state);
+
+		if last_state.estimate != new_state.estimate {
+			if let Some(ref estimate) = new_state.estimate {
+				self.env.round_estimate_updated(self.votes.number(), estimate.clone());
+			}
+		}
+
+		if last_state.finalized != new_state.finalized {
+			// votes on forks which don't contain the finalized block can no longer matter.
+			if let Some((ref f_hash, f_number)) = new_state.finalized {
+				let (prevote_weight, precommit_weight) = self.votes.prune_before(f_hash.clone(), f_number);
+				trace!(target: "afg", "Pruned votes with prevote weight {} and precommit weight {} from round {}",
+					prevote_weight, precommit_weight, self.votes.number());
+			}
+		}
+
 		if last_state.finalized != new_state.finalized && new_state.completable {
 			// send notification only when the round is completable and we've cast votes.
 			// this is a workaround that ensures when we re-instantiate the voter after
 			// a shutdown, we never re-create the same round with a base that was finalized
 			// in this round or after.
-			if let (&Some(State::Precommitted), Some((ref f_hash, ref f_number))) = (&self.state, new_state.finalized) {
+			if let (Some(Step::Precommitted), Some((ref f_hash, ref f_number))) = (self.machine.step(), new_state.finalized) {
 				let commit = Commit {
 					target_hash: f_hash.clone(),
 					target_number: *f_number,
@@ -583,7 +1127,7 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 				};
 				let finalized = (f_hash.clone(), *f_number, self.votes.number(), commit.clone());
 
-				let _ = self.finalized_sender.unbounded_send(finalized);
+				self.finalized_sender.notify(finalized);
 				self.best_finalized = Some(commit);
 			}
 		}
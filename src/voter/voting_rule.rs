@@ -0,0 +1,80 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rules restricting the blocks a voter votes for.
+//!
+//! By default we prevote for the best block containing the last round's
+//! estimate, which is often a block that was just authored. A `VotingRule`
+//! provided by the `Environment` can pick an ancestor of that block instead.
+//!
+//! Only prevotes are restricted. Precommits are for the prevote-GHOST, so they
+//! are restricted as well once enough voters apply the rule to their prevotes.
+//! Restricting precommits separately would apply the rule twice.
+
+use crate::{BlockNumberOps, Chain};
+
+/// A rule restricting the targets of our prevotes.
+pub trait VotingRule<H: Eq, N: Copy + BlockNumberOps>: Send + Sync {
+	/// Restrict the target of a prevote. `base` is the block the prevote must
+	/// build on and `target` is the block we would prevote for otherwise, which
+	/// is `base` or one of its descendents.
+	///
+	/// Returns the block to vote for instead, or `None` to keep `target`. The
+	/// returned block must be on the chain from `base` to `target`, otherwise
+	/// it is ignored.
+	fn restrict_vote(&self, chain: &dyn Chain<H, N>, base: (H, N), target: (H, N)) -> Option<(H, N)>;
+}
+
+/// Vote for the block a given number of blocks behind the target, but never
+/// for a block below the base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeforeBestBlockBy<N>(pub N);
+
+impl<H: Eq + Clone, N: Copy + BlockNumberOps + Send + Sync> VotingRule<H, N> for BeforeBestBlockBy<N> {
+	fn restrict_vote(&self, chain: &dyn Chain<H, N>, base: (H, N), target: (H, N)) -> Option<(H, N)> {
+		if target.1 - base.1 <= self.0 {
+			return Some(base);
+		}
+
+		let number = target.1 - self.0;
+		if number == target.1 {
+			return None;
+		}
+
+		// the ancestry is in reverse order from the target's parent.
+		let ancestry = chain.ancestry(base.0, target.0).ok()?;
+		let offset: usize = (target.1 - number - N::one()).as_();
+
+		ancestry.get(offset).map(|hash| (hash.clone(), number))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::chain::{DummyChain, GENESIS_HASH};
+
+	#[test]
+	fn before_best_block_by() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let rule = BeforeBestBlockBy(2);
+
+		assert_eq!(rule.restrict_vote(&chain, ("A", 2), ("E", 6)), Some(("C", 4)));
+		assert_eq!(rule.restrict_vote(&chain, ("A", 2), ("C", 4)), Some(("A", 2)));
+		assert_eq!(rule.restrict_vote(&chain, ("C", 4), ("E", 6)), Some(("C", 4)));
+		assert_eq!(BeforeBestBlockBy(0).restrict_vote(&chain, ("A", 2), ("E", 6)), None);
+	}
+}
@@ -19,9 +19,9 @@
 use std::hash::Hash;
 
 use crate::collections::{HashMap, Vec};
-use super::threshold;
+use super::{Error, ThresholdPolicy};
 
-/// A voter set, with accompanying indices.
+/// A voter set, with accompanying indices. Created with `VoterSet::new`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VoterSet<Id: Hash + Eq> {
 	weights: HashMap<Id, VoterInfo>,
@@ -38,6 +38,9 @@ impl<Id: Hash + Eq> VoterSet<Id> {
 	/// Get the length of the set.
 	pub fn len(&self) -> usize { self.voters.len() }
 
+	/// Whether the set is empty.
+	pub fn is_empty(&self) -> bool { self.voters.is_empty() }
+
 	/// Whether the set contains the key.
 	pub fn contains_key(&self, id: &Id) -> bool {
 		self.weights.contains_key(id)
@@ -53,6 +56,14 @@ impl<Id: Hash + Eq> VoterSet<Id> {
 		self.voters.get(idx).map(|&(_, weight)| weight)
 	}
 
+	/// Get the primary voter of the given round. Primaries are selected
+	/// round-robin over the canonical (sorted) order of voters.
+	///
+	/// Panics if the set is empty.
+	pub fn primary_voter(&self, round: u64) -> &(Id, u64) {
+		self.voter_by_index((round % self.voters.len() as u64) as usize)
+	}
+
 	/// Get the threshold weight.
 	pub fn threshold(&self) -> u64 { self.threshold }
 
@@ -65,20 +76,47 @@ impl<Id: Hash + Eq> VoterSet<Id> {
 	pub fn voters(&self) -> &[(Id, u64)] {
 		&self.voters
 	}
+
+	/// Iterate over the voters and their weights, in canonical order.
+	pub fn iter(&self) -> impl Iterator<Item = (&Id, u64)> {
+		self.voters.iter().map(|&(ref id, weight)| (id, weight))
+	}
+
+	/// Whether both sets contain exactly the same voters, regardless of their weights.
+	pub fn has_same_voters(&self, other: &VoterSet<Id>) -> bool {
+		self.voters.len() == other.voters.len() &&
+			self.voters.iter().zip(&other.voters).all(|((a, _), (b, _))| a == b)
+	}
 }
 
-impl<Id: Hash + Eq + Clone + Ord> std::iter::FromIterator<(Id, u64)> for VoterSet<Id> {
-	fn from_iter<I: IntoIterator<Item = (Id, u64)>>(iterable: I) -> Self {
+impl<Id: Hash + Eq + Clone + Ord> VoterSet<Id> {
+	/// Create a voter set from the given voters and weights. Fails with
+	/// `Error::WeightOverflow` if the total weight overflows.
+	///
+	/// Since the total weight is bounded, the weight of any subset of the
+	/// voters, e.g. of those which voted for a block, can't overflow either.
+	pub fn new<I: IntoIterator<Item = (Id, u64)>>(iterable: I) -> Result<Self, Error> {
+		VoterSet::with_threshold_policy(iterable, ThresholdPolicy::default())
+	}
+
+	/// Create a voter set with a threshold derived from the given fault
+	/// assumption instead of the standard one. Fails with `Error::WeightOverflow`
+	/// if the total weight overflows, or with `Error::InvalidVoterSet` if the
+	/// threshold exceeds it.
+	pub fn with_threshold_policy<I: IntoIterator<Item = (Id, u64)>>(
+		iterable: I,
+		policy: ThresholdPolicy,
+	) -> Result<Self, Error> {
 		let iter = iterable.into_iter();
 		let (lower, _) = iter.size_hint();
 
 		let mut voters = Vec::with_capacity(lower);
 		let mut weights = HashMap::with_capacity(lower);
 
-		let mut total_weight = 0;
+		let mut total_weight: u64 = 0;
 		for (id, weight) in iter {
 			voters.push((id.clone(), weight));
-			total_weight += weight;
+			total_weight = total_weight.checked_add(weight).ok_or(Error::WeightOverflow)?;
 		}
 
 		voters.sort_unstable();
@@ -87,8 +125,18 @@ impl<Id: Hash + Eq + Clone + Ord> std::iter::FromIterator<(Id, u64)> for VoterSe
 			weights.insert(id.clone(), VoterInfo { canon_idx: idx, weight: *weight });
 		}
 
-		let threshold = threshold(total_weight);
-		VoterSet { weights, voters, threshold }
+		let threshold = policy.threshold(total_weight);
+		if threshold > total_weight { return Err(Error::InvalidVoterSet) }
+
+		Ok(VoterSet { weights, voters, threshold })
+	}
+}
+
+/// Panics if the total weight of the voters overflows, use `VoterSet::new` to
+/// handle untrusted voter sets.
+impl<Id: Hash + Eq + Clone + Ord> std::iter::FromIterator<(Id, u64)> for VoterSet<Id> {
+	fn from_iter<I: IntoIterator<Item = (Id, u64)>>(iterable: I) -> Self {
+		VoterSet::new(iterable).expect("total weight of voters overflows")
 	}
 }
 
@@ -146,7 +194,9 @@ mod tests {
 		].iter().cloned().collect();
 
 		assert_eq!(v.len(), 6);
+		assert!(!v.is_empty());
 		assert_eq!(v.total_weight(), 38);
+		assert_eq!(v.iter().map(|(&id, _)| id).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 9]);
 
 		assert_eq!(v.voter_by_index(0), &(1, 5));
 		assert_eq!(v.voter_by_index(1), &(2, 7));
@@ -155,4 +205,65 @@ mod tests {
 		assert_eq!(v.voter_by_index(4), &(5, 7));
 		assert_eq!(v.voter_by_index(5), &(9, 9));
 	}
+
+	#[test]
+	fn primary_voter_is_round_robin() {
+		let v: VoterSet<usize> = [(3, 1), (1, 1), (2, 1)].iter().cloned().collect();
+
+		assert_eq!(v.primary_voter(0), &(1, 1));
+		assert_eq!(v.primary_voter(1), &(2, 1));
+		assert_eq!(v.primary_voter(2), &(3, 1));
+		assert_eq!(v.primary_voter(3), &(1, 1));
+		assert_eq!(v.primary_voter(u64::MAX), &(1, 1));
+	}
+
+	#[test]
+	fn same_voters_ignores_weights() {
+		let v1: VoterSet<usize> = [(1, 5), (2, 7), (3, 9)].iter().cloned().collect();
+		let v2: VoterSet<usize> = [(3, 1), (1, 1), (2, 1)].iter().cloned().collect();
+		let v3: VoterSet<usize> = [(1, 5), (2, 7), (4, 9)].iter().cloned().collect();
+		let v4: VoterSet<usize> = [(1, 5), (2, 7)].iter().cloned().collect();
+
+		assert!(v1.has_same_voters(&v2));
+		assert!(!v1.has_same_voters(&v3));
+		assert!(!v1.has_same_voters(&v4));
+
+		assert_eq!(v2.total_weight(), 3);
+		assert_eq!(v2.threshold(), 3);
+	}
+
+	#[test]
+	fn rejects_overflowing_weights() {
+		let max = u64::MAX;
+
+		assert_eq!(VoterSet::new(vec![(1, max), (2, 1)]).err(), Some(Error::WeightOverflow));
+
+		let v = VoterSet::new(vec![(1, max - 1), (2, 1)]).unwrap();
+		assert_eq!(v.total_weight(), max);
+		assert_eq!(v.threshold(), max - (max - 1) / 3);
+	}
+
+	#[test]
+	fn custom_threshold_policy() {
+		let voters = || vec![(1, 4), (2, 3), (3, 3)];
+
+		let v = VoterSet::with_threshold_policy(voters(), ThresholdPolicy::MaxFaultyWeight(2)).unwrap();
+		assert_eq!(v.threshold(), 7);
+		assert_eq!(VoterSet::new(voters()).unwrap().threshold(), 7);
+
+		let v = VoterSet::with_threshold_policy(voters(), ThresholdPolicy::MaxFaultyWeight(0)).unwrap();
+		assert_eq!(v.threshold(), 6);
+
+		// the threshold can't be reached.
+		assert_eq!(
+			VoterSet::with_threshold_policy(voters(), ThresholdPolicy::MaxFaultyWeight(10)).err(),
+			Some(Error::InvalidVoterSet),
+		);
+	}
+
+	#[test]
+	#[should_panic(expected = "total weight of voters overflows")]
+	fn collecting_overflowing_weights_panics() {
+		let _: VoterSet<usize> = vec![(1, u64::MAX), (2, 1)].into_iter().collect();
+	}
 }